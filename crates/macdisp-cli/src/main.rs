@@ -0,0 +1,3192 @@
+use clap::{CommandFactory, Parser, Subcommand};
+use macdisp_core::agents::{self, Subsystem};
+use macdisp_core::apply::{
+    apply_configuration, explain_configuration, find_display, parse_config, set_key, ApplyOptions,
+    ApplyProgress, ApplyResult, ApplyWarning, ConfigKey, KeyFilter, ProgressCallback,
+    SETTING_COMPLETIONS,
+};
+use macdisp_core::brightness;
+use macdisp_core::bundle::{Fingerprint, ProfileBundle};
+use macdisp_core::config::{self, CommandIds, Config, IdStrategy};
+use macdisp_core::daemon;
+use macdisp_core::ddc;
+use macdisp_core::devices::{devices_path, DeviceDb};
+use macdisp_core::dpi::densities;
+use macdisp_core::edid;
+use macdisp_core::events::{watch, watch_modes, ModeChange};
+use macdisp_core::expect::Expectations;
+use macdisp_core::export::{export_mdm_bundle, export_shell_script, format_utc_timestamp};
+use macdisp_core::gamma;
+use macdisp_core::geometry::PointPx;
+use macdisp_core::get::{field_value, DisplayField};
+use macdisp_core::hooks::run_event_hook;
+use macdisp_core::info::{display_details, format_details};
+use macdisp_core::ipc;
+use macdisp_core::layout::{
+    extended_layout, internal_off_changes, internal_off_path, internal_panel, is_current_layout,
+    save_internal_off,
+};
+use macdisp_core::lock::{self, ApplyLock};
+use macdisp_core::mirror::{compare_modes, mirror_set, MirrorSet, ModeComparison, SharedMode};
+use macdisp_core::mode_id::format_hz;
+use macdisp_core::output;
+use macdisp_core::patch;
+use macdisp_core::porcelain;
+use macdisp_core::power;
+use macdisp_core::quirks;
+use macdisp_core::recover;
+use macdisp_core::reference;
+use macdisp_core::selector::{connected_display, is_builtin, select_display, select_displays};
+use macdisp_core::server;
+use macdisp_core::session::{self, SessionState};
+use macdisp_core::settings::{self, ConfigOverrides};
+use macdisp_core::snapshot::{self, Snapshot};
+use macdisp_core::status::{status_line, StatusFormat};
+use macdisp_core::sync::SyncArrangement;
+use macdisp_core::system_prefs::{diff_arrangement, find_matching_arrangement, read_system_prefs};
+use macdisp_core::{
+    configure_displays, display_listing, format_display_command, format_display_id,
+    frame_rate_mode, get_active_displays, get_all_display_info, get_all_modes, get_current_mode,
+    get_display_identities, get_display_info, get_online_displays, hidpi_variant,
+    is_display_services_available, judder, list_displays, notch_family, notch_target, scale_ladder,
+    scale_target, set_display_mode, ConfigureOptions, DisplayConfig, DisplayMode, ModeEntry,
+    ScaleStep,
+};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+#[derive(Parser)]
+#[command(
+    name = "macdisp",
+    version = "0.1.0",
+    about = "macOS command line utility to configure display settings",
+    long_about = "A Rust implementation of displayplacer with full compatibility.\nUses DisplayServices private framework when available, falls back to CoreGraphics."
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
+    /// Read config.json from this directory (overrides MACDISP_CONFIG_DIR)
+    #[arg(long, global = true, value_name = "DIR")]
+    config_dir: Option<std::path::PathBuf>,
+
+    /// Give up on a display call after this long (overrides MACDISP_CALL_TIMEOUT_MS, 0 disables)
+    #[arg(long, global = true, value_name = "MS")]
+    call_timeout_ms: Option<u64>,
+
+    /// Match `hz:` to modes up to this many hertz away, e.g. 0.01 (overrides MACDISP_HZ_TOLERANCE)
+    #[arg(long, global = true, value_name = "HZ", value_parser = settings::parse_hz_tolerance)]
+    hz_tolerance: Option<f64>,
+
+    /// Pause between changing one display and the next, e.g. 500ms (overrides MACDISP_STAGGER_MS)
+    #[arg(long, global = true, value_name = "DURATION", value_parser = settings::parse_millis)]
+    stagger: Option<u64>,
+
+    /// Do not wait for other macdisp processes that are changing displays
+    #[arg(long, global = true)]
+    no_lock: bool,
+
+    /// Id written into generated commands and preferred for bare numbers: persistent, contextual or serial (overrides MACDISP_ID_STRATEGY)
+    #[arg(long, global = true, value_name = "STRATEGY")]
+    id_strategy: Option<IdStrategy>,
+
+    /// Before applying, save the current arrangement to this file
+    #[arg(long, global = true, value_name = "FILE")]
+    backup: Option<std::path::PathBuf>,
+
+    /// Apply even if it would leave no display enabled and unmirrored, or
+    /// mirror displays that share no safe mode
+    #[arg(long, global = true)]
+    force: bool,
+
+    /// Before mirroring, set both displays to the best mode they share
+    #[arg(long, global = true)]
+    mirror_best_mode: bool,
+
+    /// Print what would change on each display and stop without changing
+    /// anything (commands that apply configurations, extend and internal)
+    #[arg(long, global = true)]
+    dry_run: bool,
+
+    /// Do not print what is about to change on each display before applying
+    /// (commands that apply configurations)
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Leave origins as they are when rotating, instead of moving the rotated
+    /// display and its neighbours so they neither overlap nor leave a gap
+    /// (no effect while macdisp cannot rotate displays)
+    #[arg(long, global = true)]
+    keep_origins: bool,
+
+    /// Wake sleeping displays before applying
+    #[arg(long, global = true)]
+    wake: bool,
+
+    /// Put windows back where they were on their display after applying,
+    /// scaled to its new size (commands that apply configurations; needs the
+    /// Accessibility permission)
+    #[arg(long, global = true)]
+    preserve_windows: bool,
+
+    /// Leave gamma and brightness alone while BetterDisplay, Lunar or
+    /// MonitorControl holds them (overrides MACDISP_DEFER_GAMMA)
+    #[arg(long, global = true)]
+    defer_gamma: bool,
+
+    /// Apply only these keys of each configuration: mode, origin, rotation, mirror, enabled, wallpaper (comma separated)
+    #[arg(
+        long,
+        global = true,
+        value_name = "KEYS",
+        value_delimiter = ',',
+        conflicts_with = "skip"
+    )]
+    only: Vec<ConfigKey>,
+
+    /// Leave these keys of each configuration as the displays have them (comma separated)
+    #[arg(long, global = true, value_name = "KEYS", value_delimiter = ',')]
+    skip: Vec<ConfigKey>,
+
+    /// While applying, show each display as CoreGraphics reconfigures it;
+    /// `info` also shows the display id in hex
+    #[arg(short, long, global = true)]
+    verbose: bool,
+
+    /// When applying, exit with status 3 if every display already matched
+    #[arg(long, global = true)]
+    if_changed: bool,
+
+    /// Display configuration strings (e.g., "id:1 res:1920x1080 hz:60"), or
+    /// the name of a profile to apply
+    #[arg(trailing_var_arg = true)]
+    configs: Vec<String>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// List all displays and their current configurations
+    List {
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+        /// Tab-separated records whose fields never change, for shell scripts
+        #[arg(
+            long,
+            value_enum,
+            value_name = "VERSION",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "v1",
+            conflicts_with = "json"
+        )]
+        porcelain: Option<Porcelain>,
+        /// Ids in the suggested command (defaults to the id strategy)
+        #[arg(long, value_enum, value_name = "IDS")]
+        command_ids: Option<CommandIdChoice>,
+    },
+    /// Print a single property of a display, for scripts
+    Get {
+        /// Display id, persistent id, alias, "main" or "builtin"
+        selector: String,
+        /// Property to print
+        #[arg(value_enum)]
+        field: Field,
+    },
+    /// Print everything known about one display, for troubleshooting and bug reports
+    Info {
+        /// Display id, persistent id, alias, "main" or "builtin"
+        selector: String,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Exit with status 0 if a display is connected and 1 if not, for polling scripts
+    Connected {
+        /// Display id, persistent id, alias, "main" or "builtin"
+        selector: String,
+        /// Also print the answer as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Describe what configuration strings or a profile would change, without applying them
+    Explain {
+        /// Configuration strings, or the name of a profile
+        #[arg(required = true, value_name = "CONFIG")]
+        configs: Vec<String>,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Exit with status 1 and list the differences unless the displays look as expected
+    Assert {
+        /// Number of connected displays
+        #[arg(long)]
+        count: Option<usize>,
+        /// A display that must be connected (repeatable)
+        #[arg(long, value_name = "SELECTOR")]
+        has: Vec<String>,
+        /// The display that must have the menu bar
+        #[arg(long, value_name = "SELECTOR")]
+        main: Option<String>,
+        /// Resolution a display must run at (repeatable)
+        #[arg(long, value_name = "SELECTOR=WIDTHxHEIGHT")]
+        resolution: Vec<String>,
+        /// Where a display must sit in the arrangement (repeatable)
+        #[arg(long, value_name = "SELECTOR=X,Y")]
+        origin: Vec<String>,
+        /// Refresh rate a display must run at (repeatable)
+        #[arg(long, value_name = "SELECTOR=HZ")]
+        hz: Vec<String>,
+        /// Print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check whether macdisp can reach the displays from here and what limits it
+    Doctor,
+    /// Show the physical size and pixel density of each display, and the modes
+    /// that match their UI size to one display
+    Dpi {
+        /// Display whose UI size the others should match (defaults to the main display)
+        #[arg(long, value_name = "SELECTOR")]
+        reference: Option<String>,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show every display the daemon has seen, most recent first
+    Devices {
+        /// Print aliases for named displays that have none, for the config file
+        #[arg(long, conflicts_with = "json")]
+        aliases: bool,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Change a display with key=value settings instead of a config string
+    Set {
+        /// Display id, persistent id, alias, "main", "builtin" or @group
+        selector: String,
+        /// Settings such as res=2560x1440 hz=120 origin=0,0
+        #[arg(required = true, value_name = "KEY=VALUE", value_parser = SettingParser)]
+        settings: Vec<(String, String)>,
+    },
+    /// Show available display modes for a specific display
+    Modes {
+        /// Display id, persistent id, alias, "main" or "builtin"
+        selector: String,
+        /// Compare with another display: shared resolutions and the one
+        /// mirroring the two would likely use
+        #[arg(long, value_name = "SELECTOR")]
+        diff: Option<String>,
+        /// Keep running and print a line each time the current mode changes
+        #[arg(long, conflicts_with = "diff")]
+        watch: bool,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+        /// Tab-separated records whose fields never change, for shell scripts
+        #[arg(
+            long,
+            value_enum,
+            value_name = "VERSION",
+            num_args = 0..=1,
+            require_equals = true,
+            default_missing_value = "v1",
+            conflicts_with_all = ["json", "diff", "watch"]
+        )]
+        porcelain: Option<Porcelain>,
+    },
+    /// Hide or show the notch on MacBook Pro displays
+    Notch {
+        /// Action: hide, show, or toggle
+        #[arg(value_enum)]
+        action: NotchAction,
+        /// Display id, persistent id, alias, "main" or "builtin"
+        #[arg(
+            short,
+            long = "display",
+            visible_alias = "display-id",
+            value_name = "SELECTOR",
+            default_value = "builtin"
+        )]
+        display: String,
+    },
+    /// Switch between the HiDPI and unscaled variant of the current resolution
+    Hidpi {
+        /// Action: on, off, or toggle
+        #[arg(value_enum)]
+        action: HidpiAction,
+        /// Display id, persistent id, alias, "main" or "builtin"
+        selector: String,
+    },
+    /// Step along the "looks like" sizes like the scaling slider in Displays settings
+    Scale {
+        /// Display id, persistent id, alias, "main" or "builtin"
+        selector: String,
+        /// Step: larger (text), smaller (text, more space) or default
+        #[arg(value_enum)]
+        step: ScaleAction,
+    },
+    /// Pick refresh rates to suit the frame rate of what is playing
+    Hz {
+        #[command(subcommand)]
+        action: HzAction,
+    },
+    /// Show or set the brightness of a display or group
+    Brightness {
+        /// Display id, persistent id, alias, "main", "builtin" or @group
+        selector: String,
+        /// Brightness in percent; without it the current brightness is shown
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+        percent: Option<u8>,
+        /// Read the brightness from the daemon's cache, for status bars that
+        /// poll it; monitors on DDC/CI are read at most every few seconds
+        #[arg(long, conflicts_with = "percent")]
+        cached: bool,
+    },
+    /// Shift the color temperature of a display or group until interrupted
+    Temp {
+        /// Display id, persistent id, alias, "main", "builtin" or @group
+        selector: String,
+        /// White point in kelvin; 6500 is neutral
+        #[arg(value_parser = clap::value_parser!(u32).range(1000..=10000))]
+        kelvin: u32,
+    },
+    /// List or switch the reference modes of an XDR or Studio Display, which
+    /// set its white point and luminance
+    Reference {
+        #[command(subcommand)]
+        action: ReferenceAction,
+    },
+    /// Apply a JSON description of the displays, such as edited `list --json` output
+    Apply {
+        /// Read the JSON document from stdin
+        #[arg(long, required = true)]
+        stdin_json: bool,
+    },
+    /// Stop all mirroring and lay the displays out side by side
+    Extend,
+    /// Carry the arrangement to another Mac with the same monitors
+    Sync {
+        #[command(subcommand)]
+        action: SyncAction,
+    },
+    /// Turn the built-in panel off or back on while external displays stay on
+    Internal {
+        /// Action: off or on
+        #[arg(value_enum)]
+        action: InternalAction,
+    },
+    /// Reset every display to a safe mode and layout when screens stay black
+    Recover,
+    /// Put the displays back the way they were before the last change
+    Restore {
+        /// Restore a file saved with --backup instead
+        #[arg(long, value_name = "FILE")]
+        from: Option<std::path::PathBuf>,
+    },
+    /// Push display events to web dashboards over a WebSocket at /events
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8765")]
+        listen: std::net::SocketAddr,
+    },
+    /// Serve the gRPC control API
+    #[cfg(feature = "grpc")]
+    ServeGrpc {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:50051")]
+        listen: std::net::SocketAddr,
+    },
+    /// Export the current arrangement so it can be restored later
+    Export {
+        /// Emit a commented, runnable shell script instead of a single command line
+        #[arg(long)]
+        shell: bool,
+        /// Write a LaunchAgent, install script and configuration profile for MDM tools to DIR
+        #[arg(long, value_name = "DIR", conflicts_with = "shell")]
+        mdm: Option<std::path::PathBuf>,
+        /// Reverse DNS label of the LaunchAgent and profile written by --mdm
+        #[arg(long, default_value = "local.macdisp.restore", requires = "mdm")]
+        identifier: String,
+        /// Where macdisp is installed on the managed Macs
+        #[arg(long, default_value = "/usr/local/bin/macdisp", requires = "mdm")]
+        binary: String,
+        /// Profile name recorded in the script header
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Print a one-line summary for status bars
+    Status {
+        /// Output style
+        #[arg(long, value_enum, default_value_t = StatusStyle::Plain)]
+        format: StatusStyle,
+        /// Also print how long macdisp took from start-up, on standard error
+        #[arg(long)]
+        timing: bool,
+    },
+    /// Watch for display changes and print or act on each event
+    Watch {
+        /// Print events as JSON lines
+        #[arg(long)]
+        json: bool,
+        /// Run a shell command for each event (details in MACDISP_* environment variables)
+        #[arg(long, value_name = "COMMAND")]
+        exec: Option<String>,
+    },
+    /// Run in the background, applying profile rules and hooks from the config file
+    Daemon {
+        /// Override the debounce interval from the config file (milliseconds)
+        #[arg(long, value_name = "MS")]
+        debounce_ms: Option<u64>,
+        /// Pin displays to this profile and revert any external change
+        #[arg(long, value_name = "PROFILE")]
+        enforce: Option<String>,
+        /// Profile applied when no rule matches the connected displays
+        #[arg(long, value_name = "PROFILE")]
+        profile: Option<String>,
+        /// Send the brightness keys to the external display under the pointer (overrides MACDISP_BRIGHTNESS_KEYS)
+        #[arg(long)]
+        brightness_keys: bool,
+    },
+    /// List or apply profiles from the config file
+    Profile {
+        #[command(subcommand)]
+        action: ProfileAction,
+    },
+    /// Inspect the arrangement macOS has persisted for the WindowServer
+    SystemPrefs {
+        #[command(subcommand)]
+        action: SystemPrefsAction,
+    },
+    /// Talk to external monitors over DDC/CI
+    Ddc {
+        #[command(subcommand)]
+        action: DdcAction,
+    },
+    /// Inspect a display's EDID or work around what it misreports
+    Edid {
+        #[command(subcommand)]
+        action: EdidAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileAction {
+    /// List the profiles available on this Mac
+    List {
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Apply a profile
+    Apply {
+        /// Profile name (defaults to MACDISP_PROFILE or the config's "profile")
+        name: Option<String>,
+    },
+    /// Edit a profile in $EDITOR, checking it before it is saved
+    Edit {
+        /// Profile name; a new profile is created if there is none
+        name: String,
+    },
+    /// Package a profile with its aliases, variables and display fingerprints
+    Export {
+        /// Profile name
+        name: String,
+        /// Write the bundle to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<std::path::PathBuf>,
+        /// A note for whoever imports the bundle (repeatable)
+        #[arg(long, value_name = "TEXT")]
+        comment: Vec<String>,
+    },
+    /// Add a profile from a bundle, mapping its displays to the connected ones
+    Import {
+        /// Bundle written by `profile export`
+        file: std::path::PathBuf,
+        /// Save the profile under another name
+        #[arg(long)]
+        name: Option<String>,
+        /// Overwrite a profile, alias or variable of the same name
+        #[arg(long)]
+        replace: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum HzAction {
+    /// Switch to the refresh rate that shows content at FPS with the least judder
+    Match {
+        /// Display id, persistent id, alias, "main" or "builtin"
+        selector: String,
+        /// Frame rate of the content, e.g. 23.976, 25 or 59.94
+        #[arg(value_parser = parse_fps)]
+        fps: f64,
+    },
+}
+
+fn parse_fps(value: &str) -> Result<f64, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|fps| fps.is_finite() && *fps > 0.0)
+        .ok_or_else(|| format!("Invalid frame rate: {} (expected e.g. 23.976)", value))
+}
+
+#[derive(Subcommand)]
+enum SyncAction {
+    /// Write the current arrangement, naming displays by vendor, model and serial
+    Export {
+        /// Write the arrangement to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<std::path::PathBuf>,
+    },
+    /// Apply an arrangement written by `sync export` to the matching displays
+    Import {
+        /// File written by `sync export`
+        file: std::path::PathBuf,
+        /// Change nothing unless every display in the file is connected
+        #[arg(long)]
+        all: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SystemPrefsAction {
+    /// Show the arrangements stored in com.apple.windowserver.displays
+    Show {
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+    /// Compare the stored arrangement for the connected displays with the live state
+    Diff {
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ReferenceAction {
+    /// List the reference modes a display offers
+    List {
+        /// Display id, persistent id, alias, "main" or "builtin"
+        selector: String,
+        /// Output as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Switch a display or group to a reference mode
+    Set {
+        /// Display id, persistent id, alias, "main", "builtin" or @group
+        selector: String,
+        /// Mode index, name, or the unique start of a name
+        mode: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DdcAction {
+    /// List the VCP controls the monitor reports in its MCCS capabilities string
+    Caps {
+        /// Display id, persistent id, alias, "main" or "builtin"
+        selector: String,
+        /// Output in JSON format
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum EdidAction {
+    /// Generate a display override file that replaces the EDID
+    Override {
+        /// Display id, persistent id, alias, "main" or "builtin"
+        selector: String,
+        /// Advertise RGB only, for monitors macOS drives in YPbPr
+        #[arg(long)]
+        fix_rgb: bool,
+        /// Write the override here instead of printing it
+        #[arg(long, value_name = "FILE")]
+        output: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum StatusStyle {
+    /// "3 displays · main 2560x1440@120 · notch hidden"
+    Plain,
+    /// Compact label for SketchyBar items
+    Sketchybar,
+    /// ASCII separators for polybar-like bars
+    PolybarStyle,
+}
+
+impl From<StatusStyle> for StatusFormat {
+    fn from(style: StatusStyle) -> Self {
+        match style {
+            StatusStyle::Plain => StatusFormat::Plain,
+            StatusStyle::Sketchybar => StatusFormat::Sketchybar,
+            StatusStyle::PolybarStyle => StatusFormat::PolybarStyle,
+        }
+    }
+}
+
+/// Parses and validates `key=value` arguments of `set`, offering `key=` and the
+/// settings with few values for completion.
+#[derive(Clone)]
+struct SettingParser;
+
+impl clap::builder::TypedValueParser for SettingParser {
+    type Value = (String, String);
+
+    fn parse_ref(
+        &self,
+        cmd: &clap::Command,
+        _arg: Option<&clap::Arg>,
+        value: &std::ffi::OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        let invalid = |message: String| {
+            clap::Error::raw(clap::error::ErrorKind::ValueValidation, message + "\n").with_cmd(cmd)
+        };
+        let value = value
+            .to_str()
+            .ok_or_else(|| invalid("Settings must be valid UTF-8".to_string()))?;
+        let (key, val) = value
+            .split_once('=')
+            .ok_or_else(|| invalid(format!("Expected KEY=VALUE, got {}", value)))?;
+        set_key(&mut DisplayConfig::default(), key, val).map_err(invalid)?;
+        Ok((key.to_string(), val.to_string()))
+    }
+
+    fn possible_values(
+        &self,
+    ) -> Option<Box<dyn Iterator<Item = clap::builder::PossibleValue> + '_>> {
+        Some(Box::new(SETTING_COMPLETIONS.iter().map(|completion| {
+            clap::builder::PossibleValue::new(*completion)
+        })))
+    }
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum Field {
+    Id,
+    Uuid,
+    Width,
+    Height,
+    /// WIDTHxHEIGHT
+    Res,
+    Hz,
+    Depth,
+    /// X,Y
+    Origin,
+    X,
+    Y,
+    Rotation,
+    /// on or off
+    Scaling,
+    /// Mode number
+    Mode,
+    #[value(name = "mode_id")]
+    ModeId,
+    Main,
+    /// Id of the display being mirrored, empty if none
+    Mirror,
+    /// hardware or software, empty if not mirroring
+    #[value(name = "mirror_type")]
+    MirrorType,
+    Enabled,
+    Type,
+    /// 0.00-1.00, built-in and Apple displays only
+    Brightness,
+}
+
+impl From<Field> for DisplayField {
+    fn from(field: Field) -> Self {
+        match field {
+            Field::Id => DisplayField::Id,
+            Field::Uuid => DisplayField::Uuid,
+            Field::Width => DisplayField::Width,
+            Field::Height => DisplayField::Height,
+            Field::Res => DisplayField::Res,
+            Field::Hz => DisplayField::Hz,
+            Field::Depth => DisplayField::Depth,
+            Field::Origin => DisplayField::Origin,
+            Field::X => DisplayField::X,
+            Field::Y => DisplayField::Y,
+            Field::Rotation => DisplayField::Rotation,
+            Field::Scaling => DisplayField::Scaling,
+            Field::Mode => DisplayField::Mode,
+            Field::ModeId => DisplayField::ModeId,
+            Field::Main => DisplayField::Main,
+            Field::Mirror => DisplayField::Mirror,
+            Field::MirrorType => DisplayField::MirrorType,
+            Field::Enabled => DisplayField::Enabled,
+            Field::Type => DisplayField::Type,
+            Field::Brightness => DisplayField::Brightness,
+        }
+    }
+}
+
+/// Versions of the `--porcelain` output.
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum Porcelain {
+    V1,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum CommandIdChoice {
+    /// Persistent ids (UUIDs)
+    Uuid,
+    /// Serial ids such as s4251086178, stable across docks and ports
+    Serial,
+    /// Display ids, stable until the next reboot
+    Contextual,
+    /// Aliases from the config file, falling back to the id strategy
+    Alias,
+}
+
+impl CommandIdChoice {
+    fn ids(self, config: &Config) -> CommandIds {
+        match self {
+            CommandIdChoice::Uuid => CommandIds::Strategy(IdStrategy::Persistent),
+            CommandIdChoice::Serial => CommandIds::Strategy(IdStrategy::Serial),
+            CommandIdChoice::Contextual => CommandIds::Strategy(IdStrategy::Contextual),
+            CommandIdChoice::Alias => {
+                CommandIds::Aliases(config.aliases.clone(), config.id_strategy)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, clap::ValueEnum)]
+enum NotchAction {
+    /// Hide the notch by switching to a mode with smaller height
+    Hide,
+    /// Show the notch by switching to a mode with larger height
+    Show,
+    /// Toggle between hiding and showing the notch
+    Toggle,
+}
+
+#[derive(Clone, Debug, clap::ValueEnum)]
+enum InternalAction {
+    /// Turn the built-in panel off, as if the lid were closed
+    Off,
+    /// Turn the built-in panel back on
+    On,
+}
+
+#[derive(Clone, Debug, clap::ValueEnum)]
+enum HidpiAction {
+    /// Render at 2x pixels
+    On,
+    /// Render at 1x pixels
+    Off,
+    /// Switch to whichever variant is not active
+    Toggle,
+}
+
+#[derive(Clone, Debug, clap::ValueEnum)]
+enum ScaleAction {
+    /// Larger text: the next smaller "looks like" size
+    Larger,
+    /// Smaller text and more space: the next bigger "looks like" size
+    Smaller,
+    /// The size macOS uses out of the box
+    Default,
+}
+
+impl From<ScaleAction> for ScaleStep {
+    fn from(action: ScaleAction) -> Self {
+        match action {
+            ScaleAction::Larger => ScaleStep::Larger,
+            ScaleAction::Smaller => ScaleStep::Smaller,
+            ScaleAction::Default => ScaleStep::Default,
+        }
+    }
+}
+
+fn list_displays_json(ids: &CommandIds) -> String {
+    let listing = display_listing(&ipc::display_state(), ids);
+
+    serde_json::to_string_pretty(&listing)
+        .unwrap_or_else(|e| format!("{{\"error\": \"Failed to serialize JSON: {}\"}}", e))
+}
+
+fn current_arrangement_command(strategy: IdStrategy) -> String {
+    let mut cmd = String::from("macdisp");
+    for display_id in get_active_displays() {
+        if let Some(info) = get_display_info(display_id) {
+            cmd.push_str(&format!(" \"{}\"", format_display_command(&info, strategy)));
+        }
+    }
+    cmd
+}
+
+fn show_modes_porcelain(display_id: u32, version: Porcelain) {
+    let rotation = get_display_info(display_id)
+        .map(|info| info.rotation)
+        .unwrap_or(0);
+    let current = get_current_mode(display_id).map(|mode| mode.mode_number);
+    match version {
+        Porcelain::V1 => print!(
+            "{}",
+            porcelain::mode_records(&get_all_modes(display_id), current, rotation)
+        ),
+    }
+}
+
+fn show_modes(display_id: u32, json: bool) {
+    let modes = get_all_modes(display_id);
+    let current = get_current_mode(display_id);
+    let info = get_display_info(display_id);
+    let rotation = info.as_ref().map(|info| info.rotation).unwrap_or(0);
+    let mirroring = mirror_set(display_id, &get_all_display_info(), get_all_modes);
+
+    if json {
+        #[derive(serde::Serialize)]
+        struct ModesOutput {
+            display_id: u32,
+            /// Modes are listed in the panel's native orientation
+            rotation: u32,
+            current_mode: Option<ModeEntry>,
+            available_modes: Vec<ModeEntry>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            mirror_set: Option<MirrorSet>,
+            display_services_available: bool,
+        }
+
+        let id = info
+            .map(|info| info.persistent_id)
+            .unwrap_or_else(|| display_id.to_string());
+        let output = ModesOutput {
+            display_id,
+            rotation,
+            current_mode: current.map(|mode| ModeEntry::new(&mode, &id, rotation)),
+            available_modes: modes
+                .iter()
+                .map(|mode| ModeEntry::new(mode, &id, rotation))
+                .collect(),
+            mirror_set: mirroring,
+            display_services_available: is_display_services_available(),
+        };
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&output).unwrap_or_else(|e| {
+                format!("{{\"error\": \"Failed to serialize JSON: {}\"}}", e)
+            })
+        );
+    } else {
+        if rotation == 0 {
+            println!("Available modes for display {}:\n", display_id);
+        } else {
+            println!(
+                "Available modes for display {} (rotated {}°, resolutions as seen on screen):\n",
+                display_id, rotation
+            );
+        }
+        println!(
+            "{:<8} {:<12} {:<10} {:<8} {:<10} {:<6}",
+            "Mode #", "Resolution", "Hz", "Depth", "Safe", "Current"
+        );
+        println!("{:-<70}", "");
+
+        for mode in &modes {
+            let is_current = current
+                .as_ref()
+                .map(|c| c.mode_number == mode.mode_number)
+                .unwrap_or(false);
+            let marker = if is_current {
+                "*"
+            } else if rotation != 0 && !mode.available_rotated() {
+                "-"
+            } else {
+                ""
+            };
+
+            println!(
+                "{:<8} {:<12} {:<10.2} {:<8} {:<10} {:<6}",
+                mode.mode_number,
+                mode.rotated_size(rotation).to_string(),
+                mode.refresh_rate,
+                format!("{}-bit", mode.depth),
+                if mode.is_safe_for_hardware {
+                    "yes"
+                } else {
+                    "no"
+                },
+                marker
+            );
+        }
+
+        println!("\n* = current mode");
+        if rotation != 0 {
+            println!("- = not available while rotated");
+        }
+        println!(
+            "\nDisplayServices available: {}",
+            is_display_services_available()
+        );
+
+        if let Some(current) = current {
+            println!(
+                "Current mode is: {} ({} @ {}Hz)",
+                current.mode_number,
+                current.rotated_size(rotation),
+                format_hz(current.refresh_rate)
+            );
+        }
+        if let Some(set) = mirroring {
+            println!("Mirroring: {}", set.describe(display_id));
+        }
+    }
+}
+
+fn show_mode_diff(display_id: u32, other: u32, json: bool) {
+    let comparison = compare_modes(&get_all_modes(display_id), &get_all_modes(other));
+    let likely = comparison.likely_mirror_mode();
+
+    if json {
+        #[derive(serde::Serialize)]
+        struct DiffOutput<'a> {
+            display_id: u32,
+            other_display_id: u32,
+            #[serde(flatten)]
+            comparison: &'a ModeComparison,
+            likely_mirror_mode: Option<&'a SharedMode>,
+        }
+
+        let output = DiffOutput {
+            display_id,
+            other_display_id: other,
+            comparison: &comparison,
+            likely_mirror_mode: likely,
+        };
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&output).unwrap_or_else(|e| {
+                format!("{{\"error\": \"Failed to serialize JSON: {}\"}}", e)
+            })
+        );
+        return;
+    }
+
+    let describe = |mode: &DisplayMode| {
+        format!(
+            "#{} {}Hz{}{}",
+            mode.mode_number,
+            format_hz(mode.refresh_rate),
+            if mode.is_scaled { " HiDPI" } else { "" },
+            if mode.is_safe_for_hardware {
+                ""
+            } else {
+                " unsafe"
+            }
+        )
+    };
+    let sizes = |sizes: &[(u32, u32)]| {
+        if sizes.is_empty() {
+            return "none".to_string();
+        }
+        sizes
+            .iter()
+            .map(|(width, height)| format!("{}x{}", width, height))
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    println!(
+        "Resolutions shared by display {} and display {}:\n",
+        display_id, other
+    );
+    println!(
+        "{:<12} {:<20} {:<20}",
+        "Resolution",
+        format!("Display {}", display_id),
+        format!("Display {}", other)
+    );
+    println!("{:-<54}", "");
+    for shared in &comparison.shared {
+        println!(
+            "{:<12} {:<20} {:<20}",
+            format!("{}x{}", shared.width, shared.height),
+            describe(&shared.first),
+            describe(&shared.second)
+        );
+    }
+
+    println!(
+        "\nOnly display {}: {}",
+        display_id,
+        sizes(&comparison.only_first)
+    );
+    println!("Only display {}: {}", other, sizes(&comparison.only_second));
+
+    match likely {
+        Some(shared) => println!(
+            "\nMirroring would likely run at {}x{} (mode {} on display {}, mode {} on display {})",
+            shared.width,
+            shared.height,
+            shared.first.mode_number,
+            display_id,
+            shared.second.mode_number,
+            other
+        ),
+        None => println!("\nThe displays share no resolution that is safe on both"),
+    }
+}
+
+fn handle_notch_command(
+    action: NotchAction,
+    selector: &str,
+    config: &Config,
+) -> Result<(), String> {
+    let _lock = ApplyLock::acquire()?;
+    let display_id = selected_display_id(selector, config)?;
+
+    // Check if this is a built-in display
+    let display_info = get_display_info(display_id);
+    let is_builtin = display_info.as_ref().map(is_builtin).unwrap_or(false);
+
+    let current = get_current_mode(display_id)
+        .ok_or_else(|| format!("Could not get current mode for display {}", display_id))?;
+
+    let modes = get_all_modes(display_id);
+
+    // Find modes with same width, hz, color_depth, and scaling, sorted by
+    // height, or across scaling and depth when those offer a single height
+    let family = notch_family(&modes, &current);
+    let sorted_modes = family.modes;
+
+    if sorted_modes.is_empty() {
+        return Err(format!("No similar modes found for display {}", display_id));
+    }
+
+    // Check if there are actually different heights (notch-capable)
+    let min_height = sorted_modes.first().map(|m| m.pixel_height).unwrap_or(0);
+    let max_height = sorted_modes.last().map(|m| m.pixel_height).unwrap_or(0);
+
+    if min_height == max_height {
+        if is_builtin {
+            return Err(format!(
+                "Display {} does not appear to have a notch (no alternate height modes found)",
+                display_id
+            ));
+        } else {
+            return Err(format!(
+                "Display {} is not a MacBook built-in display with a notch",
+                display_id
+            ));
+        }
+    }
+
+    // Warn if this doesn't look like a notch scenario
+    let height_diff = max_height - min_height;
+    if !is_builtin && height_diff > 100 {
+        eprintln!(
+            "Warning: Display {} is not a built-in display. The height difference ({}px) may not be notch-related.",
+            display_id, height_diff
+        );
+    }
+
+    // Determine target mode based on action
+    let hide = match action {
+        NotchAction::Hide => true,
+        NotchAction::Show => false,
+        // Currently hidden, show it; currently showing or in between, hide it
+        NotchAction::Toggle => current.pixel_height != min_height,
+    };
+    let target_mode = notch_target(&sorted_modes, &current, hide);
+
+    if let Some(mode) = target_mode {
+        if mode.mode_number == current.mode_number {
+            println!(
+                "Display {} is already in the target mode ({}x{} @ {}Hz)",
+                display_id,
+                mode.width,
+                mode.height,
+                format_hz(mode.refresh_rate)
+            );
+            return Ok(());
+        }
+
+        if family.mixed && mode.is_scaled != current.is_scaled {
+            eprintln!(
+                "Warning: Display {} has no {} mode at the other height; switching to a {} mode",
+                display_id,
+                if current.is_scaled {
+                    "scaled"
+                } else {
+                    "unscaled"
+                },
+                if mode.is_scaled { "scaled" } else { "unscaled" }
+            );
+        } else if family.mixed && mode.depth != current.depth {
+            eprintln!(
+                "Warning: Display {} has no mode at the other height with color depth {}; switching to color depth {}",
+                display_id, current.depth, mode.depth
+            );
+        }
+        save_automatic_snapshot(&get_all_display_info());
+        set_display_mode(display_id, mode.mode_number)?;
+
+        let action_desc = if hide { "hidden" } else { "shown" };
+
+        println!(
+            "Notch {} on display {} (switched to mode {}: {}x{} @ {}Hz)",
+            action_desc,
+            display_id,
+            mode.mode_number,
+            mode.width,
+            mode.height,
+            format_hz(mode.refresh_rate)
+        );
+        Ok(())
+    } else {
+        Err(format!("No suitable mode found to {:?} the notch", action))
+    }
+}
+
+/// The id of the display a selector refers to.
+fn selected_display_id(selector: &str, config: &Config) -> Result<u32, String> {
+    require_display_state()?;
+    let displays = get_all_display_info();
+    select_display(selector, &displays, config).map(|info| info.id)
+}
+
+/// Fail commands that read the display state when there is no window server
+/// to read it from, pointing at what still works.
+fn require_display_state() -> Result<(), String> {
+    match session::current().problem() {
+        Some(problem) => Err(format!(
+            "{}. `macdisp list` and `macdisp info s<serial>` show the EDIDs IOKit can read",
+            problem
+        )),
+        None => Ok(()),
+    }
+}
+
+fn handle_get_command(selector: &str, field: Field, config: &Config) -> Result<(), String> {
+    require_display_state()?;
+    let displays = get_all_display_info();
+    let info = select_display(selector, &displays, config)?;
+    println!("{}", field_value(info, field.into())?);
+    Ok(())
+}
+
+fn handle_info_command(
+    selector: &str,
+    json: bool,
+    verbose: bool,
+    config: &Config,
+) -> Result<(), String> {
+    let json = json || config.format.is_json();
+    if let Some(problem) = session::current().problem() {
+        return print_headless_info(selector, &problem, json, config);
+    }
+    let displays = get_all_display_info();
+    let info = select_display(selector, &displays, config)?;
+    let details = display_details(info);
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&details)
+                .map_err(|e| format!("Failed to serialize JSON: {}", e))?
+        );
+    } else {
+        print!("{}", format_details(&details, verbose));
+    }
+    Ok(())
+}
+
+/// Whether a display is connected, printed as JSON on request.
+///
+/// Only the display list and ids are read, not modes, so this stays cheap in
+/// a tight loop.
+fn handle_connected_command(selector: &str, json: bool, config: &Config) -> bool {
+    let identities = get_display_identities();
+    let display = connected_display(selector, &identities, config);
+
+    if json || config.format.is_json() {
+        #[derive(serde::Serialize)]
+        struct ConnectedOutput<'a> {
+            selector: &'a str,
+            connected: bool,
+            display: Option<&'a macdisp_core::DisplayIdentity>,
+        }
+
+        let output = ConnectedOutput {
+            selector,
+            connected: display.is_some(),
+            display,
+        };
+        match serde_json::to_string_pretty(&output) {
+            Ok(output) => println!("{}", output),
+            Err(e) => eprintln!("Failed to serialize JSON: {}", e),
+        }
+    }
+    display.is_some()
+}
+
+fn write_mdm_bundle(
+    dir: &std::path::Path,
+    profile: Option<&str>,
+    identifier: &str,
+    binary: &str,
+) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let files = export_mdm_bundle(profile, identifier, binary)?;
+    std::fs::create_dir_all(dir)
+        .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    for file in &files {
+        let path = dir.join(&file.name);
+        std::fs::write(&path, &file.contents)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        if file.executable {
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))
+                .map_err(|e| format!("Failed to make {} executable: {}", path.display(), e))?;
+        }
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// Parse configuration strings given on the command line, resolving aliases
+/// in `id:` and `mirror:` the way profiles do.
+fn parse_config_args(args: &[String], config: &Config) -> Result<Vec<DisplayConfig>, String> {
+    args.iter()
+        .map(|arg| {
+            let mut parsed = parse_config(arg)?;
+            parsed.id = config.resolve_alias(&parsed.id).to_string();
+            parsed.mirror = parsed.mirror.map(|m| config.resolve_alias(&m).to_string());
+            Ok(parsed)
+        })
+        .collect()
+}
+
+fn handle_explain_command(
+    args: &[String],
+    json: bool,
+    config: &Config,
+    options: &ApplyOptions,
+) -> Result<(), String> {
+    let configs = match config.profile_shorthand(args) {
+        Some(name) => daemon::profile_configs(config, name)?,
+        None => parse_config_args(args, config)?,
+    };
+    let explanations = explain_configuration(configs, options)?;
+    if json || config.format.is_json() {
+        let output = serde_json::to_string_pretty(&explanations)
+            .map_err(|e| format!("Failed to serialize JSON: {}", e))?;
+        println!("{}", output);
+        return Ok(());
+    }
+    for explanation in &explanations {
+        println!("{}", explanation);
+        for warning in &explanation.warnings {
+            println!("  Warning: {}", warning);
+        }
+    }
+    Ok(())
+}
+
+fn handle_dpi_command(reference: Option<&str>, json: bool, config: &Config) -> Result<(), String> {
+    let displays = get_all_display_info();
+    let reference = match reference {
+        Some(selector) => select_display(selector, &displays, config)?.id,
+        None => {
+            displays
+                .iter()
+                .find(|info| info.is_main)
+                .ok_or("No main display found")?
+                .id
+        }
+    };
+    let densities = densities(&displays, reference);
+    if json || config.format.is_json() {
+        let output = serde_json::to_string_pretty(&densities)
+            .map_err(|e| format!("Failed to serialize JSON: {}", e))?;
+        println!("{}", output);
+        return Ok(());
+    }
+    for density in &densities {
+        println!("{}", density);
+    }
+    Ok(())
+}
+
+fn handle_devices_command(aliases: bool, json: bool, config: &Config) -> Result<(), String> {
+    let path = devices_path();
+    let mut db = DeviceDb::load(&path)?;
+    if aliases {
+        let suggested = db.suggested_aliases(&config.aliases);
+        let output = serde_json::to_string_pretty(&serde_json::json!({ "aliases": suggested }))
+            .map_err(|e| format!("Failed to serialize JSON: {}", e))?;
+        println!("{}", output);
+        return Ok(());
+    }
+
+    db.devices.sort_by(|a, b| b.last_seen.cmp(&a.last_seen));
+    if json || config.format.is_json() {
+        let output = serde_json::to_string_pretty(&db.devices)
+            .map_err(|e| format!("Failed to serialize JSON: {}", e))?;
+        println!("{}", output);
+        return Ok(());
+    }
+    if db.devices.is_empty() {
+        eprintln!(
+            "No displays recorded yet; the daemon records them in {}",
+            path.display()
+        );
+        return Ok(());
+    }
+    for device in &db.devices {
+        println!("{} ({})", device.label(), device.persistent_id);
+        println!("  Seen: {} to {}", device.first_seen, device.last_seen);
+        println!(
+            "  Last: {} at ({},{})",
+            device.last_mode, device.last_origin.0, device.last_origin.1
+        );
+    }
+    Ok(())
+}
+
+/// Print the expectations the displays do not meet and return whether all hold.
+fn handle_assert_command(
+    expect: &Expectations,
+    json: bool,
+    config: &Config,
+) -> Result<bool, String> {
+    let mismatches = expect.check(&get_all_display_info(), config);
+    if json || config.format.is_json() {
+        #[derive(serde::Serialize)]
+        struct AssertOutput<'a> {
+            ok: bool,
+            mismatches: &'a [String],
+        }
+
+        let output = AssertOutput {
+            ok: mismatches.is_empty(),
+            mismatches: &mismatches,
+        };
+        let output = serde_json::to_string_pretty(&output)
+            .map_err(|e| format!("Failed to serialize JSON: {}", e))?;
+        println!("{}", output);
+    } else {
+        for mismatch in &mismatches {
+            println!("{}", mismatch);
+        }
+    }
+    Ok(mismatches.is_empty())
+}
+
+/// Print what macdisp can reach from here and return whether it can work.
+fn handle_doctor_command() -> bool {
+    let mut healthy = true;
+    let session = session::current();
+    println!(
+        "Session: {}{}",
+        session.state.as_str(),
+        if session.ssh { " (over SSH)" } else { "" }
+    );
+    if let Some(problem) = session.problem() {
+        healthy = false;
+        println!("  {}", problem);
+    } else if session.state == SessionState::Background {
+        println!("  Another user's session is in front; display changes may not stick");
+    }
+
+    println!(
+        "DisplayServices: {}",
+        if is_display_services_available() {
+            "available"
+        } else {
+            "not available, CoreGraphics only (no scaled HiDPI modes or brightness)"
+        }
+    );
+
+    let online = if session.has_window_server() {
+        get_online_displays().len()
+    } else {
+        0
+    };
+    println!(
+        "Displays: {} through the window server, {} EDIDs through IOKit",
+        online,
+        edid::iokit_edids().len()
+    );
+
+    let agents = agents::running_agents();
+    if agents.is_empty() {
+        println!("Other display agents: none running");
+    } else {
+        println!("Other display agents:");
+        for agent in &agents {
+            println!("  {}", agent);
+        }
+    }
+
+    let path = settings::config_dir().join("config.json");
+    match settings::load(&global_overrides()) {
+        Ok(_) if path.exists() => println!("Config: {}", path.display()),
+        Ok(_) => println!("Config: {} not found, using defaults", path.display()),
+        Err(e) => {
+            healthy = false;
+            println!("Config: {}", e);
+        }
+    }
+    healthy
+}
+
+/// Show the EDID IOKit reads for the display `selector` names, when the
+/// window server cannot be reached; only serial selectors match there.
+fn print_headless_info(
+    selector: &str,
+    problem: &str,
+    json: bool,
+    config: &Config,
+) -> Result<(), String> {
+    let identities = get_display_identities();
+    let serial = connected_display(selector, &identities, config)
+        .map(|identity| identity.serial)
+        .ok_or_else(|| {
+            format!(
+                "No display matches {}; without a window server only s<serial> selectors work ({})",
+                selector, problem
+            )
+        })?;
+    let edid = edid::iokit_edids()
+        .into_iter()
+        .find(|edid| edid.serial == serial)
+        .ok_or_else(|| format!("No display matches {}", selector))?;
+    eprintln!("Warning: {}", problem);
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&edid)
+                .map_err(|e| format!("Failed to serialize JSON: {}", e))?
+        );
+    } else {
+        println!("{}", format_iokit_display(&edid));
+    }
+    Ok(())
+}
+
+fn format_iokit_display(edid: &edid::Edid) -> String {
+    format!(
+        "{} {} (serial s{}, EDID {})",
+        edid.manufacturer,
+        edid.name.as_deref().unwrap_or("(unnamed)"),
+        edid.serial,
+        edid.version
+    )
+}
+
+/// List the displays IOKit can see when the window server cannot be reached.
+fn print_headless_displays(problem: &str, json: bool) {
+    let edids = edid::iokit_edids();
+    eprintln!("Warning: {}", problem);
+    if json {
+        #[derive(serde::Serialize)]
+        struct HeadlessListing<'a> {
+            session: session::Session,
+            iokit_displays: &'a [edid::Edid],
+        }
+
+        let listing = HeadlessListing {
+            session: session::current(),
+            iokit_displays: &edids,
+        };
+        match serde_json::to_string_pretty(&listing) {
+            Ok(output) => println!("{}", output),
+            Err(e) => eprintln!("Failed to serialize JSON: {}", e),
+        }
+        return;
+    }
+
+    println!("Displays found through IOKit: {}", edids.len());
+    for edid in &edids {
+        println!("  {}", format_iokit_display(edid));
+    }
+}
+
+/// Exit status of `--if-changed` runs that found nothing to change.
+const NO_CHANGE_EXIT_CODE: i32 = 3;
+
+/// Print the outcome of an apply and return whether anything changed.
+///
+/// Text output has already shown each step and warning as it happened, so
+/// only a warning count is added; JSON output is the whole result.
+fn report_apply(result: ApplyResult, json: bool) -> bool {
+    if json {
+        match serde_json::to_string_pretty(&result) {
+            Ok(output) => println!("{}", output),
+            Err(e) => eprintln!("Failed to serialize JSON: {}", e),
+        }
+        return result.changed;
+    }
+
+    for hook in &result.hooks {
+        if !hook.success {
+            eprintln!("Warning: {}", hook.failure());
+            continue;
+        }
+        println!("Ran {} hook `{}`", hook.stage.as_str(), hook.command);
+        for line in hook.output.lines() {
+            println!("  {}", line);
+        }
+    }
+    if !result.warnings.is_empty() {
+        eprintln!(
+            "Applied with {} warning{}",
+            result.warnings.len(),
+            if result.warnings.len() == 1 { "" } else { "s" }
+        );
+    }
+    result.changed
+}
+
+/// `-v` output: one line per display and per reconfiguration callback.
+fn print_progress(progress: &ApplyProgress) {
+    match progress {
+        ApplyProgress::Display {
+            display_id,
+            index,
+            total,
+        } => eprintln!(
+            "Reconfiguring display {}, {} of {}...",
+            format_display_id(*display_id),
+            index,
+            total
+        ),
+        ApplyProgress::ReconfigureBegin { display_id } => {
+            eprintln!(
+                "  display {} is reconfiguring",
+                format_display_id(*display_id)
+            )
+        }
+        ApplyProgress::ReconfigureEnd { display_id, kind } => {
+            eprintln!(
+                "  display {} reconfigured: {}",
+                format_display_id(*display_id),
+                kind.as_str()
+            )
+        }
+    }
+}
+
+/// Save the arrangement `restore` goes back to; a failure only costs the undo.
+fn save_automatic_snapshot(displays: &[macdisp_core::DisplayInfo]) {
+    if let Err(e) = snapshot::save_automatic(displays) {
+        eprintln!("Warning: {}", e);
+    }
+}
+
+fn exit_if_unchanged(changed: bool, if_changed: bool) {
+    if if_changed && !changed {
+        std::process::exit(NO_CHANGE_EXIT_CODE);
+    }
+}
+
+fn handle_hidpi_command(
+    action: HidpiAction,
+    selector: &str,
+    config: &Config,
+) -> Result<bool, String> {
+    let _lock = ApplyLock::acquire()?;
+    let displays = get_all_display_info();
+    let display_id = select_display(selector, &displays, config)?.id;
+
+    let current = get_current_mode(display_id)
+        .ok_or_else(|| format!("Could not get current mode for display {}", display_id))?;
+    let hidpi = match action {
+        HidpiAction::On => true,
+        HidpiAction::Off => false,
+        HidpiAction::Toggle => !current.is_scaled,
+    };
+    if current.is_scaled == hidpi {
+        println!(
+            "Display {} is already {} at {}x{}",
+            display_id,
+            if hidpi { "HiDPI" } else { "unscaled" },
+            current.width,
+            current.height
+        );
+        return Ok(false);
+    }
+
+    let modes = get_all_modes(display_id);
+    let mode = hidpi_variant(&modes, &current, hidpi).ok_or_else(|| {
+        format!(
+            "Display {} has no {} mode at {}x{}",
+            display_id,
+            if hidpi { "HiDPI" } else { "unscaled" },
+            current.width,
+            current.height
+        )
+    })?;
+
+    save_automatic_snapshot(&displays);
+    set_display_mode(display_id, mode.mode_number)?;
+    println!(
+        "Set display {} to {}x{} @ {}Hz {} (mode {})",
+        display_id,
+        mode.width,
+        mode.height,
+        format_hz(mode.refresh_rate),
+        if mode.is_scaled { "HiDPI" } else { "unscaled" },
+        mode.mode_number
+    );
+    Ok(true)
+}
+
+fn handle_scale_command(selector: &str, step: ScaleStep, config: &Config) -> Result<bool, String> {
+    let _lock = ApplyLock::acquire()?;
+    let displays = get_all_display_info();
+    let display_id = select_display(selector, &displays, config)?.id;
+
+    let current = get_current_mode(display_id)
+        .ok_or_else(|| format!("Could not get current mode for display {}", display_id))?;
+    let modes = get_all_modes(display_id);
+    if step != ScaleStep::Default && scale_ladder(&modes, &current).is_empty() {
+        return Err(format!(
+            "Display {} has no HiDPI modes to scale between",
+            display_id
+        ));
+    }
+
+    let Some(mode) = scale_target(&modes, &current, step) else {
+        if step == ScaleStep::Default {
+            return Err(format!("Display {} has no default mode", display_id));
+        }
+        println!(
+            "Display {} already looks like {}, the {} size",
+            display_id,
+            current.size(),
+            if step == ScaleStep::Larger {
+                "largest text"
+            } else {
+                "most space"
+            }
+        );
+        return Ok(false);
+    };
+    if mode.mode_id() == current.mode_id() {
+        println!(
+            "Display {} already looks like {}, the default size",
+            display_id,
+            current.size()
+        );
+        return Ok(false);
+    }
+
+    save_automatic_snapshot(&displays);
+    set_display_mode(display_id, mode.mode_number)?;
+    println!(
+        "Set display {} to look like {} @ {}Hz{} (mode {})",
+        display_id,
+        mode.size(),
+        format_hz(mode.refresh_rate),
+        if mode.is_scaled { " HiDPI" } else { "" },
+        mode.mode_number
+    );
+    Ok(true)
+}
+
+/// How a refresh rate shows content at `fps`, for the messages of `hz match`.
+fn judder_note(refresh_rate: f64, fps: f64) -> String {
+    let drift = judder(refresh_rate, fps);
+    let refreshes = (refresh_rate / fps).round().max(1.0);
+    if drift < 0.0005 {
+        return format!("every frame shown for {} refreshes", refreshes);
+    }
+    format!(
+        "about {} refreshes per frame, off by one every {:.1}s",
+        refreshes,
+        1.0 / (drift * fps)
+    )
+}
+
+fn handle_hz_match_command(selector: &str, fps: f64, config: &Config) -> Result<bool, String> {
+    let _lock = ApplyLock::acquire()?;
+    let displays = get_all_display_info();
+    let display_id = select_display(selector, &displays, config)?.id;
+
+    let current = get_current_mode(display_id)
+        .ok_or_else(|| format!("Could not get current mode for display {}", display_id))?;
+    let modes = get_all_modes(display_id);
+    let mode = frame_rate_mode(&modes, &current, fps).ok_or_else(|| {
+        format!(
+            "Display {} reports no refresh rates for {}",
+            display_id,
+            current.mode_id().short()
+        )
+    })?;
+    if mode.mode_number == current.mode_number {
+        println!(
+            "Display {} is already at {}, the smoothest for {}fps: {}",
+            display_id,
+            current.mode_id().short(),
+            fps,
+            judder_note(current.refresh_rate, fps)
+        );
+        return Ok(false);
+    }
+
+    save_automatic_snapshot(&displays);
+    set_display_mode(display_id, mode.mode_number)?;
+    println!(
+        "Set display {} to {} for {}fps (mode {}): {}",
+        display_id,
+        mode.mode_id().short(),
+        fps,
+        mode.mode_number,
+        judder_note(mode.refresh_rate, fps)
+    );
+    Ok(true)
+}
+
+fn handle_extend_command(dry_run: bool) -> Result<bool, String> {
+    let _lock = ApplyLock::acquire()?;
+    let displays = get_all_display_info();
+    let changes = extended_layout(&displays);
+    if is_current_layout(&changes, &displays) {
+        println!("Displays are already extended side by side");
+        return Ok(false);
+    }
+
+    if dry_run {
+        for (id, options) in &changes {
+            let info = displays.iter().find(|info| info.id == *id);
+            let origin = options.origin.map(PointPx::from);
+            if let (Some(info), Some(origin)) = (info, origin) {
+                if info.origin() != origin {
+                    println!("Display {}: origin {} → {}", id, info.origin(), origin);
+                }
+            }
+            if options.mirror.is_some() {
+                println!("Display {}: mirroring → off", id);
+            }
+        }
+        return Ok(true);
+    }
+
+    save_automatic_snapshot(&displays);
+    configure_displays(&changes)?;
+    for (id, options) in &changes {
+        if let Some((x, y)) = options.origin {
+            println!("Set display {} origin to ({}, {})", id, x, y);
+        }
+        if options.mirror.is_some() {
+            println!("Stopped mirroring on display {}", id);
+        }
+    }
+    Ok(true)
+}
+
+fn handle_apply_json_command(options: &ApplyOptions) -> Result<ApplyResult, String> {
+    let mut document = String::new();
+    std::io::Read::read_to_string(&mut std::io::stdin(), &mut document)
+        .map_err(|e| format!("Failed to read stdin: {}", e))?;
+    let patches = patch::parse_document(&document)?;
+    let configs = patch::patch_configs(&patches, &get_all_display_info())?;
+    if configs.is_empty() {
+        if !options.quiet {
+            println!("Displays already match the JSON document");
+        }
+        return Ok(ApplyResult::default());
+    }
+    apply_configuration(configs, options)
+}
+
+fn handle_internal_command(action: InternalAction, dry_run: bool) -> Result<bool, String> {
+    let _lock = ApplyLock::acquire()?;
+    let builtin_id = internal_panel(&get_display_identities(), &internal_off_path())
+        .ok_or("This Mac has no built-in display")?;
+    let displays = get_all_display_info();
+    let active = displays.iter().any(|info| info.id == builtin_id);
+
+    let changes = match action {
+        InternalAction::Off if !active => {
+            println!("Built-in display is already off");
+            return Ok(false);
+        }
+        InternalAction::Off => internal_off_changes(&displays, builtin_id)?,
+        InternalAction::On if active => {
+            println!("Built-in display is already on");
+            return Ok(false);
+        }
+        InternalAction::On => vec![(
+            builtin_id,
+            ConfigureOptions {
+                enabled: Some(true),
+                ..Default::default()
+            },
+        )],
+    };
+
+    if dry_run {
+        for (id, options) in &changes {
+            if let (Some(info), Some(origin)) = (
+                displays.iter().find(|info| info.id == *id),
+                options.origin.map(PointPx::from),
+            ) {
+                println!("Display {}: origin {} → {}", id, info.origin(), origin);
+            }
+            if options.mirror.is_some() {
+                println!("Display {}: mirroring → off", id);
+            }
+            if let Some(enabled) = options.enabled {
+                println!(
+                    "Display {}: {}",
+                    id,
+                    if enabled { "off → on" } else { "on → off" }
+                );
+            }
+        }
+        return Ok(true);
+    }
+
+    save_automatic_snapshot(&displays);
+    configure_displays(&changes)?;
+    match action {
+        InternalAction::Off => {
+            if let Err(e) = save_internal_off(&internal_off_path(), builtin_id) {
+                eprintln!("Warning: {}; `internal on` may not find the panel", e);
+            }
+            println!("Turned off built-in display {}", builtin_id);
+        }
+        InternalAction::On => {
+            let _ = std::fs::remove_file(internal_off_path());
+            println!("Turned on built-in display {}", builtin_id);
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(feature = "grpc")]
+fn handle_serve_grpc_command(listen: std::net::SocketAddr) -> Result<(), String> {
+    let config = settings::load(&global_overrides())?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Failed to start runtime: {}", e))?;
+
+    eprintln!("Serving gRPC on {}", listen);
+    runtime.block_on(macdisp_core::grpc::serve(listen, config))
+}
+
+fn handle_set_command(
+    selector: &str,
+    settings: &[(String, String)],
+    config: &Config,
+    options: &ApplyOptions,
+) -> Result<ApplyResult, String> {
+    let displays = get_all_display_info();
+    let selected = select_displays(selector, &displays, config)?;
+
+    let mut display_config = DisplayConfig::default();
+    for (key, value) in settings {
+        set_key(&mut display_config, key, value)?;
+    }
+    if let Some(mirror) = &display_config.mirror {
+        display_config.mirror = Some(config.resolve_alias(mirror).to_string());
+    }
+
+    let configs = selected
+        .iter()
+        .map(|info| DisplayConfig {
+            id: info.persistent_id.clone(),
+            ..display_config.clone()
+        })
+        .collect();
+    apply_configuration(configs, options)
+}
+
+fn handle_restore_command(
+    from: Option<std::path::PathBuf>,
+    options: &ApplyOptions,
+) -> Result<ApplyResult, String> {
+    let path = from.unwrap_or_else(snapshot::last_snapshot_path);
+    if !path.exists() {
+        return Err(format!(
+            "No snapshot at {}; nothing has been changed yet",
+            path.display()
+        ));
+    }
+    let saved = Snapshot::load(&path)?;
+
+    // Displays unplugged since the snapshot are skipped so the rest still
+    // come back; sleeping ones would look unplugged
+    if options.wake {
+        power::wake_displays()?;
+    }
+    let connected = get_all_display_info();
+    let mut configs = Vec::new();
+    let mut skipped = Vec::new();
+    for config in saved.display_configs()? {
+        if find_display(&config.id, &connected).is_some() {
+            configs.push(config);
+        } else {
+            let message = format!("Display {} is not connected, skipping", config.id);
+            if !options.quiet {
+                eprintln!("Warning: {}", message);
+            }
+            skipped.push(ApplyWarning {
+                display_id: None,
+                message,
+            });
+        }
+    }
+    if configs.is_empty() {
+        return Err(format!(
+            "None of the displays in {} are connected",
+            path.display()
+        ));
+    }
+
+    if !options.quiet {
+        println!("Restoring the arrangement saved {}", saved.taken);
+    }
+    let mut result = apply_configuration(configs, options)?;
+    result.warnings.splice(0..0, skipped);
+    Ok(result)
+}
+
+fn handle_brightness_command(
+    selector: &str,
+    percent: Option<u8>,
+    cached: bool,
+    config: &Config,
+) -> Result<(), String> {
+    if percent.is_some() {
+        check_agents(Subsystem::Brightness, config)?;
+    }
+    let displays = get_all_display_info();
+    let selected = select_displays(selector, &displays, config)?;
+
+    // Keep going so one unreachable monitor does not stop the rest of a group
+    let mut failed = 0;
+    for info in &selected {
+        let Some(percent) = percent else {
+            // Without a daemon the cache is this one read
+            let read = || brightness::get_brightness(info.id).map_err(|e| e.to_string());
+            let brightness = if cached {
+                ipc::cached_brightness(info.id).unwrap_or_else(read)
+            } else {
+                read()
+            };
+            match brightness {
+                Ok(value) if selected.len() == 1 => println!("{:.0}%", value * 100.0),
+                Ok(value) => println!("Display {}: {:.0}%", info.id, value * 100.0),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    failed += 1;
+                }
+            }
+            continue;
+        };
+        match brightness::set_brightness(info.id, percent as f32 / 100.0) {
+            Ok(()) => {
+                ipc::forget_brightness(info.id);
+                println!("Set display {} brightness to {}%", info.id, percent)
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!(
+            "Could not {} the brightness of {} of {} displays",
+            if percent.is_some() { "set" } else { "read" },
+            failed,
+            selected.len()
+        ));
+    }
+    Ok(())
+}
+
+fn handle_reference_command(action: ReferenceAction, config: &Config) -> Result<(), String> {
+    let displays = get_all_display_info();
+    match action {
+        ReferenceAction::List { selector, json } => {
+            let info = select_display(&selector, &displays, config)?;
+            let presets = reference::presets(info.id)?;
+
+            if json || config.format.is_json() {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&presets)
+                        .map_err(|e| format!("Failed to serialize JSON: {}", e))?
+                );
+                return Ok(());
+            }
+
+            for preset in &presets {
+                let marker = if preset.active { "*" } else { " " };
+                println!("{} {:>3}  {}", marker, preset.index, preset.name);
+            }
+        }
+        ReferenceAction::Set { selector, mode } => {
+            for info in select_displays(&selector, &displays, config)? {
+                let presets = reference::presets(info.id)?;
+                let preset = reference::find_preset(&presets, &mode)
+                    .map_err(|e| format!("Display {}: {}", info.id, e))?;
+                reference::set_preset(info.id, preset.index)?;
+                println!("Switched display {} to {}", info.id, preset.name);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Refuse to change `subsystem` while another agent holds it and
+/// `defer_gamma` is set, and warn about the agent otherwise.
+fn check_agents(subsystem: Subsystem, config: &Config) -> Result<(), String> {
+    let agents = agents::running_agents();
+    let Some(agent) = agents::deferred_to(&agents, subsystem) else {
+        return Ok(());
+    };
+    if config.defer_gamma {
+        return Err(format!(
+            "Leaving {} to {} (defer_gamma is set)",
+            subsystem.as_str(),
+            agent.name
+        ));
+    }
+    eprintln!(
+        "Warning: {} is running and may change the {} back",
+        agent.name,
+        subsystem.as_str()
+    );
+    Ok(())
+}
+
+fn handle_temp_command(selector: &str, kelvin: u32, config: &Config) -> Result<(), String> {
+    check_agents(Subsystem::Gamma, config)?;
+    let displays = get_all_display_info();
+    let selected = select_displays(selector, &displays, config)?;
+    for info in &selected {
+        gamma::set_temperature(info.id, kelvin)?;
+    }
+
+    let ids: Vec<String> = selected.iter().map(|info| info.id.to_string()).collect();
+    println!(
+        "Holding {}K on display {}; press Ctrl-C to restore",
+        kelvin,
+        ids.join(", ")
+    );
+    // macOS restores the gamma tables when this process exits
+    loop {
+        std::thread::park();
+    }
+}
+
+fn handle_watch_command(json: bool, exec: Option<String>) -> Result<(), String> {
+    watch(|event| {
+        if json {
+            match serde_json::to_string(&event) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("Failed to serialize event: {}", e),
+            }
+        } else {
+            let mut line = format!("{} display {}", event.kind.as_str(), event.display_id);
+            if let Some(uuid) = &event.persistent_id {
+                line.push_str(&format!(" ({})", uuid));
+            }
+            if let Some(info) = &event.info {
+                line.push_str(&format!(
+                    ": {}x{} @ {}Hz origin:({},{})",
+                    info.width,
+                    info.height,
+                    format_hz(info.hz),
+                    info.x,
+                    info.y
+                ));
+            }
+            println!("{}", line);
+        }
+
+        if let Some(command) = &exec {
+            if let Err(e) = run_event_hook(command, &event) {
+                eprintln!("Warning: {}", e);
+            }
+        }
+    })
+}
+
+fn handle_mode_watch(display_id: u32, json: bool) -> Result<(), String> {
+    let current = get_current_mode(display_id)
+        .ok_or_else(|| format!("Could not read the current mode of display {}", display_id))?;
+    if !json {
+        println!(
+            "Watching display {} from mode {} ({} @ {}Hz); press Ctrl-C to stop",
+            display_id,
+            current.mode_number,
+            current.size(),
+            format_hz(current.refresh_rate)
+        );
+    }
+
+    watch_modes(display_id, |change| {
+        if json {
+            match serde_json::to_string(&change) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("Failed to serialize mode change: {}", e),
+            }
+        } else {
+            println!("{}", format_mode_change(&change));
+        }
+    })
+}
+
+fn format_mode_change(change: &ModeChange) -> String {
+    let describe = |mode: &Option<DisplayMode>| match mode {
+        Some(mode) => format!(
+            "mode {} ({} @ {}Hz{})",
+            mode.mode_number,
+            mode.rotated_size(change.rotation),
+            format_hz(mode.refresh_rate),
+            if mode.is_scaled { ", HiDPI" } else { "" }
+        ),
+        None => "no mode".to_string(),
+    };
+    let time = UNIX_EPOCH + Duration::from_secs_f64(change.timestamp.max(0.0));
+    let mut line = format!(
+        "[{}] display {}: {} -> {}",
+        format_utc_timestamp(time),
+        change.display_id,
+        describe(&change.from),
+        describe(&change.to)
+    );
+    if let Some(seconds) = change.since_previous {
+        line.push_str(&format!(" ({:.2}s after the previous change)", seconds));
+    }
+    line
+}
+
+fn handle_profile_command(
+    action: ProfileAction,
+    if_changed: bool,
+    cli_options: &ApplyOptions,
+) -> Result<(), String> {
+    let config = settings::load(&global_overrides())?;
+
+    match action {
+        ProfileAction::List { json } => {
+            if json || config.format.is_json() {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&config.profiles)
+                        .map_err(|e| format!("Failed to serialize JSON: {}", e))?
+                );
+                return Ok(());
+            }
+
+            match &config.machine {
+                Some(machine) => println!("Machine scope: {}\n", machine),
+                None => println!("Machine scope: none (shared profiles only)\n"),
+            }
+            for (name, configs) in &config.profiles {
+                println!("{}:", name);
+                for config_str in configs {
+                    println!("  {}", config_str);
+                }
+            }
+            Ok(())
+        }
+        ProfileAction::Apply { name } => {
+            let name = name
+                .or_else(|| config.profile.clone())
+                .ok_or("No profile named and no default profile set")?;
+            let options = ApplyOptions {
+                backup: cli_options.backup.clone(),
+                force: cli_options.force,
+                mirror_best_mode: cli_options.mirror_best_mode,
+                keep_origins: cli_options.keep_origins,
+                preview: cli_options.preview,
+                dry_run: cli_options.dry_run,
+                wake: cli_options.wake,
+                preserve_windows: cli_options.preserve_windows,
+                keys: cli_options.keys.clone(),
+                quiet: config.format.is_json(),
+                progress: cli_options.progress.clone(),
+                ..ApplyOptions::from(&config)
+            };
+            let result = daemon::apply_profile_with(&config, &name, &options)?;
+            let changed = report_apply(result, options.quiet);
+            exit_if_unchanged(changed, if_changed);
+            Ok(())
+        }
+        ProfileAction::Edit { name } => handle_profile_edit(&name, &config),
+        ProfileAction::Export {
+            name,
+            output,
+            comment,
+        } => {
+            let bundle = ProfileBundle::export(&config, &name, comment, &fingerprint_displays())?;
+            for display in bundle.displays.iter().filter(|d| d.fingerprint.is_none()) {
+                eprintln!(
+                    "Warning: display {} is not connected; the bundle cannot match it on another Mac",
+                    display.id
+                );
+            }
+            match output {
+                Some(path) => {
+                    bundle.save(&path)?;
+                    println!("Exported profile {} to {}", name, path.display());
+                }
+                None => println!(
+                    "{}",
+                    serde_json::to_string_pretty(&bundle)
+                        .map_err(|e| format!("Failed to serialize JSON: {}", e))?
+                ),
+            }
+            Ok(())
+        }
+        ProfileAction::Import {
+            file,
+            name,
+            replace,
+        } => handle_profile_import(&file, name, replace, &config),
+    }
+}
+
+/// The connected displays with the fingerprints bundles match them by.
+fn fingerprint_displays() -> Vec<(macdisp_core::DisplayInfo, Fingerprint)> {
+    get_all_display_info()
+        .into_iter()
+        .map(|info| {
+            let fingerprint = Fingerprint::of(&info);
+            (info, fingerprint)
+        })
+        .collect()
+}
+
+fn handle_sync_export(output: Option<std::path::PathBuf>) -> Result<(), String> {
+    let arrangement = SyncArrangement::export(&fingerprint_displays(), |info| {
+        edid::for_display(info.id).ok().and_then(|edid| edid.name)
+    });
+    match output {
+        Some(path) => {
+            arrangement.save(&path)?;
+            println!(
+                "Exported the arrangement of {} displays to {}",
+                arrangement.displays.len(),
+                path.display()
+            );
+        }
+        None => println!(
+            "{}",
+            serde_json::to_string_pretty(&arrangement)
+                .map_err(|e| format!("Failed to serialize JSON: {}", e))?
+        ),
+    }
+    Ok(())
+}
+
+fn handle_sync_import(
+    file: &std::path::Path,
+    all: bool,
+    options: &ApplyOptions,
+) -> Result<ApplyResult, String> {
+    let arrangement = SyncArrangement::load(file)?;
+    let local = arrangement.localize(&fingerprint_displays());
+    if let Some(missing) = local.missing.first().filter(|_| all) {
+        eprintln!("{} is not connected, nothing to do", missing);
+        return Ok(ApplyResult::default());
+    }
+    let mut skipped = Vec::new();
+    for message in local.warnings {
+        if !options.quiet {
+            eprintln!("Warning: {}", message);
+        }
+        skipped.push(ApplyWarning {
+            display_id: None,
+            message,
+        });
+    }
+    if local.configs.is_empty() {
+        return Err(format!(
+            "None of the displays in {} are connected",
+            file.display()
+        ));
+    }
+    let configs = local
+        .configs
+        .iter()
+        .map(|config_str| parse_config(config_str))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut result = apply_configuration(configs, options)?;
+    result.warnings.splice(0..0, skipped);
+    Ok(result)
+}
+
+fn handle_profile_import(
+    file: &std::path::Path,
+    name: Option<String>,
+    replace: bool,
+    config: &Config,
+) -> Result<(), String> {
+    let bundle = ProfileBundle::load(file)?;
+    let name = name.unwrap_or_else(|| bundle.name.clone());
+    for comment in &bundle.comments {
+        println!("# {}", comment);
+    }
+    let local = bundle.localize(&fingerprint_displays());
+    for warning in &local.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if !replace {
+        let mut clashes = Vec::new();
+        if config.profiles.contains_key(&name) {
+            clashes.push(format!("profile {}", name));
+        }
+        for (alias, id) in &local.aliases {
+            if config
+                .aliases
+                .get(alias)
+                .is_some_and(|existing| existing != id)
+            {
+                clashes.push(format!("alias {}", alias));
+            }
+        }
+        for (variable, value) in &local.variables {
+            if config
+                .variables
+                .get(variable)
+                .is_some_and(|existing| existing != value)
+            {
+                clashes.push(format!("variable {}", variable));
+            }
+        }
+        if !clashes.is_empty() {
+            return Err(format!(
+                "Already defined differently: {}; use --name or --replace",
+                clashes.join(", ")
+            ));
+        }
+    }
+
+    // Imported into the shared section, so every Mac using this config sees it
+    config::save_profile(
+        None,
+        &name,
+        &local.configs,
+        &local.aliases,
+        &local.variables,
+    )?;
+    println!("Imported profile {}", name);
+    for config_str in &local.configs {
+        println!("  {}", config_str);
+    }
+    Ok(())
+}
+
+/// Open `path` in $VISUAL or $EDITOR, falling back to vi.
+fn run_editor(path: &std::path::Path) -> Result<(), String> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    // Through the shell so editors given with arguments ("code --wait") work
+    let status = std::process::Command::new("/bin/sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(path)
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", editor, e))?;
+    if !status.success() {
+        return Err(format!("{} exited with {}", editor, status));
+    }
+    Ok(())
+}
+
+fn confirm(question: &str) -> bool {
+    eprint!("{} [Y/n] ", question);
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    !matches!(answer.trim().to_lowercase().as_str(), "n" | "no")
+}
+
+fn handle_profile_edit(name: &str, config: &Config) -> Result<(), String> {
+    // Machine profiles replace shared ones of the same name, so edit the one
+    // in effect on this Mac
+    let machine = config.machine.as_deref().filter(|machine| {
+        config
+            .machines
+            .get(*machine)
+            .is_some_and(|section| section.profiles.contains_key(name))
+    });
+    let original = config.profiles.get(name).cloned().unwrap_or_default();
+    let displays = get_all_display_info();
+    let path = std::env::temp_dir().join(format!("macdisp-profile-{}.txt", std::process::id()));
+
+    let mut lines = original.clone();
+    let mut errors: Vec<String> = Vec::new();
+    let result = loop {
+        let mut contents = format!(
+            "# Profile {}: one configuration string per line, e.g. id:1 res:1920x1080 hz:60\n\
+             # Lines starting with # are ignored. Saving an empty file cancels.\n",
+            name
+        );
+        for error in &errors {
+            contents.push_str(&format!("# Error: {}\n", error));
+        }
+        for line in &lines {
+            contents.push_str(line);
+            contents.push('\n');
+        }
+        if let Err(e) = std::fs::write(&path, contents) {
+            break Err(format!("Failed to write {}: {}", path.display(), e));
+        }
+        if let Err(e) = run_editor(&path) {
+            break Err(e);
+        }
+
+        lines = match std::fs::read_to_string(&path) {
+            Ok(edited) => edited
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(str::to_string)
+                .collect(),
+            Err(e) => break Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        if lines.is_empty() {
+            break Err(format!("Profile {} is empty, nothing saved", name));
+        }
+
+        let issues = daemon::check_profile(config, &lines, &displays);
+        for warning in &issues.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        if issues.errors.is_empty() {
+            break Ok(());
+        }
+        for error in &issues.errors {
+            eprintln!("Error: {}", error);
+        }
+        if !confirm("Edit again?") {
+            break Err(format!("Profile {} not saved", name));
+        }
+        errors = issues.errors;
+    };
+    let _ = std::fs::remove_file(&path);
+    result?;
+
+    if lines == original {
+        println!("Profile {} unchanged", name);
+        return Ok(());
+    }
+    config::save_profile(machine, name, &lines, &BTreeMap::new(), &BTreeMap::new())?;
+    match machine {
+        Some(machine) => println!("Saved profile {} for machine {}", name, machine),
+        None => println!("Saved profile {}", name),
+    }
+    Ok(())
+}
+
+fn handle_ddc_command(action: DdcAction, config: &Config) -> Result<(), String> {
+    match action {
+        DdcAction::Caps { selector, json } => {
+            let displays = get_all_display_info();
+            let info = select_display(&selector, &displays, config)?;
+            let caps = ddc::capabilities(info.id)?;
+
+            if json || config.format.is_json() {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&caps)
+                        .map_err(|e| format!("Failed to serialize JSON: {}", e))?
+                );
+                return Ok(());
+            }
+
+            if let Some(model) = &caps.model {
+                println!("Model: {}", model);
+            }
+            if let Some(version) = &caps.mccs_version {
+                println!("MCCS version: {}", version);
+            }
+            let (vendor, model) = quirks::display_vendor_model(info.id);
+            println!("EDID vendor/model: {:04x}/{:04x}", vendor, model);
+            if !caps.quirks.is_empty() {
+                println!(
+                    "Quirks: {}",
+                    serde_json::to_string(&caps.quirks)
+                        .map_err(|e| format!("Failed to serialize JSON: {}", e))?
+                );
+            }
+            println!("VCP controls:");
+            for vcp in &caps.vcp {
+                let mut line =
+                    format!("  {:02X} {:<36}", vcp.code, vcp.name().unwrap_or("Unknown"));
+                if !vcp.values.is_empty() {
+                    let values: Vec<String> = vcp
+                        .values
+                        .iter()
+                        .map(
+                            |&value| match (vcp.code, caps.quirks.input_source_name(value)) {
+                                (0x60, Some(name)) => format!("{:02X} ({})", value, name),
+                                _ => format!("{:02X}", value),
+                            },
+                        )
+                        .collect();
+                    line.push_str(&format!("values {}", values.join(", ")));
+                } else if let Some(value) = vcp.value {
+                    line.push_str(&format!(
+                        "range 0-{} (current {})",
+                        value.maximum, value.current
+                    ));
+                }
+                println!("{}", line.trim_end());
+            }
+            Ok(())
+        }
+    }
+}
+
+fn handle_edid_command(action: EdidAction, config: &Config) -> Result<(), String> {
+    match action {
+        EdidAction::Override {
+            selector,
+            fix_rgb,
+            output,
+        } => {
+            if !fix_rgb {
+                return Err("Nothing to override; pass --fix-rgb".to_string());
+            }
+            let displays = get_all_display_info();
+            let info = select_display(&selector, &displays, config)?;
+            let edid = edid::for_display(info.id)?;
+            if !edid.advertises_ycbcr() {
+                return Err(format!(
+                    "Display {} already advertises RGB only; an override will not change its color format",
+                    info.id
+                ));
+            }
+
+            let plist = edid.override_plist(&edid.rgb_only());
+            let source = match &output {
+                Some(path) => {
+                    std::fs::write(path, &plist)
+                        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+                    path.display().to_string()
+                }
+                None => {
+                    print!("{}", plist);
+                    "FILE".to_string()
+                }
+            };
+
+            let target = edid.override_path();
+            let dir = target.parent().unwrap_or(target.as_path());
+            eprintln!(
+                "Override for {} ({} {:04x}/{:04x}). To install:\n",
+                edid.name.as_deref().unwrap_or("unnamed display"),
+                edid.manufacturer,
+                edid.vendor_id,
+                edid.product_id
+            );
+            if output.is_none() {
+                eprintln!("  (save the output above as FILE first)");
+            }
+            eprintln!("  sudo mkdir -p {}", dir.display());
+            eprintln!("  sudo cp {} {}\n", source, target.display());
+            eprintln!("Then disconnect and reconnect the display, or log out and back in. Delete the file to undo.");
+            Ok(())
+        }
+    }
+}
+
+fn handle_system_prefs_command(action: SystemPrefsAction, config: &Config) -> Result<(), String> {
+    let prefs = read_system_prefs()?;
+
+    match action {
+        SystemPrefsAction::Show { json } => {
+            if json || config.format.is_json() {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&prefs)
+                        .map_err(|e| format!("Failed to serialize JSON: {}", e))?
+                );
+                return Ok(());
+            }
+
+            println!("Persisted arrangements in {}\n", prefs.path.display());
+            for (i, arrangement) in prefs.arrangements.iter().enumerate() {
+                println!("Arrangement {}:", i + 1);
+                for display in &arrangement.displays {
+                    let mut line = format!("  {}", display.uuid);
+                    if let (Some(w), Some(h)) = (display.width, display.height) {
+                        line.push_str(&format!(" res:{}x{}", w, h));
+                    }
+                    if let Some(hz) = display.hz.filter(|hz| *hz > 0.0) {
+                        line.push_str(&format!(" hz:{}", format_hz(hz)));
+                    }
+                    if let (Some(x), Some(y)) = (display.x, display.y) {
+                        line.push_str(&format!(" origin:({},{})", x, y));
+                    }
+                    if let Some(rotation) = display.rotation {
+                        line.push_str(&format!(" degree:{}", rotation));
+                    }
+                    println!("{}", line);
+                }
+                println!();
+            }
+        }
+        SystemPrefsAction::Diff { json } => {
+            let live = get_all_display_info();
+
+            let arrangement = find_matching_arrangement(&prefs, &live).ok_or_else(|| {
+                format!(
+                    "{} has no stored arrangement for the connected displays; macOS will place them using defaults",
+                    prefs.path.display()
+                )
+            })?;
+            let differences = diff_arrangement(arrangement, &live);
+
+            if json || config.format.is_json() {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&differences)
+                        .map_err(|e| format!("Failed to serialize JSON: {}", e))?
+                );
+            } else if differences.is_empty() {
+                println!(
+                    "Live state matches the arrangement persisted in {}",
+                    prefs.path.display()
+                );
+            } else {
+                println!(
+                    "Live state differs from the arrangement persisted in {}:\n",
+                    prefs.path.display()
+                );
+                for diff in &differences {
+                    println!(
+                        "  {} {}: persisted {} -> live {}",
+                        diff.uuid, diff.field, diff.persisted, diff.live
+                    );
+                }
+                println!("\nmacOS will restore the persisted values the next time this set of displays is connected.");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Settings for commands that work without a config file; a broken file or
+/// environment only costs them the configured defaults.
+fn settings_or_default() -> Config {
+    settings::load(&global_overrides()).unwrap_or_else(|e| {
+        eprintln!("Warning: {}", e);
+        Config::default()
+    })
+}
+
+static GLOBAL_OVERRIDES: std::sync::OnceLock<ConfigOverrides> = std::sync::OnceLock::new();
+
+/// Overrides from flags accepted by every subcommand.
+fn global_overrides() -> ConfigOverrides {
+    GLOBAL_OVERRIDES.get().cloned().unwrap_or_default()
+}
+
+/// Print what the library reports: steps to stdout, the rest to stderr.
+fn print_output(level: output::Level, message: &str) {
+    match level {
+        output::Level::Info => println!("{}", message),
+        output::Level::Notice => eprintln!("{}", message),
+        output::Level::Warning => eprintln!("Warning: {}", message),
+    }
+}
+
+/// A global apply flag given to a subcommand that does not act on it.
+///
+/// `--dry-run` is only honoured by the commands that plan their changes
+/// first and `--quiet` only by those that preview them; anywhere else the
+/// command would make the change anyway, so it is refused instead.
+fn ignored_global_flag(cli: &Cli) -> Option<&'static str> {
+    let previews = matches!(
+        cli.command,
+        None | Some(Commands::Apply { .. })
+            | Some(Commands::Set { .. })
+            | Some(Commands::Restore { .. })
+            | Some(Commands::Sync {
+                action: SyncAction::Import { .. }
+            })
+            | Some(Commands::Profile {
+                action: ProfileAction::Apply { .. }
+            })
+    );
+    let plans = previews
+        || matches!(
+            cli.command,
+            Some(Commands::Explain { .. })
+                | Some(Commands::Extend)
+                | Some(Commands::Internal { .. })
+        );
+    if cli.dry_run && !plans {
+        Some("--dry-run")
+    } else if cli.quiet && !previews {
+        Some("--quiet")
+    } else if cli.preserve_windows && !previews {
+        Some("--preserve-windows")
+    } else {
+        None
+    }
+}
+
+fn main() {
+    let started = Instant::now();
+    output::set_handler(print_output);
+    let cli = Cli::parse();
+    if let Some(flag) = ignored_global_flag(&cli) {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::ArgumentConflict,
+                format!("{} is not supported by this command", flag),
+            )
+            .exit();
+    }
+
+    if let Some(dir) = cli.config_dir {
+        settings::set_config_dir(dir);
+    }
+    let _ = GLOBAL_OVERRIDES.set(ConfigOverrides {
+        call_timeout_ms: cli.call_timeout_ms,
+        hz_tolerance: cli.hz_tolerance,
+        stagger_ms: cli.stagger,
+        defer_gamma: cli.defer_gamma.then_some(true),
+        id_strategy: cli.id_strategy,
+        ..Default::default()
+    });
+    let settings = settings_or_default();
+    settings.apply_call_timeout();
+    settings.apply_mode_set_interval();
+    settings.apply_hz_tolerance();
+    if cli.no_lock {
+        lock::set_enabled(false);
+    }
+    let apply_options = ApplyOptions {
+        backup: cli.backup.clone(),
+        force: cli.force,
+        mirror_best_mode: cli.mirror_best_mode,
+        keep_origins: cli.keep_origins,
+        preview: !cli.quiet,
+        dry_run: cli.dry_run,
+        wake: cli.wake,
+        preserve_windows: cli.preserve_windows,
+        keys: if !cli.only.is_empty() {
+            KeyFilter::Only(cli.only.clone())
+        } else if !cli.skip.is_empty() {
+            KeyFilter::Skip(cli.skip.clone())
+        } else {
+            KeyFilter::All
+        },
+        quiet: settings.format.is_json(),
+        progress: cli.verbose.then(|| ProgressCallback::new(print_progress)),
+        ..ApplyOptions::from(&settings)
+    };
+
+    match cli.command {
+        Some(Commands::List {
+            json,
+            porcelain,
+            command_ids,
+        }) => {
+            let ids = match command_ids {
+                Some(choice) => choice.ids(&settings),
+                None => CommandIds::Strategy(settings.id_strategy),
+            };
+            let json = json || settings.format.is_json();
+            if let Some(Porcelain::V1) = porcelain {
+                print!(
+                    "{}",
+                    porcelain::display_records(&ipc::display_state().displays)
+                );
+            } else if let Some(problem) = session::current().problem() {
+                print_headless_displays(&problem, json);
+            } else if json {
+                println!("{}", list_displays_json(&ids));
+            } else {
+                print!("{}", list_displays(&ids));
+            }
+        }
+        Some(Commands::Get { selector, field }) => {
+            if let Err(e) = handle_get_command(&selector, field, &settings) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Connected { selector, json }) => {
+            if !handle_connected_command(&selector, json, &settings) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Explain { configs, json }) => {
+            if let Err(e) = handle_explain_command(&configs, json, &settings, &apply_options) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Assert {
+            count,
+            has,
+            main,
+            resolution,
+            origin,
+            hz,
+            json,
+        }) => {
+            let mut expect = Expectations {
+                count,
+                connected: has,
+                main,
+                ..Default::default()
+            };
+            let result = [("res", resolution), ("origin", origin), ("hz", hz)]
+                .into_iter()
+                .flat_map(|(key, args)| args.into_iter().map(move |arg| (key, arg)))
+                .try_for_each(|(key, arg)| expect.expect_setting(key, &arg))
+                .and_then(|()| handle_assert_command(&expect, json, &settings));
+            match result {
+                Ok(true) => {}
+                Ok(false) => std::process::exit(1),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(2);
+                }
+            }
+        }
+        Some(Commands::Doctor) => {
+            if !handle_doctor_command() {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Dpi { reference, json }) => {
+            if let Err(e) = handle_dpi_command(reference.as_deref(), json, &settings) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Devices { aliases, json }) => {
+            if let Err(e) = handle_devices_command(aliases, json, &settings) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Info { selector, json }) => {
+            if let Err(e) = handle_info_command(&selector, json, cli.verbose, &settings) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Hidpi { action, selector }) => {
+            match handle_hidpi_command(action, &selector, &settings) {
+                Ok(changed) => exit_if_unchanged(changed, cli.if_changed),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Hz {
+            action: HzAction::Match { selector, fps },
+        }) => match handle_hz_match_command(&selector, fps, &settings) {
+            Ok(changed) => exit_if_unchanged(changed, cli.if_changed),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Commands::Scale { selector, step }) => {
+            match handle_scale_command(&selector, step.into(), &settings) {
+                Ok(changed) => exit_if_unchanged(changed, cli.if_changed),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Serve { listen }) => {
+            eprintln!("Serving display events on ws://{}/events", listen);
+            if let Err(e) = server::serve(listen) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        #[cfg(feature = "grpc")]
+        Some(Commands::ServeGrpc { listen }) => {
+            if let Err(e) = handle_serve_grpc_command(listen) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Brightness {
+            selector,
+            percent,
+            cached,
+        }) => {
+            if let Err(e) = handle_brightness_command(&selector, percent, cached, &settings) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Temp { selector, kelvin }) => {
+            if let Err(e) = handle_temp_command(&selector, kelvin, &settings) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Reference { action }) => {
+            if let Err(e) = handle_reference_command(action, &settings) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Apply { stdin_json: _ }) => {
+            match handle_apply_json_command(&apply_options) {
+                Ok(result) => {
+                    exit_if_unchanged(report_apply(result, apply_options.quiet), cli.if_changed)
+                }
+                Err(e) => {
+                    eprintln!("Error applying configuration: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Sync {
+            action: SyncAction::Export { output },
+        }) => {
+            if let Err(e) = handle_sync_export(output) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Sync {
+            action: SyncAction::Import { file, all },
+        }) => match handle_sync_import(&file, all, &apply_options) {
+            Ok(result) => {
+                exit_if_unchanged(report_apply(result, apply_options.quiet), cli.if_changed)
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Commands::Extend) => match handle_extend_command(cli.dry_run) {
+            Ok(changed) => exit_if_unchanged(changed, cli.if_changed),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Commands::Internal { action }) => match handle_internal_command(action, cli.dry_run) {
+            Ok(changed) => exit_if_unchanged(changed, cli.if_changed),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Commands::Recover) => {
+            let failed = recover::recover();
+            if !failed.is_empty() {
+                eprintln!(
+                    "Error: {} of the recovery steps failed; the rest were applied",
+                    failed.len()
+                );
+                std::process::exit(1);
+            }
+            println!("Recovered; `macdisp restore` goes back to the state before");
+        }
+        Some(Commands::Restore { from }) => match handle_restore_command(from, &apply_options) {
+            Ok(result) => {
+                exit_if_unchanged(report_apply(result, apply_options.quiet), cli.if_changed)
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Commands::Set {
+            selector,
+            settings: pairs,
+        }) => match handle_set_command(&selector, &pairs, &settings, &apply_options) {
+            Ok(result) => {
+                exit_if_unchanged(report_apply(result, apply_options.quiet), cli.if_changed)
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        Some(Commands::Modes {
+            selector,
+            diff,
+            watch,
+            json,
+            porcelain,
+        }) => {
+            let json = json || settings.format.is_json();
+            let resolve = |selector: &str| {
+                selected_display_id(selector, &settings).unwrap_or_else(|e| {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                })
+            };
+            let display_id = resolve(&selector);
+            match diff {
+                Some(other) => show_mode_diff(display_id, resolve(&other), json),
+                None if watch => {
+                    if let Err(e) = handle_mode_watch(display_id, json) {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+                None => match porcelain {
+                    Some(version) => show_modes_porcelain(display_id, version),
+                    None => show_modes(display_id, json),
+                },
+            }
+        }
+        Some(Commands::Notch { action, display }) => {
+            if let Err(e) = handle_notch_command(action, &display, &settings) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Export {
+            shell,
+            mdm,
+            identifier,
+            binary,
+            name,
+        }) => {
+            if let Some(dir) = mdm {
+                if let Err(e) = write_mdm_bundle(&dir, name.as_deref(), &identifier, &binary) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            } else if shell {
+                print!("{}", export_shell_script(name.as_deref()));
+            } else {
+                println!("{}", current_arrangement_command(settings.id_strategy));
+            }
+        }
+        Some(Commands::Status { format, timing }) => {
+            println!("{}", status_line(format.into()));
+            if timing {
+                eprintln!("Took {:.1}ms", started.elapsed().as_secs_f64() * 1000.0);
+            }
+        }
+        Some(Commands::Watch { json, exec }) => {
+            let json = json || settings.format.is_json();
+            if let Err(e) = handle_watch_command(json, exec) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Daemon {
+            debounce_ms,
+            enforce,
+            profile,
+            brightness_keys,
+        }) => {
+            let overrides = ConfigOverrides {
+                debounce_ms,
+                enforce,
+                profile,
+                brightness_keys: brightness_keys.then_some(true),
+                ..global_overrides()
+            };
+            if let Err(e) = daemon::run(overrides) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Profile { action }) => {
+            if let Err(e) = handle_profile_command(action, cli.if_changed, &apply_options) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Ddc { action }) => {
+            if let Err(e) = handle_ddc_command(action, &settings) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Edid { action }) => {
+            if let Err(e) = handle_edid_command(action, &settings) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::SystemPrefs { action }) => {
+            if let Err(e) = handle_system_prefs_command(action, &settings) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        None => {
+            if cli.configs.is_empty() {
+                // No arguments, list displays
+                print!(
+                    "{}",
+                    list_displays(&CommandIds::Strategy(settings.id_strategy))
+                );
+            } else if let Some(name) = settings.profile_shorthand(&cli.configs) {
+                // `macdisp work` is short for `macdisp profile apply work`
+                let action = ProfileAction::Apply {
+                    name: Some(name.to_string()),
+                };
+                if let Err(e) = handle_profile_command(action, cli.if_changed, &apply_options) {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            } else {
+                // Parse and apply configurations; resolving aliases lets
+                // commands generated with `list --command-ids alias` apply
+                let configs = match parse_config_args(&cli.configs, &settings) {
+                    Ok(configs) => configs,
+                    Err(_) if cli.configs.len() == 1 && !cli.configs[0].contains(':') => {
+                        eprintln!(
+                            "Error: {} is neither a profile nor a configuration string",
+                            cli.configs[0]
+                        );
+                        std::process::exit(1);
+                    }
+                    Err(e) => {
+                        eprintln!("Error parsing configuration: {}", e);
+                        std::process::exit(1);
+                    }
+                };
+
+                match apply_configuration(configs, &apply_options) {
+                    Ok(result) => {
+                        exit_if_unchanged(report_apply(result, apply_options.quiet), cli.if_changed)
+                    }
+                    Err(e) => {
+                        eprintln!("Error applying configuration: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+    }
+}
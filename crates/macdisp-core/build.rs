@@ -9,13 +9,22 @@ fn main() {
     println!("cargo:rustc-link-lib=framework=Foundation");
     println!("cargo:rustc-link-lib=framework=CoreGraphics");
     println!("cargo:rustc-link-lib=framework=AppKit");
+    println!("cargo:rustc-link-lib=framework=ApplicationServices");
     println!("cargo:rustc-link-lib=framework=IOKit");
+    println!("cargo:rustc-link-lib=framework=Metal");
+    println!("cargo:rustc-link-lib=framework=SystemConfiguration");
 
     // Try to link DisplayServices if available (private framework)
     // This will fail gracefully if not found
     println!("cargo:rustc-link-search=framework=/System/Library/PrivateFrameworks");
     println!("cargo:rustc-link-lib=framework=DisplayServices");
 
+    // gRPC service definitions for the optional server
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/macdisp.proto")
+        .expect("Failed to compile proto/macdisp.proto");
+
     println!("cargo:rerun-if-changed=src/objc/display_services.m");
     println!("cargo:rerun-if-changed=src/objc/display_services.h");
+    println!("cargo:rerun-if-changed=proto/macdisp.proto");
 }
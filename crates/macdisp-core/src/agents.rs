@@ -0,0 +1,171 @@
+//! Other display utilities that change the same settings as macdisp.
+//!
+//! BetterDisplay, Lunar and MonitorControl hold brightness and gamma in
+//! loops of their own, and BetterDisplay switches modes too; run next to
+//! macdisp, each undoes what the other just set.
+
+use serde::Serialize;
+use std::fmt;
+
+extern "C" {
+    fn proc_listallpids(buffer: *mut libc::c_void, buffersize: libc::c_int) -> libc::c_int;
+    fn proc_name(pid: libc::c_int, buffer: *mut libc::c_void, buffersize: u32) -> libc::c_int;
+}
+
+/// A setting another agent may hold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Subsystem {
+    /// Gamma tables, which macdisp uses for color temperature
+    Gamma,
+    Brightness,
+    Modes,
+}
+
+impl Subsystem {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Subsystem::Gamma => "gamma",
+            Subsystem::Brightness => "brightness",
+            Subsystem::Modes => "modes",
+        }
+    }
+}
+
+/// An agent macdisp knows, found by its process name.
+struct KnownAgent {
+    process: &'static str,
+    subsystems: &'static [Subsystem],
+}
+
+const KNOWN_AGENTS: &[KnownAgent] = &[
+    KnownAgent {
+        process: "BetterDisplay",
+        subsystems: &[Subsystem::Gamma, Subsystem::Brightness, Subsystem::Modes],
+    },
+    KnownAgent {
+        process: "Lunar",
+        subsystems: &[Subsystem::Gamma, Subsystem::Brightness],
+    },
+    KnownAgent {
+        process: "MonitorControl",
+        subsystems: &[Subsystem::Brightness],
+    },
+];
+
+/// A known agent that is running.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunningAgent {
+    pub name: String,
+    pub pid: i32,
+    pub subsystems: Vec<Subsystem>,
+}
+
+impl RunningAgent {
+    pub fn controls(&self, subsystem: Subsystem) -> bool {
+        self.subsystems.contains(&subsystem)
+    }
+}
+
+impl fmt::Display for RunningAgent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let subsystems: Vec<&str> = self.subsystems.iter().map(|s| s.as_str()).collect();
+        write!(
+            f,
+            "{} (pid {}) also controls {}",
+            self.name,
+            self.pid,
+            subsystems.join(", ")
+        )
+    }
+}
+
+/// The known agents among `processes`, given as pid and process name.
+pub fn detect(processes: &[(i32, String)]) -> Vec<RunningAgent> {
+    let mut running: Vec<RunningAgent> = Vec::new();
+    for (pid, name) in processes {
+        let Some(agent) = KNOWN_AGENTS.iter().find(|agent| agent.process == name) else {
+            continue;
+        };
+        // Helpers and relaunchers share the name; one entry per agent
+        if running.iter().any(|running| running.name == agent.process) {
+            continue;
+        }
+        running.push(RunningAgent {
+            name: agent.process.to_string(),
+            pid: *pid,
+            subsystems: agent.subsystems.to_vec(),
+        });
+    }
+    running
+}
+
+/// The first of `agents` that holds `subsystem`, which macdisp should leave
+/// alone under `defer_gamma`.
+pub fn deferred_to(agents: &[RunningAgent], subsystem: Subsystem) -> Option<&RunningAgent> {
+    agents.iter().find(|agent| agent.controls(subsystem))
+}
+
+/// Known agents running on this Mac, from the process list.
+pub fn running_agents() -> Vec<RunningAgent> {
+    detect(&processes())
+}
+
+fn processes() -> Vec<(i32, String)> {
+    let count = unsafe { proc_listallpids(std::ptr::null_mut(), 0) };
+    if count <= 0 {
+        return Vec::new();
+    }
+    // Leave room for processes started between the two calls
+    let mut pids = vec![0 as libc::c_int; count as usize + 32];
+    let size = (pids.len() * std::mem::size_of::<libc::c_int>()) as libc::c_int;
+    let count = unsafe { proc_listallpids(pids.as_mut_ptr() as *mut libc::c_void, size) };
+    pids.truncate(count.max(0) as usize);
+
+    pids.into_iter()
+        .filter_map(|pid| {
+            let mut buffer = [0u8; 64];
+            let len = unsafe {
+                proc_name(
+                    pid,
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len() as u32,
+                )
+            };
+            (len > 0).then(|| {
+                (
+                    pid,
+                    String::from_utf8_lossy(&buffer[..len as usize]).into_owned(),
+                )
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        let processes = vec![
+            (1, "launchd".to_string()),
+            (412, "Lunar".to_string()),
+            (413, "Lunar".to_string()),
+            (520, "MonitorControl".to_string()),
+        ];
+        let agents = detect(&processes);
+        assert_eq!(agents.len(), 2);
+        assert_eq!(agents[0].pid, 412);
+        assert_eq!(
+            agents[0].to_string(),
+            "Lunar (pid 412) also controls gamma, brightness"
+        );
+        assert_eq!(
+            deferred_to(&agents, Subsystem::Brightness).map(|a| a.pid),
+            Some(412)
+        );
+        assert!(deferred_to(&agents, Subsystem::Modes).is_none());
+        assert!(detect(&[(7, "lunar-helper".to_string())]).is_empty());
+    }
+}
@@ -0,0 +1,2412 @@
+use crate::agents::{running_agents, Subsystem};
+use crate::config::{Config, IdStrategy};
+use crate::events::{observe_reconfiguration, DisplayEventKind};
+use crate::executor::panic_message;
+use crate::geometry::{PointPx, Rect, SizePx};
+use crate::hooks::HookResult;
+use crate::lock::ApplyLock;
+use crate::mirror::{plan_mirror, SharedMode};
+use crate::mode_id::{find_mode, format_hz, same_hz};
+use crate::power::wake_displays;
+use crate::selector::Selectable;
+use crate::snapshot::{self, Snapshot};
+use crate::wallpaper;
+use crate::windows;
+use crate::{
+    configure_display, get_active_displays, get_all_display_info, get_all_modes, get_current_mode,
+    get_display_info, is_native_landscape, parse_display_id, set_display_mode, ConfigureOptions,
+    DisplayClass, DisplayConfig, DisplayInfo, DisplayMode, MirrorSetting, MirrorType,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Parse a displayplacer-style configuration string such as
+/// `"id:1 res:1920x1080 hz:60 origin:(0,0)"`.
+pub fn parse_config(config_str: &str) -> Result<DisplayConfig, String> {
+    let mut config = DisplayConfig::default();
+
+    for part in config_parts(config_str)? {
+        if let Some((key, value)) = part.split_once(':') {
+            match key {
+                "id" => config.id = value.to_string(),
+                "mode" => config.mode = Some(value.to_string()),
+                "mode_id" => config.mode_id = Some(value.parse()?),
+                "res" => {
+                    if let Some((w, h)) = value.split_once('x') {
+                        if let (Ok(width), Ok(height)) = (w.parse(), h.parse()) {
+                            config.resolution = Some((width, height));
+                        }
+                    }
+                }
+                "looks-like" | "looks_like" => {
+                    if let Some((w, h)) = value.split_once('x') {
+                        if let (Ok(width), Ok(height)) = (w.parse(), h.parse()) {
+                            config.looks_like = Some((width, height));
+                        }
+                    }
+                }
+                "hz" => config.hz = value.parse().ok(),
+                "color_depth" => config.color_depth = value.parse().ok(),
+                "scaling" => config.scaling = Some(value == "on"),
+                "origin" => match parse_origin(value) {
+                    Some((offset, true)) => config.origin_offset = Some(offset),
+                    Some((origin, false)) => config.origin = Some(origin),
+                    None => {}
+                },
+                "degree" => config.degree = value.parse().ok(),
+                "orientation" if !crate::CAN_ROTATE => return Err(orientation_unsupported()),
+                "orientation" => config.orientation = Some(value.parse()?),
+                "mirror" => config.mirror = Some(value.to_string()),
+                "mirror_type" => config.mirror_type = Some(value.parse()?),
+                "enabled" => config.enabled = value.parse().ok(),
+                "wallpaper" => config.wallpaper = Some(value.to_string()),
+                _ => {
+                    return Err(format!("Unknown configuration key: {}", key));
+                }
+            }
+        }
+    }
+
+    if config.id.is_empty() {
+        return Err("Display ID is required".to_string());
+    }
+
+    Ok(config)
+}
+
+/// Split a configuration string into its `key:value` parts at whitespace.
+/// Inside double quotes or after a backslash, whitespace belongs to the
+/// value, so `wallpaper:"~/My Pictures/clean.png"` and
+/// `wallpaper:~/My\ Pictures/clean.png` are one part each.
+fn config_parts(config_str: &str) -> Result<Vec<String>, String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quoted = false;
+    let mut chars = config_str.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted = !quoted,
+            '\\' => match chars.next() {
+                Some(escaped) => part.push(escaped),
+                None => return Err(format!("Trailing backslash in {}", config_str)),
+            },
+            c if c.is_whitespace() && !quoted => {
+                if !part.is_empty() {
+                    parts.push(std::mem::take(&mut part));
+                }
+            }
+            c => part.push(c),
+        }
+    }
+    if quoted {
+        return Err(format!("Unclosed quote in {}", config_str));
+    }
+    if !part.is_empty() {
+        parts.push(part);
+    }
+    Ok(parts)
+}
+
+/// The error for `orientation:`, which only ever asks for a rotation macdisp
+/// cannot make (see [`crate::CAN_ROTATE`]).
+fn orientation_unsupported() -> String {
+    "orientation: is not supported, as macdisp cannot rotate displays; rotate the display in System Settings"
+        .to_string()
+}
+
+/// Parse `X,Y` or `(X,Y)`. A `+` on either coordinate, or before the
+/// parentheses as in `+(-100,-50)`, makes the pair a shift from the current
+/// origin; the flag says which it is.
+fn parse_origin(value: &str) -> Option<((i32, i32), bool)> {
+    let (relative, value) = match value.strip_prefix("+(") {
+        Some(rest) => (true, rest.strip_suffix(')')?),
+        None => (false, value.trim_matches(|c| c == '(' || c == ')')),
+    };
+    let (x, y) = value.split_once(',')?;
+    let relative = relative || x.starts_with('+') || y.starts_with('+');
+    Some(((x.parse().ok()?, y.parse().ok()?), relative))
+}
+
+/// Like [`parse_config`], but every value is validated as by [`set_key`], for
+/// checking configurations before they are stored.
+pub fn parse_config_strict(config_str: &str) -> Result<DisplayConfig, String> {
+    let mut config = DisplayConfig::default();
+    for part in config_parts(config_str)? {
+        match part.split_once(':') {
+            Some(("id", value)) => config.id = value.to_string(),
+            Some((key, value)) => set_key(&mut config, key, value)?,
+            None => return Err(format!("Expected key:value, got {}", part)),
+        }
+    }
+    if config.id.is_empty() {
+        return Err("Display ID is required".to_string());
+    }
+    Ok(config)
+}
+
+/// Keys accepted by [`set_key`]. `depth` and `rotation` are accepted as
+/// aliases of `color_depth` and `degree`.
+pub const SETTING_KEYS: [&str; 13] = [
+    "res",
+    "looks-like",
+    "hz",
+    "color_depth",
+    "scaling",
+    "origin",
+    "degree",
+    "mirror",
+    "mirror_type",
+    "enabled",
+    "mode",
+    "mode_id",
+    "wallpaper",
+];
+
+/// What shells complete `set` arguments to: `key=` for keys taking any
+/// value, and every whole setting for keys with only a few values.
+pub const SETTING_COMPLETIONS: [&str; 17] = [
+    "res=",
+    "looks-like=",
+    "hz=",
+    "color_depth=",
+    "scaling=on",
+    "scaling=off",
+    "origin=",
+    "degree=",
+    "mirror=",
+    "mirror=off",
+    "mirror_type=hardware",
+    "mirror_type=software",
+    "enabled=true",
+    "enabled=false",
+    "mode=",
+    "mode_id=",
+    "wallpaper=",
+];
+
+/// Lay the keys `over` sets on top of `base`, as a profile does over the
+/// profile it extends.
+///
+/// Keys that say the same thing two ways replace each other: any part of a
+/// mode drops an explicit `mode` or `mode_id` and the other way round, an
+/// absolute origin drops a relative one, `res` and `looks-like` drop each
+/// other, and `degree` drops `orientation`. A `mode_id` under a part of a
+/// mode is first spelled out as `res`, `hz`, `color_depth` and `scaling`, so
+/// `hz:144` over it keeps its resolution.
+pub fn overlay_config(base: &mut DisplayConfig, over: DisplayConfig) {
+    let mode_parts = over.resolution.is_some()
+        || over.looks_like.is_some()
+        || over.hz.is_some()
+        || over.color_depth.is_some()
+        || over.scaling.is_some();
+    if mode_parts {
+        if let Some(mode_id) = base.mode_id.take() {
+            base.resolution = Some((mode_id.width, mode_id.height));
+            base.hz = Some(mode_id.refresh_rate());
+            base.color_depth = Some(mode_id.depth);
+            base.scaling = Some(mode_id.hidpi);
+        }
+        base.mode = None;
+    }
+    if over.resolution.is_some() || over.looks_like.is_some() {
+        base.resolution = None;
+        base.looks_like = None;
+    }
+    if over.mode.is_some() || over.mode_id.is_some() {
+        base.mode = None;
+        base.mode_id = None;
+        base.resolution = None;
+        base.looks_like = None;
+        base.hz = None;
+        base.color_depth = None;
+        base.scaling = None;
+    }
+    if over.origin.is_some() || over.origin_offset.is_some() {
+        base.origin = None;
+        base.origin_offset = None;
+    }
+    if over.degree.is_some() || over.orientation.is_some() {
+        base.degree = None;
+        base.orientation = None;
+    }
+
+    let DisplayConfig {
+        id: _,
+        mode,
+        mode_id,
+        resolution,
+        looks_like,
+        hz,
+        color_depth,
+        scaling,
+        origin,
+        origin_offset,
+        degree,
+        orientation,
+        mirror,
+        mirror_type,
+        enabled,
+        wallpaper,
+    } = over;
+    base.mode = mode.or(base.mode.take());
+    base.mode_id = mode_id.or(base.mode_id);
+    base.resolution = resolution.or(base.resolution);
+    base.looks_like = looks_like.or(base.looks_like);
+    base.hz = hz.or(base.hz);
+    base.color_depth = color_depth.or(base.color_depth);
+    base.scaling = scaling.or(base.scaling);
+    base.origin = origin.or(base.origin);
+    base.origin_offset = origin_offset.or(base.origin_offset);
+    base.degree = degree.or(base.degree);
+    base.orientation = orientation.or(base.orientation);
+    base.mirror = mirror.or(base.mirror.take());
+    base.mirror_type = mirror_type.or(base.mirror_type);
+    base.enabled = enabled.or(base.enabled);
+    base.wallpaper = wallpaper.or(base.wallpaper.take());
+}
+
+/// Set one key of `config`, rejecting malformed values.
+///
+/// Unlike [`parse_config`], which ignores values it cannot parse for
+/// displayplacer compatibility, every value is validated.
+pub fn set_key(config: &mut DisplayConfig, key: &str, value: &str) -> Result<(), String> {
+    let invalid = |expected: &str| format!("Invalid {}: {} (expected {})", key, value, expected);
+
+    match key {
+        "res" => {
+            let (w, h) = value
+                .split_once('x')
+                .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                .filter(|(w, h)| *w > 0 && *h > 0)
+                .ok_or_else(|| invalid("WIDTHxHEIGHT"))?;
+            config.resolution = Some((w, h));
+        }
+        "looks-like" | "looks_like" => {
+            let (w, h) = value
+                .split_once('x')
+                .and_then(|(w, h)| Some((w.parse::<u32>().ok()?, h.parse::<u32>().ok()?)))
+                .filter(|(w, h)| *w > 0 && *h > 0)
+                .ok_or_else(|| invalid("WIDTHxHEIGHT in points"))?;
+            config.looks_like = Some((w, h));
+        }
+        "hz" => {
+            let hz = value
+                .parse::<f64>()
+                .ok()
+                .filter(|hz| hz.is_finite() && *hz > 0.0)
+                .ok_or_else(|| invalid("a refresh rate such as 60 or 59.94"))?;
+            config.hz = Some(hz);
+        }
+        "color_depth" | "depth" => {
+            config.color_depth = Some(value.parse().map_err(|_| invalid("bits per channel"))?)
+        }
+        "scaling" => {
+            config.scaling = Some(match value {
+                "on" | "true" => true,
+                "off" | "false" => false,
+                _ => return Err(invalid("on or off")),
+            })
+        }
+        "origin" => match parse_origin(value).ok_or_else(|| invalid("X,Y or +DX,+DY"))? {
+            (offset, true) => config.origin_offset = Some(offset),
+            (origin, false) => config.origin = Some(origin),
+        },
+        "degree" | "rotation" => {
+            let degree = value
+                .parse::<u32>()
+                .ok()
+                .filter(|d| [0, 90, 180, 270].contains(d))
+                .ok_or_else(|| invalid("0, 90, 180 or 270"))?;
+            config.degree = Some(degree);
+        }
+        "orientation" if !crate::CAN_ROTATE => return Err(orientation_unsupported()),
+        "orientation" => config.orientation = Some(value.parse()?),
+        "mirror" => {
+            if value.is_empty() {
+                return Err(invalid("a display id or off"));
+            }
+            config.mirror = Some(value.to_string());
+        }
+        "mirror_type" => config.mirror_type = Some(value.parse()?),
+        "enabled" => config.enabled = Some(value.parse().map_err(|_| invalid("true or false"))?),
+        "mode" => {
+            value.parse::<u32>().map_err(|_| invalid("a mode number"))?;
+            config.mode = Some(value.to_string());
+        }
+        "mode_id" => config.mode_id = Some(value.parse()?),
+        "wallpaper" => {
+            if value.is_empty() {
+                return Err(invalid("the path of an image file"));
+            }
+            config.wallpaper = Some(value.to_string());
+        }
+        _ => {
+            return Err(format!(
+                "Unknown setting: {} (expected one of {})",
+                key,
+                SETTING_KEYS.join(", ")
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+/// Settings that influence how configurations are applied.
+#[derive(Debug, Clone, Default)]
+pub struct ApplyOptions {
+    /// Refresh rate preferred when a configuration sets a resolution but no `hz:`
+    pub default_hz: Option<f64>,
+    /// How bare numbers in `id:` and `mirror:` are resolved
+    pub id_strategy: IdStrategy,
+    /// Save the arrangement here before changing anything
+    pub backup: Option<PathBuf>,
+    /// Apply even when no display would be left usable, or mirror displays
+    /// that share no safe mode
+    pub force: bool,
+    /// Before mirroring, set both displays to the best mode they share
+    pub mirror_best_mode: bool,
+    /// Wake sleeping displays before reading their state
+    pub wake: bool,
+    /// Report nothing to [`crate::output`] while applying; the caller reports
+    /// the [`ApplyResult`]
+    pub quiet: bool,
+    /// Told about each display as it is changed, for progress indicators
+    pub progress: Option<ProgressCallback>,
+    /// Which keys of each configuration to apply
+    pub keys: KeyFilter,
+    /// Wait this long between changing one display and the next, for docks
+    /// that fail when several of their displays switch modes at once
+    pub stagger: Duration,
+    /// Leave origins alone when a display is rotated, instead of moving it
+    /// and its neighbours so they neither overlap nor leave a gap
+    pub keep_origins: bool,
+    /// Report a [`DisplayDiff`] for each display before changing it
+    pub preview: bool,
+    /// Plan and report the [`DisplayDiff`]s, then stop without changing
+    /// anything
+    pub dry_run: bool,
+    /// Record the window frames before changing anything and put the
+    /// windows back in place afterwards; see [`crate::windows`]
+    pub preserve_windows: bool,
+}
+
+/// Groups of configuration keys that can be applied on their own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigKey {
+    /// `mode`, `mode_id`, `res`, `looks-like`, `hz`, `color_depth` and `scaling`
+    Mode,
+    Origin,
+    /// `degree` and `orientation`
+    Rotation,
+    Mirror,
+    Enabled,
+    Wallpaper,
+}
+
+impl std::str::FromStr for ConfigKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mode" => Ok(ConfigKey::Mode),
+            "origin" => Ok(ConfigKey::Origin),
+            "rotation" => Ok(ConfigKey::Rotation),
+            "mirror" => Ok(ConfigKey::Mirror),
+            "enabled" => Ok(ConfigKey::Enabled),
+            "wallpaper" => Ok(ConfigKey::Wallpaper),
+            _ => Err(format!(
+                "Invalid key: {} (expected mode, origin, rotation, mirror, enabled or wallpaper)",
+                s
+            )),
+        }
+    }
+}
+
+/// Which keys of each configuration [`apply_configuration`] applies; the
+/// others are left as the display has them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum KeyFilter {
+    #[default]
+    All,
+    Only(Vec<ConfigKey>),
+    Skip(Vec<ConfigKey>),
+}
+
+impl KeyFilter {
+    pub fn includes(&self, key: ConfigKey) -> bool {
+        match self {
+            KeyFilter::All => true,
+            KeyFilter::Only(keys) => keys.contains(&key),
+            KeyFilter::Skip(keys) => !keys.contains(&key),
+        }
+    }
+
+    /// Clear the keys this filter leaves out, returning the names of those
+    /// that were set.
+    pub fn filter(&self, config: &mut DisplayConfig) -> Vec<&'static str> {
+        let mut removed = Vec::new();
+        let mut remove = |name, set: bool| {
+            if set {
+                removed.push(name);
+            }
+        };
+        if !self.includes(ConfigKey::Mode) {
+            remove("mode", config.mode.take().is_some());
+            remove("mode_id", config.mode_id.take().is_some());
+            remove("res", config.resolution.take().is_some());
+            remove("looks-like", config.looks_like.take().is_some());
+            remove("hz", config.hz.take().is_some());
+            remove("color_depth", config.color_depth.take().is_some());
+            remove("scaling", config.scaling.take().is_some());
+        }
+        if !self.includes(ConfigKey::Origin) {
+            remove(
+                "origin",
+                config.origin.take().is_some() | config.origin_offset.take().is_some(),
+            );
+        }
+        if !self.includes(ConfigKey::Rotation) {
+            remove("degree", config.degree.take().is_some());
+            remove("orientation", config.orientation.take().is_some());
+        }
+        if !self.includes(ConfigKey::Mirror) {
+            remove("mirror", config.mirror.take().is_some());
+            remove("mirror_type", config.mirror_type.take().is_some());
+        }
+        if !self.includes(ConfigKey::Enabled) {
+            remove("enabled", config.enabled.take().is_some());
+        }
+        if !self.includes(ConfigKey::Wallpaper) {
+            remove("wallpaper", config.wallpaper.take().is_some());
+        }
+        removed
+    }
+}
+
+/// A step of [`apply_configuration`], reported to [`ApplyOptions::progress`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ApplyProgress {
+    /// Starting on the `index`th of the `total` displays that need changes
+    Display {
+        display_id: u32,
+        index: usize,
+        total: usize,
+    },
+    /// CoreGraphics is about to reconfigure a display, which may blank it
+    ReconfigureBegin { display_id: u32 },
+    /// CoreGraphics has finished reconfiguring a display
+    ReconfigureEnd {
+        display_id: u32,
+        kind: DisplayEventKind,
+    },
+}
+
+/// Receives [`ApplyProgress`] events. Reconfiguration callbacks can arrive on
+/// another thread, so the function must be `Sync`.
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(&ApplyProgress) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new(callback: impl Fn(&ApplyProgress) + Send + Sync + 'static) -> Self {
+        ProgressCallback(Arc::new(callback))
+    }
+
+    fn report(&self, progress: ApplyProgress) {
+        (self.0)(&progress)
+    }
+}
+
+impl std::fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+impl From<&Config> for ApplyOptions {
+    fn from(config: &Config) -> Self {
+        ApplyOptions {
+            default_hz: config.default_hz,
+            id_strategy: config.id_strategy,
+            backup: None,
+            force: false,
+            mirror_best_mode: false,
+            wake: false,
+            quiet: false,
+            progress: None,
+            keys: KeyFilter::All,
+            stagger: Duration::from_millis(config.stagger_ms),
+            keep_origins: false,
+            preview: false,
+            dry_run: false,
+            preserve_windows: false,
+        }
+    }
+}
+
+/// Something that did not stop a configuration from being applied but may not
+/// be what was asked for.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ApplyWarning {
+    /// The display concerned, if the warning is about one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_id: Option<u32>,
+    pub message: String,
+}
+
+/// Outcome of [`apply_configuration`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ApplyResult {
+    /// False when every display already matched
+    pub changed: bool,
+    pub warnings: Vec<ApplyWarning>,
+    /// Profile hooks run around the apply, in order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<HookResult>,
+    /// What was changed on each display, or would be under
+    /// [`ApplyOptions::dry_run`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub plan: Vec<DisplayDiff>,
+}
+
+/// Progress of an apply: messages and warnings go to [`crate::output`] unless
+/// quiet, warnings also into the result.
+struct Report {
+    quiet: bool,
+    warnings: Vec<ApplyWarning>,
+}
+
+impl Report {
+    fn say(&self, message: String) {
+        if !self.quiet {
+            crate::output::info(&message);
+        }
+    }
+
+    fn warn(&mut self, display_id: Option<u32>, message: String) {
+        if !self.quiet {
+            crate::output::warning(&message);
+        }
+        self.warnings.push(ApplyWarning {
+            display_id,
+            message,
+        });
+    }
+}
+
+/// Apply parsed configurations to the connected displays, in order.
+///
+/// Displays that already match their configuration are left alone. The
+/// result tells whether any display was actually changed and collects the
+/// warnings raised on the way.
+///
+/// A panic while applying is turned into an error. If displays had already
+/// started changing, they are put back the way they were first.
+pub fn apply_configuration(
+    configs: Vec<DisplayConfig>,
+    options: &ApplyOptions,
+) -> Result<ApplyResult, String> {
+    let mut before = None;
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| {
+        apply_unguarded(configs, options, &mut before)
+    }));
+    let payload = match outcome {
+        Ok(result) => return result,
+        Err(payload) => payload,
+    };
+
+    let error = format!(
+        "Internal error while applying: {}",
+        panic_message(&*payload)
+    );
+    let Some(before) = before else {
+        return Err(format!("{}; no display was changed", error));
+    };
+    // The lock was released as the panic unwound, so this takes it again
+    let restore = ApplyOptions {
+        force: true,
+        quiet: true,
+        ..ApplyOptions::from(&Config::default())
+    };
+    let restored = before.display_configs().and_then(|configs| {
+        panic::catch_unwind(AssertUnwindSafe(|| {
+            apply_unguarded(configs, &restore, &mut None)
+        }))
+        .unwrap_or_else(|payload| Err(panic_message(&*payload)))
+    });
+    match restored {
+        Ok(_) => Err(format!(
+            "{}; the displays were put back as they were",
+            error
+        )),
+        Err(e) => Err(format!(
+            "{}; putting the displays back failed too ({}), run `macdisp restore`",
+            error, e
+        )),
+    }
+}
+
+/// [`apply_configuration`] without the panic guard. `before` is set to the
+/// arrangement just before the first display is changed.
+fn apply_unguarded(
+    mut configs: Vec<DisplayConfig>,
+    options: &ApplyOptions,
+    before: &mut Option<Snapshot>,
+) -> Result<ApplyResult, String> {
+    let _lock = ApplyLock::acquire()?;
+    let mut report = Report {
+        quiet: options.quiet,
+        warnings: Vec::new(),
+    };
+
+    // Sleeping displays are missing from the active list and reject changes
+    if options.wake && !options.dry_run {
+        match wake_displays() {
+            Ok(woken) if !woken.is_empty() => report.say(format!(
+                "Woke display {}",
+                woken
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            Ok(_) => {}
+            Err(e) => report.warn(None, e),
+        }
+    }
+
+    let displays = get_active_displays();
+    let display_info: HashMap<u32, _> = displays
+        .iter()
+        .filter_map(|&id| get_display_info(id).map(|info| (id, info)))
+        .collect();
+    let all_info: Vec<DisplayInfo> = displays
+        .iter()
+        .filter_map(|id| display_info.get(id).cloned())
+        .collect();
+
+    // Keys left out by --only/--skip are dropped before anything is checked
+    // or planned, as if the configuration never had them
+    for config in &mut configs {
+        let left_out = options.keys.filter(config);
+        if !left_out.is_empty() {
+            report.say(format!(
+                "Display {}: leaving {} as is",
+                config.id,
+                left_out.join(", ")
+            ));
+        }
+    }
+
+    if !options.keep_origins {
+        reflow_rotations(&mut configs, &all_info, options, &mut report);
+    }
+
+    if !options.force {
+        check_usable(&configs, &all_info, options.id_strategy)?;
+    }
+
+    if let Some(path) = options.backup.as_ref().filter(|_| !options.dry_run) {
+        Snapshot::of(&all_info).save(path)?;
+        report.say(format!(
+            "Saved the current arrangement to {}",
+            path.display()
+        ));
+    }
+
+    // Work out what needs changing before touching anything, so progress can
+    // count the displays and a bad id fails before the first change
+    let mut pending = Vec::new();
+    let mut wallpapers = Vec::new();
+    let mut mirror_checks = Vec::new();
+    for config in configs {
+        let plan = plan_display(config, &all_info, options, &mut report)?;
+        if let Some(check) = plan.mirror_check {
+            mirror_checks.push(check);
+        }
+        if let Some(path) = plan.wallpaper {
+            wallpapers.push((plan.display_id, path));
+        } else if plan.pending.is_none() {
+            report.say(format!("Display {}: no change", plan.display_id));
+        }
+        pending.extend(plan.pending);
+    }
+
+    let plan: Vec<DisplayDiff> = pending
+        .iter()
+        .filter_map(|display| {
+            let info = display_info.get(&display.display_id)?;
+            Some(diff_pending(display, info, options))
+        })
+        .collect();
+    // Another agent switching modes would undo these ones
+    if plan.iter().any(|diff| diff.mode.is_some()) {
+        for agent in running_agents() {
+            if agent.controls(Subsystem::Modes) {
+                report.warn(
+                    None,
+                    format!(
+                        "{} is running and may switch display modes back",
+                        agent.name
+                    ),
+                );
+            }
+        }
+    }
+    if options.preview || options.dry_run {
+        for diff in &plan {
+            report.say(diff.to_string());
+        }
+        for (display_id, path) in &wallpapers {
+            report.say(format!(
+                "Display {}: desktop picture → {}",
+                display_id,
+                path.display()
+            ));
+        }
+    }
+    if options.dry_run {
+        return Ok(ApplyResult {
+            changed: !pending.is_empty() || !wallpapers.is_empty(),
+            warnings: report.warnings,
+            hooks: Vec::new(),
+            plan,
+        });
+    }
+
+    if !pending.is_empty() {
+        if let Err(e) = snapshot::save_automatic(&all_info) {
+            report.warn(None, e);
+        }
+        *before = Some(Snapshot::of(&all_info));
+    }
+    let windows = if options.preserve_windows && !pending.is_empty() {
+        windows::record().unwrap_or_else(|e| {
+            report.warn(None, format!("{}; windows will not be put back", e));
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+
+    let total = pending.len();
+    for (index, display) in pending.iter().enumerate() {
+        if index > 0 && !options.stagger.is_zero() {
+            report.say(format!(
+                "Waiting {}ms before changing display {}",
+                options.stagger.as_millis(),
+                display.display_id
+            ));
+            std::thread::sleep(options.stagger);
+        }
+        let Some(progress) = &options.progress else {
+            apply_display(display, options, &mut report)?;
+            continue;
+        };
+        progress.report(ApplyProgress::Display {
+            display_id: display.display_id,
+            index: index + 1,
+            total,
+        });
+        observe_reconfiguration(
+            &|id, done| {
+                progress.report(match done {
+                    None => ApplyProgress::ReconfigureBegin { display_id: id },
+                    Some(kind) => ApplyProgress::ReconfigureEnd {
+                        display_id: id,
+                        kind,
+                    },
+                })
+            },
+            || apply_display(display, options, &mut report),
+        )?;
+    }
+
+    // macOS picks hardware or software mirroring itself, so say which one
+    // each new mirror got and whether it is the one asked for
+    for (display_id, wanted) in mirror_checks {
+        let granted = get_display_info(display_id).and_then(|info| info.mirror_type);
+        match (granted, wanted) {
+            (Some(granted), Some(wanted)) if granted != wanted => report.warn(
+                Some(display_id),
+                format!(
+                    "Display {} is mirrored in {}, not {}; macOS only mirrors in hardware when the GPU can drive both displays from one framebuffer",
+                    display_id,
+                    granted.as_str(),
+                    wanted.as_str()
+                ),
+            ),
+            (Some(granted), _) => report.say(format!(
+                "Display {} is mirrored in {}",
+                display_id,
+                granted.as_str()
+            )),
+            (None, Some(wanted)) => report.warn(
+                Some(display_id),
+                format!(
+                    "Display {} is not mirroring, so it cannot be mirrored in {}",
+                    display_id,
+                    wanted.as_str()
+                ),
+            ),
+            (None, None) => {}
+        }
+    }
+
+    if !windows.is_empty() {
+        // macOS moves windows off changed displays after the change returns
+        std::thread::sleep(windows::SETTLE_DELAY);
+        let restored = windows::restore(&windows, &all_info, &get_all_display_info());
+        if restored.moved > 0 {
+            report.say(format!(
+                "Put {} window{} back in place",
+                restored.moved,
+                if restored.moved == 1 { "" } else { "s" }
+            ));
+        }
+        for e in restored.failed {
+            report.warn(None, e);
+        }
+    }
+
+    // Desktop pictures follow once the displays are in place, since a
+    // display only has one while it shows its own content
+    let mut wallpaper_changed = false;
+    for (display_id, path) in wallpapers {
+        match wallpaper::set_wallpaper(display_id, &path) {
+            Ok(()) => {
+                wallpaper_changed = true;
+                report.say(format!(
+                    "Set display {} wallpaper to {}",
+                    display_id,
+                    path.display()
+                ));
+            }
+            Err(e) => report.warn(Some(display_id), format!("{}", e)),
+        }
+    }
+
+    Ok(ApplyResult {
+        changed: total > 0 || wallpaper_changed,
+        warnings: report.warnings,
+        hooks: Vec::new(),
+        plan,
+    })
+}
+
+/// Check that `info` can mirror `target` and report the resolution the mirror
+/// set will run at. Returns the modes to set first under
+/// [`ApplyOptions::mirror_best_mode`].
+fn check_mirror(
+    info: &DisplayInfo,
+    target: &DisplayInfo,
+    options: &ApplyOptions,
+    report: &mut Report,
+) -> Result<Option<SharedMode>, String> {
+    let plan = match plan_mirror(
+        info,
+        &get_all_modes(info.id),
+        target,
+        &get_all_modes(target.id),
+    ) {
+        Ok(plan) => plan,
+        Err(e) if options.force => {
+            report.warn(Some(info.id), e);
+            return Ok(None);
+        }
+        Err(e) => return Err(format!("{} (use --force to mirror anyway)", e)),
+    };
+
+    let (width, height) = (plan.mode.width, plan.mode.height);
+    if options.mirror_best_mode {
+        report.say(format!(
+            "Display {} will mirror display {} at {}x{}, setting both to it first",
+            info.id, target.id, width, height
+        ));
+        return Ok(Some(plan.mode));
+    }
+    if plan.lowered.is_empty() {
+        report.say(format!(
+            "Display {} will mirror display {} at {}x{}",
+            info.id, target.id, width, height
+        ));
+    }
+    for (id, (from_width, from_height)) in plan.lowered {
+        report.warn(
+            Some(id),
+            format!(
+                "Mirroring display {} with display {} will likely drop display {} from {}x{} to {}x{}",
+                info.id, target.id, id, from_width, from_height, width, height
+            ),
+        );
+    }
+    Ok(None)
+}
+
+/// A setting before and after applying.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change<T> {
+    pub from: T,
+    pub to: T,
+}
+
+impl<T: PartialEq> Change<T> {
+    /// The change from `from` to `to`, if they differ.
+    fn of(from: T, to: T) -> Option<Change<T>> {
+        (from != to).then_some(Change { from, to })
+    }
+}
+
+/// What applying changes on one display, worked out by the planner before
+/// anything is changed; `None` for what stays as it is.
+///
+/// Shown as one line, e.g. `Display 2: 2560x1440@60 → 3840x2160@120, origin
+/// (0,0) → (1512,0), rotation unchanged`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DisplayDiff {
+    pub display_id: u32,
+    /// Modes written like `2560x1440@60`, see
+    /// [`ModeId::short`](crate::mode_id::ModeId::short)
+    pub mode: Option<Change<String>>,
+    pub origin: Option<Change<PointPx>>,
+    pub rotation: Option<Change<u32>>,
+    /// The display this one mirrors, `None` when it shows its own content
+    pub mirror_of: Option<Change<Option<u32>>>,
+    pub enabled: Option<Change<bool>>,
+}
+
+impl DisplayDiff {
+    /// The difference between `info` and what `config` sets, with the
+    /// display ending up at `rotation` and switched to `mode`, if any.
+    pub fn between(
+        info: &DisplayInfo,
+        config: &DisplayConfig,
+        rotation: u32,
+        mode: Option<&DisplayMode>,
+    ) -> DisplayDiff {
+        let mirror_of = config.mirror.as_deref().map(|mirror| match mirror {
+            "off" | "none" => None,
+            target => target.parse::<u32>().ok(),
+        });
+        DisplayDiff {
+            display_id: info.id,
+            mode: mode.and_then(|mode| {
+                Change::of(info.mode_id.short(), mode.mode_id().short())
+                    .filter(|_| mode.mode_number != info.mode_number)
+            }),
+            origin: config
+                .origin
+                .and_then(|origin| Change::of(info.origin(), PointPx::from(origin))),
+            rotation: Change::of(info.rotation, rotation),
+            mirror_of: mirror_of.and_then(|target| Change::of(info.mirror_of, target)),
+            enabled: config
+                .enabled
+                .and_then(|enabled| Change::of(info.enabled, enabled)),
+        }
+    }
+}
+
+impl fmt::Display for DisplayDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Display {}: ", self.display_id)?;
+        match &self.mode {
+            Some(mode) => write!(f, "{} → {}", mode.from, mode.to)?,
+            None => write!(f, "mode unchanged")?,
+        }
+        match &self.origin {
+            Some(origin) => write!(f, ", origin {} → {}", origin.from, origin.to)?,
+            None => write!(f, ", origin unchanged")?,
+        }
+        match &self.rotation {
+            Some(rotation) => write!(f, ", rotation {}° → {}°", rotation.from, rotation.to)?,
+            None => write!(f, ", rotation unchanged")?,
+        }
+        if let Some(mirror) = &self.mirror_of {
+            let name = |target: Option<u32>| match target {
+                Some(id) => format!("display {}", id),
+                None => "off".to_string(),
+            };
+            write!(f, ", mirroring {} → {}", name(mirror.from), name(mirror.to))?;
+        }
+        if let Some(enabled) = &self.enabled {
+            let name = |enabled| if enabled { "enabled" } else { "disabled" };
+            write!(f, ", {} → {}", name(enabled.from), name(enabled.to))?;
+        }
+        Ok(())
+    }
+}
+
+/// What applying a configuration would do to one display, in words.
+#[derive(Debug, Clone, Serialize)]
+pub struct Explanation {
+    pub display_id: u32,
+    /// The display's name from its EDID, or its type
+    pub name: String,
+    /// The changes in the order they would be made; empty when the display
+    /// already matches
+    pub changes: Vec<String>,
+    /// Warnings applying would raise, and why it would fail if it would
+    pub warnings: Vec<String>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Display {} ({}): ", self.display_id, self.name)?;
+        if self.changes.is_empty() {
+            write!(f, "no change")
+        } else {
+            write!(f, "{}", self.changes.join(", "))
+        }
+    }
+}
+
+/// Work out what [`apply_configuration`] would do with `configs`, with the
+/// same planner and mode selection, without changing anything.
+pub fn explain_configuration(
+    mut configs: Vec<DisplayConfig>,
+    options: &ApplyOptions,
+) -> Result<Vec<Explanation>, String> {
+    let all_info = get_all_display_info();
+    let mut left_out: Vec<Vec<&str>> = configs
+        .iter_mut()
+        .map(|config| options.keys.filter(config))
+        .collect();
+    if !options.keep_origins {
+        let mut report = Report {
+            quiet: true,
+            warnings: Vec::new(),
+        };
+        reflow_rotations(&mut configs, &all_info, options, &mut report);
+        left_out.resize(configs.len(), Vec::new());
+    }
+    let mut explanations = Vec::new();
+    for (config, left_out) in configs.into_iter().zip(left_out) {
+        let mut report = Report {
+            quiet: true,
+            warnings: Vec::new(),
+        };
+        let plan = plan_display(config, &all_info, options, &mut report)?;
+        let info = all_info
+            .iter()
+            .find(|d| d.id == plan.display_id)
+            .expect("planned display is connected");
+
+        let mut changes = Vec::new();
+        if let Some(pending) = &plan.pending {
+            let modes = get_all_modes(pending.display_id);
+            describe_pending(
+                pending,
+                info,
+                &all_info,
+                &modes,
+                options,
+                &mut changes,
+                &mut report,
+            );
+        }
+        if let Some(path) = &plan.wallpaper {
+            changes.push(format!("set the desktop picture to {}", path.display()));
+        }
+        if let Some((_, Some(wanted))) = plan.mirror_check {
+            changes.push(format!(
+                "check that macOS mirrors it in {} (it cannot be asked for)",
+                wanted.as_str()
+            ));
+        }
+        if !left_out.is_empty() {
+            changes.push(format!("leave {} as is", left_out.join(", ")));
+        }
+        explanations.push(Explanation {
+            display_id: plan.display_id,
+            name: display_name(info),
+            changes,
+            warnings: report.warnings.into_iter().map(|w| w.message).collect(),
+        });
+    }
+    Ok(explanations)
+}
+
+fn display_name(info: &DisplayInfo) -> String {
+    crate::edid::read_edid(info.id)
+        .ok()
+        .and_then(|bytes| crate::edid::Edid::parse(&bytes).ok())
+        .and_then(|edid| edid.name)
+        .unwrap_or_else(|| info.display_type.clone())
+}
+
+/// Put the changes of a pending display into words, picking its mode from
+/// `modes` through [`planned_mode`], as `apply_display_config` does.
+fn describe_pending(
+    pending: &PendingDisplay,
+    info: &DisplayInfo,
+    all_info: &[DisplayInfo],
+    modes: &[DisplayMode],
+    options: &ApplyOptions,
+    changes: &mut Vec<String>,
+    report: &mut Report,
+) {
+    let config = &pending.config;
+    let display_id = pending.display_id;
+
+    let chosen = planned_mode(pending, modes, options, report);
+
+    // The size the display takes up once changed, to say where it ends up
+    let mut size = info.size();
+    match chosen {
+        Some(Ok(mode)) => {
+            size = mode.size().rotated(pending.rotation);
+            if mode.mode_number != info.mode_number {
+                changes.push(format!(
+                    "switch to {} @ {}Hz {} (mode {})",
+                    size,
+                    format_hz(mode.refresh_rate),
+                    if mode.is_scaled { "HiDPI" } else { "native" },
+                    mode.mode_number
+                ));
+            }
+        }
+        Some(Err(e)) => report.warn(Some(display_id), format!("Applying would fail: {}", e)),
+        None => {
+            if pending.rotation != info.rotation {
+                size = info.size().rotated(info.rotation).rotated(pending.rotation);
+            }
+        }
+    }
+
+    if let Some(origin) = config.origin.map(PointPx::from) {
+        if origin != info.origin() {
+            let bounds = Rect::new(origin, size);
+            let beside = all_info
+                .iter()
+                .filter(|other| {
+                    other.id != display_id && other.enabled && other.mirror_of.is_none()
+                })
+                .find_map(|other| {
+                    bounds
+                        .side_of(&other.bounds())
+                        .map(|side| format!(" {} display {}", side, other.id))
+                });
+            changes.push(format!("move to {}{}", origin, beside.unwrap_or_default()));
+        }
+    }
+    if let Some(degree) = config.degree {
+        if degree != info.rotation {
+            changes.push(format!("rotate to {}°", degree));
+        }
+    }
+    match config.mirror.as_deref() {
+        Some("off" | "none") if info.mirror_of.is_some() => {
+            changes.push("stop mirroring".to_string())
+        }
+        Some(target) => {
+            if let Ok(target) = target.parse::<u32>() {
+                if info.mirror_of != Some(target) {
+                    changes.push(format!("mirror display {}", target));
+                }
+            }
+        }
+        None => {}
+    }
+    if let Some(enabled) = config.enabled {
+        if enabled != info.enabled {
+            changes.push(if enabled { "enable" } else { "disable" }.to_string());
+        }
+    }
+}
+
+/// The mode `pending` would be set to: `None` when its configuration sets
+/// no mode, an error when the mode cannot be found.
+fn planned_mode<'a>(
+    pending: &PendingDisplay,
+    modes: &'a [DisplayMode],
+    options: &ApplyOptions,
+    report: &mut Report,
+) -> Option<Result<&'a DisplayMode, String>> {
+    let config = &pending.config;
+    let display_id = pending.display_id;
+    if let Some(mode_id) = &config.mode_id {
+        let hint = config.mode.as_ref().and_then(|m| m.parse().ok());
+        Some(find_mode(modes, mode_id, hint).ok_or_else(|| {
+            format!(
+                "Mode {} is not available on display {}",
+                mode_id, display_id
+            )
+        }))
+    } else if let Some(mode) = &config.mode {
+        Some(
+            mode.parse::<u32>()
+                .ok()
+                .and_then(|number| modes.iter().find(|m| m.mode_number == number))
+                .ok_or_else(|| format!("Invalid mode number: {}", mode)),
+        )
+    } else if config.resolution.is_some()
+        || config.looks_like.is_some()
+        || config.hz.is_some()
+        || config.color_depth.is_some()
+    {
+        Some(
+            get_current_mode(display_id)
+                .ok_or_else(|| format!("Could not get current mode for display {}", display_id))
+                .and_then(|current| {
+                    select_mode(
+                        display_id,
+                        config,
+                        modes,
+                        &current,
+                        pending.rotation,
+                        options.default_hz,
+                    )
+                })
+                .map(|choice| {
+                    for warning in choice.warnings {
+                        report.warn(Some(display_id), warning);
+                    }
+                    choice.mode
+                }),
+        )
+    } else {
+        None
+    }
+}
+
+/// What applying `pending` changes, as [`DisplayDiff`] says it.
+fn diff_pending(
+    pending: &PendingDisplay,
+    info: &DisplayInfo,
+    options: &ApplyOptions,
+) -> DisplayDiff {
+    let modes = get_all_modes(pending.display_id);
+    // Mode warnings come once the display is applied
+    let mut report = Report {
+        quiet: true,
+        warnings: Vec::new(),
+    };
+    let chosen = planned_mode(pending, &modes, options, &mut report).and_then(Result::ok);
+    DisplayDiff::between(info, &pending.config, pending.rotation, chosen)
+}
+
+/// What one configuration asks of its display, worked out before anything
+/// is applied.
+struct PlannedDisplay {
+    display_id: u32,
+    /// The changes to make, if the display does not already match
+    pending: Option<PendingDisplay>,
+    /// A desktop picture that differs from the current one
+    wallpaper: Option<PathBuf>,
+    /// Mirroring to check once applied, and the kind asked for
+    mirror_check: Option<(u32, Option<MirrorType>)>,
+}
+
+/// Give the neighbours of each display that turns sideways new origins, so
+/// the rotated display neither overlaps them nor leaves a gap (see
+/// [`crate::layout::reflow`]).
+///
+/// Displays with an origin in their configuration are placed by hand and
+/// left alone, as is every display when the rotated one has an origin.
+/// Neighbours without a configuration get one that only sets the origin.
+fn reflow_rotations(
+    configs: &mut Vec<DisplayConfig>,
+    all_info: &[DisplayInfo],
+    options: &ApplyOptions,
+    report: &mut Report,
+) {
+    // Nothing turns, so nothing needs making room for
+    if !crate::CAN_ROTATE || !options.keys.includes(ConfigKey::Origin) {
+        return;
+    }
+    let placed_by_hand =
+        |config: &DisplayConfig| config.origin.is_some() || config.origin_offset.is_some();
+    // Each rotation is laid out on top of the ones before it
+    let mut layout = all_info.to_vec();
+    for index in 0..configs.len() {
+        let config = &configs[index];
+        let Some(info) = find_display_by(&config.id, all_info, options.id_strategy) else {
+            continue;
+        };
+        let degree = config.degree.or_else(|| {
+            config
+                .orientation
+                .map(|o| o.degree(is_native_landscape(info)))
+        });
+        let Some(degree) = degree.filter(|degree| *degree != info.rotation) else {
+            continue;
+        };
+        if placed_by_hand(config)
+            || info.mirror_of.is_some()
+            || DisplayClass::of(info) != DisplayClass::Physical
+        {
+            continue;
+        }
+        let requested = config.resolution.or(config.looks_like).map(SizePx::from);
+        let size = crate::layout::rotated_size(info, requested, degree);
+        let display_id = info.id;
+
+        for (id, origin) in crate::layout::reflow(&layout, display_id, size) {
+            let Some(other) = all_info.iter().find(|other| other.id == id) else {
+                continue;
+            };
+            let existing = configs.iter().position(|config| {
+                find_display_by(&config.id, all_info, options.id_strategy)
+                    .is_some_and(|found| found.id == id)
+            });
+            match existing {
+                Some(i) if id != display_id && placed_by_hand(&configs[i]) => continue,
+                Some(i) => configs[i].origin = Some(origin.into()),
+                None => configs.push(DisplayConfig {
+                    id: other.persistent_id.clone(),
+                    origin: Some(origin.into()),
+                    ..Default::default()
+                }),
+            }
+            if let Some(moved) = layout.iter_mut().find(|moved| moved.id == id) {
+                (moved.x, moved.y) = origin.into();
+            }
+            report.say(if id == display_id {
+                format!(
+                    "Display {}: moving to {} to keep its edge beside the main display once rotated",
+                    id, origin
+                )
+            } else {
+                format!(
+                    "Display {}: moving to {} to make room for display {} rotated to {}°",
+                    id, origin, display_id, degree
+                )
+            });
+        }
+        if let Some(rotated) = layout.iter_mut().find(|rotated| rotated.id == display_id) {
+            (rotated.width, rotated.height) = size.into();
+        }
+    }
+}
+
+/// Resolve a configuration against the connected displays the way
+/// [`apply_configuration`] does: find its display and mirror target, turn
+/// orientation and relative origins into absolute values, drop what the
+/// display's class cannot change and work out whether anything differs.
+fn plan_display(
+    mut config: DisplayConfig,
+    all_info: &[DisplayInfo],
+    options: &ApplyOptions,
+    report: &mut Report,
+) -> Result<PlannedDisplay, String> {
+    let info = find_display_by(&config.id, all_info, options.id_strategy)
+        .ok_or_else(|| format!("Display {} not found", config.id))?;
+    let display_id = info.id;
+
+    // From here on the mirror target is a display id, however it was written
+    let mut mirror_mode = None;
+    if let Some(mirror) = config
+        .mirror
+        .as_deref()
+        .filter(|m| !matches!(*m, "off" | "none"))
+    {
+        let target = find_display_by(mirror, all_info, options.id_strategy)
+            .ok_or_else(|| format!("Mirror display not found: {}", mirror))?;
+        config.mirror = Some(target.id.to_string());
+        if info.mirror_of != Some(target.id) {
+            mirror_mode = check_mirror(info, target, options, report)?;
+        }
+    }
+
+    if let Some(orientation) = config.orientation {
+        if config.degree.is_some() {
+            return Err(format!(
+                "Display {}: use either degree: or orientation:, not both",
+                config.id
+            ));
+        }
+        config.degree = Some(orientation.degree(is_native_landscape(info)));
+    }
+    if let Some((dx, dy)) = config.origin_offset.take() {
+        if config.origin.is_some() {
+            return Err(format!(
+                "Display {}: use either an absolute or a relative origin, not both",
+                config.id
+            ));
+        }
+        config.origin = Some(info.origin().offset(dx, dy).into());
+    }
+
+    let class = DisplayClass::of(info);
+    let skipped = skip_unsupported(&mut config, class);
+    if !skipped.is_empty() {
+        report.warn(
+            Some(display_id),
+            format!(
+                "Display {} is a {} display, which macOS does not let change {}; skipping {}",
+                display_id,
+                class.name(),
+                if skipped.len() == 1 { "this" } else { "these" },
+                skipped.join(", ")
+            ),
+        );
+    }
+    if !crate::CAN_ROTATE {
+        if let Some(degree) = config
+            .degree
+            .take()
+            .filter(|degree| *degree != info.rotation)
+        {
+            report.warn(
+                Some(display_id),
+                format!(
+                    "Display {}: macdisp cannot rotate displays, so it stays at {}°; skipping degree:{}",
+                    display_id, info.rotation, degree
+                ),
+            );
+        }
+    }
+
+    // Not a setting macOS takes, so it never counts as a change
+    let mirror_type = config.mirror_type.take();
+    let starts_mirroring = config
+        .mirror
+        .as_deref()
+        .and_then(|m| m.parse::<u32>().ok())
+        .is_some_and(|target| info.mirror_of != Some(target));
+    let mirror_check =
+        (mirror_type.is_some() || starts_mirroring).then_some((display_id, mirror_type));
+
+    // Only pictures that differ are set; a display without one of its
+    // own (mirrored or disabled) is left to fail when it is set
+    let wallpaper = match config.wallpaper.take() {
+        Some(value) => Some(wallpaper::resolve_path(&value)?)
+            .filter(|path| wallpaper::wallpaper(display_id).map_or(true, |c| c != *path)),
+        None => None,
+    };
+    // `list` writes `enabled:true` into every line; only an actual change
+    // may reach the private call that turns displays on and off
+    config.enabled = config.enabled.filter(|&enabled| enabled != info.enabled);
+    let unchanged = config_drift(&config, info, all_info).is_empty();
+    let pending = (!unchanged).then(|| PendingDisplay {
+        display_id,
+        // Sizes in `res:` are read in the orientation the display ends up in
+        rotation: config.degree.unwrap_or(info.rotation),
+        config,
+        mirror_mode,
+        class,
+    });
+    Ok(PlannedDisplay {
+        display_id,
+        pending,
+        wallpaper,
+        mirror_check,
+    })
+}
+
+/// A display that needs changing, worked out before anything is applied.
+struct PendingDisplay {
+    display_id: u32,
+    config: DisplayConfig,
+    /// The rotation the display ends up at
+    rotation: u32,
+    /// Modes to set both displays to before mirroring
+    mirror_mode: Option<SharedMode>,
+    class: DisplayClass,
+}
+
+/// Clear the keys macOS does not let this class of display change, returning
+/// their names.
+fn skip_unsupported(config: &mut DisplayConfig, class: DisplayClass) -> Vec<&'static str> {
+    if class == DisplayClass::Physical {
+        return Vec::new();
+    }
+
+    let mut skipped = Vec::new();
+    let mut skip = |name, set: bool| {
+        if set {
+            skipped.push(name);
+        }
+    };
+    skip("mode", config.mode.take().is_some());
+    skip("mode_id", config.mode_id.take().is_some());
+    skip("res", config.resolution.take().is_some());
+    skip("looks-like", config.looks_like.take().is_some());
+    skip("hz", config.hz.take().is_some());
+    skip("color_depth", config.color_depth.take().is_some());
+    skip("scaling", config.scaling.take().is_some());
+    skip(
+        "origin",
+        config.origin.take().is_some() | config.origin_offset.take().is_some(),
+    );
+    let rotated = config.degree.take().is_some();
+    skip(
+        if config.orientation.take().is_some() {
+            "orientation"
+        } else {
+            "degree"
+        },
+        rotated,
+    );
+    skipped
+}
+
+/// Apply one configuration that is known to differ from the display's state.
+///
+/// Failures on Sidecar and AirPlay displays say where they can be changed
+/// instead of leaving just an error code.
+fn apply_display(
+    display: &PendingDisplay,
+    options: &ApplyOptions,
+    report: &mut Report,
+) -> Result<(), String> {
+    apply_display_config(display, options, report).map_err(|e| match display.class.remedy() {
+        Some(remedy) => format!(
+            "{} (display {} is a {} display: {})",
+            e,
+            display.display_id,
+            display.class.name(),
+            remedy
+        ),
+        None => e,
+    })
+}
+
+/// The mode picked for a configuration, with anything that differs from
+/// what was asked.
+#[derive(Debug)]
+pub struct ModeChoice<'a> {
+    pub mode: &'a DisplayMode,
+    pub warnings: Vec<String>,
+}
+
+/// Pick the mode `config` asks for among a display's `modes`.
+///
+/// A `color_depth` without `res` or `looks-like` keeps the current size,
+/// scaling and, where it can, refresh rate; when the depth is only offered at
+/// another rate, the closest one is used with a warning. Without a
+/// `color_depth` the current depth is preferred, so a resolution change does
+/// not also switch between 8 and 10 bits.
+pub fn select_mode<'a>(
+    display_id: u32,
+    config: &DisplayConfig,
+    modes: &'a [DisplayMode],
+    current: &DisplayMode,
+    rotation: u32,
+    default_hz: Option<f64>,
+) -> Result<ModeChoice<'a>, String> {
+    let keep_size =
+        config.color_depth.is_some() && config.resolution.is_none() && config.looks_like.is_none();
+    let scaling = config
+        .scaling
+        .or_else(|| keep_size.then_some(current.is_scaled));
+    let same_rate = |mode: &DisplayMode, hz: f64| same_hz(mode.refresh_rate, hz);
+    // Of the rates within the tolerance, the closest, so `hz:59.94` and
+    // `hz:60` tell the two apart when a display offers both
+    let closest = |candidates: &[&'a DisplayMode], hz: f64| {
+        candidates
+            .iter()
+            .copied()
+            .filter(|mode| same_rate(mode, hz))
+            .min_by(|a, b| {
+                (a.refresh_rate - hz)
+                    .abs()
+                    .total_cmp(&(b.refresh_rate - hz).abs())
+            })
+    };
+
+    let mut candidates: Vec<&DisplayMode> = modes
+        .iter()
+        .filter(|mode| {
+            let res_match = config
+                .resolution
+                .map(|size| mode.has_size(size.into(), rotation))
+                .unwrap_or(!keep_size || mode.size() == current.size());
+            // Mode sizes are in points, so a HiDPI mode of that size
+            // looks like the given resolution
+            let looks_like_match = config
+                .looks_like
+                .map(|size| mode.has_size(size.into(), rotation) && mode.is_scaled)
+                .unwrap_or(true);
+            let rotation_match = rotation == 0 || mode.available_rotated();
+            let depth_match = config.color_depth.map(|d| mode.depth == d).unwrap_or(true);
+            let scaling_match = scaling.map(|s| mode.is_scaled == s).unwrap_or(true);
+
+            res_match && looks_like_match && rotation_match && depth_match && scaling_match
+        })
+        .collect();
+    if config.color_depth.is_none() {
+        candidates.sort_by_key(|mode| mode.depth != current.depth);
+    }
+
+    let mut warnings = Vec::new();
+    let mode = match (config.hz, config.color_depth) {
+        (Some(hz), depth) => match closest(&candidates, hz) {
+            Some(mode) => mode,
+            None => {
+                let rates: Vec<String> = candidates
+                    .iter()
+                    .map(|mode| format!("{}Hz", format_hz(mode.refresh_rate)))
+                    .collect();
+                return Err(match depth {
+                    Some(depth) if !rates.is_empty() => format!(
+                        "Display {} has no {}-bit mode at {}Hz; {}-bit is offered at {}",
+                        display_id,
+                        depth,
+                        format_hz(hz),
+                        depth,
+                        rates.join(", ")
+                    ),
+                    _ => format!(
+                        "No matching mode found for display {} with specified parameters",
+                        display_id
+                    ),
+                });
+            }
+        },
+        // Keep the current rate if the new depth allows it, else the closest
+        (None, Some(depth)) => {
+            let wanted = match default_hz {
+                Some(default_hz) if !keep_size => default_hz,
+                _ => current.refresh_rate,
+            };
+            let Some(mode) = candidates.iter().copied().min_by(|a, b| {
+                (a.refresh_rate - wanted)
+                    .abs()
+                    .total_cmp(&(b.refresh_rate - wanted).abs())
+            }) else {
+                return Err(format!(
+                    "Display {} has no {}-bit mode at {}x{}",
+                    display_id, depth, current.width, current.height
+                ));
+            };
+            if keep_size && !same_rate(mode, wanted) {
+                warnings.push(format!(
+                    "Display {} has no {}-bit mode at {}Hz; using {}Hz",
+                    display_id,
+                    depth,
+                    format_hz(wanted),
+                    format_hz(mode.refresh_rate)
+                ));
+            }
+            mode
+        }
+        // Without an explicit hz, prefer the configured default rate if offered
+        (None, None) => {
+            let preferred = default_hz.and_then(|default_hz| {
+                closest(&candidates, default_hz).map(|mode| (mode, default_hz))
+            });
+            match (preferred, candidates.first()) {
+                (Some((mode, _)), _) => mode,
+                (None, Some(mode)) => {
+                    if let Some(default_hz) = default_hz {
+                        warnings.push(format!(
+                            "Display {} has no {}Hz mode at {}x{}; using {}Hz",
+                            display_id,
+                            format_hz(default_hz),
+                            mode.width,
+                            mode.height,
+                            format_hz(mode.refresh_rate)
+                        ));
+                    }
+                    mode
+                }
+                (None, None) => {
+                    return Err(match config.looks_like {
+                        Some((w, h)) => format!(
+                            "Display {} has no HiDPI mode that looks like {}x{}",
+                            display_id, w, h
+                        ),
+                        None => format!(
+                            "No matching mode found for display {} with specified parameters",
+                            display_id
+                        ),
+                    })
+                }
+            }
+        }
+    };
+    Ok(ModeChoice { mode, warnings })
+}
+
+fn apply_display_config(
+    display: &PendingDisplay,
+    options: &ApplyOptions,
+    report: &mut Report,
+) -> Result<(), String> {
+    let PendingDisplay {
+        display_id,
+        ref config,
+        ref mirror_mode,
+        ..
+    } = *display;
+    // The same pick the preview and `explain` describe
+    let modes = get_all_modes(display_id);
+    if let Some(mode) = planned_mode(display, &modes, options, report).transpose()? {
+        if !mode.is_safe_for_hardware {
+            report.warn(
+                Some(display_id),
+                format!(
+                    "Mode {} is not marked safe for display {}",
+                    mode.mode_number, display_id
+                ),
+            );
+        }
+        let current = get_current_mode(display_id);
+        let already_set = current
+            .as_ref()
+            .is_some_and(|current| current.mode_id() == mode.mode_id());
+        if !already_set {
+            set_display_mode(display_id, mode.mode_number)?;
+            let depth = match &current {
+                Some(current) if current.depth != mode.depth => format!(" {}-bit", mode.depth),
+                _ => String::new(),
+            };
+            report.say(format!(
+                "Set display {} to {}x{} @ {}Hz{} {} (mode {})",
+                display_id,
+                mode.width,
+                mode.height,
+                format_hz(mode.refresh_rate),
+                depth,
+                if mode.is_scaled { "scaled" } else { "native" },
+                mode.mode_number
+            ));
+        }
+    }
+
+    // Leave macOS no choice of resolution for the mirror set
+    if let (Some(shared), Some(target)) = (
+        mirror_mode.as_ref(),
+        config.mirror.as_deref().and_then(|m| m.parse::<u32>().ok()),
+    ) {
+        for (id, mode) in [(target, &shared.second), (display_id, &shared.first)] {
+            let current = get_current_mode(id).map(|m| m.mode_number);
+            if current != Some(mode.mode_number) {
+                set_display_mode(id, mode.mode_number)?;
+                report.say(format!(
+                    "Set display {} to {}x{} @ {}Hz (mode {})",
+                    id,
+                    mode.width,
+                    mode.height,
+                    format_hz(mode.refresh_rate),
+                    mode.mode_number
+                ));
+            }
+        }
+    }
+
+    // Handle configuration (mirroring, position, rotation, enable/disable)
+    if config.mirror.is_some()
+        || config.origin.is_some()
+        || config.degree.is_some()
+        || config.enabled.is_some()
+    {
+        let mirror = match config.mirror.as_deref() {
+            Some("off") | Some("none") => Some(MirrorSetting::Off),
+            Some(mirror_id) => mirror_id.parse().ok().map(MirrorSetting::Of),
+            None => None,
+        };
+
+        let options = ConfigureOptions {
+            origin: config.origin,
+            rotation: config.degree,
+            mirror,
+            enabled: config.enabled,
+        };
+
+        configure_display(display_id, &options)?;
+
+        if let Some((x, y)) = config.origin {
+            report.say(format!(
+                "Set display {} origin to ({}, {})",
+                display_id, x, y
+            ));
+        }
+        if let Some(degree) = config.degree {
+            report.say(format!(
+                "Set display {} rotation to {}°",
+                display_id, degree
+            ));
+        }
+        match mirror {
+            Some(MirrorSetting::Of(mirror_id)) => report.say(format!(
+                "Set display {} to mirror display {}",
+                display_id, mirror_id
+            )),
+            Some(MirrorSetting::Off) => {
+                report.say(format!("Stopped mirroring on display {}", display_id))
+            }
+            None => {}
+        }
+        if let Some(enabled) = config.enabled {
+            report.say(format!("Set display {} enabled: {}", display_id, enabled));
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply configurations without blocking the async runtime.
+///
+/// Mode switches can take a second or more while the display retrains, so the
+/// work runs on tokio's blocking pool.
+#[cfg(feature = "async")]
+pub async fn apply_configuration_async(
+    configs: Vec<DisplayConfig>,
+    options: ApplyOptions,
+) -> Result<ApplyResult, String> {
+    tokio::task::spawn_blocking(move || apply_configuration(configs, &options))
+        .await
+        .map_err(|e| format!("Apply task failed: {}", e))?
+}
+
+/// Find the display a config id refers to: a numeric display id, decimal or
+/// `0x` hex, a serial id (`s12345`) or a persistent id.
+pub fn find_display<'a>(id: &str, displays: &'a [DisplayInfo]) -> Option<&'a DisplayInfo> {
+    find_display_by(id, displays, IdStrategy::Persistent)
+}
+
+/// Like [`find_display`], but under [`IdStrategy::Serial`] a bare number is
+/// tried as a serial number before falling back to a display id.
+pub fn find_display_by<'a, D: Selectable>(
+    id: &str,
+    displays: &'a [D],
+    strategy: IdStrategy,
+) -> Option<&'a D> {
+    let by_serial = |serial: u32| {
+        displays
+            .iter()
+            .find(|d| d.serial() != 0 && d.serial() == serial)
+    };
+
+    if let Some(serial) = id.strip_prefix('s').and_then(|s| s.parse::<u32>().ok()) {
+        return by_serial(serial);
+    }
+    // Serial numbers are only ever written in decimal
+    let decimal = id.parse::<u32>().is_ok();
+    match parse_display_id(id) {
+        Some(numeric) if decimal && strategy == IdStrategy::Serial => {
+            by_serial(numeric).or_else(|| displays.iter().find(|d| d.display_id() == Some(numeric)))
+        }
+        Some(numeric) => displays.iter().find(|d| d.display_id() == Some(numeric)),
+        None => displays.iter().find(|d| d.persistent_id() == id),
+    }
+}
+
+/// Refuse configurations that would leave no display enabled and showing
+/// its own content.
+///
+/// Getting a picture back after that needs SSH or safe mode, so
+/// `apply_configuration` checks this unless forced. Ids that match no
+/// display are left for the caller to report.
+pub fn check_usable(
+    configs: &[DisplayConfig],
+    displays: &[DisplayInfo],
+    strategy: IdStrategy,
+) -> Result<(), String> {
+    // (enabled, mirroring) of every display once the configurations are applied
+    let mut after: HashMap<u32, (bool, bool)> = displays
+        .iter()
+        .map(|info| (info.id, (info.enabled, info.mirror_of.is_some())))
+        .collect();
+    for config in configs {
+        let Some(info) = find_display_by(&config.id, displays, strategy) else {
+            continue;
+        };
+        let state = after.get_mut(&info.id).expect("display was listed");
+        if let Some(enabled) = config.enabled {
+            state.0 = enabled;
+        }
+        if let Some(mirror) = &config.mirror {
+            state.1 = !matches!(mirror.as_str(), "off" | "none");
+        }
+    }
+
+    if after
+        .values()
+        .any(|&(enabled, mirroring)| enabled && !mirroring)
+    {
+        return Ok(());
+    }
+    let main_disabled = displays
+        .iter()
+        .find(|info| info.is_main)
+        .is_some_and(|main| !after[&main.id].0);
+    Err(if main_disabled {
+        "Refusing to disable the main display with no other display to take over (use --force to apply anyway)".to_string()
+    } else {
+        "Refusing to leave no display enabled and unmirrored (use --force to apply anyway)"
+            .to_string()
+    })
+}
+
+/// Describe every key of `config` the display's current state does not satisfy.
+///
+/// Returns an empty list when the display already looks the way `config` asks.
+pub fn config_drift(
+    config: &DisplayConfig,
+    info: &DisplayInfo,
+    displays: &[DisplayInfo],
+) -> Vec<String> {
+    let mut drift = Vec::new();
+
+    if let Some(mode_id) = &config.mode_id {
+        if info.mode_id != *mode_id {
+            drift.push(format!("mode_id {} (want {})", info.mode_id, mode_id));
+        }
+    } else if let Some(mode) = config.mode.as_ref().and_then(|m| m.parse::<u32>().ok()) {
+        if info.mode_number != mode {
+            drift.push(format!("mode {} (want {})", info.mode_number, mode));
+        }
+    }
+    // Display bounds are rotated; a rotated display also matches the native
+    // size, as mode selection does
+    let size = info.size();
+    let has_size = |want: SizePx| size == want || size.rotated(info.rotation) == want;
+    if let Some(want) = config.resolution.map(SizePx::from) {
+        if !has_size(want) {
+            drift.push(format!("res {} (want {})", size, want));
+        }
+    }
+    if let Some(want) = config.looks_like.map(SizePx::from) {
+        if !has_size(want) || !info.scaling {
+            drift.push(format!(
+                "looks-like {}{} (want {})",
+                size,
+                if info.scaling { "" } else { " unscaled" },
+                want
+            ));
+        }
+    }
+    if let Some(hz) = config.hz {
+        if !same_hz(info.hz, hz) {
+            drift.push(format!(
+                "hz {} (want {})",
+                format_hz(info.hz),
+                format_hz(hz)
+            ));
+        }
+    }
+    if let Some(depth) = config.color_depth {
+        if info.depth != depth {
+            drift.push(format!("color_depth {} (want {})", info.depth, depth));
+        }
+    }
+    if let Some(scaling) = config.scaling {
+        if info.scaling != scaling {
+            drift.push(format!(
+                "scaling {} (want {})",
+                if info.scaling { "on" } else { "off" },
+                if scaling { "on" } else { "off" }
+            ));
+        }
+    }
+    if let Some((x, y)) = config.origin {
+        if info.x != x || info.y != y {
+            drift.push(format!(
+                "origin ({},{}) (want ({},{}))",
+                info.x, info.y, x, y
+            ));
+        }
+    }
+    // A rotation macdisp cannot make is no drift it could put right
+    if let Some(degree) = config.degree.filter(|_| crate::CAN_ROTATE) {
+        if info.rotation != degree {
+            drift.push(format!("degree {} (want {})", info.rotation, degree));
+        }
+    }
+    if let Some(mirror) = &config.mirror {
+        // macOS picks which display of a mirror set is the master, so only
+        // whether the two displays share a set counts, not the direction
+        let master = |info: &DisplayInfo| info.mirror_of.unwrap_or(info.id);
+        let mirrored_with: Vec<String> = displays
+            .iter()
+            .filter(|other| other.id != info.id && master(other) == master(info))
+            .map(|other| other.id.to_string())
+            .collect();
+        let satisfied = match mirror.as_str() {
+            "off" | "none" => mirrored_with.is_empty(),
+            _ => find_display(mirror, displays)
+                .is_some_and(|target| target.id != info.id && master(target) == master(info)),
+        };
+        if !satisfied {
+            drift.push(format!(
+                "mirror {} (want {})",
+                if mirrored_with.is_empty() {
+                    "off".to_string()
+                } else {
+                    mirrored_with.join(",")
+                },
+                mirror
+            ));
+        }
+    }
+    if let Some(enabled) = config.enabled {
+        if info.enabled != enabled {
+            drift.push(format!("enabled {} (want {})", info.enabled, enabled));
+        }
+    }
+
+    drift
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn display(id: u32, uuid: &str) -> DisplayInfo {
+        DisplayInfo {
+            id,
+            persistent_id: uuid.to_string(),
+            contextual_id: id,
+            serial: 0,
+            x: 0,
+            y: 0,
+            width: 2560,
+            height: 1440,
+            rotation: 0,
+            hz: 60.0,
+            depth: 8,
+            scaling: false,
+            mode_number: 10,
+            mode_id: "2560x1440/2560x1440@60/d8".parse().unwrap(),
+            is_main: id == 1,
+            is_mirror: false,
+            mirror_of: None,
+            mirror_type: None,
+            enabled: true,
+            display_type: "External display".to_string(),
+        }
+    }
+
+    /// A safe 60Hz mode of `width`x`height` points at 8 bits per channel,
+    /// rendered at twice the size when `is_scaled`.
+    pub(crate) fn mode(mode_number: u32, width: u32, height: u32, is_scaled: bool) -> DisplayMode {
+        let scale = if is_scaled { 2 } else { 1 };
+        DisplayMode {
+            width,
+            height,
+            refresh_rate: 60.0,
+            depth: 8,
+            mode_number,
+            is_stretched: false,
+            is_interlaced: false,
+            is_tv_mode: false,
+            is_safe_for_hardware: true,
+            is_scaled,
+            pixel_width: width * scale,
+            pixel_height: height * scale,
+        }
+    }
+
+    #[test]
+    fn test_set_key_validates() {
+        let mut config = DisplayConfig::default();
+        set_key(&mut config, "res", "2560x1440").unwrap();
+        set_key(&mut config, "origin", "-1440,0").unwrap();
+        set_key(&mut config, "rotation", "90").unwrap();
+        set_key(&mut config, "mirror_type", "hardware").unwrap();
+        assert_eq!(config.resolution, Some((2560, 1440)));
+        assert_eq!(config.origin, Some((-1440, 0)));
+        assert_eq!(config.degree, Some(90));
+        assert_eq!(config.mirror_type, Some(MirrorType::Hardware));
+
+        assert!(set_key(&mut config, "res", "2560").is_err());
+        assert!(set_key(&mut config, "hz", "fast").is_err());
+        assert!(set_key(&mut config, "degree", "45").is_err());
+        assert!(set_key(&mut config, "mirror_type", "fast").is_err());
+        assert!(set_key(&mut config, "colour", "8").is_err());
+
+        // Nothing can turn a display, so asking how one should stand fails
+        let error = set_key(&mut config, "orientation", "portrait").unwrap_err();
+        assert!(error.contains("cannot rotate"));
+        let error = parse_config("id:1 orientation:portrait").unwrap_err();
+        assert!(error.contains("cannot rotate"));
+
+        // Every key completes, and every whole setting offered is valid
+        for key in SETTING_KEYS {
+            assert!(SETTING_COMPLETIONS
+                .iter()
+                .any(|completion| completion.starts_with(&format!("{}=", key))));
+        }
+        for completion in SETTING_COMPLETIONS {
+            let (key, value) = completion.split_once('=').unwrap();
+            assert!(SETTING_KEYS.contains(&key));
+            if !value.is_empty() {
+                set_key(&mut DisplayConfig::default(), key, value).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_relative_origin() {
+        let config = parse_config("id:1 origin:+100,+0").unwrap();
+        assert_eq!(
+            (config.origin, config.origin_offset),
+            (None, Some((100, 0)))
+        );
+        let config = parse_config("id:1 origin:(-100,+0)").unwrap();
+        assert_eq!(config.origin_offset, Some((-100, 0)));
+        let config = parse_config("id:1 origin:+(-100,-50)").unwrap();
+        assert_eq!(config.origin_offset, Some((-100, -50)));
+        // Negative coordinates alone are still absolute
+        let config = parse_config("id:1 origin:(-1440,-50)").unwrap();
+        assert_eq!(
+            (config.origin, config.origin_offset),
+            (Some((-1440, -50)), None)
+        );
+
+        let mut config = DisplayConfig::default();
+        set_key(&mut config, "origin", "+0,-20").unwrap();
+        assert_eq!(config.origin_offset, Some((0, -20)));
+        assert!(set_key(&mut config, "origin", "+(1,2").is_err());
+    }
+
+    #[test]
+    fn test_config_drift() {
+        let displays = vec![display(1, "AAAA"), display(2, "BBBB")];
+
+        let config = parse_config("id:BBBB res:2560x1440 hz:60 origin:(0,0)").unwrap();
+        let info = find_display(&config.id, &displays).unwrap();
+        assert!(config_drift(&config, info, &displays).is_empty());
+
+        let config = parse_config("id:2 res:1920x1080 origin:(2560,0)").unwrap();
+        let info = find_display(&config.id, &displays).unwrap();
+        assert_eq!(config_drift(&config, info, &displays).len(), 2);
+
+        // Bounds of a rotated display are portrait; the native size still matches
+        let mut rotated = display(2, "BBBB");
+        (rotated.width, rotated.height, rotated.rotation) = (1440, 2560, 90);
+        let config = parse_config("id:2 res:1440x2560").unwrap();
+        assert!(config_drift(&config, &rotated, &displays).is_empty());
+        let config = parse_config("id:2 res:2560x1440").unwrap();
+        assert!(config_drift(&config, &rotated, &displays).is_empty());
+        // macdisp cannot rotate, so a rotation is never drift to put right
+        let config = parse_config("id:2 degree:90").unwrap();
+        assert!(config_drift(&config, &displays[1], &displays).is_empty());
+
+        // Either display of a mirror set may be the one reported as mirroring
+        let mut mirroring = display(1, "AAAA");
+        mirroring.mirror_of = Some(2);
+        let mirrored = vec![mirroring.clone(), display(2, "BBBB"), display(3, "CCCC")];
+        let wants_mirror = parse_config("id:2 mirror:1").unwrap();
+        assert!(config_drift(&wants_mirror, &mirrored[1], &mirrored).is_empty());
+        let wants_mirror = parse_config("id:1 mirror:2").unwrap();
+        assert!(config_drift(&wants_mirror, &mirroring, &mirrored).is_empty());
+        let wants_off = parse_config("id:2 mirror:off").unwrap();
+        assert_eq!(
+            config_drift(&wants_off, &mirrored[1], &mirrored),
+            vec!["mirror 1 (want off)"]
+        );
+        let wants_other = parse_config("id:3 mirror:1").unwrap();
+        assert_eq!(
+            config_drift(&wants_other, &mirrored[2], &mirrored),
+            vec!["mirror off (want 1)"]
+        );
+    }
+
+    #[test]
+    fn test_check_usable() {
+        let displays = vec![display(1, "AAAA"), display(2, "BBBB")];
+        let check = |configs: &[&str]| {
+            let configs: Vec<_> = configs.iter().map(|c| parse_config(c).unwrap()).collect();
+            check_usable(&configs, &displays, IdStrategy::Persistent)
+        };
+
+        assert!(check(&["id:1 enabled:false"]).is_ok());
+        assert!(check(&["id:2 mirror:1"]).is_ok());
+        assert!(check(&["id:1 enabled:false", "id:2 mirror:1"])
+            .unwrap_err()
+            .contains("main display"));
+        assert!(check(&["id:2 enabled:false", "id:1 mirror:2"]).is_err());
+        assert!(check(&["id:2 enabled:false", "id:1 mirror:2", "id:1 mirror:off"]).is_ok());
+    }
+
+    #[test]
+    fn test_skip_unsupported() {
+        let mut config = parse_config("id:5 res:1366x1024 origin:2560,0 mirror:1").unwrap();
+        assert!(skip_unsupported(&mut config, DisplayClass::Physical).is_empty());
+
+        let skipped = skip_unsupported(&mut config, DisplayClass::Sidecar);
+        assert_eq!(skipped, vec!["res", "origin"]);
+        assert_eq!(config.resolution, None);
+        assert_eq!(config.mirror.as_deref(), Some("1"));
+
+        let mut sidecar = display(5, "SIDECAR");
+        sidecar.display_type = "Sidecar display".to_string();
+        assert_eq!(DisplayClass::of(&sidecar), DisplayClass::Sidecar);
+    }
+
+    #[test]
+    fn test_key_filter() {
+        let config =
+            parse_config("id:1 res:1920x1080 hz:60 origin:(0,0) degree:90 wallpaper:~/clean.png")
+                .unwrap();
+        assert_eq!(config.wallpaper.as_deref(), Some("~/clean.png"));
+        for spaced in [
+            r#"id:1 wallpaper:"~/My Pictures/clean.png""#,
+            r#"id:1 "wallpaper:~/My Pictures/clean.png""#,
+            r"id:1 wallpaper:~/My\ Pictures/clean.png",
+        ] {
+            let config = parse_config_strict(spaced).unwrap();
+            assert_eq!(config.wallpaper.as_deref(), Some("~/My Pictures/clean.png"));
+        }
+        assert!(parse_config(r#"id:1 wallpaper:"~/clean.png"#).is_err());
+
+        let mut only = config.clone();
+        let filter = KeyFilter::Only(vec![ConfigKey::Origin, ConfigKey::Rotation]);
+        assert_eq!(filter.filter(&mut only), vec!["res", "hz", "wallpaper"]);
+        assert_eq!((only.origin, only.degree), (Some((0, 0)), Some(90)));
+
+        let mut skip = config.clone();
+        assert_eq!(
+            KeyFilter::Skip(vec![ConfigKey::Mode]).filter(&mut skip),
+            vec!["res", "hz"]
+        );
+        assert_eq!(skip.resolution, None);
+        assert_eq!(skip.origin, Some((0, 0)));
+
+        let mut all = config;
+        assert!(KeyFilter::All.filter(&mut all).is_empty());
+        assert!("refresh".parse::<ConfigKey>().is_err());
+    }
+
+    #[test]
+    fn test_looks_like() {
+        let mut displays = vec![display(1, "AAAA")];
+        let config = parse_config("id:1 looks-like:1280x720").unwrap();
+        assert_eq!(config.looks_like, Some((1280, 720)));
+
+        displays[0].width = 1280;
+        displays[0].height = 720;
+        assert_eq!(config_drift(&config, &displays[0], &displays).len(), 1);
+        displays[0].scaling = true;
+        assert!(config_drift(&config, &displays[0], &displays).is_empty());
+
+        let mut config = DisplayConfig::default();
+        set_key(&mut config, "looks-like", "1440x900").unwrap();
+        assert_eq!(config.looks_like, Some((1440, 900)));
+        assert!(set_key(&mut config, "looks-like", "1440").is_err());
+    }
+
+    #[test]
+    fn test_select_mode_depth() {
+        let mode = |mode_number, width, height, hz: f64, depth| DisplayMode {
+            refresh_rate: hz,
+            depth,
+            ..self::mode(mode_number, width, height, false)
+        };
+        let modes = vec![
+            mode(1, 2560, 1440, 144.0, 8),
+            mode(2, 2560, 1440, 120.0, 10),
+            mode(3, 2560, 1440, 60.0, 10),
+            mode(4, 1920, 1080, 144.0, 10),
+            mode(5, 1920, 1080, 144.0, 8),
+            mode(6, 1920, 1080, 59.94, 8),
+            mode(7, 1920, 1080, 60.0, 8),
+        ];
+        let current = &modes[0];
+        let select = |config_str: &str| {
+            let config = parse_config(config_str).unwrap();
+            select_mode(1, &config, &modes, current, 0, None)
+                .map(|choice| (choice.mode.mode_number, choice.warnings.len()))
+        };
+
+        // A bare depth keeps the size and takes the closest rate, with a warning
+        assert_eq!(select("id:1 color_depth:10"), Ok((2, 1)));
+        assert_eq!(select("id:1 color_depth:10 hz:60"), Ok((3, 0)));
+        assert!(select("id:1 color_depth:10 hz:144")
+            .unwrap_err()
+            .contains("offered at 120Hz, 60Hz"));
+        // Without a depth, a resolution change keeps the current one
+        assert_eq!(select("id:1 res:1920x1080"), Ok((5, 0)));
+        // Both rates are within the tolerance of either; the closer one wins
+        assert_eq!(select("id:1 res:1920x1080 hz:59.94"), Ok((6, 0)));
+        assert_eq!(select("id:1 res:1920x1080 hz:60"), Ok((7, 0)));
+    }
+
+    #[test]
+    fn test_find_display_by_strategy() {
+        let mut displays = vec![display(1, "AAAA"), display(2, "BBBB")];
+        displays[0].serial = 2;
+        displays[1].serial = 90210;
+
+        assert_eq!(find_display("s90210", &displays).unwrap().id, 2);
+        assert_eq!(find_display("2", &displays).unwrap().id, 2);
+        assert!(find_display("s0", &displays).is_none());
+
+        // Under the serial strategy bare numbers are serials first
+        assert_eq!(
+            find_display_by("2", &displays, IdStrategy::Serial)
+                .unwrap()
+                .id,
+            1
+        );
+        assert_eq!(
+            find_display_by("90210", &displays, IdStrategy::Serial)
+                .unwrap()
+                .id,
+            2
+        );
+        assert_eq!(IdStrategy::Serial.id_of(&displays[1]), "s90210");
+        assert_eq!(IdStrategy::Contextual.id_of(&displays[1]), "2");
+    }
+
+    #[test]
+    fn test_display_diff() {
+        let info = display(2, "BBBB");
+        let uhd = DisplayMode {
+            refresh_rate: 120.0,
+            ..mode(11, 3840, 2160, false)
+        };
+        let config = parse_config("id:2 res:3840x2160 hz:120 origin:1512,0").unwrap();
+        let diff = DisplayDiff::between(&info, &config, 0, Some(&uhd));
+        assert_eq!(
+            diff.to_string(),
+            "Display 2: 2560x1440@60 → 3840x2160@120, origin (0,0) → (1512,0), rotation unchanged"
+        );
+
+        let config = parse_config("id:2 degree:90 mirror:1 enabled:true").unwrap();
+        let diff = DisplayDiff::between(&info, &config, 90, None);
+        assert_eq!(diff.mode, None);
+        assert_eq!(diff.enabled, None);
+        assert_eq!(
+            diff.to_string(),
+            "Display 2: mode unchanged, origin unchanged, rotation 0° → 90°, mirroring off → display 1"
+        );
+    }
+
+    #[test]
+    fn test_explain_configuration() {
+        let mut right = display(2, "BBBB");
+        right.x = 2560;
+        let all_info = vec![display(1, "AAAA"), right];
+        let fhd = mode(20, 1920, 1080, false);
+        let options = ApplyOptions::default();
+        let explain = |config: &str| {
+            let mut report = Report {
+                quiet: true,
+                warnings: Vec::new(),
+            };
+            let config = parse_config(config).unwrap();
+            let plan = plan_display(config, &all_info, &options, &mut report).unwrap();
+            let mut changes = Vec::new();
+            describe_pending(
+                plan.pending.as_ref().unwrap(),
+                &all_info[1],
+                &all_info,
+                std::slice::from_ref(&fhd),
+                &options,
+                &mut changes,
+                &mut report,
+            );
+            (changes, report.warnings.len())
+        };
+
+        // The mode that would be set; the rotation is refused with a warning
+        let (changes, warnings) = explain("id:2 mode:20 degree:90");
+        assert_eq!(changes, ["switch to 1920x1080 @ 60Hz native (mode 20)"]);
+        assert_eq!(warnings, 1);
+        // A mode apply would fail to find is a warning, not a change
+        let (changes, warnings) = explain("id:2 mode:21 origin:(-2560,0)");
+        assert_eq!(changes, ["move to (-2560,0) left of display 1"]);
+        assert_eq!(warnings, 1);
+    }
+}
@@ -0,0 +1,107 @@
+//! Brightness of any display: DisplayServices for built-in and Apple
+//! displays, DDC/CI for other monitors.
+
+use crate::ddc::{self, VcpCache, VcpValue};
+use crate::error::{DisplayError, ErrorKind};
+
+/// VCP code of the luminance control.
+const VCP_BRIGHTNESS: u8 = 0x10;
+/// Scale assumed when a monitor's maximum cannot be read; MCCS suggests 100.
+const DEFAULT_VCP_MAXIMUM: u16 = 100;
+
+/// A display's brightness between 0.0 and 1.0.
+///
+/// Displays DisplayServices cannot read are read over DDC/CI, which takes
+/// tens of milliseconds; [`crate::ipc::cached_brightness`] asks the daemon's
+/// cache instead.
+pub fn get_brightness(display_id: u32) -> Result<f32, DisplayError> {
+    get_brightness_with(display_id, ddc::get_vcp)
+}
+
+/// [`get_brightness`] with the DDC/CI read left to `read_vcp`.
+pub(crate) fn get_brightness_with(
+    display_id: u32,
+    read_vcp: impl FnOnce(u32, u8) -> Result<VcpValue, DisplayError>,
+) -> Result<f32, DisplayError> {
+    match crate::get_brightness(display_id) {
+        Err(e) if e.kind == ErrorKind::NotSupported => {
+            read_vcp(display_id, VCP_BRIGHTNESS).map(vcp_brightness)
+        }
+        result => result,
+    }
+}
+
+/// Set a display's brightness between 0.0 and 1.0.
+///
+/// Displays DisplayServices cannot control are set over DDC/CI, scaled to the
+/// maximum the monitor reports.
+pub fn set_brightness(display_id: u32, brightness: f32) -> Result<(), DisplayError> {
+    match crate::set_brightness(display_id, brightness) {
+        Err(e) if e.kind == ErrorKind::NotSupported => {
+            let maximum = ddc::get_vcp(display_id, VCP_BRIGHTNESS)
+                .map(|value| value.maximum)
+                .unwrap_or(DEFAULT_VCP_MAXIMUM);
+            ddc::set_vcp(display_id, VCP_BRIGHTNESS, vcp_level(brightness, maximum))
+        }
+        result => result,
+    }
+}
+
+/// [`set_brightness`] with the DDC/CI maximum taken from `vcp`, which is
+/// then told the value written, so repeated changes read the monitor once.
+pub(crate) fn set_brightness_cached(
+    display_id: u32,
+    brightness: f32,
+    vcp: &VcpCache,
+) -> Result<(), DisplayError> {
+    match crate::set_brightness(display_id, brightness) {
+        Err(e) if e.kind == ErrorKind::NotSupported => {
+            let maximum = vcp
+                .get(display_id, VCP_BRIGHTNESS)
+                .map(|value| value.maximum)
+                .unwrap_or(DEFAULT_VCP_MAXIMUM);
+            let level = vcp_level(brightness, maximum);
+            match ddc::set_vcp(display_id, VCP_BRIGHTNESS, level) {
+                Ok(()) => {
+                    vcp.written(display_id, VCP_BRIGHTNESS, level);
+                    Ok(())
+                }
+                Err(e) => {
+                    vcp.forget(display_id);
+                    Err(e)
+                }
+            }
+        }
+        result => result,
+    }
+}
+
+/// The brightness a VCP value stands for.
+fn vcp_brightness(value: VcpValue) -> f32 {
+    if value.maximum == 0 {
+        return 0.0;
+    }
+    (value.current as f32 / value.maximum as f32).clamp(0.0, 1.0)
+}
+
+/// The VCP value for a brightness on a control that goes up to `maximum`.
+fn vcp_level(brightness: f32, maximum: u16) -> u16 {
+    (brightness.clamp(0.0, 1.0) * maximum as f32).round() as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vcp_level() {
+        assert_eq!(vcp_level(0.7, 100), 70);
+        assert_eq!(vcp_level(0.5, 255), 128);
+        assert_eq!(vcp_level(1.5, 100), 100);
+
+        let value = |current, maximum| VcpValue { current, maximum };
+        assert_eq!(vcp_brightness(value(70, 100)), 0.7);
+        assert_eq!(vcp_brightness(value(128, 0)), 0.0);
+        assert_eq!(vcp_brightness(value(300, 255)), 1.0);
+    }
+}
@@ -0,0 +1,310 @@
+//! Profiles packaged with what another Mac needs to use them, so a team can
+//! hand out the standard layout of a shared desk as one file.
+//!
+//! Persistent display ids are made up by each Mac, so a bundle also records
+//! the vendor, model and serial number of every display its profile names.
+//! On import, ids that are not connected are mapped to the connected display
+//! with the same fingerprint.
+
+use crate::apply::parse_config;
+use crate::config::Config;
+use crate::template::variable_names;
+use crate::DisplayInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Version of the bundle file layout.
+pub const BUNDLE_FORMAT: u32 = 1;
+
+/// What identifies a display across Macs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub vendor: u32,
+    pub model: u32,
+    /// 0 when the display reports no serial number
+    pub serial: u32,
+}
+
+impl Fingerprint {
+    pub fn of(info: &DisplayInfo) -> Fingerprint {
+        let (vendor, model) = crate::quirks::display_vendor_model(info.id);
+        Fingerprint {
+            vendor,
+            model,
+            serial: info.serial,
+        }
+    }
+
+    pub(crate) fn same_model(&self, other: &Fingerprint) -> bool {
+        self.vendor == other.vendor && self.model == other.model
+    }
+}
+
+/// A display named by the bundled profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleDisplay {
+    /// The persistent id the profile uses
+    pub id: String,
+    /// `None` when the display was not connected at export
+    pub fingerprint: Option<Fingerprint>,
+}
+
+/// A profile with the aliases, variables and display fingerprints it uses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileBundle {
+    pub format: u32,
+    pub name: String,
+    /// Notes for whoever imports the bundle
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<String>,
+    pub configs: Vec<String>,
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    #[serde(default)]
+    pub displays: Vec<BundleDisplay>,
+}
+
+/// A bundle's profile rewritten for the displays of this Mac.
+#[derive(Debug, Default)]
+pub struct LocalProfile {
+    pub configs: Vec<String>,
+    pub aliases: BTreeMap<String, String>,
+    pub variables: BTreeMap<String, String>,
+    /// Displays that could not be matched or were matched by model only
+    pub warnings: Vec<String>,
+}
+
+/// Ids in the `id:` and `mirror:` keys of a configuration string.
+pub(crate) fn referenced_ids(config_str: &str) -> Vec<String> {
+    parse_config(config_str)
+        .map(|config| {
+            std::iter::once(config.id)
+                .chain(config.mirror)
+                .filter(|id| !matches!(id.as_str(), "off" | "none") && !id.contains("${"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Replace ids in the `id:` and `mirror:` keys of a configuration string.
+pub(crate) fn rewrite_ids(config_str: &str, ids: &BTreeMap<String, String>) -> String {
+    config_str
+        .split_whitespace()
+        .map(|part| match part.split_once(':') {
+            Some((key @ ("id" | "mirror"), value)) => match ids.get(value) {
+                Some(local) => format!("{}:{}", key, local),
+                None => part.to_string(),
+            },
+            _ => part.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl ProfileBundle {
+    /// Package profile `name`, fingerprinting the displays it names among
+    /// `connected`.
+    pub fn export(
+        config: &Config,
+        name: &str,
+        comments: Vec<String>,
+        connected: &[(DisplayInfo, Fingerprint)],
+    ) -> Result<ProfileBundle, String> {
+        let configs = config
+            .profiles
+            .get(name)
+            .ok_or_else(|| format!("Profile {} not found", name))?
+            .clone();
+        if let Some(parent) = configs
+            .iter()
+            .find_map(|s| crate::daemon::extended_profile(s))
+        {
+            return Err(format!(
+                "Profile {} extends {}; a bundle carries one profile, so export one that does not extend another",
+                name, parent
+            ));
+        }
+
+        let mut aliases = BTreeMap::new();
+        let mut variables = BTreeMap::new();
+        let mut displays: Vec<BundleDisplay> = Vec::new();
+        for config_str in &configs {
+            for variable in variable_names(config_str) {
+                if let Some(value) = config.variables.get(variable) {
+                    variables.insert(variable.to_string(), value.clone());
+                }
+            }
+            for id in referenced_ids(config_str) {
+                if let Some(target) = config.aliases.get(&id) {
+                    aliases.insert(id.clone(), target.clone());
+                }
+                let id = config.resolve_alias(&id).to_string();
+                if displays.iter().any(|d| d.id == id) {
+                    continue;
+                }
+                let info: Vec<DisplayInfo> = connected.iter().map(|(i, _)| i.clone()).collect();
+                let fingerprint = crate::apply::find_display(&id, &info).and_then(|found| {
+                    connected
+                        .iter()
+                        .find(|(i, _)| i.id == found.id)
+                        .map(|(_, fingerprint)| *fingerprint)
+                });
+                displays.push(BundleDisplay { id, fingerprint });
+            }
+        }
+
+        Ok(ProfileBundle {
+            format: BUNDLE_FORMAT,
+            name: name.to_string(),
+            comments,
+            configs,
+            aliases,
+            variables,
+            displays,
+        })
+    }
+
+    /// Map the bundle's displays onto `connected` and rewrite the profile and
+    /// aliases to use their local persistent ids.
+    ///
+    /// A display keeps its id when it is connected under it. Otherwise it is
+    /// matched by vendor, model and serial number, then by vendor and model
+    /// when only one connected display is that model.
+    pub fn localize(&self, connected: &[(DisplayInfo, Fingerprint)]) -> LocalProfile {
+        let mut local = LocalProfile::default();
+        let mut ids = BTreeMap::new();
+        for display in &self.displays {
+            if connected
+                .iter()
+                .any(|(info, _)| info.persistent_id.eq_ignore_ascii_case(&display.id))
+            {
+                continue;
+            }
+            let Some(fingerprint) = display.fingerprint else {
+                local.warnings.push(format!(
+                    "Display {} has no fingerprint in the bundle and is not connected",
+                    display.id
+                ));
+                continue;
+            };
+
+            let exact = connected.iter().find(|(_, f)| *f == fingerprint);
+            let same_model: Vec<_> = connected
+                .iter()
+                .filter(|(_, f)| f.same_model(&fingerprint))
+                .collect();
+            let found = match (exact, same_model.as_slice()) {
+                (Some((info, _)), _) => Some(info),
+                (None, [(info, _)]) => {
+                    local.warnings.push(format!(
+                        "Display {} matched display {} by model only; its serial number differs",
+                        display.id, info.id
+                    ));
+                    Some(info)
+                }
+                _ => None,
+            };
+            match found {
+                Some(info) => {
+                    ids.insert(display.id.clone(), info.persistent_id.clone());
+                }
+                None => local.warnings.push(format!(
+                    "No connected display matches {} (vendor {:04x}, model {:04x}, serial {})",
+                    display.id, fingerprint.vendor, fingerprint.model, fingerprint.serial
+                )),
+            }
+        }
+
+        local.configs = self
+            .configs
+            .iter()
+            .map(|config_str| rewrite_ids(config_str, &ids))
+            .collect();
+        local.aliases = self
+            .aliases
+            .iter()
+            .map(|(alias, id)| (alias.clone(), ids.get(id).unwrap_or(id).clone()))
+            .collect();
+        local.variables = self.variables.clone();
+        local
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize bundle: {}", e))?;
+        std::fs::write(path, json + "\n")
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<ProfileBundle, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let bundle: ProfileBundle = serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid profile bundle {}: {}", path.display(), e))?;
+        if bundle.format > BUNDLE_FORMAT {
+            return Err(format!(
+                "{} is a newer bundle format ({}); upgrade macdisp to import it",
+                path.display(),
+                bundle.format
+            ));
+        }
+        Ok(bundle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    fn fingerprint(model: u32, serial: u32) -> Fingerprint {
+        Fingerprint {
+            vendor: 0x10ac,
+            model,
+            serial,
+        }
+    }
+
+    #[test]
+    fn test_export_and_localize() {
+        let mut config = Config::default();
+        config
+            .aliases
+            .insert("left".to_string(), "AAAA".to_string());
+        config.variables.insert("HZ".to_string(), "60".to_string());
+        config.profiles.insert(
+            "desk".to_string(),
+            vec![
+                "id:left res:2560x1440 hz:${HZ}".to_string(),
+                "id:BBBB mirror:left".to_string(),
+            ],
+        );
+        let desk = vec![
+            (display(1, "AAAA"), fingerprint(0xa0c3, 111)),
+            (display(2, "BBBB"), fingerprint(0xa0c4, 222)),
+        ];
+
+        let bundle = ProfileBundle::export(&config, "desk", vec![], &desk).unwrap();
+        assert_eq!(bundle.aliases["left"], "AAAA");
+        assert_eq!(bundle.variables["HZ"], "60");
+        assert_eq!(bundle.displays.len(), 2);
+        assert_eq!(
+            bundle.displays[0].fingerprint,
+            Some(fingerprint(0xa0c3, 111))
+        );
+
+        // Another Mac: same first monitor, a different unit of the second
+        let other = vec![
+            (display(5, "CCCC"), fingerprint(0xa0c3, 111)),
+            (display(6, "DDDD"), fingerprint(0xa0c4, 999)),
+        ];
+        let local = bundle.localize(&other);
+        assert_eq!(local.aliases["left"], "CCCC");
+        assert_eq!(local.configs[1], "id:DDDD mirror:left");
+        assert_eq!(local.warnings.len(), 1);
+        assert!(local.warnings[0].contains("model only"));
+    }
+}
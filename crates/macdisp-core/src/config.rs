@@ -0,0 +1,572 @@
+use crate::usb::UsbId;
+use crate::DisplayInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+fn default_debounce_ms() -> u64 {
+    500
+}
+
+fn default_call_timeout_ms() -> u64 {
+    crate::DEFAULT_CALL_TIMEOUT.as_millis() as u64
+}
+
+fn default_mode_set_interval_ms() -> u64 {
+    crate::DEFAULT_MODE_SET_INTERVAL.as_millis() as u64
+}
+
+fn default_hz_tolerance() -> f64 {
+    crate::mode_id::DEFAULT_HZ_TOLERANCE
+}
+
+fn default_battery_max_hz() -> f64 {
+    60.0
+}
+
+/// Apply a profile when a set of displays or USB devices is connected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    /// Persistent ids that must all be connected for the rule to match
+    #[serde(default)]
+    pub when_connected: Vec<String>,
+    /// USB devices (`VID:PID`) that must all be present for the rule to match
+    #[serde(default)]
+    pub when_usb: Vec<UsbId>,
+    /// Name of the profile to apply
+    pub profile: String,
+}
+
+/// Lower refresh rates while running on battery.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PowerPolicy {
+    /// Highest refresh rate allowed on battery
+    #[serde(default = "default_battery_max_hz")]
+    pub battery_max_hz: f64,
+    /// Displays the policy applies to (ids or aliases); all displays when empty
+    #[serde(default)]
+    pub displays: Vec<String>,
+}
+
+/// Shell commands run around applying a profile.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ProfileHooks {
+    /// Run before the profile is applied; a failure cancels the apply
+    #[serde(default)]
+    pub pre_apply: Option<String>,
+    /// Run after the profile is applied, even when nothing changed
+    #[serde(default)]
+    pub post_apply: Option<String>,
+}
+
+/// Profiles, rules and aliases that only apply on one Mac.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MachineConfig {
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub profile_hooks: BTreeMap<String, ProfileHooks>,
+    /// Evaluated before the shared rules
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+}
+
+/// Settings read from `config.json` in the macdisp config directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Config {
+    /// Quiet period that ends a burst of reconfiguration events
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
+    /// Named lists of configuration strings
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Vec<String>>,
+    /// Commands run before and after applying a profile, by profile name
+    #[serde(default)]
+    pub profile_hooks: BTreeMap<String, ProfileHooks>,
+    /// Rules evaluated by the daemon, first match wins
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+    /// Profile the daemon pins displays to, reverting any external change
+    #[serde(default)]
+    pub enforce: Option<String>,
+    /// Friendly names for persistent display ids
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+    /// Named sets of displays (ids or aliases), selected as `@name`
+    #[serde(default)]
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// Values for `${NAME}` in profiles, next to the built-in display variables
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    /// Color temperature in kelvin the daemon holds, by display or @group
+    #[serde(default)]
+    pub temperature: BTreeMap<String, u32>,
+    /// Shell commands run by the daemon for every coalesced event
+    #[serde(default)]
+    pub hooks: Vec<String>,
+    /// Refresh rate policy applied by the daemon on battery
+    #[serde(default)]
+    pub power: Option<PowerPolicy>,
+    /// Per-machine sections keyed by hardware UUID or host name
+    #[serde(default)]
+    pub machines: BTreeMap<String, MachineConfig>,
+    /// Profile used when none is named: `profile apply` without a name, and
+    /// the daemon when no rule matches
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Output format for commands that can print JSON
+    #[serde(default)]
+    pub format: OutputFormat,
+    /// Refresh rate preferred when a configuration sets `res:` without `hz:`
+    #[serde(default)]
+    pub default_hz: Option<f64>,
+    /// How far an `hz:` value may be from a mode's refresh rate and still match it
+    #[serde(default = "default_hz_tolerance")]
+    pub hz_tolerance: f64,
+    /// Watchdog limit for a single display call; 0 disables it
+    #[serde(default = "default_call_timeout_ms")]
+    pub call_timeout_ms: u64,
+    /// Minimum time between two mode switches of one display; 0 disables it
+    #[serde(default = "default_mode_set_interval_ms")]
+    pub mode_set_interval_ms: u64,
+    /// Pause between the changes of two displays in one apply; 0 changes them back to back
+    #[serde(default)]
+    pub stagger_ms: u64,
+    /// Identifier written into generated commands and preferred when resolving ids
+    #[serde(default)]
+    pub id_strategy: IdStrategy,
+    /// Leave gamma and brightness alone while another agent such as Lunar or
+    /// BetterDisplay holds them
+    #[serde(default)]
+    pub defer_gamma: bool,
+    /// Have the daemon send the brightness keys to the external display
+    /// under the pointer
+    #[serde(default)]
+    pub brightness_keys: bool,
+    /// The `machines` key merged into this config, if any
+    #[serde(skip)]
+    pub machine: Option<String>,
+}
+
+/// Output format for commands that can print JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn is_json(self) -> bool {
+        self == OutputFormat::Json
+    }
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Invalid format: {} (expected text or json)", s)),
+        }
+    }
+}
+
+/// Which display identifier generated commands use and bare numbers mean.
+///
+/// Persistent ids survive reboots but change on some monitors; serial ids
+/// (`s12345`) are stable for monitors that report a serial number.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdStrategy {
+    #[default]
+    Persistent,
+    Contextual,
+    Serial,
+}
+
+impl IdStrategy {
+    /// The id of `info` under this strategy. Displays without a serial number
+    /// fall back to their persistent id, since `s0` is not unique.
+    pub fn id_of(self, info: &DisplayInfo) -> String {
+        match self {
+            IdStrategy::Persistent => info.persistent_id.clone(),
+            IdStrategy::Contextual => info.contextual_id.to_string(),
+            IdStrategy::Serial if info.serial != 0 => format!("s{}", info.serial),
+            IdStrategy::Serial => info.persistent_id.clone(),
+        }
+    }
+}
+
+impl std::str::FromStr for IdStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "persistent" => Ok(IdStrategy::Persistent),
+            "contextual" => Ok(IdStrategy::Contextual),
+            "serial" => Ok(IdStrategy::Serial),
+            _ => Err(format!(
+                "Invalid id strategy: {} (expected persistent, contextual or serial)",
+                s
+            )),
+        }
+    }
+}
+
+/// Ids written into the command `list` suggests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandIds {
+    Strategy(IdStrategy),
+    /// A display's alias where it has one, otherwise its id under the strategy
+    Aliases(BTreeMap<String, String>, IdStrategy),
+}
+
+impl CommandIds {
+    pub fn id_of(&self, info: &DisplayInfo) -> String {
+        match self {
+            CommandIds::Strategy(strategy) => strategy.id_of(info),
+            CommandIds::Aliases(aliases, strategy) => alias_of(aliases, info)
+                .map(str::to_string)
+                .unwrap_or_else(|| strategy.id_of(info)),
+        }
+    }
+}
+
+/// The first alias naming `info` by persistent id or serial id.
+pub fn alias_of<'a>(aliases: &'a BTreeMap<String, String>, info: &DisplayInfo) -> Option<&'a str> {
+    let serial = format!("s{}", info.serial);
+    aliases
+        .iter()
+        .find(|(_, id)| {
+            id.eq_ignore_ascii_case(&info.persistent_id) || (info.serial != 0 && **id == serial)
+        })
+        .map(|(alias, _)| alias.as_str())
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            debounce_ms: default_debounce_ms(),
+            profiles: BTreeMap::new(),
+            profile_hooks: BTreeMap::new(),
+            rules: Vec::new(),
+            enforce: None,
+            aliases: BTreeMap::new(),
+            groups: BTreeMap::new(),
+            variables: BTreeMap::new(),
+            temperature: BTreeMap::new(),
+            hooks: Vec::new(),
+            power: None,
+            machines: BTreeMap::new(),
+            profile: None,
+            format: OutputFormat::Text,
+            default_hz: None,
+            hz_tolerance: default_hz_tolerance(),
+            call_timeout_ms: default_call_timeout_ms(),
+            mode_set_interval_ms: default_mode_set_interval_ms(),
+            stagger_ms: 0,
+            id_strategy: IdStrategy::Persistent,
+            defer_gamma: false,
+            brightness_keys: false,
+            machine: None,
+        }
+    }
+}
+
+impl Config {
+    /// Install the configured watchdog limit for display calls.
+    pub fn apply_call_timeout(&self) {
+        crate::set_call_timeout(std::time::Duration::from_millis(self.call_timeout_ms));
+    }
+
+    /// Install the configured minimum interval between mode switches.
+    pub fn apply_mode_set_interval(&self) {
+        crate::set_mode_set_interval(std::time::Duration::from_millis(self.mode_set_interval_ms));
+    }
+
+    /// Install the configured refresh rate matching tolerance.
+    pub fn apply_hz_tolerance(&self) {
+        crate::mode_id::set_hz_tolerance(self.hz_tolerance);
+    }
+
+    /// Load the config file, falling back to defaults when it does not exist.
+    ///
+    /// The section of `machines` matching this Mac is merged in.
+    pub fn load() -> Result<Config, String> {
+        let path = config_path();
+        if !path.exists() {
+            return Ok(Config::default());
+        }
+        let mut config = Config::load_from(&path)?;
+        config.machine = config.apply_machine_scope(&machine_keys());
+        Ok(config)
+    }
+
+    /// Merge the first `machines` section matching one of `keys` into the
+    /// shared settings. Machine profiles, profile hooks, aliases and
+    /// variables replace shared ones of the same name and machine rules take
+    /// precedence over shared rules.
+    pub fn apply_machine_scope(&mut self, keys: &[String]) -> Option<String> {
+        let key = self
+            .machines
+            .keys()
+            .find(|name| keys.iter().any(|k| k.eq_ignore_ascii_case(name)))?
+            .clone();
+        let machine = self.machines[&key].clone();
+
+        self.profiles.extend(machine.profiles);
+        self.profile_hooks.extend(machine.profile_hooks);
+        self.aliases.extend(machine.aliases);
+        self.variables.extend(machine.variables);
+        let mut rules = machine.rules;
+        rules.append(&mut self.rules);
+        self.rules = rules;
+
+        Some(key)
+    }
+
+    /// Map an alias to its persistent id; other ids are returned unchanged.
+    pub fn resolve_alias<'a>(&'a self, id: &'a str) -> &'a str {
+        self.aliases.get(id).map(String::as_str).unwrap_or(id)
+    }
+
+    /// The profile named by `macdisp <name>`: a single argument that is not a
+    /// configuration string but is the name of a profile.
+    pub fn profile_shorthand<'a>(&self, args: &'a [String]) -> Option<&'a str> {
+        match args {
+            [name] if crate::apply::parse_config(name).is_err() => {
+                self.profiles.contains_key(name).then_some(name.as_str())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn load_from(path: &std::path::Path) -> Result<Config, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+}
+
+/// Keys identifying this Mac in the `machines` section: the hardware UUID
+/// followed by the host name.
+pub fn machine_keys() -> Vec<String> {
+    crate::get_hardware_uuid()
+        .into_iter()
+        .chain(crate::get_hostname())
+        .collect()
+}
+
+/// Directory holding macdisp's configuration; see [`crate::settings::config_dir`].
+pub fn config_dir() -> PathBuf {
+    crate::settings::config_dir()
+}
+
+pub fn config_path() -> PathBuf {
+    config_dir().join("config.json")
+}
+
+/// The section of a parsed config file that `machine` names, or the top
+/// level when it is `None`.
+fn section_mut<'a>(
+    file: &'a mut serde_json::Value,
+    machine: Option<&str>,
+) -> Result<&'a mut serde_json::Map<String, serde_json::Value>, String> {
+    let mut section = file;
+    if let Some(machine) = machine {
+        section = section
+            .get_mut("machines")
+            .and_then(|machines| machines.get_mut(machine))
+            .ok_or_else(|| format!("Machine section {} not found", machine))?;
+    }
+    section
+        .as_object_mut()
+        .ok_or_else(|| "The config file is not a JSON object".to_string())
+}
+
+/// The object under `key` in a config section, created when missing.
+fn object_mut<'a>(
+    section: &'a mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+) -> Result<&'a mut serde_json::Map<String, serde_json::Value>, String> {
+    section
+        .entry(key)
+        .or_insert_with(|| serde_json::Value::Object(Default::default()))
+        .as_object_mut()
+        .ok_or_else(|| format!("\"{}\" in the config file is not an object", key))
+}
+
+/// Set profile `name` in a parsed config file, inside the `machines` section
+/// `machine` when given.
+fn set_profile(
+    file: &mut serde_json::Value,
+    machine: Option<&str>,
+    name: &str,
+    configs: &[String],
+) -> Result<(), String> {
+    let section = section_mut(file, machine)?;
+    object_mut(section, "profiles")?.insert(name.to_string(), serde_json::json!(configs));
+    Ok(())
+}
+
+/// Add `entries` to the object under `key` (`aliases` or `variables`) in a
+/// parsed config file, replacing entries with the same name.
+fn set_entries(
+    file: &mut serde_json::Value,
+    machine: Option<&str>,
+    key: &str,
+    entries: &BTreeMap<String, String>,
+) -> Result<(), String> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let object = object_mut(section_mut(file, machine)?, key)?;
+    for (name, value) in entries {
+        object.insert(name.clone(), serde_json::json!(value));
+    }
+    Ok(())
+}
+
+/// Replace profile `name` in the config file, inside the `machines` section
+/// `machine` when given, keeping every other setting. `aliases` and
+/// `variables` are added next to it.
+///
+/// The previous file is kept as `config.json.bak`. The file is rewritten
+/// with its keys sorted.
+pub fn save_profile(
+    machine: Option<&str>,
+    name: &str,
+    configs: &[String],
+    aliases: &BTreeMap<String, String>,
+    variables: &BTreeMap<String, String>,
+) -> Result<(), String> {
+    let path = config_path();
+    let mut file = if path.exists() {
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid config {}: {}", path.display(), e))?
+    } else {
+        serde_json::json!({})
+    };
+    set_profile(&mut file, machine, name, configs)?;
+    set_entries(&mut file, machine, "aliases", aliases)?;
+    set_entries(&mut file, machine, "variables", variables)?;
+    if path.exists() {
+        let backup = path.with_extension("json.bak");
+        std::fs::copy(&path, &backup)
+            .map_err(|e| format!("Failed to back up {}: {}", path.display(), e))?;
+    } else {
+        std::fs::create_dir_all(config_dir())
+            .map_err(|e| format!("Failed to create {}: {}", config_dir().display(), e))?;
+    }
+
+    // Written next to the config and renamed, so a failure leaves it intact
+    let contents = serde_json::to_string_pretty(&file)
+        .map_err(|e| format!("Failed to serialize config: {}", e))?;
+    let temp = path.with_extension("json.tmp");
+    std::fs::write(&temp, contents + "\n")
+        .and_then(|_| std::fs::rename(&temp, &path))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_machine_scope() {
+        let mut config: Config = serde_json::from_str(
+            r#"{
+                "profiles": { "desk": ["id:1 origin:(0,0)"], "travel": ["id:1"] },
+                "rules": [{ "when_connected": ["A"], "profile": "desk" }],
+                "machines": {
+                    "Studio": {
+                        "profiles": { "desk": ["id:2 origin:(0,0)"] },
+                        "rules": [{ "when_connected": ["B"], "profile": "desk" }]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let scope = config.apply_machine_scope(&["MBP".to_string(), "studio".to_string()]);
+        assert_eq!(scope.as_deref(), Some("Studio"));
+        assert_eq!(config.profiles["desk"], vec!["id:2 origin:(0,0)"]);
+        assert!(config.profiles.contains_key("travel"));
+        assert_eq!(config.rules[0].when_connected, vec!["B"]);
+        assert_eq!(config.rules.len(), 2);
+    }
+
+    #[test]
+    fn test_profile_shorthand() {
+        let config: Config = serde_json::from_str(
+            r#"{ "profiles": { "desk": ["id:1 origin:(0,0)"], "travel": ["id:1"] } }"#,
+        )
+        .unwrap();
+
+        let args = |args: &[&str]| args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(config.profile_shorthand(&args(&["desk"])), Some("desk"));
+        assert_eq!(config.profile_shorthand(&args(&["home"])), None);
+        assert_eq!(config.profile_shorthand(&args(&["desk", "travel"])), None);
+        assert_eq!(config.profile_shorthand(&args(&["id:1"])), None);
+    }
+
+    #[test]
+    fn test_set_profile() {
+        let mut file = serde_json::json!({
+            "debounce_ms": 250,
+            "machines": { "Studio": { "aliases": {} } }
+        });
+        let configs = vec!["id:1 res:1920x1080".to_string()];
+
+        set_profile(&mut file, None, "desk", &configs).unwrap();
+        set_profile(&mut file, Some("Studio"), "desk", &configs[..0]).unwrap();
+        assert_eq!(file["profiles"]["desk"][0], "id:1 res:1920x1080");
+        assert_eq!(file["debounce_ms"], 250);
+        assert_eq!(
+            file["machines"]["Studio"]["profiles"]["desk"],
+            serde_json::json!([])
+        );
+        assert!(set_profile(&mut file, Some("Laptop"), "desk", &configs).is_err());
+
+        let aliases = BTreeMap::from([("left".to_string(), "AAAA".to_string())]);
+        set_entries(&mut file, Some("Studio"), "aliases", &aliases).unwrap();
+        assert_eq!(file["machines"]["Studio"]["aliases"]["left"], "AAAA");
+    }
+
+    #[test]
+    fn test_command_ids() {
+        let mut left = crate::apply::tests::display(1, "AAAA");
+        left.serial = 42;
+        let right = crate::apply::tests::display(2, "BBBB");
+        let aliases = BTreeMap::from([
+            ("left".to_string(), "s42".to_string()),
+            ("studio".to_string(), "bbbb".to_string()),
+        ]);
+
+        let ids = CommandIds::Aliases(aliases.clone(), IdStrategy::Persistent);
+        assert_eq!(ids.id_of(&left), "left");
+        assert_eq!(ids.id_of(&right), "studio");
+
+        let unnamed = crate::apply::tests::display(3, "CCCC");
+        let ids = CommandIds::Aliases(aliases, IdStrategy::Contextual);
+        assert_eq!(ids.id_of(&unnamed), unnamed.contextual_id.to_string());
+    }
+
+    #[test]
+    fn test_defaults_for_missing_keys() {
+        let config: Config = serde_json::from_str("{}").unwrap();
+        assert_eq!(config.debounce_ms, 500);
+        assert!(config.profiles.is_empty());
+    }
+}
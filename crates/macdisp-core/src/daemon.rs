@@ -0,0 +1,1372 @@
+use crate::agents::{deferred_to, running_agents, Subsystem};
+use crate::apply::{
+    apply_configuration, config_drift, find_display, overlay_config, parse_config,
+    parse_config_strict, ApplyOptions, ApplyResult, ApplyWarning,
+};
+use crate::config::{Config, Rule};
+use crate::ddc::VcpCache;
+use crate::devices::{devices_path, record_connected, DeviceDb};
+use crate::events::{watch, DisplayEvent, DisplayEventKind};
+use crate::export::format_utc_timestamp;
+use crate::gamma::{self, configured_temperatures};
+use crate::geometry::PointPx;
+use crate::hooks::{run_event_hook, run_profile_hook, HookStage};
+use crate::ipc::{self, StateCache};
+use crate::keys::route_brightness_keys;
+use crate::lock::ApplyLock;
+use crate::mode_id::{find_mode, format_hz, ModeId};
+use crate::power::{battery_mode, current_power_source, watch_power_source, PowerSource};
+use crate::selector::is_builtin;
+use crate::session::{self, watch_session, Session, SessionState};
+use crate::settings::{self, ConfigOverrides};
+use crate::template::{expand, is_display_variable, resolve_variables, variable_names};
+use crate::usb::{watch_usb, UsbEvent, UsbId};
+use crate::{
+    get_all_display_info, get_all_modes, get_current_mode, is_notch_hidden, notch_family,
+    notch_target, set_display_mode, DisplayConfig, DisplayInfo,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+pub(crate) fn log(message: &str) {
+    crate::output::info(&format!(
+        "[{}] {}",
+        format_utc_timestamp(SystemTime::now()),
+        message
+    ));
+}
+
+/// Merge a burst of events into at most one event per display.
+///
+/// Change flags are combined so the merged event reports the most significant
+/// change of the burst, while the display state is taken from the latest event.
+/// A display that ends the burst removed is reported as removed. Sleep and wake
+/// are kept apart from reconfigurations, reporting only the latest of them.
+pub fn coalesce(events: Vec<DisplayEvent>) -> Vec<DisplayEvent> {
+    let mut merged: Vec<DisplayEvent> = Vec::new();
+
+    for event in events {
+        let existing = merged.iter_mut().find(|e| {
+            e.display_id == event.display_id
+                && e.kind.is_sleep_change() == event.kind.is_sleep_change()
+        });
+        match existing {
+            Some(existing) if event.kind.is_sleep_change() => *existing = event,
+            Some(existing) => {
+                let flags = existing.flags | event.flags;
+                let persistent_id = event
+                    .persistent_id
+                    .clone()
+                    .or_else(|| existing.persistent_id.clone());
+                *existing = DisplayEvent {
+                    kind: if event.kind == DisplayEventKind::Removed {
+                        DisplayEventKind::Removed
+                    } else {
+                        DisplayEventKind::from_flags(flags)
+                    },
+                    flags,
+                    persistent_id,
+                    ..event
+                };
+            }
+            None => merged.push(event),
+        }
+    }
+
+    merged
+}
+
+/// First rule whose displays and USB devices are all connected.
+pub fn matching_rule<'a>(
+    rules: &'a [Rule],
+    connected: &[String],
+    usb_devices: &[UsbId],
+) -> Option<&'a Rule> {
+    rules.iter().find(|rule| {
+        (!rule.when_connected.is_empty() || !rule.when_usb.is_empty())
+            && rule
+                .when_connected
+                .iter()
+                .all(|id| connected.iter().any(|c| c == id))
+            && rule.when_usb.iter().all(|id| usb_devices.contains(id))
+    })
+}
+
+/// Something the daemon reacts to.
+enum DaemonEvent {
+    Display(DisplayEvent),
+    Usb(UsbEvent),
+    Power(PowerSource),
+    Session(Session),
+}
+
+/// The profile a line of another profile builds on, written `extends:NAME`.
+pub fn extended_profile(line: &str) -> Option<&str> {
+    line.trim()
+        .strip_prefix("extends:")
+        .filter(|name| !name.is_empty())
+}
+
+/// Parse the configuration strings of a named profile, resolving variables
+/// against the connected displays and aliases.
+///
+/// A profile may build on others with `extends:NAME` lines. The displays of
+/// those profiles come first, in order, and a line of this profile for one of
+/// their displays overrides just the keys it sets.
+pub fn profile_configs(config: &Config, name: &str) -> Result<Vec<DisplayConfig>, String> {
+    resolve_profile(config, name, &mut Vec::new(), &mut None)
+}
+
+fn resolve_profile<'a>(
+    config: &'a Config,
+    name: &'a str,
+    chain: &mut Vec<&'a str>,
+    variables: &mut Option<BTreeMap<String, String>>,
+) -> Result<Vec<DisplayConfig>, String> {
+    if chain.contains(&name) {
+        return Err(format!(
+            "Profile {} extends itself: {} -> {}",
+            name,
+            chain.join(" -> "),
+            name
+        ));
+    }
+    let strings = config
+        .profiles
+        .get(name)
+        .ok_or_else(|| match chain.last() {
+            Some(child) => format!("Profile {} extends {}, which is not found", child, name),
+            None => format!("Profile {} not found", name),
+        })?;
+
+    chain.push(name);
+    let mut inherited: Vec<DisplayConfig> = Vec::new();
+    let mut own = Vec::new();
+    for s in strings {
+        if let Some(parent) = extended_profile(s) {
+            for parent_config in resolve_profile(config, parent, chain, variables)? {
+                overlay_display(&mut inherited, parent_config);
+            }
+            continue;
+        }
+        if variables.is_none() && s.contains("${") {
+            *variables = Some(
+                resolve_variables(&config.variables, &get_all_display_info())
+                    .map_err(|e| format!("Invalid profile {}: {}", name, e))?,
+            );
+        }
+        let parsed = expand(s, variables.as_ref().unwrap_or(&BTreeMap::new()))
+            .and_then(|s| parse_config(&s))
+            .map_err(|e| format!("Invalid profile {}: {}", name, e))?;
+        own.push(DisplayConfig {
+            id: config.resolve_alias(&parsed.id).to_string(),
+            mirror: parsed
+                .mirror
+                .as_deref()
+                .map(|m| config.resolve_alias(m).to_string()),
+            ..parsed
+        });
+    }
+    chain.pop();
+
+    if inherited.is_empty() {
+        return Ok(own);
+    }
+    for display in own {
+        overlay_display(&mut inherited, display);
+    }
+    Ok(inherited)
+}
+
+/// Lay `display` over the configuration for the same display in `configs`,
+/// or add it when there is none.
+fn overlay_display(configs: &mut Vec<DisplayConfig>, display: DisplayConfig) {
+    match configs.iter_mut().find(|c| c.id == display.id) {
+        Some(existing) => overlay_config(existing, display),
+        None => configs.push(display),
+    }
+}
+
+/// Problems found by [`check_profile`].
+#[derive(Debug, Default)]
+pub struct ProfileIssues {
+    /// Lines that would fail to apply anywhere
+    pub errors: Vec<String>,
+    /// Lines that cannot be checked or would fail with the displays connected now
+    pub warnings: Vec<String>,
+}
+
+/// Check a profile's configuration strings before storing it: each must parse,
+/// and its displays are looked up among the connected ones where possible.
+pub fn check_profile(
+    config: &Config,
+    strings: &[String],
+    displays: &[DisplayInfo],
+) -> ProfileIssues {
+    let mut issues = ProfileIssues::default();
+    let variables = match resolve_variables(&config.variables, displays) {
+        Ok(variables) => variables,
+        Err(e) => {
+            issues.errors.push(e);
+            return issues;
+        }
+    };
+
+    for (index, line) in strings.iter().enumerate() {
+        let line_number = index + 1;
+        if let Some(parent) = extended_profile(line) {
+            if !config.profiles.contains_key(parent) {
+                issues.errors.push(format!(
+                    "Line {}: profile {} is not found",
+                    line_number, parent
+                ));
+            }
+            continue;
+        }
+        // Displays that are not connected still get a placeholder, so the
+        // rest of the line is checked
+        let mut line_variables = variables.clone();
+        let mut disconnected = false;
+        let mut undefined = false;
+        for name in variable_names(line) {
+            if line_variables.contains_key(name) {
+                continue;
+            }
+            if is_display_variable(name) {
+                issues.warnings.push(format!(
+                    "Line {}: ${{{}}} names a display that is not connected now",
+                    line_number, name
+                ));
+                line_variables.insert(name.to_string(), name.to_string());
+                disconnected = true;
+            } else {
+                issues.errors.push(format!(
+                    "Line {}: undefined variable ${{{}}}",
+                    line_number, name
+                ));
+                undefined = true;
+            }
+        }
+        if undefined {
+            continue;
+        }
+
+        let parsed = expand(line, &line_variables).and_then(|s| parse_config_strict(&s));
+        let parsed = match parsed {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                issues.errors.push(format!("Line {}: {}", line_number, e));
+                continue;
+            }
+        };
+        if parsed.origin_offset.is_some() {
+            issues.warnings.push(format!(
+                "Line {}: a relative origin moves the display again each time the profile is applied",
+                line_number
+            ));
+        }
+        if disconnected {
+            continue;
+        }
+        for id in std::iter::once(&parsed.id).chain(parsed.mirror.iter()) {
+            if matches!(id.as_str(), "off" | "none") {
+                continue;
+            }
+            if find_display(config.resolve_alias(id), displays).is_none() {
+                issues.warnings.push(format!(
+                    "Line {}: display {} is not connected now",
+                    line_number, id
+                ));
+            }
+        }
+    }
+    issues
+}
+
+/// Parse and apply the configuration strings of a named profile.
+///
+/// Returns whether any display was actually changed and the warnings raised.
+pub fn apply_profile(config: &Config, name: &str) -> Result<ApplyResult, String> {
+    apply_profile_with(config, name, &ApplyOptions::from(config))
+}
+
+/// Apply a named profile with `options`, running its `pre_apply` hook before
+/// and its `post_apply` hook after.
+///
+/// A failing `pre_apply` hook cancels the apply. A failing `post_apply` hook
+/// only adds a warning, since the displays have already changed.
+pub fn apply_profile_with(
+    config: &Config,
+    name: &str,
+    options: &ApplyOptions,
+) -> Result<ApplyResult, String> {
+    let configs = profile_configs(config, name)?;
+    // A dry run changes nothing, so there is nothing for hooks to prepare
+    let hooks = if options.dry_run {
+        Default::default()
+    } else {
+        config.profile_hooks.get(name).cloned().unwrap_or_default()
+    };
+
+    let mut hook_results = Vec::new();
+    if let Some(command) = &hooks.pre_apply {
+        let hook = run_profile_hook(command, HookStage::PreApply, name, None);
+        if !hook.success {
+            return Err(format!("{}; profile {} not applied", hook.failure(), name));
+        }
+        hook_results.push(hook);
+    }
+
+    let mut result = apply_configuration(configs, options)?;
+    if let Some(command) = &hooks.post_apply {
+        let hook = run_profile_hook(command, HookStage::PostApply, name, Some(result.changed));
+        if !hook.success {
+            result.warnings.push(ApplyWarning {
+                display_id: None,
+                message: hook.failure(),
+            });
+        }
+        hook_results.push(hook);
+    }
+    result.hooks = hook_results;
+    Ok(result)
+}
+
+/// Async variant of [`apply_profile`].
+#[cfg(feature = "async")]
+pub async fn apply_profile_async(config: &Config, name: &str) -> Result<ApplyResult, String> {
+    let config = config.clone();
+    let name = name.to_string();
+    tokio::task::spawn_blocking(move || apply_profile(&config, &name))
+        .await
+        .map_err(|e| format!("Apply task failed: {}", e))?
+}
+
+/// Watch for display changes, coalescing bursts before evaluating rules and
+/// running hooks. Never returns unless the event subscription fails.
+///
+/// Sending SIGHUP reloads the config file without dropping the event
+/// subscription; if the new file is invalid the previous config stays active.
+pub fn run(overrides: ConfigOverrides) -> Result<(), String> {
+    let config = settings::load(&overrides)?;
+    config.apply_call_timeout();
+    config.apply_mode_set_interval();
+    config.apply_hz_tolerance();
+    let (sender, receiver) = mpsc::channel();
+
+    install_reload_handler();
+    log(&format!("Daemon started: {}", summarize(&config)));
+
+    // Lets `list` read the display state without walking every display
+    // again, and status bars the brightness without a DDC read each time
+    let cache = StateCache::default();
+    let vcp = VcpCache::default();
+    if let Err(e) = ipc::serve(cache.clone(), vcp.clone()) {
+        log(&format!("Control socket disabled: {}", e));
+    }
+
+    if let Some(profile) = &config.enforce {
+        log(&format!("Enforcing profile {}", profile));
+    } else if let Some(profile) = &config.profile {
+        log(&format!("Default profile {}", profile));
+    }
+
+    if config.brightness_keys {
+        let agents = running_agents();
+        match deferred_to(&agents, Subsystem::Brightness).filter(|_| config.defer_gamma) {
+            Some(agent) => log(&format!("Leaving the brightness keys to {}", agent.name)),
+            None => match route_brightness_keys(vcp) {
+                Ok(()) => log("Sending the brightness keys to the display under the pointer"),
+                Err(e) => log(&format!("Brightness keys disabled: {}", e)),
+            },
+        }
+    }
+
+    thread::spawn(move || {
+        let mut daemon = Daemon::new(config, overrides);
+        if daemon.paused {
+            log("Session is not on the console, waiting for its user to return");
+        } else {
+            // Pin displays right away instead of waiting for the first change.
+            if daemon.config.enforce.is_some() {
+                daemon.enforce();
+            }
+            daemon.apply_power_policy();
+            daemon.apply_gamma();
+            daemon.record_devices();
+        }
+        daemon.process_events(receiver)
+    });
+
+    // With fast user switching every logged-in user may run a daemon, and the
+    // one in the background must leave the displays to the user in front.
+    let session_sender = sender.clone();
+    if let Err(e) = watch_session(move |session| {
+        let _ = session_sender.send(DaemonEvent::Session(session));
+    }) {
+        log(&format!("User switching detection disabled: {}", e));
+    }
+
+    // Docks often show up on USB well before their displays finish enumerating.
+    let usb_sender = sender.clone();
+    if let Err(e) = watch_usb(move |event| {
+        let _ = usb_sender.send(DaemonEvent::Usb(event));
+    }) {
+        log(&format!("USB rules disabled: {}", e));
+    }
+
+    let power_sender = sender.clone();
+    if let Err(e) = watch_power_source(move |source| {
+        let _ = power_sender.send(DaemonEvent::Power(source));
+    }) {
+        log(&format!("Power policy disabled: {}", e));
+    }
+
+    watch(move |event| {
+        cache.invalidate();
+        // The worker only goes away when the process is exiting.
+        let _ = sender.send(DaemonEvent::Display(event));
+    })
+}
+
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// How often the worker checks for a pending reload while idle.
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How long after a hotplug notch changes are taken as macOS resetting the
+/// built-in panel rather than the user.
+const NOTCH_SETTLE_TIME: Duration = Duration::from_secs(5);
+
+/// Where an enabled display sat in the arrangement.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Slot {
+    origin: PointPx,
+    mode_id: ModeId,
+}
+
+/// Where the daemon keeps the display slots, next to the snapshots, so a
+/// display disabled before the daemon restarts still goes back in place.
+fn slots_path() -> PathBuf {
+    settings::config_dir().join("slots.json")
+}
+
+/// Read the saved slots, starting empty when there are none yet.
+fn load_slots(path: &Path) -> Result<HashMap<String, Slot>, String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("Invalid display slots {}: {}", path.display(), e))
+}
+
+/// Write the slots as JSON, replacing the file in one step.
+fn save_slots(path: &Path, slots: &HashMap<String, Slot>) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(slots)
+        .map_err(|e| format!("Failed to serialize display slots: {}", e))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let partial = path.with_extension("partial");
+    std::fs::write(&partial, json + "\n")
+        .and_then(|_| std::fs::rename(&partial, path))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Note where each enabled display in `displays` sits; whether any slot
+/// changed.
+fn record_slots(slots: &mut HashMap<String, Slot>, displays: &[DisplayInfo]) -> bool {
+    let mut changed = false;
+    for info in displays {
+        if info.enabled && info.mirror_of.is_none() {
+            let slot = Slot {
+                origin: info.origin(),
+                mode_id: info.mode_id,
+            };
+            changed |= slots.insert(info.persistent_id.clone(), slot) != Some(slot);
+        }
+    }
+    changed
+}
+
+/// Configurations putting the displays in `reenabled` back in the slots they
+/// had before being disabled; displays already there are left alone.
+fn slot_restores(
+    slots: &HashMap<String, Slot>,
+    displays: &[DisplayInfo],
+    reenabled: &[u32],
+) -> Vec<DisplayConfig> {
+    displays
+        .iter()
+        .filter(|info| reenabled.contains(&info.id))
+        .filter_map(|info| {
+            let slot = slots.get(&info.persistent_id)?;
+            let moved = info.origin() != slot.origin;
+            let mode_changed = info.mode_id != slot.mode_id;
+            (moved || mode_changed).then(|| DisplayConfig {
+                id: info.persistent_id.clone(),
+                mode_id: mode_changed.then_some(slot.mode_id),
+                origin: moved.then_some(slot.origin.into()),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// The built-in panel and whether its notch is hidden, when it has one.
+fn builtin_notch() -> Option<(u32, bool)> {
+    let builtin = get_all_display_info().into_iter().find(is_builtin)?;
+    is_notch_hidden(builtin.id).map(|hidden| (builtin.id, hidden))
+}
+
+extern "C" fn request_reload(_signal: libc::c_int) {
+    RELOAD_REQUESTED.store(true, Ordering::SeqCst);
+}
+
+fn install_reload_handler() {
+    unsafe {
+        libc::signal(
+            libc::SIGHUP,
+            request_reload as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+}
+
+fn summarize(config: &Config) -> String {
+    format!(
+        "{} profiles, {} rules, {} aliases, {} hooks, debounce {}ms",
+        config.profiles.len(),
+        config.rules.len(),
+        config.aliases.len(),
+        config.hooks.len(),
+        config.debounce_ms
+    )
+}
+
+struct Daemon {
+    config: Config,
+    overrides: ConfigOverrides,
+    /// Connected displays and the profile last applied for them
+    last_applied: Option<(Vec<String>, String)>,
+    /// Connected USB devices, counting identical models separately
+    usb_devices: HashMap<UsbId, usize>,
+    power_source: Option<PowerSource>,
+    /// Modes lowered by the power policy, by persistent id, restored on AC
+    battery_saved: HashMap<String, ModeId>,
+    /// Color temperatures currently held, by display id
+    gamma_applied: BTreeMap<u32, u32>,
+    /// The agent color temperature is left to under `defer_gamma`
+    gamma_deferred_to: Option<String>,
+    /// Whether the user last had the built-in panel's notch hidden
+    notch_hidden: Option<bool>,
+    /// When a display was last connected or disconnected
+    hotplug_at: Option<Instant>,
+    /// Where each display last sat while enabled, by persistent id
+    slots: HashMap<String, Slot>,
+    /// Whether another user's session is in front, leaving the displays alone
+    paused: bool,
+    /// Every display seen so far, for `macdisp devices`
+    devices: DeviceDb,
+}
+
+impl Daemon {
+    fn new(config: Config, overrides: ConfigOverrides) -> Self {
+        let paused = session::current().state == SessionState::Background;
+        let mut slots = load_slots(&slots_path()).unwrap_or_else(|e| {
+            log(&format!("Starting without display slots: {}", e));
+            HashMap::new()
+        });
+        // A display disabled before the first event still has its slot; the
+        // arrangement of another user in front is not ours to remember.
+        if !paused && record_slots(&mut slots, &get_all_display_info()) {
+            if let Err(e) = save_slots(&slots_path(), &slots) {
+                log(&format!("Failed to save display slots: {}", e));
+            }
+        }
+        Daemon {
+            config,
+            overrides,
+            last_applied: None,
+            usb_devices: HashMap::new(),
+            power_source: current_power_source(),
+            battery_saved: HashMap::new(),
+            gamma_applied: BTreeMap::new(),
+            gamma_deferred_to: None,
+            notch_hidden: builtin_notch().map(|(_, hidden)| hidden),
+            hotplug_at: None,
+            slots,
+            paused,
+            devices: DeviceDb::load(&devices_path()).unwrap_or_else(|e| {
+                log(&format!("Starting a new device database: {}", e));
+                DeviceDb::default()
+            }),
+        }
+    }
+
+    fn process_events(mut self, receiver: Receiver<DaemonEvent>) {
+        loop {
+            match receiver.recv_timeout(RELOAD_POLL_INTERVAL) {
+                Ok(first) => {
+                    let mut burst = vec![first];
+                    let debounce = Duration::from_millis(self.config.debounce_ms);
+                    loop {
+                        match receiver.recv_timeout(debounce) {
+                            Ok(event) => burst.push(event),
+                            Err(RecvTimeoutError::Timeout) => break,
+                            Err(RecvTimeoutError::Disconnected) => return,
+                        }
+                    }
+                    self.handle_burst(burst);
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            if RELOAD_REQUESTED.swap(false, Ordering::SeqCst) {
+                self.reload();
+            }
+        }
+    }
+
+    fn reload(&mut self) {
+        match settings::load(&self.overrides) {
+            Ok(config) => {
+                config.apply_call_timeout();
+                config.apply_mode_set_interval();
+                config.apply_hz_tolerance();
+                log(&format!("Reloaded config: {}", summarize(&config)));
+                if config.brightness_keys != self.config.brightness_keys {
+                    log("brightness_keys takes effect when the daemon restarts");
+                }
+                self.config = config;
+                // Profiles may have changed, so the current rule gets another chance.
+                self.last_applied = None;
+                if !self.paused {
+                    self.evaluate();
+                    self.apply_gamma();
+                }
+            }
+            Err(e) => log(&format!(
+                "Config reload failed, keeping previous config: {}",
+                e
+            )),
+        }
+    }
+
+    fn handle_burst(&mut self, burst: Vec<DaemonEvent>) {
+        let mut display_events = Vec::new();
+        let mut usb_changes = Vec::new();
+        let mut power_changed = false;
+        let mut resumed = false;
+        for event in burst {
+            match event {
+                DaemonEvent::Power(source) => {
+                    if self.power_source != Some(source) {
+                        log(&format!("Running on {}", source.as_str()));
+                        self.power_source = Some(source);
+                        power_changed = true;
+                    }
+                }
+                DaemonEvent::Display(event) => display_events.push(event),
+                DaemonEvent::Session(session) => {
+                    let paused = session.state == SessionState::Background;
+                    if paused == self.paused {
+                        continue;
+                    }
+                    self.paused = paused;
+                    if paused {
+                        log("Session moved to the background, pausing");
+                    } else {
+                        log("Session is back on the console, resyncing displays");
+                        resumed = true;
+                    }
+                }
+                DaemonEvent::Usb(event) => {
+                    self.track_usb(event);
+                    usb_changes.push(format!(
+                        "usb {} {}",
+                        event.id,
+                        if event.added { "added" } else { "removed" }
+                    ));
+                }
+            }
+        }
+        if !usb_changes.is_empty() {
+            log(&usb_changes.join(", "));
+        }
+
+        let received = display_events.len();
+        let events = coalesce(display_events);
+        if received > 0 {
+            log(&format!(
+                "{} events coalesced into {}: {}",
+                received,
+                events.len(),
+                events
+                    .iter()
+                    .map(|e| format!("{} display {}", e.kind.as_str(), e.display_id))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+
+        // Display changes made while another user was in front are theirs;
+        // acting on them would fight that user's own daemon. The user's own
+        // hooks still hear about them.
+        if !self.paused {
+            self.act_on(&events, received, power_changed, resumed);
+        }
+
+        for hook in &self.config.hooks {
+            for event in &events {
+                if let Err(e) = run_event_hook(hook, event) {
+                    log(&e);
+                }
+            }
+        }
+    }
+
+    /// Apply rules, policies and restores after a burst of events.
+    fn act_on(
+        &mut self,
+        events: &[DisplayEvent],
+        received: usize,
+        power_changed: bool,
+        resumed: bool,
+    ) {
+        if resumed {
+            self.resync();
+        }
+
+        let applied = self.evaluate();
+
+        // Profiles may have raised the refresh rate of new displays again.
+        if power_changed || received > 0 || resumed {
+            self.apply_power_policy();
+        }
+        if received > 0 || resumed {
+            self.apply_gamma();
+            let hotplug = events
+                .iter()
+                .any(|e| matches!(e.kind, DisplayEventKind::Added | DisplayEventKind::Removed));
+            if applied {
+                // The profile just picked the panel's mode; the events it
+                // causes are neither the user's choice nor macOS resetting it.
+                self.hotplug_at = None;
+            } else {
+                self.track_notch(hotplug);
+            }
+            let reenabled: Vec<u32> = events
+                .iter()
+                .filter(|e| e.kind == DisplayEventKind::Enabled)
+                .map(|e| e.display_id)
+                .collect();
+            self.track_slots(&reenabled);
+            self.record_devices();
+        }
+    }
+
+    /// Forget what the displays looked like before another user's session
+    /// took over, so rules, the pinned profile and the color temperatures are
+    /// applied again to whatever that user left behind.
+    fn resync(&mut self) {
+        self.last_applied = None;
+        self.gamma_applied.clear();
+        self.hotplug_at = None;
+        // The slots are kept: they are only recorded while this session is in
+        // front, so they still hold this user's arrangement, not the other's.
+        self.notch_hidden = builtin_notch().map(|(_, hidden)| hidden);
+    }
+
+    fn track_usb(&mut self, event: UsbEvent) {
+        if event.added {
+            *self.usb_devices.entry(event.id).or_insert(0) += 1;
+        } else if let Some(count) = self.usb_devices.get_mut(&event.id) {
+            *count -= 1;
+            if *count == 0 {
+                self.usb_devices.remove(&event.id);
+            }
+        }
+    }
+
+    /// Hold the configured color temperatures.
+    ///
+    /// macOS resets the gamma tables on mode changes, hotplug and wake, all of
+    /// which arrive as display events, so this runs after each of them.
+    fn apply_gamma(&mut self) {
+        let mut temperatures = configured_temperatures(&self.config, &get_all_display_info());
+        // Holding gamma next to another agent that does makes the screen flicker
+        let agents = if self.config.defer_gamma && !temperatures.is_empty() {
+            running_agents()
+        } else {
+            Vec::new()
+        };
+        let deferred_to = deferred_to(&agents, Subsystem::Gamma).map(|agent| agent.name.clone());
+        if deferred_to != self.gamma_deferred_to {
+            if let Some(name) = &deferred_to {
+                log(&format!("Leaving color temperature to {}", name));
+                // The agent owns the gamma tables now, and restoring them
+                // would undo what it set
+                self.gamma_applied.clear();
+            }
+            self.gamma_deferred_to = deferred_to;
+        }
+        if self.gamma_deferred_to.is_some() {
+            temperatures.clear();
+        }
+        if temperatures.is_empty() && self.gamma_applied.is_empty() {
+            return;
+        }
+        // A display dropped from the settings goes back to its profile
+        if self
+            .gamma_applied
+            .keys()
+            .any(|id| !temperatures.contains_key(id))
+        {
+            gamma::restore();
+        }
+        for (&display_id, &kelvin) in &temperatures {
+            if let Err(e) = gamma::set_temperature(display_id, kelvin) {
+                log(&format!("Failed to hold color temperature: {}", e));
+            }
+        }
+        if temperatures.is_empty() {
+            log("Released color temperature");
+        } else if temperatures != self.gamma_applied {
+            let held: Vec<String> = temperatures
+                .iter()
+                .map(|(id, kelvin)| format!("display {} at {}K", id, kelvin))
+                .collect();
+            log(&format!("Holding color temperature: {}", held.join(", ")));
+        }
+        self.gamma_applied = temperatures;
+    }
+
+    /// Remember the notch state the user picks and put it back after a
+    /// display is connected or disconnected, which often resets the built-in
+    /// panel to its full-height mode.
+    ///
+    /// Changes shortly after a hotplug are taken as macOS resetting the panel,
+    /// any other as the user's choice. Skipped while a profile is enforced
+    /// and after a burst in which a profile was applied.
+    fn track_notch(&mut self, hotplug: bool) {
+        let now = Instant::now();
+        if hotplug {
+            self.hotplug_at = Some(now);
+        }
+        let Some((display_id, hidden)) = builtin_notch() else {
+            return;
+        };
+        let settling = self
+            .hotplug_at
+            .is_some_and(|at| now.duration_since(at) < NOTCH_SETTLE_TIME);
+        if !settling {
+            self.notch_hidden = Some(hidden);
+            return;
+        }
+
+        let wanted = match self.notch_hidden {
+            Some(wanted) if wanted != hidden && self.config.enforce.is_none() => wanted,
+            _ => return,
+        };
+        let _lock = match ApplyLock::acquire() {
+            Ok(lock) => lock,
+            Err(e) => return log(&format!("Skipping notch restore: {}", e)),
+        };
+        let Some(current) = get_current_mode(display_id) else {
+            return;
+        };
+        let family = notch_family(&get_all_modes(display_id), &current);
+        if let Some(mode) = notch_target(&family.modes, &current, wanted) {
+            log(&format!(
+                "Restoring notch {} on display {} (mode {})",
+                if wanted { "hidden" } else { "shown" },
+                display_id,
+                mode.mode_number
+            ));
+            if let Err(e) = set_display_mode(display_id, mode.mode_number) {
+                log(&format!("Failed to restore notch: {}", e));
+            }
+        }
+    }
+
+    /// Remember where each enabled display sits and put displays that are
+    /// enabled again back there, instead of at (0,0) on top of another
+    /// display where macOS tends to leave them.
+    ///
+    /// Skipped while a profile is enforced, which already decides every origin.
+    fn track_slots(&mut self, reenabled: &[u32]) {
+        let mut displays = get_all_display_info();
+        let restores = slot_restores(&self.slots, &displays, reenabled);
+        if !restores.is_empty() && self.config.enforce.is_none() {
+            for config in &restores {
+                log(&format!("Restoring display {} to its slot", config.id));
+            }
+            match apply_configuration(restores, &ApplyOptions::from(&self.config)) {
+                Ok(_) => displays = get_all_display_info(),
+                Err(e) => log(&format!("Failed to restore display slot: {}", e)),
+            }
+        }
+
+        if record_slots(&mut self.slots, &displays) {
+            if let Err(e) = save_slots(&slots_path(), &self.slots) {
+                log(&format!("Failed to save display slots: {}", e));
+            }
+        }
+    }
+
+    /// Note the connected displays, with the mode and position they ended up
+    /// with, in the device database.
+    fn record_devices(&mut self) {
+        let displays = get_all_display_info();
+        match record_connected(&mut self.devices, &displays) {
+            Ok(true) => log("Recorded a new display in the device database"),
+            Ok(false) => {}
+            Err(e) => log(&format!("Failed to update the device database: {}", e)),
+        }
+    }
+
+    /// Lower refresh rates on battery and restore them on AC power.
+    ///
+    /// Skipped while a profile is enforced, which already decides every mode.
+    fn apply_power_policy(&mut self) {
+        let policy = match &self.config.power {
+            Some(policy) if self.config.enforce.is_none() => policy.clone(),
+            _ => return,
+        };
+        let _lock = match ApplyLock::acquire() {
+            Ok(lock) => lock,
+            Err(e) => return log(&format!("Skipping power policy: {}", e)),
+        };
+
+        match self.power_source {
+            Some(PowerSource::Battery) => {
+                let targets: Vec<String> = policy
+                    .displays
+                    .iter()
+                    .map(|id| self.config.resolve_alias(id).to_string())
+                    .collect();
+                let displays = get_all_display_info();
+                for info in &displays {
+                    let targeted = targets.is_empty()
+                        || targets
+                            .iter()
+                            .any(|id| find_display(id, &displays).map(|d| d.id) == Some(info.id));
+                    if !targeted {
+                        continue;
+                    }
+                    let current = match get_current_mode(info.id) {
+                        Some(current) => current,
+                        None => continue,
+                    };
+                    let modes = get_all_modes(info.id);
+                    if let Some(mode) = battery_mode(&modes, &current, policy.battery_max_hz) {
+                        log(&format!(
+                            "On battery: display {} {}Hz -> {}Hz",
+                            info.persistent_id,
+                            format_hz(current.refresh_rate),
+                            format_hz(mode.refresh_rate)
+                        ));
+                        match set_display_mode(info.id, mode.mode_number) {
+                            Ok(()) => {
+                                self.battery_saved
+                                    .insert(info.persistent_id.clone(), current.mode_id());
+                            }
+                            Err(e) => log(&format!("Failed to lower refresh rate: {}", e)),
+                        }
+                    }
+                }
+            }
+            Some(PowerSource::Ac) => {
+                let displays = get_all_display_info();
+                for (uuid, mode_id) in self.battery_saved.drain() {
+                    // Displays unplugged on battery keep whatever they come back with.
+                    let info = match find_display(&uuid, &displays) {
+                        Some(info) => info,
+                        None => continue,
+                    };
+                    let modes = get_all_modes(info.id);
+                    let mode = match find_mode(&modes, &mode_id, None) {
+                        Some(mode) => mode,
+                        None => continue,
+                    };
+                    if info.mode_id == mode_id {
+                        continue;
+                    }
+                    log(&format!(
+                        "On AC power: restoring display {} to {}",
+                        uuid, mode_id
+                    ));
+                    if let Err(e) = set_display_mode(info.id, mode.mode_number) {
+                        log(&format!("Failed to restore refresh rate: {}", e));
+                    }
+                }
+            }
+            None => {}
+        }
+    }
+
+    /// Enforce the pinned profile, or apply the one whose rules match.
+    /// Returns whether a profile was applied.
+    fn evaluate(&mut self) -> bool {
+        if self.config.enforce.is_some() {
+            self.enforce()
+        } else {
+            self.evaluate_rules()
+        }
+    }
+
+    /// Revert any display that has drifted from the pinned profile.
+    fn enforce(&mut self) -> bool {
+        let profile = match &self.config.enforce {
+            Some(profile) => profile.clone(),
+            None => return false,
+        };
+
+        let configs = match profile_configs(&self.config, &profile) {
+            Ok(configs) => configs,
+            Err(e) => {
+                log(&format!("Cannot enforce: {}", e));
+                return false;
+            }
+        };
+
+        let displays = get_all_display_info();
+        let drifted: Vec<DisplayConfig> = configs
+            .into_iter()
+            .filter(|config| match find_display(&config.id, &displays) {
+                Some(info) => {
+                    let drift = config_drift(config, info, &displays);
+                    if !drift.is_empty() {
+                        log(&format!(
+                            "Display {} drifted: {}",
+                            info.persistent_id,
+                            drift.join(", ")
+                        ));
+                    }
+                    !drift.is_empty()
+                }
+                // Pinned displays that are not connected cannot drift.
+                None => false,
+            })
+            .collect();
+
+        if drifted.is_empty() {
+            return false;
+        }
+
+        log(&format!(
+            "Reverting {} display(s) to profile {}",
+            drifted.len(),
+            profile
+        ));
+        if let Err(e) = apply_configuration(drifted, &ApplyOptions::from(&self.config)) {
+            log(&format!("Failed to revert to profile {}: {}", profile, e));
+        }
+        true
+    }
+
+    fn evaluate_rules(&mut self) -> bool {
+        let mut connected: Vec<String> = get_all_display_info()
+            .into_iter()
+            .map(|info| info.persistent_id)
+            .collect();
+        connected.sort();
+
+        let rules: Vec<Rule> = self
+            .config
+            .rules
+            .iter()
+            .map(|rule| Rule {
+                when_connected: rule
+                    .when_connected
+                    .iter()
+                    .map(|id| self.config.resolve_alias(id).to_string())
+                    .collect(),
+                when_usb: rule.when_usb.clone(),
+                profile: rule.profile.clone(),
+            })
+            .collect();
+        let usb_devices: Vec<UsbId> = self.usb_devices.keys().copied().collect();
+
+        // The default profile covers display sets no rule mentions
+        let profile = match matching_rule(&rules, &connected, &usb_devices) {
+            Some(rule) => rule.profile.clone(),
+            None => match &self.config.profile {
+                Some(profile) => profile.clone(),
+                None => return false,
+            },
+        };
+
+        // Keyed on the connected displays, so a profile applied as soon as a
+        // dock appears on USB is applied again once its displays enumerate.
+        let already_applied = self
+            .last_applied
+            .as_ref()
+            .map(|(ids, applied)| *ids == connected && *applied == profile)
+            .unwrap_or(false);
+        if already_applied {
+            return false;
+        }
+
+        log(&format!("Applying profile {}", profile));
+        match apply_profile(&self.config, &profile) {
+            Ok(result) => {
+                for hook in &result.hooks {
+                    if hook.success {
+                        log(&format!(
+                            "Ran {} hook `{}`",
+                            hook.stage.as_str(),
+                            hook.command
+                        ));
+                    } else {
+                        log(&hook.failure());
+                    }
+                }
+                self.last_applied = Some((connected, profile))
+            }
+            Err(e) => log(&format!("Failed to apply profile {}: {}", profile, e)),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(display_id: u32, kind: DisplayEventKind, flags: u32) -> DisplayEvent {
+        DisplayEvent {
+            kind,
+            display_id,
+            persistent_id: None,
+            flags,
+            timestamp: 0.0,
+            info: None,
+        }
+    }
+
+    #[test]
+    fn test_coalesce_merges_per_display() {
+        let events = vec![
+            event(1, DisplayEventKind::Added, 1 << 4),
+            event(2, DisplayEventKind::Moved, 1 << 1),
+            event(1, DisplayEventKind::ModeChanged, 1 << 3),
+            event(1, DisplayEventKind::Moved, 1 << 1),
+        ];
+
+        let merged = coalesce(events);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].display_id, 1);
+        assert_eq!(merged[0].kind, DisplayEventKind::Added);
+        assert_eq!(merged[1].kind, DisplayEventKind::Moved);
+    }
+
+    #[test]
+    fn test_coalesce_keeps_removal() {
+        let events = vec![
+            event(3, DisplayEventKind::Added, 1 << 4),
+            event(3, DisplayEventKind::Removed, 1 << 5),
+        ];
+
+        let merged = coalesce(events);
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].kind, DisplayEventKind::Removed);
+    }
+
+    #[test]
+    fn test_coalesce_keeps_sleep_apart() {
+        let events = vec![
+            event(1, DisplayEventKind::Asleep, 0),
+            event(1, DisplayEventKind::Disabled, 1 << 9),
+            event(1, DisplayEventKind::Awake, 0),
+        ];
+
+        let merged = coalesce(events);
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].kind, DisplayEventKind::Awake);
+        assert_eq!(merged[1].kind, DisplayEventKind::Disabled);
+    }
+
+    #[test]
+    fn test_slot_restores() {
+        let mut displays = vec![
+            crate::apply::tests::display(1, "AAAA"),
+            crate::apply::tests::display(2, "BBBB"),
+        ];
+        let slots = HashMap::from([
+            (
+                "BBBB".to_string(),
+                Slot {
+                    origin: PointPx::new(2560, 0),
+                    mode_id: displays[1].mode_id,
+                },
+            ),
+            (
+                "AAAA".to_string(),
+                Slot {
+                    origin: PointPx::ORIGIN,
+                    mode_id: displays[0].mode_id,
+                },
+            ),
+        ]);
+        // macOS stacked display 2 on display 1
+        displays[1].x = 0;
+
+        let restores = slot_restores(&slots, &displays, &[1, 2]);
+        assert_eq!(restores.len(), 1);
+        assert_eq!(restores[0].id, "BBBB");
+        assert_eq!(restores[0].origin, Some((2560, 0)));
+        assert_eq!(restores[0].mode_id, None);
+        assert!(slot_restores(&slots, &displays, &[1]).is_empty());
+    }
+
+    #[test]
+    fn test_slots_round_trip() {
+        let mut displays = vec![
+            crate::apply::tests::display(1, "AAAA"),
+            crate::apply::tests::display(2, "BBBB"),
+        ];
+        displays[1].x = 2560;
+        let mut slots = HashMap::new();
+        assert!(record_slots(&mut slots, &displays));
+        assert!(!record_slots(&mut slots, &displays));
+
+        // A disabled display keeps its last slot
+        displays[1].enabled = false;
+        displays[1].x = 0;
+        assert!(!record_slots(&mut slots, &displays));
+        assert_eq!(slots["BBBB"].origin, PointPx::new(2560, 0));
+
+        let path = std::env::temp_dir().join(format!("macdisp-slots-{}.json", std::process::id()));
+        save_slots(&path, &slots).unwrap();
+        assert_eq!(load_slots(&path).unwrap(), slots);
+        std::fs::remove_file(&path).unwrap();
+        assert!(load_slots(&path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_check_profile() {
+        let config = Config {
+            aliases: BTreeMap::from([("left".to_string(), "AAAA".to_string())]),
+            ..Config::default()
+        };
+        let displays = vec![crate::apply::tests::display(1, "AAAA")];
+        let check = |lines: &[&str]| {
+            let lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+            check_profile(&config, &lines, &displays)
+        };
+
+        let issues = check(&["id:left res:1920x1080", "id:${MAIN} mirror:off"]);
+        assert!(issues.errors.is_empty() && issues.warnings.is_empty());
+
+        let issues = check(&["id:left res:wide", "id:${DESK}"]);
+        assert_eq!(issues.errors.len(), 2);
+
+        let issues = check(&["id:BBBB hz:60", "id:${EXTERNAL_2} hz:60"]);
+        assert!(issues.errors.is_empty());
+        assert_eq!(issues.warnings.len(), 2);
+
+        let issues = check(&["extends:docked", "id:left hz:60"]);
+        assert_eq!(issues.errors, vec!["Line 1: profile docked is not found"]);
+    }
+
+    #[test]
+    fn test_profile_inheritance() {
+        let profile = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect();
+        let mut config = Config {
+            aliases: BTreeMap::from([("desk".to_string(), "BBBB".to_string())]),
+            ..Config::default()
+        };
+        config.profiles = BTreeMap::from([
+            (
+                "docked".to_string(),
+                profile(&[
+                    "id:AAAA res:1512x982 origin:(0,0)",
+                    "id:BBBB mode_id:2560x1440/2560x1440@60/d8 origin:(1512,0)",
+                ]),
+            ),
+            (
+                "docked-4k".to_string(),
+                profile(&["extends:docked", "id:desk hz:144", "id:CCCC enabled:false"]),
+            ),
+            ("loop".to_string(), profile(&["extends:docked-loop"])),
+            ("docked-loop".to_string(), profile(&["extends:loop"])),
+            ("orphan".to_string(), profile(&["extends:missing"])),
+        ]);
+
+        let configs = profile_configs(&config, "docked-4k").unwrap();
+        assert_eq!(configs.len(), 3);
+        assert_eq!(configs[0].resolution, Some((1512, 982)));
+        assert_eq!(configs[1].id, "BBBB");
+        assert_eq!(configs[1].hz, Some(144.0));
+        assert_eq!(configs[1].mode_id, None);
+        // The parent's mode_id still gives the resolution and depth
+        assert_eq!(configs[1].resolution, Some((2560, 1440)));
+        assert_eq!(configs[1].color_depth, Some(8));
+        assert_eq!(configs[1].scaling, Some(false));
+        assert_eq!(configs[1].origin, Some((1512, 0)));
+        assert_eq!(configs[2].enabled, Some(false));
+
+        assert_eq!(
+            profile_configs(&config, "loop").unwrap_err(),
+            "Profile loop extends itself: loop -> docked-loop -> loop"
+        );
+        assert_eq!(
+            profile_configs(&config, "orphan").unwrap_err(),
+            "Profile orphan extends missing, which is not found"
+        );
+    }
+
+    #[test]
+    fn test_matching_rule_requires_all_displays() {
+        let rules = vec![
+            Rule {
+                when_connected: vec!["A".to_string(), "B".to_string()],
+                when_usb: vec![],
+                profile: "docked".to_string(),
+            },
+            Rule {
+                when_connected: vec!["A".to_string()],
+                when_usb: vec![],
+                profile: "laptop".to_string(),
+            },
+        ];
+
+        let connected = vec!["A".to_string()];
+        assert_eq!(
+            matching_rule(&rules, &connected, &[]).unwrap().profile,
+            "laptop"
+        );
+
+        let connected = vec!["A".to_string(), "B".to_string()];
+        assert_eq!(
+            matching_rule(&rules, &connected, &[]).unwrap().profile,
+            "docked"
+        );
+    }
+
+    #[test]
+    fn test_matching_rule_on_usb_device() {
+        let dock: UsbId = "0bda:8153".parse().unwrap();
+        let rules = vec![Rule {
+            when_connected: vec![],
+            when_usb: vec![dock],
+            profile: "docked".to_string(),
+        }];
+
+        assert!(matching_rule(&rules, &[], &[]).is_none());
+        assert_eq!(
+            matching_rule(&rules, &[], &[dock]).unwrap().profile,
+            "docked"
+        );
+    }
+}
@@ -0,0 +1,725 @@
+//! DDC/CI access to external monitors: the MCCS capabilities string and VCP
+//! feature values.
+
+use crate::error::{DisplayError, ErrorKind};
+use crate::executor;
+use crate::quirks::{self, Quirks};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+extern "C" {
+    fn ds_ddc_transact(
+        display_id: u32,
+        request: *const u8,
+        request_len: usize,
+        reply: *mut u8,
+        reply_len: usize,
+        delay_us: u32,
+    ) -> i32;
+}
+
+/// I2C address of the display (0x37) in 8-bit write form.
+const DISPLAY_ADDRESS: u8 = 0x6E;
+/// Source address of messages from the host.
+const HOST_ADDRESS: u8 = 0x51;
+/// Destination used in place of the host address when checksumming replies.
+const REPLY_CHECKSUM_SEED: u8 = 0x50;
+
+const GET_VCP_REQUEST: u8 = 0x01;
+const GET_VCP_REPLY: u8 = 0x02;
+const SET_VCP_REQUEST: u8 = 0x03;
+const CAPABILITIES_REQUEST: u8 = 0xF3;
+const CAPABILITIES_REPLY: u8 = 0xE3;
+
+/// Time the monitor needs between a request and its reply, unless its quirks
+/// say otherwise.
+const REPLY_DELAY_MS: u64 = 50;
+/// Capabilities fragments carry at most 32 bytes of data.
+const CAPABILITIES_REPLY_LEN: usize = 38;
+const GET_VCP_REPLY_LEN: usize = 11;
+/// Longest capabilities string accepted; real ones are a few hundred bytes.
+const MAX_CAPABILITIES_LEN: usize = 8192;
+const ATTEMPTS: usize = 3;
+/// How long the daemon answers with a VCP value before reading it again.
+const VCP_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// Wrap a DDC/CI payload in the host-to-display frame.
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut message = vec![HOST_ADDRESS, 0x80 | payload.len() as u8];
+    message.extend_from_slice(payload);
+    let checksum = message
+        .iter()
+        .fold(DISPLAY_ADDRESS, |checksum, byte| checksum ^ byte);
+    message.push(checksum);
+    message
+}
+
+/// Check a display-to-host frame and return its payload.
+fn unframe(reply: &[u8]) -> Result<&[u8], &'static str> {
+    if reply.len() < 3 || reply[0] != DISPLAY_ADDRESS || reply[1] & 0x80 == 0 {
+        return Err("malformed DDC reply");
+    }
+    let len = (reply[1] & 0x7F) as usize;
+    if reply.len() < len + 3 {
+        return Err("truncated DDC reply");
+    }
+    let checksum = reply[..len + 2]
+        .iter()
+        .fold(REPLY_CHECKSUM_SEED, |checksum, byte| checksum ^ byte);
+    if checksum != reply[len + 2] {
+        return Err("DDC reply checksum mismatch");
+    }
+    Ok(&reply[2..len + 2])
+}
+
+/// Send one request and return the payload of the reply, retrying the
+/// corrupted replies that are common on the first exchange after a wake.
+fn exchange(
+    operation: &'static str,
+    display_id: u32,
+    payload: &[u8],
+    reply_len: usize,
+    quirks: &Quirks,
+) -> Result<Vec<u8>, DisplayError> {
+    let request = frame(payload);
+    let delay_us =
+        (quirks.reply_delay_ms.unwrap_or(REPLY_DELAY_MS) * 1000).min(u32::MAX as u64) as u32;
+    let mut last_error = "no DDC reply";
+
+    for _ in 0..ATTEMPTS {
+        let request = request.clone();
+        let result = executor::with_timeout(move || {
+            let mut reply = vec![0u8; reply_len];
+            let status = crate::error::status(|| unsafe {
+                ds_ddc_transact(
+                    display_id,
+                    request.as_ptr(),
+                    request.len(),
+                    reply.as_mut_ptr(),
+                    reply.len(),
+                    delay_us,
+                )
+            });
+            (status, reply)
+        });
+
+        let reply = match result {
+            Some((status, reply)) if status.is_ok() => reply,
+            Some((status, _)) => {
+                return Err(DisplayError::from_status(operation, display_id, status))
+            }
+            None => {
+                return Err(DisplayError::timeout(
+                    operation,
+                    display_id,
+                    crate::call_timeout(),
+                ))
+            }
+        };
+        match unframe(&reply) {
+            Ok(payload) => return Ok(payload.to_vec()),
+            Err(reason) => last_error = reason,
+        }
+    }
+
+    Err(DisplayError {
+        operation,
+        display_id,
+        kind: ErrorKind::InvalidResponse(last_error),
+        detail: None,
+    })
+}
+
+/// Send a request that has no reply.
+fn send(operation: &'static str, display_id: u32, payload: &[u8]) -> Result<(), DisplayError> {
+    let request = frame(payload);
+    let result = executor::with_timeout(move || {
+        crate::error::status(|| unsafe {
+            ds_ddc_transact(
+                display_id,
+                request.as_ptr(),
+                request.len(),
+                std::ptr::null_mut(),
+                0,
+                0,
+            )
+        })
+    });
+    crate::error::check(operation, display_id, result)
+}
+
+/// Read the raw MCCS capabilities string of an external display.
+pub fn read_capabilities(display_id: u32) -> Result<String, DisplayError> {
+    read_capabilities_with(display_id, &quirks::for_display(display_id))
+}
+
+fn read_capabilities_with(display_id: u32, quirks: &Quirks) -> Result<String, DisplayError> {
+    const OPERATION: &str = "read DDC capabilities";
+    let mut data = Vec::new();
+
+    loop {
+        let offset = data.len() as u16;
+        let [high, low] = offset.to_be_bytes();
+        let payload = exchange(
+            OPERATION,
+            display_id,
+            &[CAPABILITIES_REQUEST, high, low],
+            CAPABILITIES_REPLY_LEN,
+            quirks,
+        )?;
+
+        if payload.len() < 3 || payload[0] != CAPABILITIES_REPLY || payload[1..3] != [high, low] {
+            return Err(DisplayError {
+                operation: OPERATION,
+                display_id,
+                kind: ErrorKind::InvalidResponse("unexpected capabilities fragment"),
+                detail: None,
+            });
+        }
+        // An empty fragment marks the end of the string
+        let fragment = &payload[3..];
+        if fragment.is_empty() {
+            break;
+        }
+        data.extend_from_slice(fragment);
+        if data.len() > MAX_CAPABILITIES_LEN {
+            return Err(DisplayError {
+                operation: OPERATION,
+                display_id,
+                kind: ErrorKind::InvalidResponse("capabilities string too long"),
+                detail: None,
+            });
+        }
+    }
+
+    // Some monitors NUL-terminate the string
+    let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+    Ok(String::from_utf8_lossy(&data[..end]).into_owned())
+}
+
+/// Current and maximum value of a VCP feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct VcpValue {
+    pub current: u16,
+    pub maximum: u16,
+}
+
+/// Read a VCP feature such as brightness (0x10).
+pub fn get_vcp(display_id: u32, code: u8) -> Result<VcpValue, DisplayError> {
+    get_vcp_with(display_id, code, &quirks::for_display(display_id))
+}
+
+/// Set a VCP feature such as brightness (0x10).
+///
+/// Monitors do not acknowledge writes; read the value back to confirm one.
+pub fn set_vcp(display_id: u32, code: u8, value: u16) -> Result<(), DisplayError> {
+    let [high, low] = value.to_be_bytes();
+    send(
+        "set DDC value",
+        display_id,
+        &[SET_VCP_REQUEST, code, high, low],
+    )
+}
+
+/// Clears the `refreshing` mark of a cached value when its background read
+/// ends, however it ends.
+struct Refreshing {
+    cache: VcpCache,
+    key: (u32, u8),
+}
+
+impl Drop for Refreshing {
+    fn drop(&mut self) {
+        if let Some(entry) = self.cache.lock().get_mut(&self.key) {
+            entry.refreshing = false;
+        }
+    }
+}
+
+/// A VCP value as the daemon last read it.
+struct CachedVcp {
+    value: Result<VcpValue, DisplayError>,
+    read_at: Instant,
+    refreshing: bool,
+}
+
+/// VCP values kept by the daemon, so status bars polling the brightness do
+/// not put a DDC exchange on the monitor's I2C bus each time.
+///
+/// A value past its TTL is still answered while a background thread reads
+/// it again; only a value never read before waits for the monitor. Failures
+/// are kept too, so a monitor without the control is not asked every time.
+#[derive(Clone)]
+pub(crate) struct VcpCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<(u32, u8), CachedVcp>>>,
+}
+
+impl Default for VcpCache {
+    fn default() -> Self {
+        VcpCache::with_ttl(VCP_CACHE_TTL)
+    }
+}
+
+impl VcpCache {
+    pub(crate) fn with_ttl(ttl: Duration) -> Self {
+        VcpCache {
+            ttl,
+            entries: Arc::default(),
+        }
+    }
+
+    /// The VCP value from the cache, reading it from the monitor if it was
+    /// never read.
+    pub(crate) fn get(&self, display_id: u32, code: u8) -> Result<VcpValue, DisplayError> {
+        self.get_with(display_id, code, get_vcp)
+    }
+
+    fn get_with(
+        &self,
+        display_id: u32,
+        code: u8,
+        read: fn(u32, u8) -> Result<VcpValue, DisplayError>,
+    ) -> Result<VcpValue, DisplayError> {
+        let key = (display_id, code);
+        if let Some(entry) = self.lock().get_mut(&key) {
+            if entry.read_at.elapsed() >= self.ttl && !entry.refreshing {
+                entry.refreshing = true;
+                let cache = self.clone();
+                thread::spawn(move || {
+                    // A read that panics must not leave the value stale for good
+                    let refresh = Refreshing { cache, key };
+                    let value = read(display_id, code);
+                    refresh.cache.store(key, value);
+                });
+            }
+            return entry.value.clone();
+        }
+        let value = read(display_id, code);
+        self.store(key, value.clone());
+        value
+    }
+
+    fn store(&self, key: (u32, u8), value: Result<VcpValue, DisplayError>) {
+        self.lock().insert(
+            key,
+            CachedVcp {
+                value,
+                read_at: Instant::now(),
+                refreshing: false,
+            },
+        );
+    }
+
+    /// Note a value just written to the monitor, keeping the maximum read
+    /// before; a value never read stays unknown.
+    pub(crate) fn written(&self, display_id: u32, code: u8, current: u16) {
+        if let Some(entry) = self.lock().get_mut(&(display_id, code)) {
+            if let Ok(value) = &mut entry.value {
+                value.current = current;
+                entry.read_at = Instant::now();
+            }
+        }
+    }
+
+    /// Drop the values of a display, after it was changed.
+    pub(crate) fn forget(&self, display_id: u32) {
+        self.lock().retain(|(id, _), _| *id != display_id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(u32, u8), CachedVcp>> {
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn get_vcp_with(display_id: u32, code: u8, quirks: &Quirks) -> Result<VcpValue, DisplayError> {
+    const OPERATION: &str = "read DDC value";
+    // Reads of write-only controls return garbage that would pass as a value
+    if quirks.is_write_only(code) {
+        return Err(DisplayError {
+            operation: OPERATION,
+            display_id,
+            kind: ErrorKind::NotSupported,
+            detail: None,
+        });
+    }
+    let payload = exchange(
+        OPERATION,
+        display_id,
+        &[GET_VCP_REQUEST, code],
+        GET_VCP_REPLY_LEN,
+        quirks,
+    )?;
+
+    // opcode, result, code, type, max (2), current (2)
+    match payload.as_slice() {
+        [GET_VCP_REPLY, 0, reply_code, _, max_high, max_low, cur_high, cur_low]
+            if *reply_code == code =>
+        {
+            Ok(VcpValue {
+                current: u16::from_be_bytes([*cur_high, *cur_low]),
+                maximum: u16::from_be_bytes([*max_high, *max_low]),
+            })
+        }
+        // The monitor does not support this code
+        [GET_VCP_REPLY, 1, ..] => Err(DisplayError {
+            operation: OPERATION,
+            display_id,
+            kind: ErrorKind::NotSupported,
+            detail: None,
+        }),
+        _ => Err(DisplayError {
+            operation: OPERATION,
+            display_id,
+            kind: ErrorKind::InvalidResponse("unexpected VCP reply"),
+            detail: None,
+        }),
+    }
+}
+
+/// A VCP code listed in the capabilities string.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VcpCapability {
+    pub code: u8,
+    /// Allowed values for non-continuous controls such as the input source;
+    /// empty for continuous controls
+    pub values: Vec<u8>,
+    /// Read from the monitor for continuous controls
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<VcpValue>,
+}
+
+impl VcpCapability {
+    pub fn name(&self) -> Option<&'static str> {
+        vcp_name(self.code)
+    }
+}
+
+/// A parsed MCCS capabilities string.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    pub raw: String,
+    pub model: Option<String>,
+    #[serde(rename = "type")]
+    pub display_type: Option<String>,
+    pub mccs_version: Option<String>,
+    /// Supported DDC/CI command opcodes
+    pub commands: Vec<u8>,
+    pub vcp: Vec<VcpCapability>,
+    /// Workarounds applied for this monitor
+    #[serde(skip_serializing_if = "Quirks::is_empty")]
+    pub quirks: Quirks,
+}
+
+/// Index of the parenthesis closing the one `s` starts with.
+fn closing_paren(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            return Some(i);
+        }
+    }
+    None
+}
+
+/// Split `key(value)key(value)...` into pairs, honouring nested parentheses.
+fn groups(s: &str) -> Result<Vec<(&str, &str)>, String> {
+    let mut groups = Vec::new();
+    let mut rest = s.trim();
+
+    while !rest.is_empty() {
+        let open = rest
+            .find('(')
+            .ok_or_else(|| format!("Expected '(' after {}", rest))?;
+        let close = closing_paren(&rest[open..])
+            .map(|i| open + i)
+            .ok_or_else(|| format!("Unbalanced parentheses in {}", rest))?;
+
+        groups.push((rest[..open].trim(), &rest[open + 1..close]));
+        rest = rest[close + 1..].trim_start();
+    }
+    Ok(groups)
+}
+
+/// Parse runs of two-digit hex codes, with or without separating spaces.
+fn hex_codes(s: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = s.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() & 1 == 1 {
+        return Err(format!("Odd number of hex digits in {}", s.trim()));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).map_err(|_| format!("Invalid hex code: {}", pair))
+        })
+        .collect()
+}
+
+fn vcp_list(s: &str) -> Result<Vec<VcpCapability>, String> {
+    let mut vcp = Vec::new();
+    let mut rest = s.trim();
+
+    while !rest.is_empty() {
+        // At most two characters, counted as characters so a garbled
+        // string cannot split one
+        let code_end = rest
+            .char_indices()
+            .take_while(|&(_, c)| !c.is_whitespace() && c != '(')
+            .take(2)
+            .last()
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let code = u8::from_str_radix(&rest[..code_end], 16)
+            .map_err(|_| format!("Invalid VCP code in {}", rest))?;
+        rest = rest[code_end..].trim_start();
+
+        let mut values = Vec::new();
+        if rest.starts_with('(') {
+            let close =
+                closing_paren(rest).ok_or_else(|| format!("Unbalanced parentheses in {}", rest))?;
+            values = hex_codes(&rest[1..close])?;
+            rest = rest[close + 1..].trim_start();
+        }
+
+        vcp.push(VcpCapability {
+            code,
+            values,
+            value: None,
+        });
+    }
+    Ok(vcp)
+}
+
+/// Parse a capabilities string such as
+/// `(prot(monitor)type(lcd)model(U2720Q)cmds(01 02 03)vcp(10 12 60(0F 11))mccs_ver(2.1))`.
+pub fn parse_capabilities(raw: &str) -> Result<Capabilities, String> {
+    let trimmed = raw.trim();
+    let inner = trimmed
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .unwrap_or(trimmed);
+
+    let mut caps = Capabilities {
+        raw: raw.to_string(),
+        ..Default::default()
+    };
+    for (key, value) in groups(inner)? {
+        match key.to_ascii_lowercase().as_str() {
+            "model" => caps.model = Some(value.trim().to_string()),
+            "type" => caps.display_type = Some(value.trim().to_string()),
+            "mccs_ver" => caps.mccs_version = Some(value.trim().to_string()),
+            "cmds" => caps.commands = hex_codes(value)?,
+            "vcp" => caps.vcp = vcp_list(value)?,
+            // prot, mswhql, asset_eep, vcpname and vendor extensions
+            _ => {}
+        }
+    }
+    Ok(caps)
+}
+
+/// Read and parse the capabilities of an external display, including the
+/// current value and range of every continuous control.
+pub fn capabilities(display_id: u32) -> Result<Capabilities, DisplayError> {
+    let quirks = quirks::for_display(display_id);
+    let raw = read_capabilities_with(display_id, &quirks)?;
+    let mut caps = parse_capabilities(&raw).map_err(|_| DisplayError {
+        operation: "read DDC capabilities",
+        display_id,
+        kind: ErrorKind::InvalidResponse("malformed capabilities string"),
+        detail: None,
+    })?;
+
+    for vcp in caps.vcp.iter_mut().filter(|vcp| vcp.values.is_empty()) {
+        // Not every listed control answers; leave those without a range
+        vcp.value = get_vcp_with(display_id, vcp.code, &quirks).ok();
+    }
+    caps.quirks = quirks;
+    Ok(caps)
+}
+
+/// The MCCS name of a VCP code.
+pub fn vcp_name(code: u8) -> Option<&'static str> {
+    Some(match code {
+        0x02 => "New Control Value",
+        0x04 => "Restore Factory Defaults",
+        0x05 => "Restore Factory Brightness/Contrast",
+        0x08 => "Restore Factory Color",
+        0x0B => "Color Temperature Increment",
+        0x0C => "Color Temperature Request",
+        0x10 => "Brightness",
+        0x12 => "Contrast",
+        0x14 => "Color Preset",
+        0x16 => "Red Gain",
+        0x18 => "Green Gain",
+        0x1A => "Blue Gain",
+        0x52 => "Active Control",
+        0x60 => "Input Source",
+        0x62 => "Audio Volume",
+        0x6C => "Red Black Level",
+        0x6E => "Green Black Level",
+        0x70 => "Blue Black Level",
+        0x86 => "Display Scaling",
+        0x87 => "Sharpness",
+        0x8D => "Audio Mute",
+        0xAA => "Screen Orientation",
+        0xAC => "Horizontal Frequency",
+        0xAE => "Vertical Frequency",
+        0xB2 => "Flat Panel Sub-Pixel Layout",
+        0xB6 => "Display Technology Type",
+        0xC0 => "Display Usage Time",
+        0xC6 => "Application Enable Key",
+        0xC8 => "Display Controller Type",
+        0xC9 => "Display Firmware Level",
+        0xCA => "OSD",
+        0xCC => "OSD Language",
+        0xD6 => "Power Mode",
+        0xDC => "Display Mode",
+        0xDF => "VCP Version",
+        0xE0..=0xFF => "Manufacturer Specific",
+        _ => return None,
+    })
+}
+
+/// The MCCS name of an input source value (VCP 0x60).
+pub fn input_source_name(value: u8) -> Option<&'static str> {
+    Some(match value {
+        0x01 => "VGA-1",
+        0x02 => "VGA-2",
+        0x03 => "DVI-1",
+        0x04 => "DVI-2",
+        0x0F => "DisplayPort-1",
+        0x10 => "DisplayPort-2",
+        0x11 => "HDMI-1",
+        0x12 => "HDMI-2",
+        0x1B => "USB-C",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        // Get VCP brightness, from the DDC/CI specification
+        assert_eq!(frame(&[0x01, 0x10]), vec![0x51, 0x82, 0x01, 0x10, 0xAC]);
+
+        let reply = [0x6E, 0x88, 0x02, 0x00, 0x10, 0x00, 0x00, 0x64, 0x00, 0x32];
+        let checksum = reply.iter().fold(REPLY_CHECKSUM_SEED, |c, b| c ^ b);
+        let mut reply = reply.to_vec();
+        reply.push(checksum);
+        assert_eq!(unframe(&reply).unwrap().len(), 8);
+
+        *reply.last_mut().unwrap() ^= 0xFF;
+        assert!(unframe(&reply).is_err());
+    }
+
+    #[test]
+    fn test_vcp_cache() {
+        use std::sync::atomic::{AtomicU16, Ordering};
+        static READS: AtomicU16 = AtomicU16::new(0);
+        fn read(_: u32, _: u8) -> Result<VcpValue, DisplayError> {
+            let current = READS.fetch_add(1, Ordering::SeqCst) + 1;
+            Ok(VcpValue {
+                current,
+                maximum: 100,
+            })
+        }
+
+        let cache = VcpCache::with_ttl(Duration::from_secs(3600));
+        assert_eq!(cache.get_with(2, 0x10, read).unwrap().current, 1);
+        assert_eq!(cache.get_with(2, 0x10, read).unwrap().current, 1);
+        assert_eq!(READS.load(Ordering::SeqCst), 1);
+        cache.forget(2);
+        assert_eq!(cache.get_with(2, 0x10, read).unwrap().current, 2);
+        // Writes update the value without reading the monitor again
+        cache.written(2, 0x10, 70);
+        cache.written(2, 0x12, 50);
+        assert_eq!(
+            cache.get_with(2, 0x10, read).unwrap(),
+            VcpValue {
+                current: 70,
+                maximum: 100
+            }
+        );
+        assert!(!cache.lock().contains_key(&(2, 0x12)));
+        assert_eq!(READS.load(Ordering::SeqCst), 2);
+
+        // A stale value is answered at once and read again in the background
+        let stale = VcpCache::with_ttl(Duration::ZERO);
+        stale.store((3, 0x10), read(3, 0x10));
+        assert_eq!(stale.get_with(3, 0x10, read).unwrap().current, 3);
+        for _ in 0..100 {
+            if stale.lock()[&(3, 0x10)].value.as_ref().unwrap().current == 4 {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the stale value was not read again");
+    }
+
+    #[test]
+    fn test_vcp_cache_failed_refresh() {
+        fn panics(_: u32, _: u8) -> Result<VcpValue, DisplayError> {
+            panic!("monitor went away");
+        }
+
+        let stale = VcpCache::with_ttl(Duration::ZERO);
+        let value = VcpValue {
+            current: 50,
+            maximum: 100,
+        };
+        stale.store((3, 0x10), Ok(value));
+        assert_eq!(stale.get_with(3, 0x10, panics), Ok(value));
+        // The next request may try again
+        for _ in 0..100 {
+            if !stale.lock()[&(3, 0x10)].refreshing {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("the failed refresh was never cleared");
+    }
+
+    #[test]
+    fn test_parse_capabilities() {
+        let caps = parse_capabilities(
+            "(prot(monitor)type(LCD)model(U2720Q)cmds(01 02 03 07 0C E3 F3)\
+             vcp(02 04 05 10 12 14(01 05 08 0B) 60(0F 11 1B) D6(01 04 05) E9)mccs_ver(2.1))",
+        )
+        .unwrap();
+
+        assert_eq!(caps.model.as_deref(), Some("U2720Q"));
+        assert_eq!(caps.mccs_version.as_deref(), Some("2.1"));
+        assert_eq!(caps.commands.len(), 7);
+        let input = caps.vcp.iter().find(|v| v.code == 0x60).unwrap();
+        assert_eq!(input.values, vec![0x0F, 0x11, 0x1B]);
+        assert_eq!(input.name(), Some("Input Source"));
+        assert_eq!(caps.vcp.len(), 9);
+
+        // Some monitors leave out the spaces
+        let caps = parse_capabilities("(vcp(021012D6(0104)))").unwrap();
+        let codes: Vec<u8> = caps.vcp.iter().map(|v| v.code).collect();
+        assert_eq!(codes, vec![0x02, 0x10, 0x12, 0xD6]);
+        assert_eq!(caps.vcp[3].values, vec![0x01, 0x04]);
+
+        assert_eq!(
+            parse_capabilities("(vcp(14(01 05) E9))").unwrap().vcp.len(),
+            2
+        );
+        assert!(parse_capabilities("(vcp(10 12").is_err());
+        assert!(parse_capabilities("(vcp(1é 12))").is_err());
+        assert!(parse_capabilities("(vcp(10 é))").is_err());
+    }
+}
@@ -0,0 +1,223 @@
+//! Every display this Mac has seen, kept by the daemon.
+//!
+//! Hot desks see many monitors come and go; the database records who was
+//! plugged in when, and what each display was last set to.
+
+use crate::edid::Edid;
+use crate::export::format_utc_timestamp;
+use crate::mode_id::ModeId;
+use crate::DisplayInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// A display that was connected at least once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Device {
+    pub persistent_id: String,
+    pub serial: u32,
+    /// Three-letter PNP id from the EDID, e.g. `DEL`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_id: Option<u32>,
+    /// Monitor name from the EDID
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub display_type: String,
+    /// When the display was first connected, `YYYY-MM-DD HH:MM:SS UTC`
+    pub first_seen: String,
+    /// When the display was last seen connected
+    pub last_seen: String,
+    pub last_mode: ModeId,
+    /// Where the display last sat in the arrangement
+    pub last_origin: (i32, i32),
+}
+
+impl Device {
+    /// The EDID name, or the display type when the EDID has none.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.display_type)
+    }
+}
+
+/// The displays seen so far, in the order they were first connected.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceDb {
+    pub devices: Vec<Device>,
+}
+
+impl DeviceDb {
+    /// Read the database, starting empty when the file does not exist yet.
+    pub fn load(path: &Path) -> Result<DeviceDb, String> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(DeviceDb::default()),
+            Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+        };
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid device database {}: {}", path.display(), e))
+    }
+
+    /// Write the database as JSON, replacing the file in one step.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize device database: {}", e))?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+        }
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, json + "\n")
+            .and_then(|_| std::fs::rename(&partial, path))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Note the connected `displays` as seen at `now`.
+    ///
+    /// `identify` is only asked about displays not in the database yet, so
+    /// EDIDs are read once per monitor. Returns whether a new display was
+    /// added.
+    pub fn record(
+        &mut self,
+        displays: &[DisplayInfo],
+        now: &str,
+        identify: impl Fn(&DisplayInfo) -> Option<Edid>,
+    ) -> bool {
+        let mut added = false;
+        for info in displays {
+            if let Some(device) = self
+                .devices
+                .iter_mut()
+                .find(|device| device.persistent_id == info.persistent_id)
+            {
+                device.last_seen = now.to_string();
+                device.last_mode = info.mode_id;
+                device.last_origin = (info.x, info.y);
+                continue;
+            }
+            let edid = identify(info);
+            self.devices.push(Device {
+                persistent_id: info.persistent_id.clone(),
+                serial: info.serial,
+                manufacturer: edid.as_ref().map(|edid| edid.manufacturer.clone()),
+                product_id: edid.as_ref().map(|edid| edid.product_id),
+                name: edid.and_then(|edid| edid.name),
+                display_type: info.display_type.clone(),
+                first_seen: now.to_string(),
+                last_seen: now.to_string(),
+                last_mode: info.mode_id,
+                last_origin: (info.x, info.y),
+            });
+            added = true;
+        }
+        added
+    }
+
+    /// Aliases for the named external displays that have none in `aliases`,
+    /// made from their EDID names, e.g. `dell-u2720q`.
+    pub fn suggested_aliases(
+        &self,
+        aliases: &BTreeMap<String, String>,
+    ) -> BTreeMap<String, String> {
+        let mut suggested = BTreeMap::new();
+        for device in &self.devices {
+            let Some(name) = &device.name else {
+                continue;
+            };
+            if device.display_type.contains("built")
+                || aliases.values().any(|id| *id == device.persistent_id)
+            {
+                continue;
+            }
+            let base = slug(name);
+            if base.is_empty() {
+                continue;
+            }
+            let mut alias = base.clone();
+            let mut n = 2;
+            while aliases.contains_key(&alias) || suggested.contains_key(&alias) {
+                alias = format!("{}-{}", base, n);
+                n += 1;
+            }
+            suggested.insert(alias, device.persistent_id.clone());
+        }
+        suggested
+    }
+}
+
+/// Lowercase words joined by dashes.
+fn slug(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_ascii_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Where the daemon keeps the device database.
+pub fn devices_path() -> PathBuf {
+    crate::settings::config_dir().join("devices.json")
+}
+
+/// Add the connected displays to `db` and save it to [`devices_path`].
+pub fn record_connected(db: &mut DeviceDb, displays: &[DisplayInfo]) -> Result<bool, String> {
+    let added = db.record(displays, &format_utc_timestamp(SystemTime::now()), |info| {
+        crate::edid::for_display(info.id).ok()
+    });
+    db.save(&devices_path())?;
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    #[test]
+    fn test_record() {
+        let mut db = DeviceDb::default();
+        let mut displays = vec![display(1, "AAAA"), display(2, "BBBB")];
+        let identify = |info: &DisplayInfo| {
+            (info.persistent_id == "BBBB").then(|| Edid {
+                manufacturer: "DEL".to_string(),
+                vendor_id: 4268,
+                product_id: 41200,
+                serial: 0,
+                name: Some("DELL U2720Q".to_string()),
+                native: Some((3840, 2160)),
+                size_mm: Some((597, 336)),
+                version: "1.4".to_string(),
+                color_encoding: crate::edid::ColorEncoding::Rgb444,
+                cta_ycbcr: false,
+                extensions: 0,
+                raw: Vec::new(),
+            })
+        };
+        assert!(db.record(&displays, "2026-01-01 09:00:00 UTC", identify));
+
+        displays[1].x = 2560;
+        displays.remove(0);
+        assert!(!db.record(&displays, "2026-01-02 09:00:00 UTC", |_| {
+            panic!("known displays are not identified again")
+        }));
+        assert_eq!(db.devices.len(), 2);
+        assert_eq!(db.devices[0].last_seen, "2026-01-01 09:00:00 UTC");
+        let dell = &db.devices[1];
+        assert_eq!(dell.label(), "DELL U2720Q");
+        assert_eq!(dell.first_seen, "2026-01-01 09:00:00 UTC");
+        assert_eq!(dell.last_seen, "2026-01-02 09:00:00 UTC");
+        assert_eq!(dell.last_origin, (2560, 0));
+
+        let mut aliases = BTreeMap::new();
+        assert_eq!(
+            db.suggested_aliases(&aliases),
+            BTreeMap::from([("dell-u2720q".to_string(), "BBBB".to_string())])
+        );
+        aliases.insert("dell-u2720q".to_string(), "CCCC".to_string());
+        assert!(db.suggested_aliases(&aliases).contains_key("dell-u2720q-2"));
+        aliases.insert("desk".to_string(), "BBBB".to_string());
+        assert!(db.suggested_aliases(&aliases).is_empty());
+    }
+}
@@ -0,0 +1,245 @@
+//! Pixel density of each display, to pick modes that make windows and text
+//! the same size on mixed monitors.
+//!
+//! What sets the size of the UI is not the panel's pixels per inch but how
+//! many points fit in an inch: a 27" 4K monitor at "looks like 2560x1440"
+//! shows about as much per inch as a 27" 1440p one.
+
+use crate::edid::Edid;
+use crate::{DisplayInfo, DisplayMode};
+use serde::Serialize;
+use std::fmt;
+
+const MM_PER_INCH: f64 = 25.4;
+
+/// How densely a display shows the UI in its current mode.
+#[derive(Debug, Clone, Serialize)]
+pub struct Density {
+    pub display_id: u32,
+    pub name: String,
+    /// Width and height of the panel in millimetres
+    pub size_mm: Option<(f64, f64)>,
+    pub diagonal_inches: Option<f64>,
+    /// The panel's native resolution, from the EDID
+    pub native: Option<(u32, u32)>,
+    /// Pixels per inch of the panel at its native resolution
+    pub ppi: Option<f64>,
+    /// Size of the current mode in points
+    pub points: (u32, u32),
+    /// Backing pixels per point in the current mode, 2 for HiDPI
+    pub scale: f64,
+    /// Points per inch in the current mode; the lower, the larger the UI
+    pub points_per_inch: Option<f64>,
+    /// How large the UI looks next to the reference display, 1.2 for 20% larger
+    pub relative_size: Option<f64>,
+    /// The mode that comes closest to the reference display's points per inch
+    pub closest: Option<ClosestMode>,
+}
+
+/// A mode suggested to match the reference display.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClosestMode {
+    /// The configuration keys selecting it, e.g. `looks-like:2560x1440`
+    pub config: String,
+    pub points_per_inch: f64,
+}
+
+fn diagonal((width, height): (f64, f64)) -> f64 {
+    width.hypot(height)
+}
+
+/// Points (or pixels) per inch of `size` shown on a panel of `size_mm`.
+pub fn per_inch(size: (u32, u32), size_mm: (f64, f64)) -> f64 {
+    diagonal((size.0 as f64, size.1 as f64)) / (diagonal(size_mm) / MM_PER_INCH)
+}
+
+impl Density {
+    /// Measure a display from its current mode and, when there is one, its
+    /// EDID. `fallback_size_mm` is used when the EDID gives no size, as
+    /// built-in panels often do.
+    pub fn measure(
+        info: &DisplayInfo,
+        edid: Option<&Edid>,
+        fallback_size_mm: Option<(f64, f64)>,
+    ) -> Density {
+        let size_mm = edid
+            .and_then(|edid| edid.size_mm)
+            .map(|(w, h)| (w as f64, h as f64))
+            .or(fallback_size_mm);
+        let native = edid.and_then(|edid| edid.native);
+        let points = (info.mode_id.width, info.mode_id.height);
+        Density {
+            display_id: info.id,
+            name: edid
+                .and_then(|edid| edid.name.clone())
+                .unwrap_or_else(|| info.display_type.clone()),
+            size_mm,
+            diagonal_inches: size_mm.map(|size| diagonal(size) / MM_PER_INCH),
+            native,
+            ppi: native
+                .zip(size_mm)
+                .map(|(native, size)| per_inch(native, size)),
+            points,
+            scale: info.mode_id.pixel_width as f64 / info.mode_id.width.max(1) as f64,
+            points_per_inch: size_mm.map(|size| per_inch(points, size)),
+            relative_size: None,
+            closest: None,
+        }
+    }
+
+    /// Compare with the reference display, suggesting the mode among `modes`
+    /// that shows the UI at the size closest to the reference's.
+    pub fn compare(&mut self, reference: &Density, modes: &[DisplayMode]) {
+        let (Some(own), Some(target)) = (self.points_per_inch, reference.points_per_inch) else {
+            return;
+        };
+        self.relative_size = Some(target / own);
+        if self.display_id == reference.display_id {
+            return;
+        }
+        self.closest = self
+            .size_mm
+            .and_then(|size_mm| closest_mode(modes, size_mm, target));
+    }
+}
+
+/// The mode among `modes` whose points per inch on a panel of `size_mm` is
+/// closest to `target`; HiDPI modes win ties, as they look sharper.
+pub fn closest_mode(
+    modes: &[DisplayMode],
+    size_mm: (f64, f64),
+    target: f64,
+) -> Option<ClosestMode> {
+    let mode = modes
+        .iter()
+        .filter(|mode| mode.is_safe_for_hardware && !mode.is_interlaced)
+        .min_by(|a, b| {
+            let distance =
+                |mode: &DisplayMode| (per_inch((mode.width, mode.height), size_mm) - target).abs();
+            distance(a)
+                .total_cmp(&distance(b))
+                .then(b.is_scaled.cmp(&a.is_scaled))
+        })?;
+    Some(ClosestMode {
+        config: format!(
+            "{}:{}x{}",
+            if mode.is_scaled { "looks-like" } else { "res" },
+            mode.width,
+            mode.height
+        ),
+        points_per_inch: per_inch((mode.width, mode.height), size_mm),
+    })
+}
+
+impl fmt::Display for Density {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Display {} ({})", self.display_id, self.name)?;
+        match (self.diagonal_inches, self.size_mm) {
+            (Some(inches), Some((w, h))) => write!(f, ": {:.1}\" {:.0}x{:.0}mm", inches, w, h)?,
+            _ => write!(f, ": physical size unknown")?,
+        }
+        if let (Some((w, h)), Some(ppi)) = (self.native, self.ppi) {
+            write!(f, ", {}x{} panel at {:.0} ppi", w, h, ppi)?;
+        }
+        write!(
+            f,
+            "\n  Current: {}x{} points at {}x",
+            self.points.0,
+            self.points.1,
+            (self.scale * 100.0).round() / 100.0
+        )?;
+        if let Some(ppi) = self.points_per_inch {
+            write!(f, ", {:.0} points per inch", ppi)?;
+        }
+        if let Some(relative) = self.relative_size {
+            let percent = ((relative - 1.0) * 100.0).round();
+            if percent > 0.0 {
+                write!(f, ", UI {:.0}% larger", percent)?;
+            } else if percent < 0.0 {
+                write!(f, ", UI {:.0}% smaller", -percent)?;
+            }
+        }
+        if let Some(closest) = &self.closest {
+            write!(
+                f,
+                "\n  Closest match: {} ({:.0} points per inch)",
+                closest.config, closest.points_per_inch
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Measure every display and compare each with `reference`, the display
+/// whose UI size the others should match.
+pub fn densities(displays: &[DisplayInfo], reference: u32) -> Vec<Density> {
+    let mut densities: Vec<Density> = displays
+        .iter()
+        .map(|info| {
+            let edid = crate::edid::for_display(info.id).ok();
+            let fallback = edid
+                .as_ref()
+                .and_then(|edid| edid.size_mm)
+                .is_none()
+                .then(|| crate::info::screen_size_mm(info.id))
+                .flatten();
+            Density::measure(info, edid.as_ref(), fallback)
+        })
+        .collect();
+    let Some(reference) = densities
+        .iter()
+        .find(|d| d.display_id == reference)
+        .cloned()
+    else {
+        return densities;
+    };
+    for density in &mut densities {
+        let modes = if density.display_id == reference.display_id {
+            Vec::new()
+        } else {
+            crate::get_all_modes(density.display_id)
+        };
+        density.compare(&reference, &modes);
+    }
+    densities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::{display, mode};
+
+    #[test]
+    fn test_density() {
+        // A 27" 1440p monitor next to a 27" 4K one at its default 1920x1080
+        let mut qhd = display(1, "AAAA");
+        qhd.mode_id = "2560x1440/2560x1440@60/d8".parse().unwrap();
+        let mut uhd = display(2, "BBBB");
+        uhd.mode_id = "1920x1080/3840x2160@60/d8/hidpi".parse().unwrap();
+        let size = Some((597.0, 336.0));
+
+        let reference = Density::measure(&qhd, None, size);
+        let mut density = Density::measure(&uhd, None, size);
+        assert_eq!(density.scale, 2.0);
+        assert_eq!(density.diagonal_inches.map(f64::round), Some(27.0));
+        assert_eq!(reference.points_per_inch.map(f64::round), Some(109.0));
+
+        let modes = [
+            mode(1, 3840, 2160, false),
+            mode(2, 1920, 1080, true),
+            mode(3, 2560, 1440, true),
+            mode(4, 3008, 1692, true),
+        ];
+        density.compare(&reference, &modes);
+        assert!(density.relative_size.unwrap() > 1.3);
+        assert_eq!(
+            density.closest.as_ref().map(|c| c.config.as_str()),
+            Some("looks-like:2560x1440")
+        );
+        assert!(density.to_string().contains("UI 33% larger"));
+
+        let unknown = Density::measure(&uhd, None, None);
+        assert_eq!(unknown.points_per_inch, None);
+        assert!(unknown.to_string().contains("physical size unknown"));
+    }
+}
@@ -0,0 +1,419 @@
+//! EDID parsing and display override files for monitors that misreport their
+//! capabilities.
+//!
+//! macOS reads `/Library/Displays/Contents/Resources/Overrides` when a display
+//! connects, and an `IODisplayEDID` entry there replaces the EDID the monitor
+//! sent.
+
+use crate::encoding::base64;
+use crate::error::{DisplayError, ErrorKind};
+use crate::executor;
+use serde::Serialize;
+use std::path::PathBuf;
+
+extern "C" {
+    fn ds_read_edid(display_id: u32, buffer: *mut u8, capacity: usize, length: *mut usize) -> i32;
+    fn ds_read_iokit_edid(
+        index: usize,
+        buffer: *mut u8,
+        capacity: usize,
+        length: *mut usize,
+    ) -> i32;
+}
+
+const BLOCK_SIZE: usize = 128;
+const HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+/// The base block and up to 255 extensions.
+const MAX_EDID_LEN: usize = 256 * BLOCK_SIZE;
+/// More displays than any Mac drives, to bound the IOKit enumeration.
+const MAX_IOKIT_DISPLAYS: usize = 16;
+
+/// Maximum image size in centimetres, horizontal then vertical.
+const SCREEN_SIZE_CM: usize = 21;
+/// Feature support byte: bits 3-4 give the colour encodings of digital inputs.
+const FEATURES: usize = 24;
+const DIGITAL_ENCODING_MASK: u8 = 0x18;
+/// The first 18-byte descriptor, the preferred timing on any EDID 1.3 or later.
+const PREFERRED_TIMING: usize = 54;
+const EXTENSION_COUNT: usize = 126;
+
+/// CTA-861 extension tag; byte 3 of the block flags YCbCr support.
+const CTA_TAG: u8 = 0x02;
+const CTA_FLAGS: usize = 3;
+const CTA_YCBCR_MASK: u8 = 0x30;
+
+pub const OVERRIDES_DIR: &str = "/Library/Displays/Contents/Resources/Overrides";
+
+/// Colour encodings a digital input advertises in the base block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorEncoding {
+    Rgb444,
+    Rgb444Ycbcr444,
+    Rgb444Ycbcr422,
+    Rgb444Ycbcr444Ycbcr422,
+    /// Analog inputs describe a colour type instead
+    Analog,
+}
+
+impl ColorEncoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorEncoding::Rgb444 => "RGB only",
+            ColorEncoding::Rgb444Ycbcr444 => "RGB and YCbCr 4:4:4",
+            ColorEncoding::Rgb444Ycbcr422 => "RGB and YCbCr 4:2:2",
+            ColorEncoding::Rgb444Ycbcr444Ycbcr422 => "RGB and YCbCr 4:4:4/4:2:2",
+            ColorEncoding::Analog => "analog",
+        }
+    }
+}
+
+/// The fields of an EDID that overrides and quirks are keyed on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Edid {
+    /// Three-letter PNP id, e.g. `DEL`
+    pub manufacturer: String,
+    /// Manufacturer id as macOS reports it (`CGDisplayVendorNumber`)
+    pub vendor_id: u32,
+    /// Product code as macOS reports it (`CGDisplayModelNumber`)
+    pub product_id: u32,
+    pub serial: u32,
+    pub name: Option<String>,
+    /// Resolution of the preferred timing, the panel's native one
+    pub native: Option<(u32, u32)>,
+    /// Width and height of the image in millimetres
+    pub size_mm: Option<(u32, u32)>,
+    pub version: String,
+    pub color_encoding: ColorEncoding,
+    /// Whether a CTA-861 extension advertises YCbCr support
+    pub cta_ycbcr: bool,
+    /// Extension blocks present, which may be fewer than the base block claims
+    pub extensions: usize,
+    #[serde(skip)]
+    pub raw: Vec<u8>,
+}
+
+fn checksum_ok(block: &[u8]) -> bool {
+    block.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) == 0
+}
+
+fn fix_checksum(block: &mut [u8]) {
+    let sum = block[..BLOCK_SIZE - 1]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    block[BLOCK_SIZE - 1] = 0u8.wrapping_sub(sum);
+}
+
+/// The display name from the 0xFC descriptor, if there is one.
+fn descriptor_name(base: &[u8]) -> Option<String> {
+    base[54..126]
+        .chunks(18)
+        .find(|descriptor| descriptor[..3] == [0, 0, 0] && descriptor[3] == 0xFC)
+        .map(|descriptor| {
+            let text = &descriptor[5..];
+            let end = text.iter().position(|&b| b == b'\n').unwrap_or(text.len());
+            String::from_utf8_lossy(&text[..end]).trim().to_string()
+        })
+        .filter(|name| !name.is_empty())
+}
+
+/// The preferred detailed timing, unless the first descriptor holds
+/// something else.
+fn preferred_timing(base: &[u8]) -> Option<&[u8]> {
+    let timing = &base[PREFERRED_TIMING..PREFERRED_TIMING + 18];
+    (timing[0] != 0 || timing[1] != 0).then_some(timing)
+}
+
+/// The image size from the preferred timing, which is in millimetres, or else
+/// the centimetres of the base block. Projectors leave both at zero.
+fn image_size_mm(base: &[u8]) -> Option<(u32, u32)> {
+    let from_timing = preferred_timing(base).map(|t| {
+        (
+            t[12] as u32 | (t[14] as u32 & 0xF0) << 4,
+            t[13] as u32 | (t[14] as u32 & 0x0F) << 8,
+        )
+    });
+    let from_base = (
+        base[SCREEN_SIZE_CM] as u32 * 10,
+        base[SCREEN_SIZE_CM + 1] as u32 * 10,
+    );
+    from_timing
+        .into_iter()
+        .chain(std::iter::once(from_base))
+        .find(|(w, h)| *w > 0 && *h > 0)
+}
+
+impl Edid {
+    /// Parse an EDID, keeping only the extension blocks that were supplied.
+    pub fn parse(bytes: &[u8]) -> Result<Edid, String> {
+        if bytes.len() < BLOCK_SIZE {
+            return Err(format!(
+                "EDID is {} bytes, expected at least 128",
+                bytes.len()
+            ));
+        }
+        let base = &bytes[..BLOCK_SIZE];
+        if base[..8] != HEADER {
+            return Err("EDID has an invalid header".to_string());
+        }
+        if !checksum_ok(base) {
+            return Err("EDID base block checksum mismatch".to_string());
+        }
+
+        let vendor_id = u16::from_be_bytes([base[8], base[9]]);
+        let manufacturer: String = [10, 5, 0]
+            .iter()
+            .map(|shift| (b'A' - 1 + ((vendor_id >> shift) & 0x1F) as u8) as char)
+            .collect();
+        let color_encoding = if base[20] & 0x80 == 0 {
+            ColorEncoding::Analog
+        } else {
+            match (base[FEATURES] & DIGITAL_ENCODING_MASK) >> 3 {
+                0 => ColorEncoding::Rgb444,
+                1 => ColorEncoding::Rgb444Ycbcr444,
+                2 => ColorEncoding::Rgb444Ycbcr422,
+                _ => ColorEncoding::Rgb444Ycbcr444Ycbcr422,
+            }
+        };
+
+        let claimed = base[EXTENSION_COUNT] as usize;
+        let extensions = claimed.min(bytes.len() / BLOCK_SIZE - 1);
+        let raw = bytes[..(extensions + 1) * BLOCK_SIZE].to_vec();
+        let cta_ycbcr = raw[BLOCK_SIZE..]
+            .chunks(BLOCK_SIZE)
+            .any(|block| block[0] == CTA_TAG && block[CTA_FLAGS] & CTA_YCBCR_MASK != 0);
+
+        Ok(Edid {
+            manufacturer,
+            vendor_id: vendor_id as u32,
+            product_id: u16::from_le_bytes([base[10], base[11]]) as u32,
+            serial: u32::from_le_bytes([base[12], base[13], base[14], base[15]]),
+            name: descriptor_name(base),
+            native: preferred_timing(base).map(|t| {
+                (
+                    t[2] as u32 | (t[4] as u32 & 0xF0) << 4,
+                    t[5] as u32 | (t[7] as u32 & 0xF0) << 4,
+                )
+            }),
+            size_mm: image_size_mm(base),
+            version: format!("{}.{}", base[18], base[19]),
+            color_encoding,
+            cta_ycbcr,
+            extensions,
+            raw,
+        })
+    }
+
+    /// Whether the monitor offers anything besides RGB, which is what makes
+    /// macOS pick YPbPr on some of them.
+    pub fn advertises_ycbcr(&self) -> bool {
+        !matches!(
+            self.color_encoding,
+            ColorEncoding::Rgb444 | ColorEncoding::Analog
+        ) || self.cta_ycbcr
+    }
+
+    /// A copy of the EDID that only advertises RGB 4:4:4, with checksums fixed.
+    pub fn rgb_only(&self) -> Vec<u8> {
+        let mut bytes = self.raw.clone();
+        if self.color_encoding != ColorEncoding::Analog {
+            bytes[FEATURES] &= !DIGITAL_ENCODING_MASK;
+        }
+        // Blocks that could not be read are dropped from the override
+        bytes[EXTENSION_COUNT] = self.extensions as u8;
+        for block in bytes.chunks_mut(BLOCK_SIZE) {
+            if block[0] == CTA_TAG {
+                block[CTA_FLAGS] &= !CTA_YCBCR_MASK;
+            }
+            fix_checksum(block);
+        }
+        bytes
+    }
+
+    /// Where macOS looks for an override of this display.
+    pub fn override_path(&self) -> PathBuf {
+        PathBuf::from(OVERRIDES_DIR)
+            .join(format!("DisplayVendorID-{:x}", self.vendor_id))
+            .join(format!("DisplayProductID-{:x}", self.product_id))
+    }
+
+    /// The contents of an override file that replaces the EDID with `edid`.
+    pub fn override_plist(&self, edid: &[u8]) -> String {
+        let name = self
+            .name
+            .clone()
+            .unwrap_or_else(|| format!("{} {:04x}", self.manufacturer, self.product_id));
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>DisplayProductName</key>
+	<string>{} (RGB)</string>
+	<key>DisplayProductID</key>
+	<integer>{}</integer>
+	<key>DisplayVendorID</key>
+	<integer>{}</integer>
+	<key>IODisplayEDID</key>
+	<data>{}</data>
+</dict>
+</plist>
+"#,
+            xml_escape(&name),
+            self.product_id,
+            self.vendor_id,
+            base64(edid)
+        )
+    }
+}
+
+pub(crate) fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Read the EDID of a display, from the framebuffer on Intel Macs or over
+/// I2C on Apple silicon.
+pub fn read_edid(display_id: u32) -> Result<Vec<u8>, DisplayError> {
+    let result = executor::with_timeout(move || {
+        let mut buffer = vec![0u8; MAX_EDID_LEN];
+        let mut length = 0usize;
+        let status = crate::error::status(|| unsafe {
+            ds_read_edid(display_id, buffer.as_mut_ptr(), buffer.len(), &mut length)
+        });
+        buffer.truncate(length.min(MAX_EDID_LEN));
+        (status, buffer)
+    });
+    match result {
+        Some((status, buffer)) if status.is_ok() => Ok(buffer),
+        Some((status, _)) => Err(DisplayError::from_status("read EDID", display_id, status)),
+        None => Err(DisplayError::timeout(
+            "read EDID",
+            display_id,
+            crate::call_timeout(),
+        )),
+    }
+}
+
+/// The EDIDs of the connected displays, read through IOKit alone.
+///
+/// This works without a window server session, where there are no display ids
+/// to pass to [`for_display`]. It covers displays with a framebuffer EDID
+/// (Intel Macs) and external displays answering over I2C (Apple silicon), but
+/// not built-in panels on Apple silicon. Unreadable EDIDs are skipped.
+pub fn iokit_edids() -> Vec<Edid> {
+    let mut edids = Vec::new();
+    for index in 0..MAX_IOKIT_DISPLAYS {
+        let result = executor::with_timeout(move || {
+            let mut buffer = vec![0u8; MAX_EDID_LEN];
+            let mut length = 0usize;
+            let status = crate::error::status(|| unsafe {
+                ds_read_iokit_edid(index, buffer.as_mut_ptr(), buffer.len(), &mut length)
+            });
+            buffer.truncate(length.min(MAX_EDID_LEN));
+            (status, buffer)
+        });
+        match result {
+            Some((status, bytes)) if status.is_ok() => edids.extend(Edid::parse(&bytes).ok()),
+            Some((status, _))
+                if ErrorKind::from_code(status.code) == ErrorKind::DisplayNotFound =>
+            {
+                break
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+    edids
+}
+
+/// Read and parse the EDID of a display.
+pub fn for_display(display_id: u32) -> Result<Edid, DisplayError> {
+    let bytes = read_edid(display_id)?;
+    Edid::parse(&bytes).map_err(|_| DisplayError {
+        operation: "read EDID",
+        display_id,
+        kind: ErrorKind::InvalidResponse("malformed EDID"),
+        detail: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A Dell base block with a CTA extension, both advertising YCbCr.
+    fn sample() -> Vec<u8> {
+        let mut base = vec![0u8; BLOCK_SIZE];
+        base[..8].copy_from_slice(&HEADER);
+        base[8..10].copy_from_slice(&[0x10, 0xAC]);
+        base[10..12].copy_from_slice(&[0xA2, 0x41]);
+        base[18..20].copy_from_slice(&[1, 4]);
+        base[20] = 0xA5;
+        base[SCREEN_SIZE_CM..SCREEN_SIZE_CM + 2].copy_from_slice(&[60, 34]);
+        base[FEATURES] = 0x3A;
+        // 3840x2160 on a 597x336mm panel
+        base[54..72].copy_from_slice(&[
+            0x08, 0xE8, 0x00, 0x30, 0xF2, 0x70, 0x5A, 0x80, 0xB0, 0x58, 0x8A, 0x00, 0x55, 0x50,
+            0x21, 0x00, 0x00, 0x1E,
+        ]);
+        base[72..77].copy_from_slice(&[0, 0, 0, 0xFC, 0]);
+        base[77..90].copy_from_slice(b"DELL U2720Q\n ");
+        base[EXTENSION_COUNT] = 1;
+        fix_checksum(&mut base);
+
+        let mut cta = vec![0u8; BLOCK_SIZE];
+        cta[0] = CTA_TAG;
+        cta[1] = 3;
+        cta[CTA_FLAGS] = 0x70;
+        fix_checksum(&mut cta);
+
+        base.extend(cta);
+        base
+    }
+
+    #[test]
+    fn test_parse() {
+        let edid = Edid::parse(&sample()).unwrap();
+        assert_eq!(edid.manufacturer, "DEL");
+        assert_eq!(edid.vendor_id, 0x10AC);
+        assert_eq!(edid.product_id, 0x41A2);
+        assert_eq!(edid.name.as_deref(), Some("DELL U2720Q"));
+        assert_eq!(edid.native, Some((3840, 2160)));
+        assert_eq!(edid.size_mm, Some((597, 336)));
+        assert_eq!(edid.color_encoding, ColorEncoding::Rgb444Ycbcr444Ycbcr422);
+        assert!(edid.advertises_ycbcr());
+        assert_eq!(
+            edid.override_path(),
+            PathBuf::from(OVERRIDES_DIR).join("DisplayVendorID-10ac/DisplayProductID-41a2")
+        );
+
+        let mut corrupt = sample();
+        corrupt[30] ^= 0xFF;
+        assert!(Edid::parse(&corrupt).is_err());
+        assert!(Edid::parse(&sample()[..64]).is_err());
+
+        // Without a preferred timing the size comes from the base block
+        let mut no_timing = sample();
+        no_timing[54..72].fill(0);
+        fix_checksum(&mut no_timing[..BLOCK_SIZE]);
+        let edid = Edid::parse(&no_timing).unwrap();
+        assert_eq!(edid.native, None);
+        assert_eq!(edid.size_mm, Some((600, 340)));
+    }
+
+    #[test]
+    fn test_rgb_only() {
+        let edid = Edid::parse(&sample()).unwrap();
+        let fixed = Edid::parse(&edid.rgb_only()).unwrap();
+        assert_eq!(fixed.color_encoding, ColorEncoding::Rgb444);
+        assert!(!fixed.advertises_ycbcr());
+        assert!(fixed.raw.chunks(BLOCK_SIZE).all(checksum_ok));
+
+        // A missing extension is dropped instead of left dangling
+        let base_only = Edid::parse(&sample()[..BLOCK_SIZE]).unwrap();
+        assert_eq!(base_only.extensions, 0);
+        assert_eq!(base_only.rgb_only()[EXTENSION_COUNT], 0);
+    }
+}
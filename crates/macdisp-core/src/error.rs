@@ -0,0 +1,428 @@
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::c_char;
+use std::time::Duration;
+
+const ERROR_MESSAGE_LEN: usize = 256;
+
+#[repr(C)]
+struct RawError {
+    code: i32,
+    message: [c_char; ERROR_MESSAGE_LEN],
+}
+
+extern "C" {
+    fn ds_take_last_error(error: *mut RawError);
+}
+
+/// Why a display call failed, decoded from a CGError or a shim return code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// kCGErrorFailure (1000)
+    Failure,
+    /// kCGErrorIllegalArgument (1001)
+    IllegalArgument,
+    /// kCGErrorInvalidConnection (1002)
+    InvalidConnection,
+    /// kCGErrorInvalidContext (1003)
+    InvalidContext,
+    /// kCGErrorCannotComplete (1004)
+    CannotComplete,
+    /// kCGErrorNotImplemented (1006)
+    NotImplemented,
+    /// kCGErrorRangeCheck (1007)
+    RangeCheck,
+    /// kCGErrorTypeCheck (1008)
+    TypeCheck,
+    /// kCGErrorInvalidOperation (1010)
+    InvalidOperation,
+    /// kCGErrorNoneAvailable (1011)
+    NoneAvailable,
+    /// The shim was called with a missing or malformed argument
+    InvalidArgument,
+    /// The display id did not match any online display
+    DisplayNotFound,
+    /// The display does not offer the requested mode
+    ModeNotFound,
+    /// The display does not support the operation
+    NotSupported,
+    /// The call did not return within the watchdog timeout
+    Timeout(Duration),
+    /// The shim returned data that failed validation
+    InvalidResponse(&'static str),
+    /// The display accepted a mode but kept reverting to another one
+    ModeRejected { requested: u32, actual: u32 },
+    /// A newer mode switch of the display was made instead
+    Superseded,
+    /// A code not listed above
+    Unknown(i32),
+}
+
+impl ErrorKind {
+    pub fn from_code(code: i32) -> Self {
+        match code {
+            1000 => ErrorKind::Failure,
+            1001 => ErrorKind::IllegalArgument,
+            1002 => ErrorKind::InvalidConnection,
+            1003 => ErrorKind::InvalidContext,
+            1004 => ErrorKind::CannotComplete,
+            1006 => ErrorKind::NotImplemented,
+            1007 => ErrorKind::RangeCheck,
+            1008 => ErrorKind::TypeCheck,
+            1010 => ErrorKind::InvalidOperation,
+            1011 => ErrorKind::NoneAvailable,
+            -1 => ErrorKind::InvalidArgument,
+            -2 => ErrorKind::DisplayNotFound,
+            -3 => ErrorKind::ModeNotFound,
+            -4 => ErrorKind::NotSupported,
+            other => ErrorKind::Unknown(other),
+        }
+    }
+
+    pub fn code(&self) -> i32 {
+        match self {
+            ErrorKind::Failure => 1000,
+            ErrorKind::IllegalArgument => 1001,
+            ErrorKind::InvalidConnection => 1002,
+            ErrorKind::InvalidContext => 1003,
+            ErrorKind::CannotComplete => 1004,
+            ErrorKind::NotImplemented => 1006,
+            ErrorKind::RangeCheck => 1007,
+            ErrorKind::TypeCheck => 1008,
+            ErrorKind::InvalidOperation => 1010,
+            ErrorKind::NoneAvailable => 1011,
+            ErrorKind::InvalidArgument => -1,
+            ErrorKind::DisplayNotFound => -2,
+            ErrorKind::ModeNotFound => -3,
+            ErrorKind::NotSupported => -4,
+            // Detected on the Rust side, never returned by the shim
+            ErrorKind::Timeout(_) => -100,
+            ErrorKind::InvalidResponse(_) => -101,
+            ErrorKind::ModeRejected { .. } => -102,
+            ErrorKind::Superseded => -103,
+            ErrorKind::Unknown(code) => *code,
+        }
+    }
+
+    /// The CoreGraphics constant name, if the code is a CGError.
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            ErrorKind::Failure => Some("kCGErrorFailure"),
+            ErrorKind::IllegalArgument => Some("kCGErrorIllegalArgument"),
+            ErrorKind::InvalidConnection => Some("kCGErrorInvalidConnection"),
+            ErrorKind::InvalidContext => Some("kCGErrorInvalidContext"),
+            ErrorKind::CannotComplete => Some("kCGErrorCannotComplete"),
+            ErrorKind::NotImplemented => Some("kCGErrorNotImplemented"),
+            ErrorKind::RangeCheck => Some("kCGErrorRangeCheck"),
+            ErrorKind::TypeCheck => Some("kCGErrorTypeCheck"),
+            ErrorKind::InvalidOperation => Some("kCGErrorInvalidOperation"),
+            ErrorKind::NoneAvailable => Some("kCGErrorNoneAvailable"),
+            _ => None,
+        }
+    }
+
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            ErrorKind::Failure => "the window server reported a general failure",
+            ErrorKind::IllegalArgument => "the display id, mode or position was rejected",
+            ErrorKind::InvalidConnection => "there is no connection to the window server",
+            ErrorKind::InvalidContext => "the configuration transaction was no longer valid",
+            ErrorKind::CannotComplete => "the window server could not complete the change",
+            ErrorKind::NotImplemented => "this operation is not supported on this macOS version",
+            ErrorKind::RangeCheck => "a value was out of range for this display",
+            ErrorKind::TypeCheck => "a value had the wrong type",
+            ErrorKind::InvalidOperation => "the operation is not allowed in the current state",
+            ErrorKind::NoneAvailable => "the requested resource is not available",
+            ErrorKind::InvalidArgument => "an argument was missing or malformed",
+            ErrorKind::DisplayNotFound => "the display is not connected or not online",
+            ErrorKind::ModeNotFound => "the display does not offer the requested mode",
+            ErrorKind::NotSupported => "the display does not support this operation",
+            ErrorKind::Timeout(_) => "the call did not return in time",
+            ErrorKind::InvalidResponse(_) => "the display service returned malformed data",
+            ErrorKind::ModeRejected { .. } => "mode rejected by display",
+            ErrorKind::Superseded => "a newer mode switch of the display replaced it",
+            ErrorKind::Unknown(_) => "an unrecognized error occurred",
+        }
+    }
+
+    /// What the user can try next, when there is something useful to suggest.
+    pub fn remedy(&self) -> Option<&'static str> {
+        match self {
+            ErrorKind::IllegalArgument | ErrorKind::DisplayNotFound => {
+                Some("Check the display id with `macdisp list`.")
+            }
+            ErrorKind::InvalidConnection => Some(
+                "Run macdisp from a logged-in GUI session; SSH sessions and launch daemons outside the user session cannot change displays.",
+            ),
+            ErrorKind::InvalidContext | ErrorKind::CannotComplete => Some(
+                "Another app may be reconfiguring displays at the same time; wait a moment and retry.",
+            ),
+            ErrorKind::ModeNotFound | ErrorKind::RangeCheck => {
+                Some("List the supported modes with `macdisp modes <display_id>`.")
+            }
+            ErrorKind::NotImplemented => {
+                Some("Use a different option or check for a macdisp update.")
+            }
+            ErrorKind::NotSupported => Some(
+                "Built-in and Apple displays support it through DisplayServices; most other external monitors do not.",
+            ),
+            ErrorKind::Timeout(_) => Some(
+                "A display may be asleep or half-connected; wake or reconnect it and retry, or raise call_timeout_ms.",
+            ),
+            ErrorKind::InvalidOperation => Some(
+                "Displays in a mirror set or the only active display cannot always be changed; adjust the mirror set first.",
+            ),
+            ErrorKind::ModeRejected { .. } => Some(
+                "The monitor may not support this mode over its current cable or input; try a lower refresh rate or resolution.",
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// A failed call to change a display.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayError {
+    /// What was being attempted, e.g. "set display mode"
+    pub operation: &'static str,
+    pub display_id: u32,
+    pub kind: ErrorKind,
+    /// What the failing system call reported, e.g. "CGConfigureDisplayOrigin
+    /// rejected (-5000,0) for display 2", when the shim recorded it
+    pub detail: Option<String>,
+}
+
+impl DisplayError {
+    pub fn new(operation: &'static str, display_id: u32, code: i32) -> Self {
+        DisplayError {
+            operation,
+            display_id,
+            kind: ErrorKind::from_code(code),
+            detail: None,
+        }
+    }
+
+    pub fn timeout(operation: &'static str, display_id: u32, after: Duration) -> Self {
+        DisplayError {
+            operation,
+            display_id,
+            kind: ErrorKind::Timeout(after),
+            detail: None,
+        }
+    }
+
+    pub(crate) fn from_status(operation: &'static str, display_id: u32, status: Status) -> Self {
+        DisplayError {
+            detail: status.detail,
+            ..DisplayError::new(operation, display_id, status.code)
+        }
+    }
+}
+
+impl fmt::Display for DisplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to {} on display {}: {} ",
+            self.operation,
+            self.display_id,
+            self.kind.explanation()
+        )?;
+        match (self.kind, self.kind.name()) {
+            (ErrorKind::Timeout(after), _) => {
+                write!(f, "(timed out after {:.1}s)", after.as_secs_f64())?
+            }
+            (ErrorKind::InvalidResponse(reason), _) => write!(f, "({})", reason)?,
+            (ErrorKind::ModeRejected { requested, actual }, _) => write!(
+                f,
+                "(switched back to mode {} instead of mode {})",
+                actual, requested
+            )?,
+            (_, Some(name)) => write!(f, "({}, error code {}", name, self.kind.code())?,
+            (_, None) => write!(f, "(error code {}", self.kind.code())?,
+        }
+        if !matches!(
+            self.kind,
+            ErrorKind::Timeout(_) | ErrorKind::InvalidResponse(_) | ErrorKind::ModeRejected { .. }
+        ) {
+            match &self.detail {
+                Some(detail) => write!(f, "; {})", detail)?,
+                None => write!(f, ")")?,
+            }
+        }
+        if let Some(remedy) = self.kind.remedy() {
+            write!(f, ". {}", remedy)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DisplayError {}
+
+impl From<DisplayError> for String {
+    fn from(error: DisplayError) -> Self {
+        error.to_string()
+    }
+}
+
+/// A shim return code, with what the shim recorded about the failure.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Status {
+    pub code: i32,
+    pub detail: Option<String>,
+}
+
+impl Status {
+    pub fn is_ok(&self) -> bool {
+        self.code == 0
+    }
+}
+
+impl From<i32> for Status {
+    fn from(code: i32) -> Self {
+        Status { code, detail: None }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "error code {}", self.code)?;
+        if let Some(detail) = &self.detail {
+            write!(f, " ({})", detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Make a shim call and collect the failure it recorded.
+///
+/// The shim records failures per thread, so this must run on the thread that
+/// makes the call, i.e. inside the [`with_timeout`](crate::executor::with_timeout)
+/// closure.
+pub(crate) fn status(call: impl FnOnce() -> i32) -> Status {
+    unsafe { ds_take_last_error(std::ptr::null_mut()) };
+    let code = call();
+    if code == 0 {
+        return Status::default();
+    }
+
+    let mut raw = RawError {
+        code: 0,
+        message: [0; ERROR_MESSAGE_LEN],
+    };
+    unsafe { ds_take_last_error(&mut raw) };
+    // A failure that returned early without recording leaves nothing, or a
+    // record from a fallback path with a different code
+    let detail = (raw.code == code)
+        .then(|| {
+            raw.message[ERROR_MESSAGE_LEN - 1] = 0;
+            unsafe { CStr::from_ptr(raw.message.as_ptr()) }
+                .to_string_lossy()
+                .into_owned()
+        })
+        .filter(|message| !message.is_empty());
+    Status { code, detail }
+}
+
+/// Turn a shim return code into a `Result`; `None` means the call timed out.
+pub(crate) fn check(
+    operation: &'static str,
+    display_id: u32,
+    status: Option<impl Into<Status>>,
+) -> Result<(), DisplayError> {
+    match status.map(Into::into) {
+        Some(status) if status.is_ok() => Ok(()),
+        Some(status) => Err(DisplayError::from_status(operation, display_id, status)),
+        None => Err(DisplayError::timeout(
+            operation,
+            display_id,
+            crate::call_timeout(),
+        )),
+    }
+}
+
+/// Turn a validated shim response into a `Result`; `None` means the call timed out.
+pub(crate) fn check_response<T>(
+    operation: &'static str,
+    display_id: u32,
+    response: Option<Result<T, &'static str>>,
+) -> Result<T, DisplayError> {
+    match response {
+        Some(Ok(value)) => Ok(value),
+        Some(Err(reason)) => Err(DisplayError {
+            operation,
+            display_id,
+            kind: ErrorKind::InvalidResponse(reason),
+            detail: None,
+        }),
+        None => Err(DisplayError::timeout(
+            operation,
+            display_id,
+            crate::call_timeout(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_codes() {
+        for code in [
+            1000, 1001, 1002, 1003, 1004, 1006, 1007, 1008, 1010, 1011, -1, -2, -3, -4,
+        ] {
+            let kind = ErrorKind::from_code(code);
+            assert!(!matches!(kind, ErrorKind::Unknown(_)));
+            assert_eq!(kind.code(), code);
+        }
+        assert_eq!(ErrorKind::from_code(1005), ErrorKind::Unknown(1005));
+    }
+
+    #[test]
+    fn test_message() {
+        let error = DisplayError::new("set display mode", 2, 1001);
+        assert_eq!(
+            error.to_string(),
+            "Failed to set display mode on display 2: the display id, mode or position was rejected \
+             (kCGErrorIllegalArgument, error code 1001). Check the display id with `macdisp list`."
+        );
+        assert!(check("configure display", 1, Some(0)).is_ok());
+
+        let error = DisplayError::timeout("set display mode", 3, Duration::from_secs(10));
+        assert!(error.to_string().contains("(timed out after 10.0s)"));
+    }
+
+    #[test]
+    fn test_message_with_detail() {
+        let status = Status {
+            code: 1001,
+            detail: Some("CGConfigureDisplayOrigin rejected (-5000,0) for display 2".to_string()),
+        };
+        let error = check("configure display", 2, Some(status)).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::IllegalArgument);
+        assert!(error.to_string().contains(
+            "(kCGErrorIllegalArgument, error code 1001; CGConfigureDisplayOrigin rejected \
+             (-5000,0) for display 2). Check"
+        ));
+
+        let status = Status {
+            code: -4,
+            detail: Some("DisplayServicesSetBrightness returned 7".to_string()),
+        };
+        assert_eq!(
+            status.to_string(),
+            "error code -4 (DisplayServicesSetBrightness returned 7)"
+        );
+        assert_eq!(Status::from(-4).to_string(), "error code -4");
+
+        let error = DisplayError::new("set brightness", 2, -4);
+        assert_eq!(ErrorKind::NotSupported.name(), None);
+        assert_eq!(
+            error.to_string(),
+            "Failed to set brightness on display 2: the display does not support this operation \
+             (error code -4). Built-in and Apple displays support it through DisplayServices; most \
+             other external monitors do not."
+        );
+    }
+}
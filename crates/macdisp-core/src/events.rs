@@ -0,0 +1,538 @@
+use crate::executor::guard_callback;
+use crate::mode_id::format_hz;
+use crate::{
+    get_all_display_info, get_current_mode, get_display_info, get_online_displays, is_asleep,
+    DisplayInfo, DisplayMode,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::os::raw::c_void;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// CGDisplayChangeSummaryFlags
+const BEGIN_CONFIGURATION_FLAG: u32 = 1 << 0;
+const MOVED_FLAG: u32 = 1 << 1;
+const SET_MAIN_FLAG: u32 = 1 << 2;
+const SET_MODE_FLAG: u32 = 1 << 3;
+const ADD_FLAG: u32 = 1 << 4;
+const REMOVE_FLAG: u32 = 1 << 5;
+const ENABLED_FLAG: u32 = 1 << 8;
+const DISABLED_FLAG: u32 = 1 << 9;
+const MIRROR_FLAG: u32 = 1 << 10;
+const UNMIRROR_FLAG: u32 = 1 << 11;
+const DESKTOP_SHAPE_CHANGED_FLAG: u32 = 1 << 12;
+
+type ReconfigurationCallback = extern "C" fn(display: u32, flags: u32, user_info: *mut c_void);
+type DisplayPowerCallback = extern "C" fn(context: *mut c_void);
+
+extern "C" {
+    fn CGDisplayRegisterReconfigurationCallback(
+        callback: ReconfigurationCallback,
+        user_info: *mut c_void,
+    ) -> i32;
+    fn CGDisplayRemoveReconfigurationCallback(
+        callback: ReconfigurationCallback,
+        user_info: *mut c_void,
+    ) -> i32;
+    fn ds_watch_display_power(
+        callback: DisplayPowerCallback,
+        context: *mut c_void,
+        watch: *mut *mut c_void,
+    ) -> i32;
+    fn ds_unwatch_display_power(watch: *mut c_void);
+}
+
+/// What changed about a display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DisplayEventKind {
+    Added,
+    Removed,
+    Enabled,
+    Disabled,
+    Mirrored,
+    Unmirrored,
+    ModeChanged,
+    Moved,
+    MainChanged,
+    Reconfigured,
+    /// The display went to sleep while staying connected
+    Asleep,
+    /// The display woke up from sleep
+    Awake,
+}
+
+impl DisplayEventKind {
+    /// Pick the most significant change from a CGDisplayChangeSummaryFlags value.
+    pub fn from_flags(flags: u32) -> Self {
+        const PRIORITY: [(u32, DisplayEventKind); 9] = [
+            (ADD_FLAG, DisplayEventKind::Added),
+            (REMOVE_FLAG, DisplayEventKind::Removed),
+            (ENABLED_FLAG, DisplayEventKind::Enabled),
+            (DISABLED_FLAG, DisplayEventKind::Disabled),
+            (MIRROR_FLAG, DisplayEventKind::Mirrored),
+            (UNMIRROR_FLAG, DisplayEventKind::Unmirrored),
+            (SET_MODE_FLAG, DisplayEventKind::ModeChanged),
+            (MOVED_FLAG, DisplayEventKind::Moved),
+            (SET_MAIN_FLAG, DisplayEventKind::MainChanged),
+        ];
+
+        PRIORITY
+            .iter()
+            .find(|(flag, _)| flags & flag != 0)
+            .map(|(_, kind)| *kind)
+            .unwrap_or(DisplayEventKind::Reconfigured)
+    }
+
+    /// Whether this reports a display going to sleep or waking up rather than
+    /// a reconfiguration.
+    pub fn is_sleep_change(&self) -> bool {
+        matches!(self, DisplayEventKind::Asleep | DisplayEventKind::Awake)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DisplayEventKind::Added => "added",
+            DisplayEventKind::Removed => "removed",
+            DisplayEventKind::Enabled => "enabled",
+            DisplayEventKind::Disabled => "disabled",
+            DisplayEventKind::Mirrored => "mirrored",
+            DisplayEventKind::Unmirrored => "unmirrored",
+            DisplayEventKind::ModeChanged => "mode_changed",
+            DisplayEventKind::Moved => "moved",
+            DisplayEventKind::MainChanged => "main_changed",
+            DisplayEventKind::Reconfigured => "reconfigured",
+            DisplayEventKind::Asleep => "asleep",
+            DisplayEventKind::Awake => "awake",
+        }
+    }
+}
+
+/// A display reconfiguration reported by CoreGraphics, or a display going to
+/// sleep or waking up.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisplayEvent {
+    pub kind: DisplayEventKind,
+    pub display_id: u32,
+    pub persistent_id: Option<String>,
+    /// CGDisplayChangeSummaryFlags; 0 for sleep and wake
+    pub flags: u32,
+    /// Seconds since the Unix epoch
+    pub timestamp: f64,
+    /// State after the change; `None` for removed displays
+    pub info: Option<DisplayInfo>,
+}
+
+impl DisplayEvent {
+    /// Environment variables describing this event for hook commands.
+    pub fn env_vars(&self) -> Vec<(String, String)> {
+        let mut vars = vec![
+            ("MACDISP_EVENT".to_string(), self.kind.as_str().to_string()),
+            (
+                "MACDISP_DISPLAY_ID".to_string(),
+                self.display_id.to_string(),
+            ),
+            ("MACDISP_FLAGS".to_string(), self.flags.to_string()),
+        ];
+
+        if let Some(uuid) = &self.persistent_id {
+            vars.push(("MACDISP_DISPLAY_UUID".to_string(), uuid.clone()));
+        }
+
+        if let Some(info) = &self.info {
+            vars.push((
+                "MACDISP_RESOLUTION".to_string(),
+                format!("{}x{}", info.width, info.height),
+            ));
+            vars.push(("MACDISP_HZ".to_string(), format_hz(info.hz)));
+            vars.push((
+                "MACDISP_ORIGIN".to_string(),
+                format!("{},{}", info.x, info.y),
+            ));
+            vars.push(("MACDISP_ROTATION".to_string(), info.rotation.to_string()));
+            vars.push(("MACDISP_IS_MAIN".to_string(), info.is_main.to_string()));
+            vars.push((
+                "MACDISP_DISPLAY_TYPE".to_string(),
+                info.display_type.clone(),
+            ));
+        }
+
+        vars
+    }
+}
+
+struct WatchState<'a> {
+    known: HashMap<u32, String>,
+    /// Online displays that were asleep at the last check
+    asleep: HashSet<u32>,
+    handler: Box<dyn FnMut(DisplayEvent) + 'a>,
+}
+
+fn timestamp() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+fn asleep_displays() -> HashSet<u32> {
+    get_online_displays()
+        .into_iter()
+        .filter(|&id| is_asleep(id))
+        .collect()
+}
+
+/// Report the displays that went to sleep or woke up since the last check.
+fn report_sleep_changes(state: &mut WatchState) {
+    let asleep = asleep_displays();
+    let mut changes: Vec<(u32, DisplayEventKind)> = asleep
+        .difference(&state.asleep)
+        .map(|&id| (id, DisplayEventKind::Asleep))
+        .chain(
+            state
+                .asleep
+                .difference(&asleep)
+                .map(|&id| (id, DisplayEventKind::Awake)),
+        )
+        .collect();
+    changes.sort_by_key(|&(id, _)| id);
+    state.asleep = asleep;
+
+    for (display, kind) in changes {
+        let info = get_display_info(display);
+        let persistent_id = info
+            .as_ref()
+            .map(|info| info.persistent_id.clone())
+            .or_else(|| state.known.get(&display).cloned());
+        (state.handler)(DisplayEvent {
+            kind,
+            display_id: display,
+            persistent_id,
+            flags: 0,
+            timestamp: timestamp(),
+            info,
+        });
+    }
+}
+
+extern "C" fn display_power_callback(context: *mut c_void) {
+    let state = unsafe { &mut *(context as *mut WatchState) };
+    guard_callback("display power", || report_sleep_changes(state));
+}
+
+extern "C" fn reconfiguration_callback(display: u32, flags: u32, user_info: *mut c_void) {
+    // Each change is reported twice; only act once the change has completed.
+    if flags & BEGIN_CONFIGURATION_FLAG != 0 {
+        return;
+    }
+
+    let state = unsafe { &mut *(user_info as *mut WatchState) };
+    guard_callback("display reconfiguration", || {
+        report_reconfiguration(state, display, flags)
+    });
+}
+
+fn report_reconfiguration(state: &mut WatchState, display: u32, flags: u32) {
+    let kind = DisplayEventKind::from_flags(flags);
+
+    let info = if kind == DisplayEventKind::Removed {
+        None
+    } else {
+        get_display_info(display)
+    };
+
+    let persistent_id = match &info {
+        Some(info) => {
+            state.known.insert(display, info.persistent_id.clone());
+            Some(info.persistent_id.clone())
+        }
+        None if kind == DisplayEventKind::Removed => state.known.remove(&display),
+        None => state.known.get(&display).cloned(),
+    };
+
+    // Shape changes without anything else are a side effect of other events.
+    if kind != DisplayEventKind::Reconfigured || flags & DESKTOP_SHAPE_CHANGED_FLAG == 0 {
+        (state.handler)(DisplayEvent {
+            kind,
+            display_id: display,
+            persistent_id,
+            flags,
+            timestamp: timestamp(),
+            info,
+        });
+    }
+
+    // A monitor going into standby on its own does not wake the wrangler, but
+    // usually leaves the active display list.
+    report_sleep_changes(state);
+}
+
+/// Call `handler` for every display reconfiguration, and whenever a display
+/// goes to sleep or wakes up, until the process exits.
+///
+/// This runs the current thread's CFRunLoop and never returns unless the
+/// callback could not be registered.
+pub fn watch<F>(handler: F) -> Result<(), String>
+where
+    F: FnMut(DisplayEvent),
+{
+    let known = get_all_display_info()
+        .into_iter()
+        .map(|info| (info.id, info.persistent_id))
+        .collect();
+
+    let mut state = WatchState {
+        known,
+        asleep: asleep_displays(),
+        handler: Box::new(handler),
+    };
+    let user_info = &mut state as *mut WatchState as *mut c_void;
+
+    let result =
+        unsafe { CGDisplayRegisterReconfigurationCallback(reconfiguration_callback, user_info) };
+    if result != 0 {
+        return Err(format!(
+            "Failed to register display reconfiguration callback: error code {}",
+            result
+        ));
+    }
+
+    // Reconfiguration events still arrive without sleep tracking.
+    let mut power_watch = std::ptr::null_mut();
+    let result =
+        unsafe { ds_watch_display_power(display_power_callback, user_info, &mut power_watch) };
+    if result != 0 {
+        crate::output::warning(&format!(
+            "Display sleep events disabled: error code {}",
+            result
+        ));
+    }
+
+    core_foundation::runloop::CFRunLoop::run_current();
+
+    unsafe {
+        CGDisplayRemoveReconfigurationCallback(reconfiguration_callback, user_info);
+        if !power_watch.is_null() {
+            ds_unwatch_display_power(power_watch);
+        }
+    }
+    Ok(())
+}
+
+/// A display switching from one mode to another, as reported by
+/// [`watch_modes`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ModeChange {
+    pub display_id: u32,
+    /// Seconds since the Unix epoch
+    pub timestamp: f64,
+    /// Seconds since the previous change; `None` for the first one seen
+    pub since_previous: Option<f64>,
+    /// Rotation of the display after the change, for laying out the sizes
+    pub rotation: u32,
+    /// `None` when the mode could not be read, e.g. while the display was off
+    pub from: Option<DisplayMode>,
+    pub to: Option<DisplayMode>,
+}
+
+/// The current mode of one display, to tell which events changed it.
+struct ModeTracker {
+    display_id: u32,
+    current: Option<DisplayMode>,
+    changed_at: Option<f64>,
+}
+
+impl ModeTracker {
+    /// Record the mode read after an event, returning the change if it differs.
+    ///
+    /// Modes are compared by [`ModeId`](crate::mode_id::ModeId), so a refresh
+    /// rate switch at the same resolution counts as a change.
+    fn update(
+        &mut self,
+        timestamp: f64,
+        rotation: u32,
+        mode: Option<DisplayMode>,
+    ) -> Option<ModeChange> {
+        let id = |mode: &Option<DisplayMode>| mode.as_ref().map(DisplayMode::mode_id);
+        if id(&self.current) == id(&mode) {
+            return None;
+        }
+        let change = ModeChange {
+            display_id: self.display_id,
+            timestamp,
+            since_previous: self.changed_at.map(|at| timestamp - at),
+            rotation,
+            from: std::mem::replace(&mut self.current, mode.clone()),
+            to: mode,
+        };
+        self.changed_at = Some(timestamp);
+        Some(change)
+    }
+}
+
+/// Call `handler` every time the current mode of `display_id` changes, until
+/// the run loop is stopped.
+///
+/// Changes are read back after each reconfiguration or wake of the display,
+/// so a switch and its revert that macOS reports as one reconfiguration are
+/// not seen.
+pub fn watch_modes<F>(display_id: u32, mut handler: F) -> Result<(), String>
+where
+    F: FnMut(ModeChange),
+{
+    let mut tracker = ModeTracker {
+        display_id,
+        current: get_current_mode(display_id),
+        changed_at: None,
+    };
+    watch(move |event| {
+        if event.display_id != display_id {
+            return;
+        }
+        let rotation = event.info.as_ref().map_or(0, |info| info.rotation);
+        if let Some(change) =
+            tracker.update(event.timestamp, rotation, get_current_mode(display_id))
+        {
+            handler(change);
+        }
+    })
+}
+
+extern "C" fn observe_callback(display: u32, flags: u32, user_info: *mut c_void) {
+    let handler = unsafe { &*(user_info as *const &ReconfigurationHandler) };
+    guard_callback("apply progress", || {
+        if flags & BEGIN_CONFIGURATION_FLAG != 0 {
+            handler(display, None);
+        } else {
+            handler(display, Some(DisplayEventKind::from_flags(flags)));
+        }
+    });
+}
+
+/// Called with `None` when a display is about to be reconfigured and with what
+/// changed once it has been.
+pub type ReconfigurationHandler<'a> = dyn Fn(u32, Option<DisplayEventKind>) + Sync + 'a;
+
+/// Pass the reconfiguration callbacks caused while `work` runs to `handler`.
+///
+/// The callbacks announcing a change arrive while it is being made; the ones
+/// reporting it done are delivered through the run loop, so it is run briefly
+/// after `work` returns. If the callback cannot be registered `work` still runs,
+/// unobserved.
+pub fn observe_reconfiguration<R>(handler: &ReconfigurationHandler, work: impl FnOnce() -> R) -> R {
+    let user_info = &handler as *const &ReconfigurationHandler as *mut c_void;
+    let registration =
+        (unsafe { CGDisplayRegisterReconfigurationCallback(observe_callback, user_info) } == 0)
+            .then_some(Registration(user_info));
+    let result = work();
+    if registration.is_some() {
+        core_foundation::runloop::CFRunLoop::run_in_mode(
+            unsafe { core_foundation::runloop::kCFRunLoopDefaultMode },
+            RECONFIGURATION_DRAIN_TIME,
+            false,
+        );
+    }
+    result
+}
+
+/// The callback registered by [`observe_reconfiguration`], removed on drop so
+/// that it is gone before the handler it points to, also when `work` panics
+/// and the apply rolls back.
+struct Registration(*mut c_void);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        unsafe { CGDisplayRemoveReconfigurationCallback(observe_callback, self.0) };
+    }
+}
+
+/// How long [`observe_reconfiguration`] waits for the callbacks that report a
+/// change done.
+const RECONFIGURATION_DRAIN_TIME: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// Display events as an async [`Stream`](futures_core::Stream).
+///
+/// CoreGraphics only delivers reconfiguration callbacks to a running CFRunLoop,
+/// so the stream is fed by a background thread running one; callers do not
+/// need to manage it. The thread exits at the first event after the stream is
+/// dropped.
+#[cfg(feature = "async")]
+pub fn stream() -> impl futures_core::Stream<Item = DisplayEvent> {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+
+    std::thread::spawn(move || {
+        let result = watch(move |event| {
+            if sender.send(event).is_err() {
+                core_foundation::runloop::CFRunLoop::get_current().stop();
+            }
+        });
+        if let Err(e) = result {
+            crate::output::warning(&e);
+        }
+    });
+
+    EventStream { receiver }
+}
+
+#[cfg(feature = "async")]
+struct EventStream {
+    receiver: tokio::sync::mpsc::UnboundedReceiver<DisplayEvent>,
+}
+
+#[cfg(feature = "async")]
+impl futures_core::Stream for EventStream {
+    type Item = DisplayEvent;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<DisplayEvent>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_kind_from_flags() {
+        assert_eq!(
+            DisplayEventKind::from_flags(ADD_FLAG | ENABLED_FLAG),
+            DisplayEventKind::Added
+        );
+        assert_eq!(
+            DisplayEventKind::from_flags(SET_MODE_FLAG | DESKTOP_SHAPE_CHANGED_FLAG),
+            DisplayEventKind::ModeChanged
+        );
+        assert_eq!(
+            DisplayEventKind::from_flags(DESKTOP_SHAPE_CHANGED_FLAG),
+            DisplayEventKind::Reconfigured
+        );
+    }
+
+    #[test]
+    fn test_mode_tracker() {
+        let mode = |width: u32, refresh_rate: f64| DisplayMode {
+            refresh_rate,
+            ..crate::apply::tests::mode(width, width, 1440, false)
+        };
+        let mut tracker = ModeTracker {
+            display_id: 2,
+            current: Some(mode(2560, 60.0)),
+            changed_at: None,
+        };
+
+        assert!(tracker.update(10.0, 0, Some(mode(2560, 60.0))).is_none());
+
+        let change = tracker.update(11.0, 0, Some(mode(2560, 144.0))).unwrap();
+        assert_eq!(change.since_previous, None);
+        assert_eq!(change.from.map(|m| m.refresh_rate), Some(60.0));
+        assert_eq!(change.to.map(|m| m.refresh_rate), Some(144.0));
+
+        let change = tracker.update(11.5, 0, None).unwrap();
+        assert_eq!(change.since_previous, Some(0.5));
+        assert!(change.to.is_none());
+        assert!(tracker.update(12.0, 0, None).is_none());
+    }
+}
@@ -0,0 +1,197 @@
+use std::any::Any;
+use std::os::raw::c_void;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+extern "C" {
+    fn ds_run_on_executor(work: extern "C" fn(*mut c_void), context: *mut c_void);
+}
+
+/// Held while a display configuration transaction is in flight.
+///
+/// CoreGraphics configuration transactions from different threads can
+/// interleave and complete each other's half-built changes, so every call that
+/// changes display state goes through [`serialized`].
+static CONFIGURATION_LOCK: Mutex<()> = Mutex::new(());
+
+fn configuration_lock() -> MutexGuard<'static, ()> {
+    // The lock guards no data, so a panic while holding it leaves nothing broken.
+    CONFIGURATION_LOCK
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Run `f` while no other thread is changing the display configuration.
+pub(crate) fn serialized<T>(f: impl FnOnce() -> T) -> T {
+    let _guard = configuration_lock();
+    f()
+}
+
+/// Default limit for a single DisplayServices or CoreGraphics call.
+///
+/// Mode switches that retrain a link legitimately take a few seconds.
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+static CALL_TIMEOUT_MS: AtomicU64 = AtomicU64::new(DEFAULT_CALL_TIMEOUT.as_millis() as u64);
+
+/// Limit how long a potentially blocking display call may take before it is
+/// reported as timed out. `Duration::ZERO` disables the watchdog.
+pub fn set_call_timeout(timeout: Duration) {
+    CALL_TIMEOUT_MS.store(timeout.as_millis() as u64, Ordering::SeqCst);
+}
+
+pub fn call_timeout() -> Duration {
+    Duration::from_millis(CALL_TIMEOUT_MS.load(Ordering::SeqCst))
+}
+
+/// Run `f` on a watchdog thread, giving up after [`call_timeout`].
+///
+/// Returns `None` on timeout. A hung call cannot be cancelled, so its thread is
+/// left behind; if it holds the configuration lock, later changes time out too
+/// instead of freezing the caller.
+pub(crate) fn with_timeout<F, T>(f: F) -> Option<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let timeout = call_timeout();
+    if timeout.is_zero() {
+        return Some(f());
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let handle = thread::spawn(move || {
+        let _ = sender.send(f());
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(value) => Some(value),
+        Err(RecvTimeoutError::Timeout) => None,
+        Err(RecvTimeoutError::Disconnected) => match handle.join() {
+            Err(payload) => panic::resume_unwind(payload),
+            Ok(()) => None,
+        },
+    }
+}
+
+/// The message a panic was raised with.
+pub(crate) fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+/// Run the body of a callback that C code calls, stopping a panic at the
+/// boundary.
+///
+/// Unwinding into the CoreGraphics, IOKit or shim frames below the callback
+/// is undefined behaviour, so the panic is reported as a notice and the
+/// callback returns as if the event had not happened.
+pub(crate) fn guard_callback(callback: &str, f: impl FnOnce()) {
+    if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+        crate::output::notice(&format!(
+            "macdisp: panic in {} callback, event dropped: {}",
+            callback,
+            panic_message(&*payload)
+        ));
+    }
+}
+
+struct Job<F, T> {
+    work: Option<F>,
+    result: Option<std::thread::Result<T>>,
+}
+
+extern "C" fn run_job<F, T>(context: *mut c_void)
+where
+    F: FnOnce() -> T,
+{
+    let job = unsafe { &mut *(context as *mut Job<F, T>) };
+    if let Some(work) = job.work.take() {
+        // Unwinding across the C frames of the shim is undefined behaviour.
+        job.result = Some(panic::catch_unwind(AssertUnwindSafe(work)));
+    }
+}
+
+/// Run `f` on the executor thread and wait for its result.
+///
+/// The executor thread keeps a CFRunLoop running for the life of the process,
+/// so IOKit notification sources registered from `f` keep delivering events
+/// even when the caller's thread never runs a run loop (tokio workers, plain
+/// `std::thread`s). Panics in `f` are resumed on the calling thread.
+pub(crate) fn on_run_loop_thread<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send,
+    T: Send,
+{
+    let mut job = Job {
+        work: Some(f),
+        result: None,
+    };
+    unsafe {
+        ds_run_on_executor(run_job::<F, T>, &mut job as *mut Job<F, T> as *mut c_void);
+    }
+
+    match job.result {
+        Some(Ok(value)) => value,
+        Some(Err(payload)) => panic::resume_unwind(payload),
+        None => panic!("executor thread did not run the job"),
+    }
+}
+
+/// The public data types are plain values that can be shared across threads.
+#[allow(dead_code)]
+fn assert_public_types_are_send_sync() {
+    fn assert<T: Send + Sync>() {}
+    assert::<crate::DisplayMode>();
+    assert::<crate::DisplayInfo>();
+    assert::<crate::DisplayConfig>();
+    assert::<crate::ConfigureOptions>();
+    assert::<crate::mode_id::ModeId>();
+    assert::<crate::events::DisplayEvent>();
+    assert::<crate::usb::UsbEvent>();
+    assert::<crate::power::PowerSource>();
+    assert::<crate::config::Config>();
+    assert::<crate::apply::ApplyOptions>();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_timeout() {
+        assert_eq!(with_timeout(|| 7), Some(7));
+
+        set_call_timeout(Duration::from_millis(20));
+        let result = with_timeout(|| thread::sleep(Duration::from_secs(1)));
+        set_call_timeout(DEFAULT_CALL_TIMEOUT);
+        assert!(result.is_none());
+    }
+
+    #[test]
+    fn test_guard_callback() {
+        let mut ran = false;
+        guard_callback("test", || ran = true);
+        assert!(ran);
+        guard_callback("test", || panic!("boom"));
+
+        let payload = panic::catch_unwind(|| panic!("mode {} missing", 7)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "mode 7 missing");
+    }
+
+    #[test]
+    fn test_serialized_survives_panic() {
+        let result = panic::catch_unwind(|| serialized(|| panic!("boom")));
+        assert!(result.is_err());
+        assert_eq!(serialized(|| 42), 42);
+    }
+}
@@ -0,0 +1,126 @@
+//! Expectations about the connected displays, for scripts that must not run
+//! against the wrong setup.
+
+use crate::apply::{config_drift, set_key};
+use crate::config::Config;
+use crate::selector::select_display;
+use crate::{DisplayConfig, DisplayInfo};
+
+/// What the connected displays should look like. Empty expectations always hold.
+#[derive(Debug, Clone, Default)]
+pub struct Expectations {
+    /// How many displays are connected, sleeping, mirrored and disabled ones included
+    pub count: Option<usize>,
+    /// Selectors of displays that must be connected
+    pub connected: Vec<String>,
+    /// Selector of the display that must have the menu bar
+    pub main: Option<String>,
+    /// Settings a display must have, checked the way the daemon checks drift
+    pub settings: Vec<(String, DisplayConfig)>,
+}
+
+impl Expectations {
+    /// Expect a display to have a setting, from `SELECTOR=VALUE` and a key of
+    /// [`set_key`] such as `res`.
+    pub fn expect_setting(&mut self, key: &str, arg: &str) -> Result<(), String> {
+        let (selector, value) = arg
+            .rsplit_once('=')
+            .ok_or_else(|| format!("Expected SELECTOR=VALUE for {}, got {}", key, arg))?;
+        let mut config = DisplayConfig::default();
+        set_key(&mut config, key, value)?;
+        self.settings.push((selector.to_string(), config));
+        Ok(())
+    }
+
+    /// Describe every expectation the displays do not meet.
+    ///
+    /// Returns an empty list when they all hold.
+    pub fn check(&self, displays: &[DisplayInfo], config: &Config) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        let mut report = |mismatch: String| {
+            if !mismatches.contains(&mismatch) {
+                mismatches.push(mismatch);
+            }
+        };
+
+        if let Some(count) = self.count {
+            if displays.len() != count {
+                report(format!("display count {} (want {})", displays.len(), count));
+            }
+        }
+        for selector in &self.connected {
+            if select_display(selector, displays, config).is_err() {
+                report(format!("{}: not connected", selector));
+            }
+        }
+        if let Some(selector) = &self.main {
+            match select_display(selector, displays, config) {
+                Ok(info) if info.is_main => {}
+                Ok(_) => report(format!(
+                    "{}: not the main display (main is {})",
+                    selector,
+                    displays
+                        .iter()
+                        .find(|d| d.is_main)
+                        .map_or("none".to_string(), |d| format!("display {}", d.id))
+                )),
+                Err(_) => report(format!("{}: not connected", selector)),
+            }
+        }
+        for (selector, wanted) in &self.settings {
+            match select_display(selector, displays, config) {
+                Ok(info) => {
+                    for drift in config_drift(wanted, info, displays) {
+                        report(format!("{}: {}", selector, drift));
+                    }
+                }
+                Err(_) => report(format!("{}: not connected", selector)),
+            }
+        }
+        mismatches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    #[test]
+    fn test_check() {
+        let mut displays = vec![display(1, "AAAA"), display(2, "BBBB")];
+        displays[1].x = 2560;
+        let mut config = Config::default();
+        config
+            .aliases
+            .insert("desk".to_string(), "BBBB".to_string());
+
+        let mut expect = Expectations {
+            count: Some(2),
+            connected: vec!["desk".to_string()],
+            main: Some("1".to_string()),
+            ..Default::default()
+        };
+        expect.expect_setting("res", "desk=2560x1440").unwrap();
+        expect.expect_setting("origin", "desk=2560,0").unwrap();
+        assert!(expect.check(&displays, &config).is_empty());
+
+        expect.count = Some(3);
+        expect.main = Some("desk".to_string());
+        expect.connected.push("CCCC".to_string());
+        expect.expect_setting("res", "desk=3840x2160").unwrap();
+        expect.expect_setting("hz", "CCCC=60").unwrap();
+        assert_eq!(
+            expect.check(&displays, &config),
+            vec![
+                "display count 2 (want 3)",
+                "CCCC: not connected",
+                "desk: not the main display (main is display 1)",
+                "desk: res 2560x1440 (want 3840x2160)",
+            ]
+        );
+
+        assert!(expect.expect_setting("res", "desk").is_err());
+        assert!(expect.expect_setting("res", "desk=big").is_err());
+    }
+}
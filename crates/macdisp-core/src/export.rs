@@ -0,0 +1,438 @@
+use crate::bundle::Fingerprint;
+use crate::edid::{self, xml_escape};
+use crate::mode_id::format_hz;
+use crate::sync::SyncArrangement;
+use crate::{get_all_display_info, DisplayInfo};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Ends the heredoc holding the arrangement in exported scripts; JSON never
+/// has it alone on a line.
+const LAYOUT_MARKER: &str = "MACDISP_LAYOUT";
+
+/// Build a commented, idempotent shell script that restores the current
+/// display arrangement.
+///
+/// The script names the monitors by their EDID vendor, model and serial
+/// number, as `sync export` does, so it works on every Mac they are connected
+/// to. It refuses to run (and exits cleanly) when any of the displays it
+/// describes is not connected, so it is safe to call from login hooks.
+pub fn export_shell_script(profile: Option<&str>) -> String {
+    let connected = connected_displays();
+    shell_script_for(&connected, profile, SystemTime::now())
+}
+
+/// The connected displays with their fingerprints.
+fn connected_displays() -> Vec<(DisplayInfo, Fingerprint)> {
+    get_all_display_info()
+        .into_iter()
+        .map(|info| {
+            let fingerprint = Fingerprint::of(&info);
+            (info, fingerprint)
+        })
+        .collect()
+}
+
+fn shell_script_for(
+    connected: &[(DisplayInfo, Fingerprint)],
+    profile: Option<&str>,
+    now: SystemTime,
+) -> String {
+    let arrangement = SyncArrangement::export(connected, |info| {
+        edid::for_display(info.id).ok().and_then(|edid| edid.name)
+    });
+    let mut script = String::new();
+
+    script.push_str("#!/bin/sh\n");
+    script.push_str(&format!(
+        "# Generated by macdisp {} on {}\n",
+        env!("CARGO_PKG_VERSION"),
+        format_utc_timestamp(now)
+    ));
+    script.push_str(&format!("# Profile: {}\n", profile.unwrap_or("(unnamed)")));
+    script.push_str("#\n");
+    script.push_str("# Displays:\n");
+    for (i, ((info, _), display)) in connected.iter().zip(&arrangement.displays).enumerate() {
+        script.push_str(&format!("#   {}. {}\n", i + 1, describe_display(info)));
+        let fingerprint = &display.fingerprint;
+        script.push_str(&format!(
+            "#      {}: vendor {:#06x}, model {:#06x}, serial {}\n",
+            display.key, fingerprint.vendor, fingerprint.model, fingerprint.serial
+        ));
+    }
+    script.push_str("#\n");
+    script.push_str("# Re-running this script is harmless: displays already in the requested\n");
+    script.push_str("# state are left as they are.\n\n");
+
+    script.push_str("set -eu\n\n");
+    script.push_str("MACDISP=\"${MACDISP:-macdisp}\"\n\n");
+
+    // Persistent ids differ from Mac to Mac, so the arrangement goes in as a
+    // sync file, which `sync import` matches to the monitors themselves
+    script.push_str("LAYOUT=\"$(mktemp)\"\n");
+    script.push_str("trap 'rm -f \"$LAYOUT\"' EXIT\n");
+    script.push_str(&format!("cat > \"$LAYOUT\" <<'{}'\n", LAYOUT_MARKER));
+    script.push_str(
+        &serde_json::to_string_pretty(&arrangement).expect("arrangement serializes to JSON"),
+    );
+    script.push_str(&format!("\n{}\n\n", LAYOUT_MARKER));
+
+    script.push_str("# Only applies when every display in this layout is connected\n");
+    script.push_str("\"$MACDISP\" sync import --all \"$LAYOUT\"\n");
+
+    script
+}
+
+/// Where the files of an MDM bundle are installed.
+pub const MDM_SCRIPT_DIR: &str = "/Library/Application Support/macdisp";
+pub const MDM_LAUNCH_AGENT_DIR: &str = "/Library/LaunchAgents";
+
+/// One file of an MDM bundle.
+#[derive(Debug, Clone)]
+pub struct BundleFile {
+    pub name: String,
+    pub contents: String,
+    pub executable: bool,
+}
+
+/// Build the files that pin Macs to the current arrangement at every login,
+/// for deployment by an MDM tool:
+///
+/// - `<identifier>.sh`, the script from [`export_shell_script`]
+/// - `<identifier>.plist`, a LaunchAgent running it in each user's session
+/// - `install.sh`, which puts both in place when run as root
+/// - `<identifier>.mobileconfig`, a configuration profile that keeps users
+///   from turning the LaunchAgent off in Login Items (macOS 13 and later)
+///
+/// `binary` is where macdisp is installed on the managed Macs.
+pub fn export_mdm_bundle(
+    profile: Option<&str>,
+    identifier: &str,
+    binary: &str,
+) -> Result<Vec<BundleFile>, String> {
+    let connected = connected_displays();
+    mdm_bundle_for(&connected, profile, identifier, binary, SystemTime::now())
+}
+
+fn mdm_bundle_for(
+    connected: &[(DisplayInfo, Fingerprint)],
+    profile: Option<&str>,
+    identifier: &str,
+    binary: &str,
+    now: SystemTime,
+) -> Result<Vec<BundleFile>, String> {
+    // The identifier ends up in file names and the install script unquoted
+    let valid = !identifier.is_empty()
+        && identifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+    if !valid {
+        return Err(format!(
+            "Invalid identifier: {} (expected reverse DNS such as com.example.displays)",
+            identifier
+        ));
+    }
+    if !binary.starts_with('/') {
+        return Err(format!(
+            "Invalid binary: {} (launchd needs an absolute path)",
+            binary
+        ));
+    }
+
+    let script_path = format!("{}/{}.sh", MDM_SCRIPT_DIR, identifier);
+    let plist_path = format!("{}/{}.plist", MDM_LAUNCH_AGENT_DIR, identifier);
+    let description = match profile {
+        Some(profile) => format!("the {} display arrangement", profile),
+        None => "the display arrangement".to_string(),
+    };
+
+    let launch_agent = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>Label</key>
+	<string>{identifier}</string>
+	<key>ProgramArguments</key>
+	<array>
+		<string>/bin/sh</string>
+		<string>{script}</string>
+	</array>
+	<key>EnvironmentVariables</key>
+	<dict>
+		<key>MACDISP</key>
+		<string>{binary}</string>
+	</dict>
+	<key>LimitLoadToSessionType</key>
+	<string>Aqua</string>
+	<key>RunAtLoad</key>
+	<true/>
+</dict>
+</plist>
+"#,
+        identifier = identifier,
+        script = xml_escape(&script_path),
+        binary = xml_escape(binary),
+    );
+
+    let install = format!(
+        r#"#!/bin/sh
+# Installs the {identifier} LaunchAgent, which restores
+# {description} at every login.
+# Run as root from the directory holding these files, for example from an MDM
+# script policy or a package postinstall script.
+
+set -eu
+cd "$(dirname "$0")"
+
+install -d -o root -g wheel -m 755 "{script_dir}"
+install -o root -g wheel -m 755 {identifier}.sh "{script_path}"
+install -o root -g wheel -m 644 {identifier}.plist "{plist_path}"
+
+# Start it for the user logged in now; everyone else gets it at their next login
+uid="$(stat -f %u /dev/console)"
+if [ "$uid" -ne 0 ]; then
+    launchctl bootout "gui/$uid/{identifier}" 2>/dev/null || true
+    launchctl bootstrap "gui/$uid" "{plist_path}"
+fi
+"#,
+        identifier = identifier,
+        description = description,
+        script_dir = MDM_SCRIPT_DIR,
+        script_path = script_path,
+        plist_path = plist_path,
+    );
+
+    let profile_name = xml_escape(profile.unwrap_or("display arrangement"));
+    let mobileconfig = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>PayloadContent</key>
+	<array>
+		<dict>
+			<key>PayloadDisplayName</key>
+			<string>macdisp login item</string>
+			<key>PayloadIdentifier</key>
+			<string>{identifier}.servicemanagement</string>
+			<key>PayloadType</key>
+			<string>com.apple.servicemanagement</string>
+			<key>PayloadUUID</key>
+			<string>{rules_uuid}</string>
+			<key>PayloadVersion</key>
+			<integer>1</integer>
+			<key>Rules</key>
+			<array>
+				<dict>
+					<key>Comment</key>
+					<string>Restores {description} at login</string>
+					<key>RuleType</key>
+					<string>Label</string>
+					<key>RuleValue</key>
+					<string>{identifier}</string>
+				</dict>
+			</array>
+		</dict>
+	</array>
+	<key>PayloadDescription</key>
+	<string>Keeps the LaunchAgent that restores {description} enabled. Install the LaunchAgent with install.sh.</string>
+	<key>PayloadDisplayName</key>
+	<string>macdisp: {profile_name}</string>
+	<key>PayloadIdentifier</key>
+	<string>{identifier}</string>
+	<key>PayloadScope</key>
+	<string>System</string>
+	<key>PayloadType</key>
+	<string>Configuration</string>
+	<key>PayloadUUID</key>
+	<string>{profile_uuid}</string>
+	<key>PayloadVersion</key>
+	<integer>1</integer>
+</dict>
+</plist>
+"#,
+        identifier = identifier,
+        description = xml_escape(&description),
+        profile_name = profile_name,
+        rules_uuid = payload_uuid(&format!("{}.servicemanagement", identifier)),
+        profile_uuid = payload_uuid(identifier),
+    );
+
+    Ok(vec![
+        BundleFile {
+            name: format!("{}.sh", identifier),
+            contents: shell_script_for(connected, profile, now),
+            executable: true,
+        },
+        BundleFile {
+            name: format!("{}.plist", identifier),
+            contents: launch_agent,
+            executable: false,
+        },
+        BundleFile {
+            name: "install.sh".to_string(),
+            contents: install,
+            executable: true,
+        },
+        BundleFile {
+            name: format!("{}.mobileconfig", identifier),
+            contents: mobileconfig,
+            executable: false,
+        },
+    ])
+}
+
+/// A UUID derived from `name`, so deploying a newer bundle replaces the
+/// installed profile instead of adding a second one.
+fn payload_uuid(name: &str) -> String {
+    // FNV-1a, run twice with different offsets for 128 bits
+    let fnv = |offset: u64| {
+        name.bytes().fold(offset, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+    };
+    let high = fnv(0xcbf2_9ce4_8422_2325);
+    let low = fnv(0x6c62_272e_07bb_0142);
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:04X}-{:012X}",
+        high >> 32,
+        (high >> 16) & 0xFFFF,
+        high & 0xFFFF,
+        low >> 48,
+        low & 0xFFFF_FFFF_FFFF
+    )
+}
+
+fn describe_display(info: &DisplayInfo) -> String {
+    let mut desc = format!(
+        "{}, {}x{} @ {}Hz, origin ({},{})",
+        info.display_type,
+        info.width,
+        info.height,
+        format_hz(info.hz),
+        info.x,
+        info.y
+    );
+    if info.rotation != 0 {
+        desc.push_str(&format!(", rotated {}°", info.rotation));
+    }
+    if let Some(mirror_id) = info.mirror_of {
+        desc.push_str(&format!(", mirroring {}", mirror_id));
+    }
+    if info.is_main {
+        desc.push_str(", main display");
+    }
+    desc
+}
+
+/// Format a timestamp as `YYYY-MM-DD HH:MM:SS UTC` without pulling in a date crate.
+pub fn format_utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil-from-days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        (rem % 3_600) / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+    use std::time::Duration;
+
+    #[test]
+    fn test_format_utc_timestamp() {
+        assert_eq!(format_utc_timestamp(UNIX_EPOCH), "1970-01-01 00:00:00 UTC");
+        assert_eq!(
+            format_utc_timestamp(UNIX_EPOCH + Duration::from_secs(1_709_210_096)),
+            "2024-02-29 12:34:56 UTC"
+        );
+    }
+
+    #[test]
+    fn test_shell_script_without_displays() {
+        let script = shell_script_for(&[], Some("desk"), UNIX_EPOCH);
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains("# Profile: desk\n"));
+        assert!(script.contains("set -eu\n"));
+    }
+
+    #[test]
+    fn test_shell_script_matches_monitors() {
+        let fingerprint = Fingerprint {
+            vendor: 0x10ac,
+            model: 0xa0c4,
+            serial: 111,
+        };
+        let script = shell_script_for(&[(display(2, "BBBB"), fingerprint)], None, UNIX_EPOCH);
+        assert!(script.contains("#      display1: vendor 0x10ac, model 0xa0c4, serial 111\n"));
+        assert!(script.contains("\"configs\": [\n    \"id:display1 res:2560x1440"));
+        assert!(script.ends_with("\"$MACDISP\" sync import --all \"$LAYOUT\"\n"));
+        // This Mac's persistent ids mean nothing on the others
+        assert!(!script.contains("BBBB"));
+    }
+
+    #[test]
+    fn test_mdm_bundle() {
+        let files = mdm_bundle_for(
+            &[],
+            Some("lab"),
+            "edu.example.displays",
+            "/usr/local/bin/macdisp",
+            UNIX_EPOCH,
+        )
+        .unwrap();
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "edu.example.displays.sh",
+                "edu.example.displays.plist",
+                "install.sh",
+                "edu.example.displays.mobileconfig"
+            ]
+        );
+        assert!(files[1].contents.contains(
+            "<string>/Library/Application Support/macdisp/edu.example.displays.sh</string>"
+        ));
+        assert!(files[3]
+            .contents
+            .contains("<string>com.apple.servicemanagement</string>"));
+        assert_eq!(
+            payload_uuid("edu.example.displays"),
+            payload_uuid("edu.example.displays")
+        );
+        assert_ne!(payload_uuid("a"), payload_uuid("b"));
+
+        assert!(mdm_bundle_for(
+            &[],
+            None,
+            "bad id; rm",
+            "/usr/local/bin/macdisp",
+            UNIX_EPOCH
+        )
+        .is_err());
+        assert!(mdm_bundle_for(&[], None, "edu.example", "macdisp", UNIX_EPOCH).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Color temperature through the display gamma tables.
+//!
+//! macOS restores the ColorSync gamma of every display when the process that
+//! changed it exits, so a temperature lasts only as long as macdisp runs.
+
+use crate::config::Config;
+use crate::error::{self, DisplayError};
+use crate::selector::{select_display, GROUP_PREFIX};
+use crate::DisplayInfo;
+use std::collections::BTreeMap;
+
+extern "C" {
+    #[allow(clippy::too_many_arguments)]
+    fn CGSetDisplayTransferByFormula(
+        display: u32,
+        red_min: f32,
+        red_max: f32,
+        red_gamma: f32,
+        green_min: f32,
+        green_max: f32,
+        green_gamma: f32,
+        blue_min: f32,
+        blue_max: f32,
+        blue_gamma: f32,
+    ) -> i32;
+    fn CGDisplayRestoreColorSyncSettings();
+}
+
+/// The temperature that leaves colors unchanged.
+pub const NEUTRAL_KELVIN: u32 = 6500;
+/// Temperatures accepted by [`set_temperature`].
+pub const KELVIN_RANGE: std::ops::RangeInclusive<u32> = 1000..=10000;
+
+/// Red, green and blue gains that shift white to `kelvin`.
+///
+/// Uses Tanner Helland's fit of the blackbody curve, scaled so that
+/// [`NEUTRAL_KELVIN`] is exactly white.
+pub fn channel_gains(kelvin: u32) -> (f32, f32, f32) {
+    let (red, green, blue) = blackbody(kelvin);
+    let (white_red, white_green, white_blue) = blackbody(NEUTRAL_KELVIN);
+    (
+        (red / white_red).min(1.0),
+        (green / white_green).min(1.0),
+        (blue / white_blue).min(1.0),
+    )
+}
+
+fn blackbody(kelvin: u32) -> (f32, f32, f32) {
+    let temp = kelvin as f64 / 100.0;
+    let red = if temp <= 66.0 {
+        255.0
+    } else {
+        329.698727446 * (temp - 60.0).powf(-0.1332047592)
+    };
+    let green = if temp <= 66.0 {
+        99.4708025861 * temp.ln() - 161.1195681661
+    } else {
+        288.1221695283 * (temp - 60.0).powf(-0.0755148492)
+    };
+    let blue = if temp >= 66.0 {
+        255.0
+    } else if temp <= 19.0 {
+        0.0
+    } else {
+        138.5177312231 * (temp - 10.0).ln() - 305.0447927307
+    };
+    let channel = |value: f64| (value.clamp(0.0, 255.0) / 255.0) as f32;
+    (channel(red), channel(green), channel(blue))
+}
+
+/// Shift the white point of a display to `kelvin`, clamped to
+/// [`KELVIN_RANGE`].
+pub fn set_temperature(display_id: u32, kelvin: u32) -> Result<(), DisplayError> {
+    let kelvin = kelvin.clamp(*KELVIN_RANGE.start(), *KELVIN_RANGE.end());
+    let (red, green, blue) = channel_gains(kelvin);
+    let result = crate::executor::with_timeout(move || unsafe {
+        CGSetDisplayTransferByFormula(display_id, 0.0, red, 1.0, 0.0, green, 1.0, 0.0, blue, 1.0)
+    });
+    error::check("set color temperature", display_id, result)
+}
+
+/// The connected displays covered by the config's `temperature` settings,
+/// with their temperature.
+///
+/// Displays that are not connected are skipped, so a group keeps working
+/// while some of its members are unplugged. A display's own entry takes
+/// precedence over the groups it is in.
+pub fn configured_temperatures(config: &Config, displays: &[DisplayInfo]) -> BTreeMap<u32, u32> {
+    let mut temperatures = BTreeMap::new();
+    let (groups, singles): (Vec<_>, Vec<_>) = config
+        .temperature
+        .iter()
+        .partition(|(selector, _)| selector.starts_with(GROUP_PREFIX));
+
+    for (selector, &kelvin) in groups {
+        let members = config
+            .groups
+            .get(&selector[GROUP_PREFIX.len_utf8()..])
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        for member in members {
+            if let Ok(info) = select_display(member, displays, config) {
+                temperatures.insert(info.id, kelvin);
+            }
+        }
+    }
+    for (selector, &kelvin) in singles {
+        if let Ok(info) = select_display(selector, displays, config) {
+            temperatures.insert(info.id, kelvin);
+        }
+    }
+    temperatures
+}
+
+/// Put every display back on its ColorSync profile.
+pub fn restore() {
+    unsafe { CGDisplayRestoreColorSyncSettings() };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_configured_temperatures() {
+        use crate::apply::tests::display;
+
+        let displays = vec![display(1, "AAAA"), display(2, "BBBB")];
+        let mut config = Config::default();
+        config.groups.insert(
+            "desk".to_string(),
+            vec!["AAAA".to_string(), "BBBB".to_string(), "CCCC".to_string()],
+        );
+        config.temperature.insert("@desk".to_string(), 4500);
+        config.temperature.insert("2".to_string(), 3400);
+        config.temperature.insert("@unknown".to_string(), 5000);
+
+        let temperatures = configured_temperatures(&config, &displays);
+        assert_eq!(temperatures, BTreeMap::from([(1, 4500), (2, 3400)]));
+    }
+
+    #[test]
+    fn test_channel_gains() {
+        assert_eq!(channel_gains(NEUTRAL_KELVIN), (1.0, 1.0, 1.0));
+
+        let (red, green, blue) = channel_gains(3400);
+        assert_eq!(red, 1.0);
+        assert!(blue < green && green < red);
+
+        let (red, _, blue) = channel_gains(9000);
+        assert!(red < 1.0);
+        assert_eq!(blue, 1.0);
+    }
+}
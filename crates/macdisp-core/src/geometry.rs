@@ -0,0 +1,260 @@
+//! Positions and sizes in the global display space.
+//!
+//! macOS arranges displays in one coordinate space whose origin is the top
+//! left corner of the main display, with y growing downwards. Its units are
+//! the "looks like" size of each display, so a HiDPI display renders more
+//! pixels than its size here; [`DisplayMode::pixel_size`](crate::DisplayMode::pixel_size)
+//! is the only size in backing pixels.
+
+use crate::is_sideways;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A position in the global display space.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct PointPx {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl PointPx {
+    pub const ORIGIN: PointPx = PointPx { x: 0, y: 0 };
+
+    pub fn new(x: i32, y: i32) -> Self {
+        PointPx { x, y }
+    }
+
+    /// This point moved by `dx` and `dy`.
+    pub fn offset(self, dx: i32, dy: i32) -> Self {
+        PointPx::new(self.x + dx, self.y + dy)
+    }
+}
+
+impl From<(i32, i32)> for PointPx {
+    fn from((x, y): (i32, i32)) -> Self {
+        PointPx { x, y }
+    }
+}
+
+impl From<PointPx> for (i32, i32) {
+    fn from(point: PointPx) -> Self {
+        (point.x, point.y)
+    }
+}
+
+impl fmt::Display for PointPx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "({},{})", self.x, self.y)
+    }
+}
+
+/// A width and height, either in the global display space or in pixels.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct SizePx {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl SizePx {
+    pub fn new(width: u32, height: u32) -> Self {
+        SizePx { width, height }
+    }
+
+    /// The size as laid out on screen at `rotation` degrees, for a size in the
+    /// panel's native orientation. Turning it back is the same swap.
+    pub fn rotated(self, rotation: u32) -> Self {
+        if is_sideways(rotation) {
+            SizePx::new(self.height, self.width)
+        } else {
+            self
+        }
+    }
+
+    /// Whether the size is at least as wide as it is tall.
+    pub fn is_landscape(self) -> bool {
+        self.width >= self.height
+    }
+
+    /// Whether the two sizes have the same aspect ratio, to within 1%.
+    pub fn has_aspect_of(self, other: SizePx) -> bool {
+        let a = self.width as u64 * other.height as u64;
+        let b = other.width as u64 * self.height as u64;
+        a.abs_diff(b) * 100 <= a.max(b)
+    }
+
+    pub fn area(self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+}
+
+impl From<(u32, u32)> for SizePx {
+    fn from((width, height): (u32, u32)) -> Self {
+        SizePx { width, height }
+    }
+}
+
+impl From<SizePx> for (u32, u32) {
+    fn from(size: SizePx) -> Self {
+        (size.width, size.height)
+    }
+}
+
+impl fmt::Display for SizePx {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
+}
+
+/// The area a display covers in the global display space.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Rect {
+    pub origin: PointPx,
+    pub size: SizePx,
+}
+
+impl Rect {
+    pub fn new(origin: PointPx, size: SizePx) -> Self {
+        Rect { origin, size }
+    }
+
+    pub fn left(&self) -> i32 {
+        self.origin.x
+    }
+
+    pub fn top(&self) -> i32 {
+        self.origin.y
+    }
+
+    /// The first column past the right edge.
+    pub fn right(&self) -> i32 {
+        self.origin.x.saturating_add_unsigned(self.size.width)
+    }
+
+    /// The first row below the bottom edge.
+    pub fn bottom(&self) -> i32 {
+        self.origin.y.saturating_add_unsigned(self.size.height)
+    }
+
+    /// Where a rectangle placed flush against the right edge, top-aligned,
+    /// would start.
+    pub fn right_neighbor_origin(&self) -> PointPx {
+        PointPx::new(self.right(), self.top())
+    }
+
+    pub fn contains(&self, point: PointPx) -> bool {
+        (self.left()..self.right()).contains(&point.x)
+            && (self.top()..self.bottom()).contains(&point.y)
+    }
+
+    /// Whether the two rectangles share any area. Displays that only touch
+    /// along an edge do not overlap.
+    pub fn overlaps(&self, other: &Rect) -> bool {
+        self.left() < other.right()
+            && other.left() < self.right()
+            && self.top() < other.bottom()
+            && other.top() < self.bottom()
+    }
+
+    /// Where this rectangle sits against `other` when they share part of an
+    /// edge: `"right of"`, `"left of"`, `"above"` or `"below"`.
+    pub fn side_of(&self, other: &Rect) -> Option<&'static str> {
+        let rows_overlap = self.top() < other.bottom() && other.top() < self.bottom();
+        let columns_overlap = self.left() < other.right() && other.left() < self.right();
+        if rows_overlap && self.left() == other.right() {
+            Some("right of")
+        } else if rows_overlap && self.right() == other.left() {
+            Some("left of")
+        } else if columns_overlap && self.bottom() == other.top() {
+            Some("above")
+        } else if columns_overlap && self.top() == other.bottom() {
+            Some("below")
+        } else {
+            None
+        }
+    }
+
+    /// The smallest rectangle covering both.
+    pub fn union(&self, other: &Rect) -> Rect {
+        let left = self.left().min(other.left());
+        let top = self.top().min(other.top());
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+        Rect::new(
+            PointPx::new(left, top),
+            SizePx::new(right.abs_diff(left), bottom.abs_diff(top)),
+        )
+    }
+
+    /// The area both cover, if they overlap.
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        self.overlaps(other).then(|| {
+            let left = self.left().max(other.left());
+            let top = self.top().max(other.top());
+            let right = self.right().min(other.right());
+            let bottom = self.bottom().min(other.bottom());
+            Rect::new(
+                PointPx::new(left, top),
+                SizePx::new(right.abs_diff(left), bottom.abs_diff(top)),
+            )
+        })
+    }
+}
+
+impl fmt::Display for Rect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.size, self.origin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotated() {
+        let size = SizePx::new(2560, 1440);
+        assert_eq!(size.rotated(0), size);
+        assert_eq!(size.rotated(90), SizePx::new(1440, 2560));
+        assert_eq!(size.rotated(180), size);
+        assert_eq!(size.rotated(270).rotated(270), size);
+        assert!(size.is_landscape());
+        assert!(!size.rotated(90).is_landscape());
+
+        assert!(size.has_aspect_of(SizePx::new(1920, 1080)));
+        assert!(SizePx::new(1512, 982).has_aspect_of(SizePx::new(3024, 1964)));
+        assert!(!SizePx::new(1512, 982).has_aspect_of(SizePx::new(1512, 945)));
+    }
+
+    #[test]
+    fn test_rect() {
+        let main = Rect::new(PointPx::ORIGIN, SizePx::new(1512, 982));
+        let left = Rect::new(PointPx::new(-2560, -458), SizePx::new(2560, 1440));
+        assert_eq!(main.right_neighbor_origin(), PointPx::new(1512, 0));
+        assert_eq!(left.right(), 0);
+        assert!(!main.overlaps(&left));
+        assert!(main.overlaps(&Rect::new(PointPx::new(1500, 900), SizePx::new(100, 100))));
+        assert!(left.contains(PointPx::new(-1, -458)));
+        assert!(!left.contains(PointPx::ORIGIN));
+        assert_eq!(
+            main.union(&left),
+            Rect::new(PointPx::new(-2560, -458), SizePx::new(4072, 1440))
+        );
+        assert_eq!(left.to_string(), "2560x1440 at (-2560,-458)");
+        assert_eq!(main.intersection(&left), None);
+        assert_eq!(
+            main.intersection(&Rect::new(PointPx::new(1500, 900), SizePx::new(100, 100))),
+            Some(Rect::new(PointPx::new(1500, 900), SizePx::new(12, 82)))
+        );
+
+        assert_eq!(main.side_of(&left), Some("right of"));
+        assert_eq!(left.side_of(&main), Some("left of"));
+        let below = Rect::new(PointPx::new(200, 982), SizePx::new(1920, 1080));
+        assert_eq!(below.side_of(&main), Some("below"));
+        assert_eq!(main.side_of(&below), Some("above"));
+        // Touching only at a corner is not sitting beside
+        let corner = Rect::new(PointPx::new(1512, 982), SizePx::new(100, 100));
+        assert_eq!(corner.side_of(&main), None);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::mode_id::format_hz;
+use crate::{get_brightness, DisplayInfo};
+use std::str::FromStr;
+
+/// A single property of a display, printed raw by `macdisp get`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayField {
+    Id,
+    Uuid,
+    Width,
+    Height,
+    Res,
+    Hz,
+    Depth,
+    Origin,
+    X,
+    Y,
+    Rotation,
+    Scaling,
+    Mode,
+    ModeId,
+    Main,
+    Mirror,
+    MirrorType,
+    Enabled,
+    Type,
+    Brightness,
+}
+
+impl FromStr for DisplayField {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "id" => DisplayField::Id,
+            "uuid" => DisplayField::Uuid,
+            "width" => DisplayField::Width,
+            "height" => DisplayField::Height,
+            "res" => DisplayField::Res,
+            "hz" => DisplayField::Hz,
+            "depth" | "color_depth" => DisplayField::Depth,
+            "origin" => DisplayField::Origin,
+            "x" => DisplayField::X,
+            "y" => DisplayField::Y,
+            "rotation" | "degree" => DisplayField::Rotation,
+            "scaling" => DisplayField::Scaling,
+            "mode" => DisplayField::Mode,
+            "mode_id" => DisplayField::ModeId,
+            "main" => DisplayField::Main,
+            "mirror" => DisplayField::Mirror,
+            "mirror_type" => DisplayField::MirrorType,
+            "enabled" => DisplayField::Enabled,
+            "type" => DisplayField::Type,
+            "brightness" => DisplayField::Brightness,
+            _ => return Err(format!("Unknown field: {}", s)),
+        })
+    }
+}
+
+/// The raw value of `field`, in the same notation the config strings use.
+///
+/// Fields with no value (e.g. `mirror` on a display that is not mirroring)
+/// are empty.
+pub fn field_value(info: &DisplayInfo, field: DisplayField) -> Result<String, String> {
+    Ok(match field {
+        DisplayField::Id => info.id.to_string(),
+        DisplayField::Uuid => info.persistent_id.clone(),
+        DisplayField::Width => info.width.to_string(),
+        DisplayField::Height => info.height.to_string(),
+        DisplayField::Res => format!("{}x{}", info.width, info.height),
+        DisplayField::Hz => format_hz(info.hz),
+        DisplayField::Depth => info.depth.to_string(),
+        DisplayField::Origin => format!("{},{}", info.x, info.y),
+        DisplayField::X => info.x.to_string(),
+        DisplayField::Y => info.y.to_string(),
+        DisplayField::Rotation => info.rotation.to_string(),
+        DisplayField::Scaling => if info.scaling { "on" } else { "off" }.to_string(),
+        DisplayField::Mode => info.mode_number.to_string(),
+        DisplayField::ModeId => info.mode_id.to_string(),
+        DisplayField::Main => info.is_main.to_string(),
+        DisplayField::Mirror => info.mirror_of.map(|id| id.to_string()).unwrap_or_default(),
+        DisplayField::MirrorType => info
+            .mirror_type
+            .map(|t| t.as_str().to_string())
+            .unwrap_or_default(),
+        DisplayField::Enabled => info.enabled.to_string(),
+        DisplayField::Type => info.display_type.clone(),
+        DisplayField::Brightness => format!("{:.2}", get_brightness(info.id)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    #[test]
+    fn test_field_values() {
+        let mut info = display(2, "BBBB");
+        info.x = -1440;
+        info.hz = 59.94;
+
+        let value = |field: &str| field_value(&info, field.parse().unwrap()).unwrap();
+        assert_eq!(value("res"), "2560x1440");
+        assert_eq!(value("origin"), "-1440,0");
+        assert_eq!(value("hz"), "59.94");
+        assert_eq!(value("scaling"), "off");
+        assert_eq!(value("mirror"), "");
+        assert_eq!(value("mirror_type"), "");
+        assert!("bogus".parse::<DisplayField>().is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Optional gRPC control server, enabled with the `grpc` feature.
+//!
+//! The service is described in `proto/macdisp.proto`. Display calls block, so
+//! every handler runs them on tokio's blocking pool.
+
+use crate::apply::{apply_configuration, parse_config, ApplyOptions, ApplyResult};
+use crate::config::Config;
+use crate::{daemon, events, get_all_display_info, get_all_modes, DisplayInfo, DisplayMode};
+use futures_core::Stream;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, watch};
+use tonic::{transport::Server, Request, Response, Status, Streaming};
+
+pub mod proto {
+    tonic::include_proto!("macdisp");
+}
+
+use proto::mac_disp_server::{MacDisp, MacDispServer};
+
+impl From<&DisplayInfo> for proto::Display {
+    fn from(info: &DisplayInfo) -> Self {
+        proto::Display {
+            id: info.id,
+            persistent_id: info.persistent_id.clone(),
+            contextual_id: info.contextual_id,
+            serial: info.serial,
+            x: info.x,
+            y: info.y,
+            width: info.width,
+            height: info.height,
+            rotation: info.rotation,
+            hz: info.hz,
+            depth: info.depth,
+            scaling: info.scaling,
+            mode_number: info.mode_number,
+            mode_id: info.mode_id.to_string(),
+            is_main: info.is_main,
+            is_mirror: info.is_mirror,
+            mirror_of: info.mirror_of,
+            mirror_type: info.mirror_type.map(|t| t.as_str().to_string()),
+            enabled: info.enabled,
+            display_type: info.display_type.clone(),
+        }
+    }
+}
+
+impl From<&DisplayMode> for proto::Mode {
+    fn from(mode: &DisplayMode) -> Self {
+        proto::Mode {
+            width: mode.width,
+            height: mode.height,
+            refresh_rate: mode.refresh_rate,
+            depth: mode.depth,
+            mode_number: mode.mode_number,
+            is_stretched: mode.is_stretched,
+            is_interlaced: mode.is_interlaced,
+            is_tv_mode: mode.is_tv_mode,
+            is_safe_for_hardware: mode.is_safe_for_hardware,
+            is_scaled: mode.is_scaled,
+            pixel_width: mode.pixel_width,
+            pixel_height: mode.pixel_height,
+            mode_id: mode.mode_id().to_string(),
+        }
+    }
+}
+
+impl From<&events::DisplayEvent> for proto::DisplayEvent {
+    fn from(event: &events::DisplayEvent) -> Self {
+        proto::DisplayEvent {
+            kind: event.kind.as_str().to_string(),
+            display_id: event.display_id,
+            persistent_id: event.persistent_id.clone(),
+            flags: event.flags,
+            timestamp: event.timestamp,
+            info: event.info.as_ref().map(proto::Display::from),
+        }
+    }
+}
+
+/// Run a blocking display call without stalling the runtime.
+async fn blocking<T, F>(f: F) -> Result<T, Status>
+where
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| Status::internal(format!("Display task failed: {}", e)))?
+        .map_err(Status::internal)
+}
+
+fn apply_response(result: ApplyResult) -> proto::ApplyResponse {
+    proto::ApplyResponse {
+        changed: result.changed,
+        warnings: result.warnings.into_iter().map(|w| w.message).collect(),
+    }
+}
+
+struct Service {
+    config: Arc<Config>,
+}
+
+#[tonic::async_trait]
+impl MacDisp for Service {
+    async fn list_displays(
+        &self,
+        _request: Request<proto::ListDisplaysRequest>,
+    ) -> Result<Response<proto::ListDisplaysResponse>, Status> {
+        let displays = blocking(|| Ok(get_all_display_info())).await?;
+        Ok(Response::new(proto::ListDisplaysResponse {
+            displays: displays.iter().map(proto::Display::from).collect(),
+        }))
+    }
+
+    async fn list_modes(
+        &self,
+        request: Request<proto::ListModesRequest>,
+    ) -> Result<Response<proto::ListModesResponse>, Status> {
+        let display_id = request.into_inner().display_id;
+        let modes = blocking(move || Ok(get_all_modes(display_id))).await?;
+        Ok(Response::new(proto::ListModesResponse {
+            modes: modes.iter().map(proto::Mode::from).collect(),
+        }))
+    }
+
+    async fn list_profiles(
+        &self,
+        _request: Request<proto::ListProfilesRequest>,
+    ) -> Result<Response<proto::ListProfilesResponse>, Status> {
+        Ok(Response::new(proto::ListProfilesResponse {
+            profiles: self
+                .config
+                .profiles
+                .iter()
+                .map(|(name, configs)| proto::Profile {
+                    name: name.clone(),
+                    configs: configs.clone(),
+                })
+                .collect(),
+            default_profile: self.config.profile.clone(),
+        }))
+    }
+
+    async fn apply_profile(
+        &self,
+        request: Request<proto::ApplyProfileRequest>,
+    ) -> Result<Response<proto::ApplyResponse>, Status> {
+        let name = request.into_inner().name;
+        let name = if name.is_empty() {
+            self.config.profile.clone().ok_or_else(|| {
+                Status::invalid_argument("No profile named and no default profile set")
+            })?
+        } else {
+            name
+        };
+        if !self.config.profiles.contains_key(&name) {
+            return Err(Status::not_found(format!("Profile not found: {}", name)));
+        }
+
+        let config = Arc::clone(&self.config);
+        let result = blocking(move || daemon::apply_profile(&config, &name)).await?;
+        Ok(Response::new(apply_response(result)))
+    }
+
+    async fn apply(
+        &self,
+        request: Request<proto::ApplyRequest>,
+    ) -> Result<Response<proto::ApplyResponse>, Status> {
+        let configs = request
+            .into_inner()
+            .configs
+            .iter()
+            .map(|config_str| parse_config(config_str))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(Status::invalid_argument)?;
+
+        let options = ApplyOptions::from(self.config.as_ref());
+        let result = blocking(move || apply_configuration(configs, &options)).await?;
+        Ok(Response::new(apply_response(result)))
+    }
+
+    type EventsStream = Pin<Box<dyn Stream<Item = Result<proto::DisplayEvent, Status>> + Send>>;
+
+    async fn events(
+        &self,
+        request: Request<Streaming<proto::EventSubscription>>,
+    ) -> Result<Response<Self::EventsStream>, Status> {
+        let mut inbound = request.into_inner();
+        let (filter_sender, filter) = watch::channel(Vec::<u32>::new());
+        let (sender, receiver) = mpsc::channel(64);
+
+        // Each subscription message replaces the filter. The last filter stays
+        // in effect after the client closes its side.
+        tokio::spawn(async move {
+            while let Ok(Some(subscription)) = inbound.message().await {
+                if filter_sender.send(subscription.display_ids).is_err() {
+                    break;
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            let mut display_events = Box::pin(events::stream());
+            loop {
+                let event = tokio::select! {
+                    event = std::future::poll_fn(|cx| display_events.as_mut().poll_next(cx)) => event,
+                    _ = sender.closed() => break,
+                };
+                let Some(event) = event else { break };
+
+                let wanted = {
+                    let ids = filter.borrow();
+                    ids.is_empty() || ids.contains(&event.display_id)
+                };
+                if wanted
+                    && sender
+                        .send(Ok(proto::DisplayEvent::from(&event)))
+                        .await
+                        .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream { receiver })))
+    }
+}
+
+struct ReceiverStream<T> {
+    receiver: mpsc::Receiver<T>,
+}
+
+impl<T> Stream for ReceiverStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Serve the gRPC API on `addr` until the process is stopped.
+///
+/// There is no authentication: bind to loopback and reach it over SSH, or put
+/// it behind a proxy that authenticates clients.
+pub async fn serve(addr: SocketAddr, config: Config) -> Result<(), String> {
+    let service = Service {
+        config: Arc::new(config),
+    };
+
+    Server::builder()
+        .add_service(MacDispServer::new(service))
+        .serve(addr)
+        .await
+        .map_err(|e| format!("gRPC server failed: {}", e))
+}
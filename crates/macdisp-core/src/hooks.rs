@@ -0,0 +1,146 @@
+use crate::events::DisplayEvent;
+use serde::Serialize;
+use std::process::Command;
+
+/// Run a user command through `/bin/sh -c` with the event exported as
+/// `MACDISP_*` environment variables.
+///
+/// The command runs to completion before the next event is handled, so hooks
+/// never overlap. A non-zero exit status is reported as an error.
+pub fn run_event_hook(command: &str, event: &DisplayEvent) -> Result<(), String> {
+    let status = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .envs(event.env_vars())
+        .status()
+        .map_err(|e| format!("Failed to run hook `{}`: {}", command, e))?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("Hook `{}` exited with {}", command, status))
+    }
+}
+
+/// When a profile hook runs relative to applying the profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookStage {
+    PreApply,
+    PostApply,
+}
+
+impl HookStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HookStage::PreApply => "pre_apply",
+            HookStage::PostApply => "post_apply",
+        }
+    }
+}
+
+/// Outcome of a profile hook, reported with the apply.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HookResult {
+    pub stage: HookStage,
+    pub command: String,
+    pub success: bool,
+    /// `None` when the command could not be started or was killed by a signal
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// What the command printed, stdout then stderr
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub output: String,
+}
+
+impl HookResult {
+    /// One line describing a failed hook, with the last line it printed.
+    pub fn failure(&self) -> String {
+        let status = match self.exit_code {
+            Some(code) => format!("exited with {}", code),
+            None => "failed".to_string(),
+        };
+        match self.output.lines().last() {
+            Some(line) => format!(
+                "{} hook `{}` {}: {}",
+                self.stage.as_str(),
+                self.command,
+                status,
+                line
+            ),
+            None => format!("{} hook `{}` {}", self.stage.as_str(), self.command, status),
+        }
+    }
+}
+
+/// Run a profile's `pre_apply` or `post_apply` command through `/bin/sh -c`,
+/// capturing its output.
+///
+/// The command sees `MACDISP_HOOK` (the stage) and `MACDISP_PROFILE`, and
+/// after the apply `MACDISP_CHANGED` (`1` when a display was changed).
+pub fn run_profile_hook(
+    command: &str,
+    stage: HookStage,
+    profile: &str,
+    changed: Option<bool>,
+) -> HookResult {
+    let mut process = Command::new("/bin/sh");
+    process
+        .arg("-c")
+        .arg(command)
+        .env("MACDISP_HOOK", stage.as_str())
+        .env("MACDISP_PROFILE", profile);
+    if let Some(changed) = changed {
+        process.env("MACDISP_CHANGED", if changed { "1" } else { "0" });
+    }
+
+    let (success, exit_code, output) = match process.output() {
+        Ok(output) => {
+            let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+            text.push_str(&String::from_utf8_lossy(&output.stderr));
+            (
+                output.status.success(),
+                output.status.code(),
+                text.trim_end().to_string(),
+            )
+        }
+        Err(e) => (false, None, format!("Failed to run: {}", e)),
+    };
+    HookResult {
+        stage,
+        command: command.to_string(),
+        success,
+        exit_code,
+        output,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_profile_hook() {
+        let result = run_profile_hook(
+            "echo $MACDISP_HOOK $MACDISP_PROFILE $MACDISP_CHANGED",
+            HookStage::PostApply,
+            "desk",
+            Some(true),
+        );
+        assert!(result.success);
+        assert_eq!(result.output, "post_apply desk 1");
+
+        let result = run_profile_hook(
+            "echo starting; echo no yabai >&2; exit 3",
+            HookStage::PreApply,
+            "desk",
+            None,
+        );
+        assert!(!result.success);
+        assert_eq!(result.exit_code, Some(3));
+        assert_eq!(
+            result.failure(),
+            "pre_apply hook `echo starting; echo no yabai >&2; exit 3` exited with 3: no yabai"
+        );
+    }
+}
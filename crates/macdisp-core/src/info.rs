@@ -0,0 +1,319 @@
+//! Everything macdisp can find out about one display, gathered for
+//! `macdisp info` and for bug reports.
+//!
+//! Each source is read on its own. One that fails, such as DDC/CI on a
+//! monitor with it switched off, is recorded as unavailable with the reason
+//! instead of failing the whole report.
+
+use crate::ddc::Capabilities;
+use crate::edid::Edid;
+use crate::executor;
+use crate::mode_id::format_hz;
+use crate::reference::ReferencePreset;
+use crate::{DisplayClass, DisplayInfo};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::os::raw::c_char;
+
+/// Mirrors `DS_DETAIL_LEN` in display_services.h.
+const DETAIL_LEN: usize = 128;
+
+/// Layout of `DisplayDetails` in display_services.h.
+#[repr(C)]
+struct RawDetails {
+    connection: [c_char; DETAIL_LEN],
+    gpu: [c_char; DETAIL_LEN],
+    color_space: [c_char; DETAIL_LEN],
+    width_mm: f64,
+    height_mm: f64,
+}
+
+extern "C" {
+    fn ds_get_display_details(display_id: u32, details: *mut RawDetails) -> i32;
+}
+
+impl RawDetails {
+    fn size_mm(&self) -> Option<(f64, f64)> {
+        (self.width_mm > 0.0 && self.height_mm > 0.0).then_some((self.width_mm, self.height_mm))
+    }
+}
+
+fn read_details(display_id: u32) -> Option<(crate::error::Status, RawDetails)> {
+    executor::with_timeout(move || {
+        let mut raw = RawDetails {
+            connection: [0; DETAIL_LEN],
+            gpu: [0; DETAIL_LEN],
+            color_space: [0; DETAIL_LEN],
+            width_mm: 0.0,
+            height_mm: 0.0,
+        };
+        let status =
+            crate::error::status(|| unsafe { ds_get_display_details(display_id, &mut raw) });
+        (status, raw)
+    })
+}
+
+/// Width and height of the panel in millimetres as macOS reports them, which
+/// also works for built-in panels without a readable EDID.
+pub fn screen_size_mm(display_id: u32) -> Option<(f64, f64)> {
+    read_details(display_id)
+        .filter(|(status, _)| status.is_ok())
+        .and_then(|(_, raw)| raw.size_mm())
+}
+
+/// A NUL-terminated detail, `None` when the shim left it empty.
+fn detail(raw: &[c_char]) -> Option<String> {
+    let bytes: Vec<u8> = raw
+        .iter()
+        .take_while(|&&c| c != 0)
+        .map(|&c| c as u8)
+        .collect();
+    (!bytes.is_empty()).then(|| String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Everything known about a display.
+#[derive(Debug, Clone, Serialize)]
+pub struct DisplayDetails {
+    pub display: DisplayInfo,
+    /// "physical", "Sidecar" or "AirPlay"
+    pub class: &'static str,
+    /// How the display is attached: "built-in", "HDMI", "DisplayPort", ...
+    pub connection: Option<String>,
+    /// The GPU driving the display
+    pub gpu: Option<String>,
+    /// Name of the color profile in use
+    pub color_space: Option<String>,
+    /// Width and height of the panel in millimetres
+    pub size_mm: Option<(f64, f64)>,
+    pub edid: Option<Edid>,
+    /// 0.0-1.0, for displays DisplayServices can dim
+    pub brightness: Option<f32>,
+    pub ddc: Option<Capabilities>,
+    /// `None` when the display has no notch to hide
+    pub notch_hidden: Option<bool>,
+    pub reference_modes: Option<Vec<ReferencePreset>>,
+    /// Why each source that returned nothing was unavailable, by field
+    pub unavailable: BTreeMap<&'static str, String>,
+}
+
+impl DisplayDetails {
+    /// Keep `result` in the report, or record why it is missing.
+    fn take<T, E: fmt::Display>(&mut self, field: &'static str, result: Result<T, E>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.unavailable.insert(field, e.to_string());
+                None
+            }
+        }
+    }
+
+    /// The display's name from its EDID, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.edid.as_ref().and_then(|edid| edid.name.as_deref())
+    }
+}
+
+/// Read every source macdisp knows about for a display.
+///
+/// DDC/CI is skipped for built-in panels and streamed displays, which cannot
+/// answer it, so their reports do not wait on a timeout.
+pub fn display_details(info: &DisplayInfo) -> DisplayDetails {
+    let class = DisplayClass::of(info);
+    let mut details = DisplayDetails {
+        display: info.clone(),
+        class: class.name(),
+        connection: None,
+        gpu: None,
+        color_space: None,
+        size_mm: None,
+        edid: None,
+        brightness: None,
+        ddc: None,
+        notch_hidden: crate::is_notch_hidden(info.id),
+        reference_modes: None,
+        unavailable: BTreeMap::new(),
+    };
+
+    let display_id = info.id;
+    let raw = read_details(display_id);
+    let checked = crate::error::check(
+        "read display details",
+        display_id,
+        raw.as_ref().map(|(status, _)| status.clone()),
+    );
+    if let (Some(()), Some((_, raw))) = (details.take("connection", checked), raw) {
+        details.connection = detail(&raw.connection);
+        details.gpu = detail(&raw.gpu);
+        details.color_space = detail(&raw.color_space);
+        details.size_mm = raw.size_mm();
+    }
+
+    details.edid = details.take("edid", crate::edid::for_display(display_id));
+    details.brightness = details.take("brightness", crate::get_brightness(display_id));
+    details.ddc = if crate::selector::is_builtin(info) {
+        details.take::<_, &str>("ddc", Err("built-in panels have no DDC/CI"))
+    } else if class != DisplayClass::Physical {
+        let reason = format!("{} displays have no DDC/CI", class.name());
+        details.take::<_, String>("ddc", Err(reason))
+    } else {
+        details.take("ddc", crate::ddc::capabilities(display_id))
+    };
+    details.reference_modes =
+        details.take("reference_modes", crate::reference::presets(display_id));
+    details
+}
+
+/// The report as `macdisp info` prints it; `verbose` adds the id in hex.
+pub fn format_details(details: &DisplayDetails, verbose: bool) -> String {
+    let info = &details.display;
+    let mut output = if verbose {
+        format!("Display {}", crate::format_display_id(info.id))
+    } else {
+        format!("Display {}", info.id)
+    };
+    if let Some(name) = details.name() {
+        output.push_str(&format!(": {}", name));
+    }
+    output.push('\n');
+
+    let mut line = |label: &str, value: String| {
+        output.push_str(&format!("  {:<16}{}\n", format!("{}:", label), value));
+    };
+    line("Persistent id", info.persistent_id.clone());
+    line("Contextual id", info.contextual_id.to_string());
+    line("Serial", format!("s{}", info.serial));
+    line("Type", format!("{} ({})", info.display_type, details.class));
+    line(
+        "Mode",
+        format!(
+            "{}x{} hz:{} color_depth:{} scaling:{} mode {}",
+            info.width,
+            info.height,
+            format_hz(info.hz),
+            info.depth,
+            if info.scaling { "on" } else { "off" },
+            info.mode_number
+        ),
+    );
+    let mut placement = format!("({},{}) rotation {}", info.x, info.y, info.rotation);
+    if info.is_main {
+        placement.push_str(", main display");
+    }
+    if let Some(target) = info.mirror_of {
+        placement.push_str(&format!(", mirroring display {}", target));
+        if let Some(mirror_type) = info.mirror_type {
+            placement.push_str(&format!(" in {}", mirror_type.as_str()));
+        }
+    }
+    if !info.enabled {
+        placement.push_str(", disabled");
+    }
+    line("Origin", placement);
+
+    if let Some(connection) = &details.connection {
+        line("Connection", connection.clone());
+    }
+    if let Some(gpu) = &details.gpu {
+        line("GPU", gpu.clone());
+    }
+    if let Some(color_space) = &details.color_space {
+        line("Color profile", color_space.clone());
+    }
+    if let Some((width, height)) = details.size_mm {
+        let diagonal = (width * width + height * height).sqrt() / 25.4;
+        line(
+            "Size",
+            format!("{:.0}x{:.0} mm ({:.1}\")", width, height, diagonal),
+        );
+    }
+    if let Some(edid) = &details.edid {
+        let mut summary = format!(
+            "{} {:04x}/{:04x} version {}, {}",
+            edid.manufacturer,
+            edid.vendor_id,
+            edid.product_id,
+            edid.version,
+            edid.color_encoding.name()
+        );
+        if edid.cta_ycbcr {
+            summary.push_str(", YCbCr in CTA extension");
+        }
+        summary.push_str(&format!(", {} extension block(s)", edid.extensions));
+        line("EDID", summary);
+    }
+    if let Some(brightness) = details.brightness {
+        line("Brightness", format!("{:.0}%", brightness * 100.0));
+    }
+    if let Some(hidden) = details.notch_hidden {
+        line("Notch", if hidden { "hidden" } else { "shown" }.to_string());
+    }
+    if let Some(presets) = details.reference_modes.as_ref().filter(|p| !p.is_empty()) {
+        let names: Vec<String> = presets
+            .iter()
+            .map(|p| {
+                if p.active {
+                    format!("{} (active)", p.name)
+                } else {
+                    p.name.clone()
+                }
+            })
+            .collect();
+        line("Reference modes", names.join(", "));
+    }
+    if let Some(ddc) = &details.ddc {
+        let mut summary = format!("{} VCP controls", ddc.vcp.len());
+        if let Some(version) = &ddc.mccs_version {
+            summary.push_str(&format!(", MCCS {}", version));
+        }
+        if let Some(model) = &ddc.model {
+            summary.push_str(&format!(", model {}", model));
+        }
+        line("DDC/CI", summary);
+    }
+
+    if !details.unavailable.is_empty() {
+        output.push_str("Unavailable:\n");
+        for (field, reason) in &details.unavailable {
+            output.push_str(&format!("  {}: {}\n", field, reason));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    #[test]
+    fn test_format_details() {
+        let mut details = DisplayDetails {
+            display: display(2, "AAAA"),
+            class: "physical",
+            connection: Some("HDMI".to_string()),
+            gpu: Some("Apple M2 Pro".to_string()),
+            color_space: None,
+            size_mm: Some((597.0, 336.0)),
+            edid: None,
+            brightness: None,
+            ddc: None,
+            notch_hidden: None,
+            reference_modes: Some(vec![]),
+            unavailable: BTreeMap::new(),
+        };
+        let brightness = details.take::<f32, _>("brightness", Err("not supported by this display"));
+        assert_eq!(brightness, None);
+
+        let output = format_details(&details, false);
+        assert!(output.starts_with("Display 2\n"));
+        assert!(format_details(&details, true).starts_with("Display 2 (0x00000002)\n"));
+        assert!(output.contains("  Connection:     HDMI\n"));
+        assert!(output.contains("  GPU:            Apple M2 Pro\n"));
+        assert!(output.contains("597x336 mm (27.0\")"));
+        assert!(!output.contains("Color profile"));
+        assert!(!output.contains("Reference modes"));
+        assert!(output.ends_with("Unavailable:\n  brightness: not supported by this display\n"));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::ddc::VcpCache;
+use crate::{
+    get_all_display_info, get_all_modes, is_display_services_available, DisplayInfo, DisplayMode,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How long the CLI waits for the daemon before querying displays itself.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Everything `list` prints, as cached by the daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisplayState {
+    pub display_services: bool,
+    pub displays: Vec<DisplayInfo>,
+    /// Available modes by display id
+    pub modes: BTreeMap<u32, Vec<DisplayMode>>,
+}
+
+impl DisplayState {
+    /// Walk CoreGraphics and DisplayServices for the current state.
+    pub fn query() -> Self {
+        let displays = get_all_display_info();
+        let modes = displays
+            .iter()
+            .map(|info| (info.id, get_all_modes(info.id)))
+            .collect();
+        DisplayState {
+            display_services: is_display_services_available(),
+            displays,
+            modes,
+        }
+    }
+}
+
+/// The daemon's control socket, next to the apply lock.
+pub fn socket_path() -> PathBuf {
+    let uid = unsafe { libc::getuid() };
+    std::env::temp_dir().join(format!("macdisp-{}.sock", uid))
+}
+
+/// The display state from a running daemon's cache, falling back to querying
+/// the displays directly when no daemon answers.
+pub fn display_state() -> DisplayState {
+    cached_state().unwrap_or_else(DisplayState::query)
+}
+
+/// Ask a running daemon for its cached display state.
+pub fn cached_state() -> Option<DisplayState> {
+    serde_json::from_str(&request("state")?).ok()
+}
+
+/// Ask a running daemon for a display's brightness, which it keeps for DDC
+/// monitors and reads again in the background once it is a few seconds old.
+/// `None` when no daemon answers.
+pub fn cached_brightness(display_id: u32) -> Option<Result<f32, String>> {
+    serde_json::from_str(&request(&format!("brightness {}", display_id))?).ok()
+}
+
+/// Tell a running daemon a display's brightness changed, so it does not
+/// answer with the old value.
+pub fn forget_brightness(display_id: u32) {
+    let _ = request(&format!("forget {}", display_id));
+}
+
+/// Send one request line to a running daemon and read its answer.
+fn request(line: &str) -> Option<String> {
+    let mut stream = UnixStream::connect(socket_path()).ok()?;
+    stream.set_read_timeout(Some(QUERY_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(QUERY_TIMEOUT)).ok()?;
+    stream.write_all(format!("{}\n", line).as_bytes()).ok()?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    Some(response)
+}
+
+/// State shared between the daemon's event callback and its socket.
+///
+/// Every display event bumps the generation and drops the cached state, which
+/// is rebuilt on the next query. A state computed while an event arrived is
+/// not stored, so the cache never holds an arrangement older than the last event.
+#[derive(Clone, Default)]
+pub(crate) struct StateCache {
+    inner: Arc<Mutex<(u64, Option<DisplayState>)>>,
+}
+
+impl StateCache {
+    pub(crate) fn invalidate(&self) {
+        let mut inner = self.lock();
+        inner.0 += 1;
+        inner.1 = None;
+    }
+
+    fn get(&self) -> DisplayState {
+        let generation = {
+            let inner = self.lock();
+            if let Some(state) = &inner.1 {
+                return state.clone();
+            }
+            inner.0
+        };
+
+        let state = DisplayState::query();
+        let mut inner = self.lock();
+        if inner.0 == generation {
+            inner.1 = Some(state.clone());
+        }
+        state
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, (u64, Option<DisplayState>)> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Answer queries on the control socket from a background thread.
+pub(crate) fn serve(cache: StateCache, vcp: VcpCache) -> Result<(), String> {
+    let path = socket_path();
+    if UnixStream::connect(&path).is_ok() {
+        return Err(format!("{} is in use by another daemon", path.display()));
+    }
+    // Left behind by a daemon that did not exit cleanly
+    let _ = std::fs::remove_file(&path);
+
+    let listener = UnixListener::bind(&path)
+        .map_err(|e| format!("Failed to listen on {}: {}", path.display(), e))?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("Failed to restrict {}: {}", path.display(), e))?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A client that connects and says nothing, or a DDC read that
+            // waits for the monitor, must not hold up the others
+            let (cache, vcp) = (cache.clone(), vcp.clone());
+            thread::spawn(move || {
+                if let Err(e) = answer(stream, &cache, &vcp) {
+                    crate::daemon::log(&format!("Control socket: {}", e));
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn answer(stream: UnixStream, cache: &StateCache, vcp: &VcpCache) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(QUERY_TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    let mut request = String::new();
+    BufReader::new(&stream)
+        .read_line(&mut request)
+        .map_err(|e| e.to_string())?;
+
+    let request = request.trim();
+    let (command, argument) = request.split_once(' ').unwrap_or((request, ""));
+    let display_id = || {
+        argument
+            .parse::<u32>()
+            .map_err(|_| format!("Invalid display id: {}", argument))
+    };
+    let json = match command {
+        "state" => serde_json::to_string(&cache.get()),
+        "brightness" => {
+            let display_id = display_id()?;
+            let brightness =
+                crate::brightness::get_brightness_with(display_id, |id, code| vcp.get(id, code))
+                    .map_err(|e| e.to_string());
+            serde_json::to_string(&brightness)
+        }
+        "forget" => {
+            vcp.forget(display_id()?);
+            return Ok(());
+        }
+        _ => return Err(format!("Unknown request: {}", request)),
+    }
+    .map_err(|e| format!("Failed to serialize answer: {}", e))?;
+    (&stream)
+        .write_all(json.as_bytes())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        let state = DisplayState {
+            display_services: true,
+            displays: vec![crate::apply::tests::display(2, "AAAA")],
+            modes: BTreeMap::from([(2, Vec::new())]),
+        };
+        let json = serde_json::to_string(&state).unwrap();
+        let parsed: DisplayState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.displays[0].persistent_id, "AAAA");
+        assert!(parsed.modes.contains_key(&2));
+    }
+}
@@ -0,0 +1,194 @@
+//! The brightness keys for external monitors.
+//!
+//! macOS sends the brightness keys to the built-in panel and Apple displays
+//! only. With `brightness_keys` on, the daemon catches them while the pointer
+//! is on another display, sets that display's brightness, over DDC/CI where
+//! needed, and shows the system brightness OSD on it.
+
+use crate::brightness::{get_brightness_with, set_brightness_cached};
+use crate::daemon::log;
+use crate::ddc::VcpCache;
+use crate::error::{self, DisplayError, ErrorKind};
+use crate::executor::{guard_callback, on_run_loop_thread};
+use core_graphics::display::CGDisplay;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::os::raw::c_void;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type BrightnessKeyCallback = extern "C" fn(up: bool, display_id: u32, context: *mut c_void) -> bool;
+
+extern "C" {
+    fn ds_tap_brightness_keys(callback: BrightnessKeyCallback, context: *mut c_void) -> i32;
+    fn ds_show_brightness_osd(display_id: u32, brightness: f32) -> i32;
+}
+
+/// Share of the full range one press changes, as on the built-in panel.
+pub const BRIGHTNESS_STEP: f32 = 1.0 / 16.0;
+
+/// How long the level last set is trusted for the next press. Past that it
+/// is read again, in case the monitor's own buttons changed it.
+const LEVEL_TTL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrightnessKey {
+    Up,
+    Down,
+}
+
+impl BrightnessKey {
+    fn steps(self) -> i32 {
+        match self {
+            BrightnessKey::Up => 1,
+            BrightnessKey::Down => -1,
+        }
+    }
+}
+
+/// The brightness `steps` presses away from `current`, up being positive.
+///
+/// Lands on whole steps, so the OSD fills the same squares as for the
+/// built-in panel.
+pub fn stepped_brightness(current: f32, steps: i32) -> f32 {
+    let step = (current / BRIGHTNESS_STEP).round() + steps as f32;
+    (step * BRIGHTNESS_STEP).clamp(0.0, 1.0)
+}
+
+type KeyHandler = Box<dyn FnMut(BrightnessKey, u32) -> bool + Send>;
+
+extern "C" fn brightness_key_callback(up: bool, display_id: u32, context: *mut c_void) -> bool {
+    let handler = unsafe { &mut *(context as *mut KeyHandler) };
+    let key = if up {
+        BrightnessKey::Up
+    } else {
+        BrightnessKey::Down
+    };
+    let mut handled = false;
+    guard_callback("brightness key", || handled = handler(key, display_id));
+    handled
+}
+
+/// Call `handler` with each brightness key press and the display under the
+/// pointer. Presses it returns `true` for are kept from macOS.
+///
+/// The handler runs while macOS waits for the key, so it must return at once.
+/// Needs the Accessibility permission; the registration lasts until the
+/// process exits.
+pub fn tap_brightness_keys<F>(handler: F) -> Result<(), String>
+where
+    F: FnMut(BrightnessKey, u32) -> bool + Send + 'static,
+{
+    let handler: Box<KeyHandler> = Box::new(Box::new(handler));
+    let context = Box::into_raw(handler) as usize;
+
+    let status = on_run_loop_thread(move || {
+        error::status(|| unsafe {
+            ds_tap_brightness_keys(brightness_key_callback, context as *mut c_void)
+        })
+    });
+    if !status.is_ok() {
+        drop(unsafe { Box::from_raw(context as *mut KeyHandler) });
+        return Err(format!(
+            "Failed to intercept the brightness keys: {}",
+            status
+        ));
+    }
+    Ok(())
+}
+
+/// Show the system brightness OSD on a display, filled to `brightness`.
+pub fn show_brightness_osd(display_id: u32, brightness: f32) -> Result<(), DisplayError> {
+    let status = error::status(|| unsafe { ds_show_brightness_osd(display_id, brightness) });
+    error::check("show the brightness OSD", display_id, Some(status))
+}
+
+/// Send the brightness keys pressed over external displays to those
+/// displays, for the daemon.
+///
+/// Displays found to have no brightness control are left to macOS from then
+/// on, so their presses change the built-in panel as before. DDC/CI values
+/// go through the daemon's `vcp` cache, which status bars read too.
+pub(crate) fn route_brightness_keys(vcp: VcpCache) -> Result<(), String> {
+    let uncontrolled: Arc<Mutex<HashSet<u32>>> = Arc::default();
+    let (sender, receiver) = mpsc::channel();
+
+    let skip = Arc::clone(&uncontrolled);
+    tap_brightness_keys(move |key, display_id| {
+        if CGDisplay::new(display_id).is_builtin()
+            || skip
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .contains(&display_id)
+        {
+            return false;
+        }
+        sender.send((key, display_id)).is_ok()
+    })?;
+    thread::spawn(move || step_brightness(receiver, uncontrolled, vcp));
+    Ok(())
+}
+
+fn step_brightness(
+    receiver: Receiver<(BrightnessKey, u32)>,
+    uncontrolled: Arc<Mutex<HashSet<u32>>>,
+    vcp: VcpCache,
+) {
+    let mut levels: HashMap<u32, (f32, Instant)> = HashMap::new();
+    while let Ok(first) = receiver.recv() {
+        // Presses that queued up during a slow DDC write are made in one go
+        let mut steps: BTreeMap<u32, i32> = BTreeMap::new();
+        for (key, display_id) in std::iter::once(first).chain(receiver.try_iter()) {
+            *steps.entry(display_id).or_default() += key.steps();
+        }
+
+        for (display_id, steps) in steps {
+            let current = match levels.get(&display_id) {
+                Some((level, set_at)) if set_at.elapsed() < LEVEL_TTL => Ok(*level),
+                _ => get_brightness_with(display_id, |id, code| vcp.get(id, code)),
+            };
+            let result = current.and_then(|current| {
+                let level = stepped_brightness(current, steps);
+                set_brightness_cached(display_id, level, &vcp).map(|()| level)
+            });
+            match result {
+                Ok(level) => {
+                    levels.insert(display_id, (level, Instant::now()));
+                    // Without the OSD the change still happens, just unseen
+                    let _ = show_brightness_osd(display_id, level);
+                }
+                Err(e) => {
+                    levels.remove(&display_id);
+                    if e.kind == ErrorKind::NotSupported {
+                        log(&format!(
+                            "Display {} has no brightness control, leaving its brightness keys to macOS",
+                            display_id
+                        ));
+                        uncontrolled
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .insert(display_id);
+                    } else {
+                        log(&format!("Brightness key: {}", e));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stepped_brightness() {
+        assert_eq!(stepped_brightness(0.5, 1), 0.5625);
+        assert_eq!(stepped_brightness(0.5, -3), 0.3125);
+        // Off-grid levels snap to the nearest step first
+        assert_eq!(stepped_brightness(0.52, 1), 0.5625);
+        assert_eq!(stepped_brightness(0.97, 2), 1.0);
+        assert_eq!(stepped_brightness(0.05, -4), 0.0);
+    }
+}
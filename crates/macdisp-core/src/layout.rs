@@ -0,0 +1,361 @@
+use crate::geometry::{PointPx, Rect, SizePx};
+use crate::{ConfigureOptions, DisplayIdentity, DisplayInfo, MirrorSetting};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Changes that dissolve every mirror set and place the displays side by side.
+///
+/// The main display stays at (0,0) and the others follow it to the right,
+/// top-aligned, in their current left-to-right order, each at the size of its
+/// current mode.
+pub fn extended_layout(displays: &[DisplayInfo]) -> Vec<(u32, ConfigureOptions)> {
+    let mut ordered: Vec<&DisplayInfo> = displays.iter().collect();
+    ordered.sort_by_key(|info| (!info.is_main, info.origin(), info.id));
+
+    let mut next = PointPx::ORIGIN;
+    ordered
+        .into_iter()
+        .map(|info| {
+            let options = ConfigureOptions {
+                origin: Some(next.into()),
+                mirror: info.mirror_of.map(|_| MirrorSetting::Off),
+                ..Default::default()
+            };
+            next = Rect::new(next, info.size()).right_neighbor_origin();
+            (info.id, options)
+        })
+        .collect()
+}
+
+/// Whether `changes` would leave `displays` exactly as they are.
+pub fn is_current_layout(changes: &[(u32, ConfigureOptions)], displays: &[DisplayInfo]) -> bool {
+    changes.iter().all(|(id, options)| {
+        displays.iter().any(|info| {
+            info.id == *id
+                && options.mirror.is_none()
+                && options.origin.map_or(info.origin(), PointPx::from) == info.origin()
+        })
+    })
+}
+
+/// Changes that turn off the built-in panel `builtin_id` while the external
+/// displays stay on, without closing the lid.
+///
+/// Displays mirroring the built-in panel stop mirroring it, as does the panel
+/// itself. When the built-in panel is the main display, the arrangement moves
+/// so that the external display nearest (0,0) takes over the menu bar.
+/// Refuses when no external display is active, which would leave the Mac
+/// without a screen.
+pub fn internal_off_changes(
+    displays: &[DisplayInfo],
+    builtin_id: u32,
+) -> Result<Vec<(u32, ConfigureOptions)>, String> {
+    let builtin = displays
+        .iter()
+        .find(|info| info.id == builtin_id)
+        .ok_or_else(|| format!("Built-in display {} is not active", builtin_id))?;
+    let anchor = displays
+        .iter()
+        .filter(|info| info.enabled && info.id != builtin_id)
+        .filter(|info| info.mirror_of.is_none_or(|master| master == builtin_id))
+        .min_by_key(|info| (info.x.abs() + info.y.abs(), info.id))
+        .ok_or("Refusing to turn off the built-in display with no external display connected")?;
+    let (dx, dy) = if builtin.is_main {
+        (-anchor.x, -anchor.y)
+    } else {
+        (0, 0)
+    };
+
+    let mut changes = vec![(
+        builtin_id,
+        ConfigureOptions {
+            mirror: builtin.mirror_of.map(|_| MirrorSetting::Off),
+            enabled: Some(false),
+            ..Default::default()
+        },
+    )];
+    for info in displays.iter().filter(|info| info.id != builtin_id) {
+        let options = ConfigureOptions {
+            origin: ((dx, dy) != (0, 0)).then(|| info.origin().offset(dx, dy).into()),
+            mirror: (info.mirror_of == Some(builtin_id)).then_some(MirrorSetting::Off),
+            ..Default::default()
+        };
+        if !options.is_empty() {
+            changes.push((info.id, options));
+        }
+    }
+    Ok(changes)
+}
+
+/// Where `internal off` remembers the panel it turned off, since a disabled
+/// display is not guaranteed to stay in the online list.
+pub fn internal_off_path() -> PathBuf {
+    crate::settings::config_dir().join("internal-off")
+}
+
+/// Remember at `path` that `internal off` turned off `builtin_id`.
+pub fn save_internal_off(path: &Path, builtin_id: u32) -> Result<(), String> {
+    match path.parent() {
+        Some(dir) => std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e)),
+        None => Ok(()),
+    }
+    .and_then(|_| {
+        std::fs::write(path, format!("{}\n", builtin_id))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    })
+}
+
+/// The built-in panel `internal` works on: the one among `identities`, or
+/// else the one `internal off` remembered at `path`.
+pub fn internal_panel(identities: &[DisplayIdentity], path: &Path) -> Option<u32> {
+    identities
+        .iter()
+        .find(|identity| identity.is_builtin == Some(true))
+        .and_then(|identity| identity.id)
+        .or_else(|| std::fs::read_to_string(path).ok()?.trim().parse().ok())
+}
+
+/// The size `info` takes up once rotated to `degree`, at the `res:` or
+/// `looks-like:` size `requested` when there is one.
+///
+/// Mode sizes match written either way round, so a requested size is turned
+/// to stand the way the display will.
+pub fn rotated_size(info: &DisplayInfo, requested: Option<SizePx>, degree: u32) -> SizePx {
+    let native = info.size().rotated(info.rotation);
+    let size = requested.unwrap_or(native);
+    let landscape = native.is_landscape() != crate::is_sideways(degree);
+    if size.is_landscape() == landscape || size.width == size.height {
+        size
+    } else {
+        size.rotated(90)
+    }
+}
+
+/// New origins that keep the arrangement free of overlaps and gaps when
+/// `display_id` changes to `size`, as it does when rotated.
+///
+/// The display keeps the corner facing the main display: one left of the
+/// main display grows leftwards, one above it upwards, and any other keeps
+/// its top left corner. Displays beside an edge that moves, and the ones
+/// beside those, move with it. The main display never moves.
+pub fn reflow(displays: &[DisplayInfo], display_id: u32, size: SizePx) -> Vec<(u32, PointPx)> {
+    let Some(info) = displays.iter().find(|info| info.id == display_id) else {
+        return Vec::new();
+    };
+    let old = info.bounds();
+    let dx = size.width as i32 - old.size.width as i32;
+    let dy = size.height as i32 - old.size.height as i32;
+    let grows_left = old.right() <= 0;
+    let grows_up = old.bottom() <= 0;
+
+    let placed: Vec<&DisplayInfo> = displays
+        .iter()
+        .filter(|other| other.enabled && other.mirror_of.is_none() && other.id != display_id)
+        .collect();
+    // Follow the chain of displays beside the moving edge
+    let chain = |side: &str| {
+        let mut moved = Vec::new();
+        let mut frontier = vec![old];
+        while let Some(bounds) = frontier.pop() {
+            for other in &placed {
+                if !moved.contains(&other.id) && other.bounds().side_of(&bounds) == Some(side) {
+                    moved.push(other.id);
+                    frontier.push(other.bounds());
+                }
+            }
+        }
+        moved
+    };
+
+    let mut shifts: BTreeMap<u32, (i32, i32)> = BTreeMap::new();
+    shifts.insert(
+        display_id,
+        (
+            if grows_left { -dx } else { 0 },
+            if grows_up { -dy } else { 0 },
+        ),
+    );
+    if dx != 0 {
+        let (side, shift) = if grows_left {
+            ("left of", -dx)
+        } else {
+            ("right of", dx)
+        };
+        for id in chain(side) {
+            shifts.entry(id).or_default().0 = shift;
+        }
+    }
+    if dy != 0 {
+        let (side, shift) = if grows_up {
+            ("above", -dy)
+        } else {
+            ("below", dy)
+        };
+        for id in chain(side) {
+            shifts.entry(id).or_default().1 = shift;
+        }
+    }
+
+    shifts
+        .into_iter()
+        .filter(|&(_, shift)| shift != (0, 0))
+        .filter_map(|(id, (dx, dy))| {
+            let info = displays.iter().find(|info| info.id == id)?;
+            (!info.is_main).then(|| (id, info.origin().offset(dx, dy)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    #[test]
+    fn test_extended_layout() {
+        let mut builtin = display(1, "AAAA");
+        builtin.width = 1512;
+        let mut left = display(2, "BBBB");
+        left.x = -2560;
+        let mut mirror = display(3, "CCCC");
+        mirror.mirror_of = Some(1);
+        mirror.is_mirror = true;
+
+        let displays = vec![builtin, left, mirror];
+        let changes = extended_layout(&displays);
+        let origins: Vec<_> = changes
+            .iter()
+            .map(|(id, options)| (*id, options.origin.unwrap()))
+            .collect();
+        assert_eq!(origins, vec![(1, (0, 0)), (2, (1512, 0)), (3, (4072, 0))]);
+        assert_eq!(changes[2].1.mirror, Some(MirrorSetting::Off));
+        assert!(!is_current_layout(&changes, &displays));
+    }
+
+    #[test]
+    fn test_internal_off_changes() {
+        // The built-in panel is main, one monitor mirrors it and another
+        // sits to its right
+        let mut builtin = display(1, "AAAA");
+        builtin.display_type = "MacBook built-in display".to_string();
+        let mut mirror = display(2, "BBBB");
+        mirror.mirror_of = Some(1);
+        mirror.is_mirror = true;
+        let mut right = display(3, "CCCC");
+        right.x = 2560;
+
+        let changes = internal_off_changes(&[builtin.clone(), mirror, right.clone()], 1).unwrap();
+        assert_eq!(
+            changes,
+            vec![
+                (
+                    1,
+                    ConfigureOptions {
+                        enabled: Some(false),
+                        ..Default::default()
+                    }
+                ),
+                (
+                    2,
+                    ConfigureOptions {
+                        mirror: Some(MirrorSetting::Off),
+                        ..Default::default()
+                    }
+                ),
+            ]
+        );
+
+        // Without the mirror, the monitor on the right becomes main
+        let changes = internal_off_changes(&[builtin.clone(), right], 1).unwrap();
+        assert_eq!(changes[1].1.origin, Some((0, 0)));
+
+        let err = internal_off_changes(&[builtin], 1).unwrap_err();
+        assert!(err.contains("no external display"));
+        assert!(internal_off_changes(&[], 1).is_err());
+    }
+
+    #[test]
+    fn test_internal_panel_round_trip() {
+        let panel = DisplayIdentity {
+            id: Some(1),
+            persistent_id: "AAAA".to_string(),
+            serial: 0,
+            is_main: true,
+            is_builtin: Some(true),
+        };
+        let monitor = DisplayIdentity {
+            id: Some(2),
+            persistent_id: "BBBB".to_string(),
+            serial: 7,
+            is_main: false,
+            is_builtin: Some(false),
+        };
+        let path =
+            std::env::temp_dir().join(format!("macdisp-test-{}-internal", std::process::id()));
+        assert_eq!(
+            internal_panel(&[panel.clone(), monitor.clone()], &path),
+            Some(1)
+        );
+
+        // Off: the panel may leave the online list, so `on` reads it back
+        save_internal_off(&path, 1).unwrap();
+        let found = internal_panel(std::slice::from_ref(&monitor), &path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(found, Some(1));
+
+        // On: once the file is gone only a listed panel is found
+        assert_eq!(internal_panel(&[monitor], &path), None);
+    }
+
+    #[test]
+    fn test_reflow() {
+        // Left to right: a monitor left of the main display, the main
+        // display, a monitor to turn portrait and one more beyond it
+        let mut left = display(2, "BBBB");
+        left.x = -2560;
+        let main = display(1, "AAAA");
+        let mut turned = display(3, "CCCC");
+        turned.x = 2560;
+        let mut beyond = display(4, "DDDD");
+        beyond.x = 5120;
+        let mut below = display(5, "EEEE");
+        below.x = 2560;
+        below.y = 1440;
+        let displays = vec![left, main, turned, beyond, below];
+
+        let portrait = SizePx::new(1440, 2560);
+        assert_eq!(
+            reflow(&displays, 3, portrait),
+            vec![(4, PointPx::new(4000, 0)), (5, PointPx::new(2560, 2560))]
+        );
+        // Left of the main display, the right edge stays flush with it
+        assert_eq!(
+            reflow(&displays, 2, portrait),
+            vec![(2, PointPx::new(-1440, 0))]
+        );
+        // The main display keeps (0,0) and pushes its neighbours instead
+        assert_eq!(
+            reflow(&displays, 1, portrait),
+            vec![(3, PointPx::new(1440, 0)), (4, PointPx::new(4000, 0))]
+        );
+        assert!(reflow(&displays, 3, SizePx::new(2560, 1440)).is_empty());
+
+        // `degree:90 res:2560x1440` names the mode as it is listed; the
+        // display still stands portrait
+        let turned = &displays[2];
+        assert_eq!(rotated_size(turned, None, 90), portrait);
+        assert_eq!(
+            rotated_size(turned, Some(SizePx::new(2560, 1440)), 90),
+            portrait
+        );
+        assert_eq!(
+            rotated_size(turned, Some(SizePx::new(1080, 1920)), 270),
+            SizePx::new(1080, 1920)
+        );
+        assert_eq!(
+            rotated_size(turned, Some(SizePx::new(1080, 1920)), 180),
+            SizePx::new(1920, 1080)
+        );
+    }
+}
@@ -0,0 +1,1834 @@
+//! The display configuration engine behind the `macdisp` command.
+//!
+//! Everything that talks to CoreGraphics, DisplayServices and IOKit lives
+//! here, with the display models and the apply planner, so an app can drive
+//! displays without the command line interface. The library prints nothing;
+//! messages go to the handler set with [`output::set_handler`].
+//!
+//! # Stability
+//!
+//! macdisp-core follows semantic versioning. While it is at 0.x, a minor
+//! release may break the API and a patch release does not. This covers:
+//!
+//! - the display model in this module: [`DisplayInfo`], [`DisplayMode`],
+//!   [`DisplayConfig`] and the functions that read and change displays
+//! - [`apply`], [`config`], [`settings`], [`mode_id`], [`geometry`],
+//!   [`events`], [`error`] and [`output`]
+//!
+//! The other public modules, such as [`daemon`], [`server`] and report
+//! formatting like [`format_display_list`], serve the `macdisp` command and
+//! may change in any release. The JSON of `list --json` is versioned on its
+//! own by [`LISTING_SCHEMA_VERSION`].
+
+use config::{CommandIds, IdStrategy};
+use core_graphics::display::{CGDisplayBounds, CGGetActiveDisplayList};
+use error::DisplayError;
+pub use executor::{call_timeout, set_call_timeout, DEFAULT_CALL_TIMEOUT};
+use geometry::{PointPx, Rect, SizePx};
+use mode_id::{format_hz, ModeId};
+use serde::{Deserialize, Serialize};
+pub use throttle::{mode_set_interval, set_mode_set_interval, DEFAULT_MODE_SET_INTERVAL};
+
+pub mod agents;
+pub mod apply;
+pub mod brightness;
+pub mod bundle;
+pub mod config;
+pub mod daemon;
+pub mod ddc;
+pub mod devices;
+pub mod dpi;
+pub mod edid;
+mod encoding;
+pub mod error;
+pub mod events;
+mod executor;
+pub mod expect;
+pub mod export;
+pub mod gamma;
+pub mod geometry;
+pub mod get;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod hooks;
+pub mod info;
+pub mod ipc;
+pub mod keys;
+pub mod layout;
+pub mod lock;
+pub mod mirror;
+pub mod mode_id;
+pub mod output;
+pub mod patch;
+pub mod porcelain;
+pub mod power;
+pub mod quirks;
+pub mod recover;
+pub mod reference;
+pub mod selector;
+pub mod server;
+pub mod session;
+pub mod settings;
+pub mod snapshot;
+pub mod status;
+pub mod sync;
+pub mod system_prefs;
+pub mod template;
+mod throttle;
+pub mod usb;
+pub mod wallpaper;
+pub mod windows;
+
+#[repr(C)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisplayMode {
+    pub width: u32,
+    pub height: u32,
+    pub refresh_rate: f64,
+    pub depth: u32,
+    pub mode_number: u32,
+    pub is_stretched: bool,
+    pub is_interlaced: bool,
+    pub is_tv_mode: bool,
+    pub is_safe_for_hardware: bool,
+    pub is_scaled: bool,
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+}
+
+/// Layout of `DisplayMode` in display_services.h as it comes over FFI.
+///
+/// C bools are read as bytes so an out-of-range value is rejected instead of
+/// becoming an invalid Rust `bool`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct RawDisplayMode {
+    width: u32,
+    height: u32,
+    refresh_rate: f64,
+    depth: u32,
+    mode_number: u32,
+    is_stretched: u8,
+    is_interlaced: u8,
+    is_tv_mode: u8,
+    is_safe_for_hardware: u8,
+    is_scaled: u8,
+    pixel_width: u32,
+    pixel_height: u32,
+}
+
+/// More modes than any display reports; a larger count means a corrupt list.
+const MAX_MODES: usize = 4096;
+
+/// Largest plausible dimension, in points or pixels.
+const MAX_DIMENSION: u32 = 1 << 16;
+
+impl RawDisplayMode {
+    fn validate(&self) -> Result<DisplayMode, &'static str> {
+        let flag = |value: u8| match value {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err("mode has an invalid boolean flag"),
+        };
+
+        if self.width == 0 || self.height == 0 {
+            return Err("mode has a zero dimension");
+        }
+        if [self.width, self.height, self.pixel_width, self.pixel_height]
+            .iter()
+            .any(|d| *d > MAX_DIMENSION)
+        {
+            return Err("mode has an implausible dimension");
+        }
+        if !self.refresh_rate.is_finite() || !(0.0..1000.0).contains(&self.refresh_rate) {
+            return Err("mode has an invalid refresh rate");
+        }
+
+        Ok(DisplayMode {
+            width: self.width,
+            height: self.height,
+            refresh_rate: self.refresh_rate,
+            depth: self.depth,
+            mode_number: self.mode_number,
+            is_stretched: flag(self.is_stretched)?,
+            is_interlaced: flag(self.is_interlaced)?,
+            is_tv_mode: flag(self.is_tv_mode)?,
+            is_safe_for_hardware: flag(self.is_safe_for_hardware)?,
+            is_scaled: flag(self.is_scaled)?,
+            pixel_width: self.pixel_width,
+            pixel_height: self.pixel_height,
+        })
+    }
+}
+
+fn is_aligned<T>(ptr: *const T) -> bool {
+    (ptr as usize) & (std::mem::align_of::<T>() - 1) == 0
+}
+
+#[repr(C)]
+struct DisplayModeList {
+    modes: *mut RawDisplayMode,
+    count: usize,
+}
+
+impl DisplayModeList {
+    /// Copy the modes out of the list after checking the pointer and count.
+    ///
+    /// # Safety
+    /// When `modes` is non-null, aligned and `count` is within bounds, it must
+    /// point to `count` readable elements.
+    unsafe fn to_modes(&self) -> Result<Vec<DisplayMode>, &'static str> {
+        if self.count == 0 {
+            return Ok(Vec::new());
+        }
+        if self.modes.is_null() {
+            return Err("mode list has a null modes pointer");
+        }
+        if self.count > MAX_MODES {
+            return Err("mode list count is implausibly large");
+        }
+        if !is_aligned(self.modes) {
+            return Err("mode list pointer is misaligned");
+        }
+
+        std::slice::from_raw_parts(self.modes, self.count)
+            .iter()
+            .map(RawDisplayMode::validate)
+            .collect()
+    }
+}
+
+/// Mirrors `DisplayConfigureOptions` in display_services.h. Every value has an
+/// explicit `has_*` flag so that zero is never mistaken for "unchanged".
+#[repr(C)]
+#[derive(Default)]
+struct RawConfigureOptions {
+    has_origin: bool,
+    x: i32,
+    y: i32,
+    has_rotation: bool,
+    rotation: u32,
+    has_mirror: bool,
+    mirror_display_id: u32,
+    has_enabled: bool,
+    enabled: bool,
+}
+
+extern "C" {
+    fn CGGetOnlineDisplayList(max_displays: u32, displays: *mut u32, count: *mut u32) -> i32;
+    fn ds_is_available() -> bool;
+    fn ds_get_display_uuid(display_id: u32) -> *mut std::os::raw::c_char;
+    fn ds_get_display_type(display_id: u32) -> *mut std::os::raw::c_char;
+    fn ds_get_hardware_uuid() -> *mut std::os::raw::c_char;
+    fn ds_get_brightness(display_id: u32, brightness: *mut f32) -> i32;
+    fn ds_set_brightness(display_id: u32, brightness: f32) -> i32;
+    fn ds_get_all_modes(display_id: u32) -> *mut DisplayModeList;
+    fn ds_get_current_mode(display_id: u32) -> *mut RawDisplayMode;
+    fn ds_set_mode(display_id: u32, mode_number: u32) -> i32;
+    fn ds_configure_display(display_id: u32, options: *const RawConfigureOptions) -> i32;
+    fn ds_configure_displays(
+        display_ids: *const u32,
+        options: *const RawConfigureOptions,
+        count: usize,
+    ) -> i32;
+    fn ds_free_mode_list(list: *mut DisplayModeList);
+    fn ds_free_mode(mode: *mut RawDisplayMode);
+    fn ds_free_string(str: *mut std::os::raw::c_char);
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisplayInfo {
+    pub id: u32,
+    pub persistent_id: String, // UUID
+    pub contextual_id: u32,
+    pub serial: u32,
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    pub rotation: u32,
+    pub hz: f64,
+    pub depth: u32,
+    pub scaling: bool,
+    pub mode_number: u32,
+    pub mode_id: ModeId,
+    pub is_main: bool,
+    pub is_mirror: bool,
+    pub mirror_of: Option<u32>,
+    /// How macOS mirrors the display, when it is mirroring another
+    #[serde(default)]
+    pub mirror_type: Option<MirrorType>,
+    pub enabled: bool,
+    pub display_type: String,
+}
+
+impl DisplayInfo {
+    /// The top left corner of the display in the global display space.
+    pub fn origin(&self) -> PointPx {
+        PointPx::new(self.x, self.y)
+    }
+
+    /// The size the display takes up in the arrangement, already rotated.
+    pub fn size(&self) -> SizePx {
+        SizePx::new(self.width, self.height)
+    }
+
+    pub fn bounds(&self) -> Rect {
+        Rect::new(self.origin(), self.size())
+    }
+}
+
+/// Read a CGDirectDisplayID written in decimal or, as Console logs and
+/// `ioreg` print it, in hex with a `0x` prefix.
+pub fn parse_display_id(value: &str) -> Option<u32> {
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// A display id in both forms, e.g. `69732992 (0x04280A80)`.
+pub fn format_display_id(id: u32) -> String {
+    format!("{} ({:#010X})", id, id)
+}
+
+/// How a display is attached, as far as that limits what macOS lets change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayClass {
+    /// Built-in panels and monitors on a cable
+    Physical,
+    /// An iPad extending or mirroring the Mac
+    Sidecar,
+    /// A display streamed over AirPlay, such as an Apple TV
+    AirPlay,
+}
+
+impl DisplayClass {
+    pub fn of(info: &DisplayInfo) -> Self {
+        if info.display_type.starts_with("Sidecar") {
+            DisplayClass::Sidecar
+        } else if info.display_type.starts_with("AirPlay") {
+            DisplayClass::AirPlay
+        } else {
+            DisplayClass::Physical
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DisplayClass::Physical => "physical",
+            DisplayClass::Sidecar => "Sidecar",
+            DisplayClass::AirPlay => "AirPlay",
+        }
+    }
+
+    /// Where the display can be changed instead, for displays macOS manages
+    /// itself. Their modes, origin and rotation follow the receiving device.
+    pub fn remedy(&self) -> Option<&'static str> {
+        match self {
+            DisplayClass::Physical => None,
+            DisplayClass::Sidecar => {
+                Some("change it from the Sidecar menu in Control Center or Displays settings")
+            }
+            DisplayClass::AirPlay => Some(
+                "change it from Screen Mirroring in Control Center, or disconnect and reconnect the receiver",
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DisplayConfig {
+    pub id: String,
+    pub mode: Option<String>,
+    pub mode_id: Option<ModeId>,
+    pub resolution: Option<(u32, u32)>,
+    /// Point size of a HiDPI mode, whatever its backing pixel resolution
+    pub looks_like: Option<(u32, u32)>,
+    pub hz: Option<f64>,
+    pub color_depth: Option<u32>,
+    pub scaling: Option<bool>,
+    pub origin: Option<(i32, i32)>,
+    /// Shift from the current origin, written `origin:+dx,+dy`, resolved to
+    /// `origin` when applied
+    pub origin_offset: Option<(i32, i32)>,
+    pub degree: Option<u32>,
+    /// Rotation named by how the display should stand, resolved against the
+    /// panel's native orientation when applied
+    pub orientation: Option<Orientation>,
+    pub mirror: Option<String>,
+    /// Mirroring the display is expected to get. macOS cannot be asked for
+    /// either kind, so this is never applied, only checked afterwards.
+    pub mirror_type: Option<MirrorType>,
+    pub enabled: Option<bool>,
+    /// Image file for the desktop picture, as written in the configuration
+    pub wallpaper: Option<String>,
+}
+
+pub fn is_display_services_available() -> bool {
+    unsafe { ds_is_available() }
+}
+
+/// The IOPlatformUUID identifying this Mac.
+pub fn get_hardware_uuid() -> Option<String> {
+    let uuid_ptr = unsafe { ds_get_hardware_uuid() };
+    if uuid_ptr.is_null() {
+        return None;
+    }
+    let c_str = unsafe { std::ffi::CStr::from_ptr(uuid_ptr) };
+    let uuid = c_str.to_string_lossy().to_string();
+    unsafe { ds_free_string(uuid_ptr) };
+    Some(uuid)
+}
+
+/// The host name of this Mac, without a trailing `.local`.
+pub fn get_hostname() -> Option<String> {
+    let mut buffer = [0u8; 256];
+    let result =
+        unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) };
+    if result != 0 {
+        return None;
+    }
+    let len = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    let name = String::from_utf8_lossy(&buffer[..len]).to_string();
+    Some(name.trim_end_matches(".local").to_string())
+}
+
+pub fn get_active_displays() -> Vec<u32> {
+    let mut display_count = 0u32;
+    unsafe {
+        CGGetActiveDisplayList(0, std::ptr::null_mut(), &mut display_count);
+        let mut displays = vec![0u32; display_count as usize];
+        CGGetActiveDisplayList(display_count, displays.as_mut_ptr(), &mut display_count);
+        displays.truncate(display_count as usize);
+        displays
+    }
+}
+
+/// Connected displays, including sleeping ones the active list leaves out.
+pub fn get_online_displays() -> Vec<u32> {
+    let mut display_count = 0u32;
+    unsafe {
+        CGGetOnlineDisplayList(0, std::ptr::null_mut(), &mut display_count);
+        let mut displays = vec![0u32; display_count as usize];
+        CGGetOnlineDisplayList(display_count, displays.as_mut_ptr(), &mut display_count);
+        displays.truncate(display_count as usize);
+        displays
+    }
+}
+
+pub fn is_asleep(display_id: u32) -> bool {
+    unsafe { core_graphics::display::CGDisplayIsAsleep(display_id) != 0 }
+}
+
+/// The display `display_id` mirrors, in software or hardware, if any.
+pub fn mirrors_display(display_id: u32) -> Option<u32> {
+    let primary = unsafe { core_graphics::display::CGDisplayMirrorsDisplay(display_id) };
+    (primary != 0).then_some(primary)
+}
+
+pub fn get_display_info(display_id: u32) -> Option<DisplayInfo> {
+    let main_id = unsafe { core_graphics::display::CGMainDisplayID() };
+    display_info_with_main(display_id, main_id)
+}
+
+/// Fetch information for every active display in a single pass.
+///
+/// The display list and main display are only queried once, which keeps this
+/// cheap enough for status bars that poll frequently.
+pub fn get_all_display_info() -> Vec<DisplayInfo> {
+    let main_id = unsafe { core_graphics::display::CGMainDisplayID() };
+    get_active_displays()
+        .into_iter()
+        .filter_map(|id| display_info_with_main(id, main_id))
+        .collect()
+}
+
+fn display_info_with_main(display_id: u32, main_id: u32) -> Option<DisplayInfo> {
+    let bounds = unsafe { CGDisplayBounds(display_id) };
+    let mode = get_current_mode(display_id)?;
+
+    let is_main = main_id == display_id;
+    let is_mirror = unsafe { core_graphics::display::CGDisplayIsInMirrorSet(display_id) != 0 };
+    let mirror_of = if is_mirror {
+        unsafe {
+            let mirror_display = core_graphics::display::CGDisplayMirrorsDisplay(display_id);
+            if mirror_display != 0 {
+                Some(mirror_display)
+            } else {
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let mirror_type = mirror_of.map(|_| {
+        if unsafe { core_graphics::display::CGDisplayIsInHWMirrorSet(display_id) != 0 } {
+            MirrorType::Hardware
+        } else {
+            MirrorType::Software
+        }
+    });
+
+    let persistent_id = display_uuid(display_id);
+    // The mode is in the panel's native orientation, the arrangement is not
+    let rotation = unsafe { core_graphics::display::CGDisplayRotation(display_id) as u32 };
+    let size = mode.rotated_size(rotation);
+
+    // Get display type
+    let type_ptr = unsafe { ds_get_display_type(display_id) };
+    let display_type = if !type_ptr.is_null() {
+        let c_str = unsafe { std::ffi::CStr::from_ptr(type_ptr) };
+        let dtype = c_str.to_string_lossy().to_string();
+        unsafe { ds_free_string(type_ptr) };
+        dtype
+    } else {
+        "Unknown".to_string()
+    };
+
+    Some(DisplayInfo {
+        id: display_id,
+        persistent_id,
+        contextual_id: display_id, // Contextual ID is same as numeric ID
+        serial: unsafe { core_graphics::display::CGDisplaySerialNumber(display_id) },
+        x: bounds.origin.x as i32,
+        y: bounds.origin.y as i32,
+        width: size.width,
+        height: size.height,
+        rotation,
+        hz: mode.refresh_rate,
+        depth: mode.depth,
+        scaling: mode.is_scaled,
+        mode_number: mode.mode_number,
+        mode_id: mode.mode_id(),
+        is_main,
+        is_mirror,
+        mirror_of,
+        mirror_type,
+        enabled: unsafe { core_graphics::display::CGDisplayIsActive(display_id) != 0 },
+        display_type,
+    })
+}
+
+/// The persistent id of a display, or its display id when it has none.
+fn display_uuid(display_id: u32) -> String {
+    let uuid_ptr = unsafe { ds_get_display_uuid(display_id) };
+    if uuid_ptr.is_null() {
+        return display_id.to_string();
+    }
+    let c_str = unsafe { std::ffi::CStr::from_ptr(uuid_ptr) };
+    let uuid = c_str.to_string_lossy().to_string();
+    unsafe { ds_free_string(uuid_ptr) };
+    uuid
+}
+
+/// Just enough of a display to match a selector against.
+#[derive(Debug, Clone, Serialize)]
+pub struct DisplayIdentity {
+    /// `None` without a window server session, which hands out the ids
+    pub id: Option<u32>,
+    pub persistent_id: String,
+    pub serial: u32,
+    pub is_main: bool,
+    /// `None` when it cannot be told without a window server session
+    pub is_builtin: Option<bool>,
+}
+
+/// Identify every connected display, sleeping ones included, without reading
+/// modes, so scripts can poll for a display cheaply.
+///
+/// Without a window server session the displays come from the EDIDs IOKit can
+/// read instead, with no id, persistent id or built-in flag, so only serial
+/// selectors match them.
+pub fn get_display_identities() -> Vec<DisplayIdentity> {
+    if !session::current().has_window_server() {
+        return edid::iokit_edids()
+            .into_iter()
+            .map(|edid| DisplayIdentity {
+                id: None,
+                persistent_id: String::new(),
+                serial: edid.serial,
+                is_main: false,
+                is_builtin: None,
+            })
+            .collect();
+    }
+
+    let main_id = unsafe { core_graphics::display::CGMainDisplayID() };
+    get_online_displays()
+        .into_iter()
+        .map(|id| DisplayIdentity {
+            id: Some(id),
+            persistent_id: display_uuid(id),
+            serial: unsafe { core_graphics::display::CGDisplaySerialNumber(id) },
+            is_main: id == main_id,
+            is_builtin: Some(unsafe { core_graphics::display::CGDisplayIsBuiltin(id) != 0 }),
+        })
+        .collect()
+}
+
+/// All modes of a display.
+///
+/// Returns an empty list for unknown displays, and an error if the display does
+/// not answer within [`call_timeout`] or the shim returns a malformed list.
+pub fn try_get_all_modes(display_id: u32) -> Result<Vec<DisplayMode>, DisplayError> {
+    let modes = executor::with_timeout(move || unsafe {
+        let list_ptr = ds_get_all_modes(display_id);
+        if list_ptr.is_null() {
+            return Ok(Vec::new());
+        }
+
+        let modes = (*list_ptr).to_modes();
+        ds_free_mode_list(list_ptr);
+        modes
+    });
+    error::check_response("list modes", display_id, modes)
+}
+
+/// All modes of a display; empty (with a warning) when they cannot be read.
+pub fn get_all_modes(display_id: u32) -> Vec<DisplayMode> {
+    try_get_all_modes(display_id).unwrap_or_else(|e| {
+        output::warning(&e.to_string());
+        Vec::new()
+    })
+}
+
+/// The current mode of a display, `None` for unknown displays.
+pub fn try_get_current_mode(display_id: u32) -> Result<Option<DisplayMode>, DisplayError> {
+    let mode = executor::with_timeout(move || unsafe {
+        let mode_ptr = ds_get_current_mode(display_id);
+        if mode_ptr.is_null() {
+            return Ok(None);
+        }
+        if !is_aligned(mode_ptr) {
+            // Not safe to read, and not safe to hand back to free() either.
+            return Err("mode pointer is misaligned");
+        }
+
+        let mode = (*mode_ptr).validate();
+        ds_free_mode(mode_ptr);
+        mode.map(Some)
+    });
+    error::check_response("read the current mode", display_id, mode)
+}
+
+/// The current mode of a display; `None` (with a warning) when it cannot be read.
+pub fn get_current_mode(display_id: u32) -> Option<DisplayMode> {
+    try_get_current_mode(display_id).unwrap_or_else(|e| {
+        output::warning(&e.to_string());
+        None
+    })
+}
+
+/// Brightness of a display between 0.0 and 1.0.
+///
+/// Only displays DisplayServices can control (built-in and Apple displays)
+/// report a value; others fail with [`error::ErrorKind::NotSupported`].
+pub fn get_brightness(display_id: u32) -> Result<f32, DisplayError> {
+    let response = executor::with_timeout(move || {
+        let mut brightness = 0.0f32;
+        let status = error::status(|| unsafe { ds_get_brightness(display_id, &mut brightness) });
+        (status, brightness)
+    });
+    match response {
+        Some((status, brightness)) if status.is_ok() && (0.0..=1.0).contains(&brightness) => {
+            Ok(brightness)
+        }
+        Some((status, _)) if status.is_ok() => error::check_response(
+            "read brightness",
+            display_id,
+            Some(Err("brightness is outside 0.0-1.0")),
+        ),
+        Some((status, _)) => Err(DisplayError::from_status(
+            "read brightness",
+            display_id,
+            status,
+        )),
+        None => error::check_response::<f32>("read brightness", display_id, None),
+    }
+}
+
+/// Set the brightness of a display between 0.0 and 1.0.
+///
+/// Like [`get_brightness`], only built-in and Apple displays are supported;
+/// [`brightness::set_brightness`] falls back to DDC/CI for other monitors.
+pub fn set_brightness(display_id: u32, brightness: f32) -> Result<(), DisplayError> {
+    let result = executor::with_timeout(move || {
+        error::status(|| unsafe { ds_set_brightness(display_id, brightness) })
+    });
+    error::check("set brightness", display_id, result)
+}
+
+/// The modes a notch hide or show can switch between.
+#[derive(Debug, Clone, Default)]
+pub struct NotchFamily {
+    /// Sorted by pixel height; the first entry is the "notch hidden" mode
+    pub modes: Vec<DisplayMode>,
+    /// Whether the modes were gathered across scaled and unscaled modes or
+    /// color depths, because the current ones offer a single height
+    pub mixed: bool,
+}
+
+/// Modes that differ from `current` only in height, sorted by height.
+///
+/// On notched MacBook panels the shorter variant keeps the menu bar out of the
+/// notch area, so the first entry is the "notch hidden" mode.
+pub fn notch_mode_family(modes: &[DisplayMode], current: &DisplayMode) -> Vec<DisplayMode> {
+    notch_family(modes, current).modes
+}
+
+/// Like [`notch_mode_family`], but when the current scaling and depth offer
+/// only one height, falls back to every mode of the same pixel width and
+/// rate. Some MacBooks have the notch-hidden variant in the scaled modes
+/// only; at each height the mode closest to the current one is kept.
+pub fn notch_family(modes: &[DisplayMode], current: &DisplayMode) -> NotchFamily {
+    let same_rate = |mode: &DisplayMode| mode_id::same_hz(mode.refresh_rate, current.refresh_rate);
+    let mut strict: Vec<DisplayMode> = modes
+        .iter()
+        .filter(|mode| {
+            mode.width == current.width
+                && same_rate(mode)
+                && mode.depth == current.depth
+                && mode.is_scaled == current.is_scaled
+        })
+        .cloned()
+        .collect();
+    strict.sort_by_key(|mode| mode.height);
+    if strict.first().map(|m| m.height) != strict.last().map(|m| m.height) {
+        return NotchFamily {
+            modes: strict,
+            mixed: false,
+        };
+    }
+
+    let closeness = |mode: &DisplayMode| {
+        (
+            mode.is_scaled == current.is_scaled,
+            mode.depth == current.depth,
+        )
+    };
+    let mut mixed: Vec<DisplayMode> = Vec::new();
+    for mode in modes
+        .iter()
+        .filter(|mode| mode.pixel_width == current.pixel_width && same_rate(mode))
+    {
+        match mixed
+            .iter_mut()
+            .find(|m| m.pixel_height == mode.pixel_height)
+        {
+            Some(existing) => {
+                if closeness(mode) > closeness(existing) {
+                    *existing = mode.clone();
+                }
+            }
+            None => mixed.push(mode.clone()),
+        }
+    }
+    mixed.sort_by_key(|mode| mode.pixel_height);
+    if mixed.len() > 1 {
+        NotchFamily {
+            modes: mixed,
+            mixed: true,
+        }
+    } else {
+        NotchFamily {
+            modes: strict,
+            mixed: false,
+        }
+    }
+}
+
+/// The mode of a notch family that hides (`hidden == true`) or shows the
+/// notch, next to `current` in height.
+pub fn notch_target<'a>(
+    family: &'a [DisplayMode],
+    current: &DisplayMode,
+    hidden: bool,
+) -> Option<&'a DisplayMode> {
+    if hidden {
+        family
+            .iter()
+            .rev()
+            .find(|mode| mode.pixel_height < current.pixel_height)
+            .or_else(|| family.first())
+    } else {
+        family
+            .iter()
+            .find(|mode| mode.pixel_height > current.pixel_height)
+            .or_else(|| family.last())
+    }
+}
+
+/// Whether the notch is currently hidden on a display.
+///
+/// Returns `None` when the display has no alternate-height modes to switch between.
+pub fn is_notch_hidden(display_id: u32) -> Option<bool> {
+    let current = get_current_mode(display_id)?;
+    let family = notch_family(&get_all_modes(display_id), &current).modes;
+    let min_height = family.first()?.pixel_height;
+    let max_height = family.last()?.pixel_height;
+    if min_height == max_height {
+        return None;
+    }
+    Some(current.pixel_height == min_height)
+}
+
+/// The HiDPI (`hidpi == true`) or unscaled variant of `current`: a mode that
+/// looks the same size in points but renders at 2x or 1x pixels.
+///
+/// Prefers the current refresh rate and depth, then the fastest rate.
+pub fn hidpi_variant<'a>(
+    modes: &'a [DisplayMode],
+    current: &DisplayMode,
+    hidpi: bool,
+) -> Option<&'a DisplayMode> {
+    mode_of_size(modes, current, current.size(), hidpi)
+}
+
+/// How unevenly a display at `refresh_rate` shows content at `fps`: the
+/// share of a refresh each frame drifts by against a whole number of
+/// refreshes. 0 for an exact multiple, such as 119.88Hz for 23.976fps.
+pub fn judder(refresh_rate: f64, fps: f64) -> f64 {
+    let ratio = refresh_rate / fps;
+    (ratio - ratio.round().max(1.0)).abs()
+}
+
+/// The mode like `current`, at the same size, scaling and depth, whose
+/// refresh rate shows `fps` content with the least judder. Of rates equally
+/// smooth to a thousandth of a refresh, the fastest wins.
+///
+/// Compares the precise refresh rates of the modes, so 23.976fps picks
+/// 47.952Hz or 119.88Hz over 48Hz or 120Hz when the display offers them.
+pub fn frame_rate_mode<'a>(
+    modes: &'a [DisplayMode],
+    current: &DisplayMode,
+    fps: f64,
+) -> Option<&'a DisplayMode> {
+    modes
+        .iter()
+        .filter(|mode| {
+            mode.size() == current.size()
+                && mode.pixel_width == current.pixel_width
+                && mode.pixel_height == current.pixel_height
+                && mode.is_scaled == current.is_scaled
+                && mode.depth == current.depth
+                && mode.is_safe_for_hardware
+                && !mode.is_interlaced
+                && mode.refresh_rate > 0.0
+        })
+        .min_by_key(|mode| {
+            (
+                (judder(mode.refresh_rate, fps) * 1000.0).round() as u64,
+                std::cmp::Reverse((mode.refresh_rate * 1000.0).round() as u64),
+            )
+        })
+}
+
+/// The mode of `size` points rendered HiDPI or not that is closest to
+/// `current`: the same refresh rate and depth if offered, then the fastest.
+fn mode_of_size<'a>(
+    modes: &'a [DisplayMode],
+    current: &DisplayMode,
+    size: SizePx,
+    hidpi: bool,
+) -> Option<&'a DisplayMode> {
+    modes
+        .iter()
+        .filter(|mode| mode.size() == size && mode.is_scaled == hidpi && !mode.is_interlaced)
+        .max_by(|a, b| {
+            let same_rate =
+                |m: &DisplayMode| mode_id::same_hz(m.refresh_rate, current.refresh_rate);
+            (same_rate(a), a.depth == current.depth)
+                .cmp(&(same_rate(b), b.depth == current.depth))
+                .then(a.refresh_rate.total_cmp(&b.refresh_rate))
+        })
+}
+
+/// A move along the scaling slider in Displays settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleStep {
+    /// Larger text: the next smaller "looks like" size
+    Larger,
+    /// More space: the next bigger "looks like" size
+    Smaller,
+    /// The size macOS uses out of the box
+    Default,
+}
+
+/// The "looks like" sizes of the HiDPI modes at the aspect ratio of
+/// `current`, from the largest text to the most space, as the scaling slider
+/// in Displays settings orders them.
+///
+/// Keeping to one aspect ratio leaves out the other half of a notch family,
+/// so stepping never shows or hides the notch.
+pub fn scale_ladder(modes: &[DisplayMode], current: &DisplayMode) -> Vec<SizePx> {
+    let mut sizes: Vec<SizePx> = modes
+        .iter()
+        .filter(|mode| {
+            mode.is_scaled && !mode.is_interlaced && mode.size().has_aspect_of(current.size())
+        })
+        .map(DisplayMode::size)
+        .collect();
+    sizes.sort_by_key(|size| (size.width, size.height));
+    sizes.dedup();
+    sizes
+}
+
+/// The mode a [`ScaleStep`] from `current` leads to, or `None` at the end of
+/// the ladder or when the display has no HiDPI modes.
+///
+/// The default is the HiDPI size that renders exactly at the panel's native
+/// pixels, half its width and height, which is what macOS picks for Retina
+/// panels; displays without one default to their native unscaled size.
+pub fn scale_target<'a>(
+    modes: &'a [DisplayMode],
+    current: &DisplayMode,
+    step: ScaleStep,
+) -> Option<&'a DisplayMode> {
+    let ladder = scale_ladder(modes, current);
+    let size = current.size();
+    match step {
+        ScaleStep::Larger => {
+            let target = ladder.iter().rev().find(|s| s.width < size.width)?;
+            mode_of_size(modes, current, *target, true)
+        }
+        ScaleStep::Smaller => {
+            let target = ladder.iter().find(|s| s.width > size.width)?;
+            mode_of_size(modes, current, *target, true)
+        }
+        ScaleStep::Default => {
+            let native = modes
+                .iter()
+                .filter(|mode| {
+                    !mode.is_scaled
+                        && !mode.is_interlaced
+                        && mode.size().has_aspect_of(current.size())
+                })
+                .map(DisplayMode::size)
+                .max_by_key(|size| size.area())?;
+            modes
+                .iter()
+                .filter(|mode| mode.is_scaled && mode.pixel_size() == native)
+                .find_map(|mode| mode_of_size(modes, current, mode.size(), true))
+                .or_else(|| mode_of_size(modes, current, native, false))
+        }
+    }
+}
+
+/// Whether macdisp can turn a display. macOS has no public call for it and
+/// the shim refuses every rotation but the current one, so configurations
+/// keep each display's rotation and warn about the rest.
+pub const CAN_ROTATE: bool = false;
+
+/// Whether a rotation turns the display on its side.
+pub fn is_sideways(rotation: u32) -> bool {
+    rotation % 180 == 90
+}
+
+/// How a display stands, as an alternative to a rotation in degrees.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+    LandscapeFlipped,
+    PortraitFlipped,
+}
+
+impl Orientation {
+    /// The rotation that makes a panel stand this way. Rotations are
+    /// clockwise, so a landscape panel turned to portrait is at 90 degrees.
+    pub fn degree(self, native_landscape: bool) -> u32 {
+        let from_landscape = match self {
+            Orientation::Landscape => 0,
+            Orientation::Portrait => 90,
+            Orientation::LandscapeFlipped => 180,
+            Orientation::PortraitFlipped => 270,
+        };
+        if native_landscape {
+            from_landscape
+        } else {
+            (from_landscape + 270) % 360
+        }
+    }
+}
+
+impl std::str::FromStr for Orientation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "landscape" => Ok(Orientation::Landscape),
+            "portrait" => Ok(Orientation::Portrait),
+            "landscape-flipped" => Ok(Orientation::LandscapeFlipped),
+            "portrait-flipped" => Ok(Orientation::PortraitFlipped),
+            _ => Err(format!(
+                "Invalid orientation: {} (expected landscape, portrait, landscape-flipped or portrait-flipped)",
+                s
+            )),
+        }
+    }
+}
+
+/// How macOS mirrors a display. Hardware mirroring scans both displays out
+/// of one framebuffer; software mirroring copies every frame, which adds
+/// latency. macOS picks hardware mirroring whenever the GPU allows it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MirrorType {
+    Hardware,
+    Software,
+}
+
+impl MirrorType {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MirrorType::Hardware => "hardware",
+            MirrorType::Software => "software",
+        }
+    }
+}
+
+impl std::str::FromStr for MirrorType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "hardware" => Ok(MirrorType::Hardware),
+            "software" => Ok(MirrorType::Software),
+            _ => Err(format!(
+                "Invalid mirror_type: {} (expected hardware or software)",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether the panel of a display is wider than tall when not rotated.
+pub fn is_native_landscape(info: &DisplayInfo) -> bool {
+    info.size().rotated(info.rotation).is_landscape()
+}
+
+impl DisplayMode {
+    /// The "looks like" size in the panel's native orientation.
+    pub fn size(&self) -> SizePx {
+        SizePx::new(self.width, self.height)
+    }
+
+    /// The size in backing pixels, twice [`size`](Self::size) for HiDPI modes.
+    pub fn pixel_size(&self) -> SizePx {
+        SizePx::new(self.pixel_width, self.pixel_height)
+    }
+
+    /// The size as laid out on screen at `rotation` degrees. Modes are
+    /// reported in the panel's native orientation.
+    pub fn rotated_size(&self, rotation: u32) -> SizePx {
+        self.size().rotated(rotation)
+    }
+
+    /// Whether the mode can be used while the display is rotated. Interlaced
+    /// and TV modes are scanned out unrotated, so macOS drops them.
+    pub fn available_rotated(&self) -> bool {
+        !self.is_interlaced && !self.is_tv_mode
+    }
+
+    /// Whether `res:WxH` names this mode at `rotation`. The native size is
+    /// accepted too, so configurations written before rotation keep working.
+    pub fn has_size(&self, size: SizePx, rotation: u32) -> bool {
+        self.rotated_size(rotation) == size || self.size() == size
+    }
+}
+
+/// Time a monitor gets to settle on a new mode before it is read back.
+const MODE_SETTLE_TIME: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Attempts at a mode switch before the display is considered to reject it.
+const MODE_SET_ATTEMPTS: usize = 2;
+
+/// Switch a display to a mode and check that it stays there.
+///
+/// Some monitors accept a mode and then bounce back to the previous one, so
+/// the current mode is read back after a short pause and the switch retried
+/// once. A display that keeps reverting fails with
+/// [`error::ErrorKind::ModeRejected`].
+///
+/// Switches of one display are at least [`mode_set_interval`] apart, also
+/// across macdisp processes. Of the switches waiting for their turn in one
+/// process only the newest is made; the others fail with
+/// [`error::ErrorKind::Superseded`] without touching the display.
+pub fn set_display_mode(display_id: u32, mode_number: u32) -> Result<(), DisplayError> {
+    throttle::mode_set(display_id, || {
+        hold_mode(
+            display_id,
+            mode_number,
+            || set_mode_once(display_id, mode_number),
+            || {
+                std::thread::sleep(MODE_SETTLE_TIME);
+                Ok(try_get_current_mode(display_id)?.map(|mode| mode.mode_number))
+            },
+        )
+    })
+    .unwrap_or_else(|| {
+        Err(DisplayError {
+            operation: "set display mode",
+            display_id,
+            kind: error::ErrorKind::Superseded,
+            detail: Some(format!("mode {} was not set", mode_number)),
+        })
+    })
+}
+
+fn set_mode_once(display_id: u32, mode_number: u32) -> Result<(), DisplayError> {
+    let result = executor::with_timeout(move || {
+        executor::serialized(|| error::status(|| unsafe { ds_set_mode(display_id, mode_number) }))
+    });
+    error::check("set display mode", display_id, result)
+}
+
+/// Run `set` until `current` reports `mode_number`, up to [`MODE_SET_ATTEMPTS`]
+/// times. A display whose current mode cannot be read is taken at its word.
+fn hold_mode(
+    display_id: u32,
+    mode_number: u32,
+    mut set: impl FnMut() -> Result<(), DisplayError>,
+    mut current: impl FnMut() -> Result<Option<u32>, DisplayError>,
+) -> Result<(), DisplayError> {
+    let mut actual = mode_number;
+    for _ in 0..MODE_SET_ATTEMPTS {
+        set()?;
+        match current()? {
+            Some(mode) if mode != mode_number => actual = mode,
+            _ => return Ok(()),
+        }
+    }
+    Err(DisplayError {
+        operation: "set display mode",
+        display_id,
+        kind: error::ErrorKind::ModeRejected {
+            requested: mode_number,
+            actual,
+        },
+        detail: None,
+    })
+}
+
+/// Mirroring change requested for a display.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MirrorSetting {
+    /// Mirror the display with this id
+    Of(u32),
+    /// Remove the display from its mirror set
+    Off,
+}
+
+/// Changes to make to a display in a single configuration transaction.
+///
+/// Fields left as `None` are not touched.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConfigureOptions {
+    pub origin: Option<(i32, i32)>,
+    /// Fails with [`ErrorKind::NotSupported`](error::ErrorKind::NotSupported)
+    /// unless it is the current rotation (see [`CAN_ROTATE`])
+    pub rotation: Option<u32>,
+    /// The display to mirror. CoreGraphics has no say in hardware or
+    /// software mirroring; macOS picks it, so a configuration's
+    /// `mirror_type` is only checked once the transaction completes.
+    pub mirror: Option<MirrorSetting>,
+    pub enabled: Option<bool>,
+}
+
+impl ConfigureOptions {
+    pub fn is_empty(&self) -> bool {
+        self.origin.is_none()
+            && self.rotation.is_none()
+            && self.mirror.is_none()
+            && self.enabled.is_none()
+    }
+
+    fn to_raw(&self) -> RawConfigureOptions {
+        let mut raw = RawConfigureOptions::default();
+        if let Some((x, y)) = self.origin {
+            raw.has_origin = true;
+            raw.x = x;
+            raw.y = y;
+        }
+        if let Some(rotation) = self.rotation {
+            raw.has_rotation = true;
+            raw.rotation = rotation;
+        }
+        if let Some(mirror) = self.mirror {
+            raw.has_mirror = true;
+            raw.mirror_display_id = match mirror {
+                MirrorSetting::Of(id) => id,
+                // kCGNullDirectDisplay
+                MirrorSetting::Off => 0,
+            };
+        }
+        if let Some(enabled) = self.enabled {
+            raw.has_enabled = true;
+            raw.enabled = enabled;
+        }
+        raw
+    }
+}
+
+pub fn configure_display(display_id: u32, options: &ConfigureOptions) -> Result<(), DisplayError> {
+    let raw = options.to_raw();
+    let result = executor::with_timeout(move || {
+        executor::serialized(|| error::status(|| unsafe { ds_configure_display(display_id, &raw) }))
+    });
+    error::check("configure display", display_id, result)
+}
+
+/// Apply changes to several displays in a single configuration transaction.
+///
+/// Either every change takes effect or none does. Errors name the first display.
+pub fn configure_displays(changes: &[(u32, ConfigureOptions)]) -> Result<(), DisplayError> {
+    let Some(&(first_id, _)) = changes.first() else {
+        return Ok(());
+    };
+    let ids: Vec<u32> = changes.iter().map(|(id, _)| *id).collect();
+    let raw: Vec<RawConfigureOptions> = changes.iter().map(|(_, o)| o.to_raw()).collect();
+    let result = executor::with_timeout(move || {
+        executor::serialized(|| {
+            error::status(|| unsafe {
+                ds_configure_displays(ids.as_ptr(), raw.as_ptr(), ids.len())
+            })
+        })
+    });
+    error::check("configure displays", first_id, result)
+}
+
+/// The config string that recreates `info`, identifying the display by the
+/// id `strategy` selects.
+pub fn format_display_command(info: &DisplayInfo, strategy: IdStrategy) -> String {
+    format_display_command_with_id(info, &strategy.id_of(info))
+}
+
+/// Like [`format_display_command`], naming the display `id`.
+pub fn format_display_command_with_id(info: &DisplayInfo, id: &str) -> String {
+    let mut cmd = format!(
+        "id:{} res:{}x{} hz:{} color_depth:{} ",
+        id,
+        info.width,
+        info.height,
+        format_hz(info.hz),
+        info.depth
+    );
+
+    if info.scaling {
+        cmd.push_str("scaling:on ");
+    } else {
+        cmd.push_str("scaling:off ");
+    }
+
+    cmd.push_str(&format!("origin:({},{}) ", info.x, info.y));
+
+    cmd.push_str(&format!("degree:{} ", info.rotation));
+
+    if let Some(mirror_id) = info.mirror_of {
+        cmd.push_str(&format!("mirror:{} ", mirror_id));
+    }
+
+    if info.enabled {
+        cmd.push_str("enabled:true");
+    } else {
+        cmd.push_str("enabled:false");
+    }
+
+    cmd
+}
+
+/// The `list` output, read from the daemon's cache when it is running.
+pub fn list_displays(ids: &CommandIds) -> String {
+    format_display_list(&ipc::display_state(), ids)
+}
+
+pub fn format_display_list(state: &ipc::DisplayState, ids: &CommandIds) -> String {
+    let mut output = String::new();
+
+    if !state.display_services {
+        output.push_str("DisplayServices available: false\n");
+        output.push_str("Using CoreGraphics API (official Apple API)\n\n");
+    }
+
+    for info in &state.displays {
+        output.push_str(&format!("Persistent screen id: {}\n", info.persistent_id));
+        output.push_str(&format!("Contextual screen id: {}\n", info.contextual_id));
+        output.push_str(&format!("Serial screen id: s{}\n", info.serial));
+        output.push_str(&format!("Type: {}\n", info.display_type));
+        output.push_str(&format!("Resolution: {}x{}\n", info.width, info.height));
+        output.push_str(&format!("Hertz: {}\n", format_hz(info.hz)));
+        output.push_str(&format!("Color Depth: {}\n", info.depth));
+        output.push_str(&format!(
+            "Scaling: {}\n",
+            if info.scaling { "on" } else { "off" }
+        ));
+        output.push_str(&format!("Origin: ({},{})", info.x, info.y));
+        if info.is_main {
+            output.push_str(" - main display");
+        }
+        output.push('\n');
+        output.push_str(&format!("Rotation: {}", info.rotation));
+        if info.rotation != 0 {
+            output.push_str(" - rotate internal screen example (may crash computer, but will be rotated after rebooting): ");
+            output.push_str(&format!(
+                "`macdisp \"id:{} degree:90\"`",
+                info.persistent_id
+            ));
+        }
+        output.push('\n');
+        output.push_str(&format!("Enabled: {}\n", info.enabled));
+        if let Some(set) = listed_mirror_set(state, info.id) {
+            output.push_str(&format!("Mirroring: {}\n", set.describe(info.id)));
+        }
+
+        let modes = state.modes.get(&info.id).map(Vec::as_slice).unwrap_or(&[]);
+        if !modes.is_empty() {
+            output.push_str(&format!("Resolutions for rotation {}:\n", info.rotation));
+            for (i, mode) in modes.iter().enumerate() {
+                let is_current = mode.mode_number == info.mode_number;
+                output.push_str(&format!(
+                    "  mode {}: res:{} hz:{} color_depth:{}",
+                    i,
+                    mode.rotated_size(info.rotation),
+                    format_hz(mode.refresh_rate),
+                    mode.depth
+                ));
+                if mode.is_scaled {
+                    output.push_str(" scaling:on");
+                }
+                if is_current {
+                    output.push_str(" <-- current mode");
+                } else if info.rotation != 0 && !mode.available_rotated() {
+                    output.push_str(" (not available rotated)");
+                }
+                output.push('\n');
+            }
+        }
+
+        output.push('\n');
+    }
+
+    output.push_str("Execute the command below to set your screens to the current arrangement.");
+    if *ids == CommandIds::Strategy(IdStrategy::Persistent) {
+        output.push_str(" If screen ids are switching, rerun with `--command-ids serial` or `--command-ids alias` to use serial ids or your aliases instead of persistent ids.");
+    }
+    output.push_str("\n\n");
+    output.push_str("macdisp ");
+
+    for info in &state.displays {
+        output.push_str(&format!(
+            "\"{}\" ",
+            format_display_command_with_id(info, &ids.id_of(info))
+        ));
+    }
+
+    output.push('\n');
+    output
+}
+
+/// Version of the [`DisplayListing`] layout, raised when a field is removed
+/// or changes meaning.
+pub const LISTING_SCHEMA_VERSION: u32 = 2;
+
+/// Everything `list` prints, structured for `list --json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DisplayListing {
+    pub schema_version: u32,
+    pub backend: Backend,
+    pub displays: Vec<ListedDisplay>,
+    /// Command that sets every display to the current arrangement
+    pub suggested_command: String,
+}
+
+/// Which API macdisp is using to read and change displays.
+#[derive(Debug, Clone, Serialize)]
+pub struct Backend {
+    pub display_services: bool,
+    /// `"DisplayServices"` or `"CoreGraphics"`
+    pub api: &'static str,
+}
+
+/// A display with its modes and the configuration string describing it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListedDisplay {
+    #[serde(flatten)]
+    pub info: DisplayInfo,
+    /// Configuration string for the current state, as in `suggested_command`
+    pub config: String,
+    /// The mirror set the display is in, with the resolution it runs at
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mirror_set: Option<mirror::MirrorSet>,
+    pub modes: Vec<ListedMode>,
+}
+
+/// The mirror set `display_id` is in, from the modes listed in `state`.
+fn listed_mirror_set(state: &ipc::DisplayState, display_id: u32) -> Option<mirror::MirrorSet> {
+    mirror::mirror_set(display_id, &state.displays, |id| {
+        state.modes.get(&id).cloned().unwrap_or_default()
+    })
+}
+
+/// A mode as `list` shows it: numbered, and sized as laid out at the
+/// display's current rotation.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListedMode {
+    /// Position in the list, as in `mode N` of the text output
+    pub index: usize,
+    #[serde(flatten)]
+    pub mode: DisplayMode,
+    /// Width and height at the display's rotation, as `res:` takes them
+    pub rotated_width: u32,
+    pub rotated_height: u32,
+    pub current: bool,
+    pub available_rotated: bool,
+}
+
+/// A mode as `modes --json` shows it, with what a GUI needs to switch to it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModeEntry {
+    #[serde(flatten)]
+    pub mode: DisplayMode,
+    /// Stable identity of the mode, as `mode_id:` takes it
+    pub mode_id: ModeId,
+    /// Rendered at 2x: the pixel size is double the point size
+    pub is_hidpi: bool,
+    /// Size in points at the display's rotation, as `res:` takes it
+    pub point_width: u32,
+    pub point_height: u32,
+    /// A configuration string that selects exactly this mode
+    pub selectable_by: String,
+}
+
+impl ModeEntry {
+    /// Describe `mode` of the display that configuration strings call `id`.
+    pub fn new(mode: &DisplayMode, id: &str, rotation: u32) -> ModeEntry {
+        let mode_id = mode.mode_id();
+        let SizePx {
+            width: point_width,
+            height: point_height,
+        } = mode.rotated_size(rotation);
+        ModeEntry {
+            mode: mode.clone(),
+            mode_id,
+            is_hidpi: mode_id.hidpi,
+            point_width,
+            point_height,
+            selectable_by: format!("id:{} mode_id:{}", id, mode_id),
+        }
+    }
+}
+
+/// The structured form of [`format_display_list`].
+pub fn display_listing(state: &ipc::DisplayState, ids: &CommandIds) -> DisplayListing {
+    let displays: Vec<ListedDisplay> = state
+        .displays
+        .iter()
+        .map(|info| {
+            let modes = state.modes.get(&info.id).map(Vec::as_slice).unwrap_or(&[]);
+            ListedDisplay {
+                info: info.clone(),
+                config: format_display_command_with_id(info, &ids.id_of(info)),
+                mirror_set: listed_mirror_set(state, info.id),
+                modes: modes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, mode)| {
+                        let SizePx {
+                            width: rotated_width,
+                            height: rotated_height,
+                        } = mode.rotated_size(info.rotation);
+                        ListedMode {
+                            index,
+                            mode: mode.clone(),
+                            rotated_width,
+                            rotated_height,
+                            current: mode.mode_number == info.mode_number,
+                            available_rotated: mode.available_rotated(),
+                        }
+                    })
+                    .collect(),
+            }
+        })
+        .collect();
+
+    let suggested_command = std::iter::once("macdisp".to_string())
+        .chain(displays.iter().map(|d| format!("\"{}\"", d.config)))
+        .collect::<Vec<_>>()
+        .join(" ");
+    DisplayListing {
+        schema_version: LISTING_SCHEMA_VERSION,
+        backend: Backend {
+            display_services: state.display_services,
+            api: if state.display_services {
+                "DisplayServices"
+            } else {
+                "CoreGraphics"
+            },
+        },
+        displays,
+        suggested_command,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::mode;
+
+    fn raw_mode() -> RawDisplayMode {
+        RawDisplayMode {
+            width: 1920,
+            height: 1080,
+            refresh_rate: 60.0,
+            depth: 8,
+            mode_number: 3,
+            is_safe_for_hardware: 1,
+            pixel_width: 1920,
+            pixel_height: 1080,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_display_id() {
+        assert_eq!(parse_display_id("69732992"), Some(0x04280A80));
+        assert_eq!(parse_display_id("0x04280A80"), Some(69732992));
+        assert_eq!(parse_display_id("0X4280a80"), Some(69732992));
+        assert_eq!(parse_display_id("0x"), None);
+        assert_eq!(parse_display_id("04280A80"), None);
+        assert_eq!(format_display_id(69732992), "69732992 (0x04280A80)");
+    }
+
+    #[test]
+    fn test_raw_mode_validation() {
+        assert!(raw_mode().validate().unwrap().is_safe_for_hardware);
+
+        let bad_flag = RawDisplayMode {
+            is_scaled: 7,
+            ..raw_mode()
+        };
+        assert!(bad_flag.validate().is_err());
+
+        let bad_rate = RawDisplayMode {
+            refresh_rate: f64::NAN,
+            ..raw_mode()
+        };
+        assert!(bad_rate.validate().is_err());
+    }
+
+    #[test]
+    fn test_mode_list_guards() {
+        let mut modes = [raw_mode(), raw_mode()];
+        let list = DisplayModeList {
+            modes: modes.as_mut_ptr(),
+            count: 2,
+        };
+        assert_eq!(unsafe { list.to_modes() }.unwrap().len(), 2);
+
+        let null = DisplayModeList {
+            modes: std::ptr::null_mut(),
+            count: 2,
+        };
+        assert!(unsafe { null.to_modes() }.is_err());
+
+        let huge = DisplayModeList {
+            modes: modes.as_mut_ptr(),
+            count: MAX_MODES + 1,
+        };
+        assert!(unsafe { huge.to_modes() }.is_err());
+    }
+
+    #[test]
+    fn test_hidpi_variant() {
+        let mode = |mode_number, refresh_rate, is_scaled, pixel_width| DisplayMode {
+            refresh_rate,
+            mode_number,
+            is_scaled,
+            pixel_width,
+            ..raw_mode().validate().unwrap()
+        };
+        let modes = vec![
+            mode(1, 60.0, false, 1920),
+            mode(2, 30.0, true, 3840),
+            mode(3, 60.0, true, 3840),
+        ];
+
+        assert_eq!(
+            hidpi_variant(&modes, &modes[0], true).map(|m| m.mode_number),
+            Some(3)
+        );
+        assert_eq!(
+            hidpi_variant(&modes, &modes[1], false).map(|m| m.mode_number),
+            Some(1)
+        );
+        assert!(hidpi_variant(&modes[..1], &modes[0], true).is_none());
+    }
+
+    #[test]
+    fn test_frame_rate_mode() {
+        let mode = |mode_number, refresh_rate| DisplayMode {
+            refresh_rate,
+            mode_number,
+            ..raw_mode().validate().unwrap()
+        };
+        let modes = vec![
+            mode(1, 60.0),
+            mode(2, 47.952),
+            mode(3, 48.0),
+            mode(4, 120.0),
+            mode(5, 59.94),
+        ];
+
+        let film = frame_rate_mode(&modes, &modes[0], 23.976).unwrap();
+        assert_eq!(film.mode_number, 2);
+        assert!(judder(120.0, 23.976) > 0.004);
+        assert!(judder(119.88, 23.976) < 1e-9);
+        // 24fps is smooth at 48Hz and 120Hz; the faster wins
+        assert_eq!(
+            frame_rate_mode(&modes, &modes[0], 24.0).map(|m| m.mode_number),
+            Some(4)
+        );
+        assert_eq!(
+            frame_rate_mode(&modes, &modes[0], 29.97).map(|m| m.mode_number),
+            Some(5)
+        );
+        let mut hidpi = modes[0].clone();
+        hidpi.is_scaled = true;
+        assert!(frame_rate_mode(&modes, &hidpi, 24.0).is_none());
+    }
+
+    #[test]
+    fn test_scale_target() {
+        let modes = vec![
+            mode(1, 3024, 1964, false),
+            mode(2, 1147, 745, true),
+            mode(3, 1352, 878, true),
+            mode(4, 1512, 982, true),
+            mode(5, 1800, 1169, true),
+            mode(6, 1512, 945, true),
+            mode(7, 1512, 982, false),
+        ];
+        let ladder = scale_ladder(&modes, &modes[3]);
+        assert_eq!(
+            ladder,
+            vec![
+                SizePx::new(1147, 745),
+                SizePx::new(1352, 878),
+                SizePx::new(1512, 982),
+                SizePx::new(1800, 1169),
+            ]
+        );
+
+        let step = |current: usize, step| {
+            scale_target(&modes, &modes[current], step).map(|m| m.mode_number)
+        };
+        assert_eq!(step(3, ScaleStep::Larger), Some(3));
+        assert_eq!(step(3, ScaleStep::Smaller), Some(5));
+        assert_eq!(step(1, ScaleStep::Larger), None);
+        assert_eq!(step(4, ScaleStep::Smaller), None);
+        assert_eq!(step(1, ScaleStep::Default), Some(4));
+        // Unscaled at the default size still steps along the HiDPI ladder
+        assert_eq!(step(6, ScaleStep::Smaller), Some(5));
+
+        let external = vec![mode(1, 1920, 1080, false), mode(2, 1280, 720, false)];
+        assert!(scale_ladder(&external, &external[0]).is_empty());
+        assert_eq!(
+            scale_target(&external, &external[1], ScaleStep::Default).map(|m| m.mode_number),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_notch_family_across_scaling() {
+        // The notch-hidden height only exists as a scaled mode
+        let modes = vec![
+            mode(1, 3024, 1964, false),
+            mode(2, 1512, 982, true),
+            mode(3, 1512, 945, true),
+        ];
+
+        let family = notch_family(&modes, &modes[0]);
+        assert!(family.mixed);
+        let numbers: Vec<u32> = family.modes.iter().map(|m| m.mode_number).collect();
+        assert_eq!(numbers, vec![3, 1]);
+        assert_eq!(
+            notch_target(&family.modes, &modes[0], true).map(|m| m.mode_number),
+            Some(3)
+        );
+        assert_eq!(
+            notch_target(&family.modes, &modes[2], false).map(|m| m.mode_number),
+            Some(1)
+        );
+
+        let family = notch_family(&modes, &modes[1]);
+        assert!(!family.mixed);
+        assert_eq!(family.modes.len(), 2);
+    }
+
+    #[test]
+    fn test_mode_entry_selects_mode() {
+        let mode = DisplayMode {
+            is_scaled: true,
+            pixel_width: 3840,
+            pixel_height: 2160,
+            ..raw_mode().validate().unwrap()
+        };
+        let entry = ModeEntry::new(&mode, "AAAA", 90);
+        assert!(entry.is_hidpi);
+        assert_eq!((entry.point_width, entry.point_height), (1080, 1920));
+        assert_eq!(
+            entry.selectable_by,
+            "id:AAAA mode_id:1920x1080/3840x2160@60/d8/hidpi"
+        );
+
+        let config = apply::parse_config(&entry.selectable_by).unwrap();
+        let found = mode_id::find_mode(std::slice::from_ref(&mode), &config.mode_id.unwrap(), None);
+        assert_eq!(found.map(|m| m.mode_number), Some(mode.mode_number));
+    }
+
+    #[test]
+    fn test_rotated_size() {
+        let mode = DisplayMode {
+            width: 2560,
+            height: 1440,
+            ..raw_mode().validate().unwrap()
+        };
+        let landscape = SizePx::new(2560, 1440);
+        let portrait = SizePx::new(1440, 2560);
+        assert_eq!(mode.rotated_size(0), landscape);
+        assert_eq!(mode.rotated_size(90), portrait);
+        assert_eq!(mode.rotated_size(180), landscape);
+        assert_eq!(mode.rotated_size(270), portrait);
+
+        assert!(mode.has_size(portrait, 90));
+        assert!(mode.has_size(landscape, 90));
+        assert!(!mode.has_size(portrait, 0));
+
+        let interlaced = DisplayMode {
+            is_interlaced: true,
+            ..mode.clone()
+        };
+        assert!(mode.available_rotated());
+        assert!(!interlaced.available_rotated());
+    }
+
+    #[test]
+    fn test_display_listing() {
+        let mut info = crate::apply::tests::display(1, "AAAA");
+        info.rotation = 90;
+        let mode = DisplayMode {
+            width: 2560,
+            height: 1440,
+            mode_number: info.mode_number,
+            ..raw_mode().validate().unwrap()
+        };
+        let state = ipc::DisplayState {
+            display_services: false,
+            displays: vec![info],
+            modes: std::collections::BTreeMap::from([(1, vec![mode])]),
+        };
+
+        let listing = display_listing(&state, &CommandIds::Strategy(IdStrategy::Persistent));
+        assert_eq!(listing.backend.api, "CoreGraphics");
+        let mode = &listing.displays[0].modes[0];
+        assert_eq!((mode.rotated_width, mode.rotated_height), (1440, 2560));
+        assert!(mode.current);
+        assert_eq!(
+            listing.suggested_command,
+            format!("macdisp \"{}\"", listing.displays[0].config)
+        );
+
+        let json = serde_json::to_value(&listing).unwrap();
+        assert_eq!(json["displays"][0]["persistent_id"], "AAAA");
+        assert_eq!(json["displays"][0]["modes"][0]["refresh_rate"], 60.0);
+    }
+
+    #[test]
+    fn test_orientation() {
+        assert_eq!("portrait".parse::<Orientation>(), Ok(Orientation::Portrait));
+        assert!("upside-down".parse::<Orientation>().is_err());
+
+        assert_eq!(Orientation::Portrait.degree(true), 90);
+        assert_eq!(Orientation::PortraitFlipped.degree(true), 270);
+        // A portrait panel already stands upright at 0 degrees
+        assert_eq!(Orientation::Portrait.degree(false), 0);
+        assert_eq!(Orientation::Landscape.degree(false), 270);
+        assert_eq!(Orientation::PortraitFlipped.degree(false), 180);
+
+        // A landscape panel turned on its side is laid out as portrait
+        let mode = mode(10, 2560, 1440, false);
+        let size = mode.rotated_size(90);
+        let info = DisplayInfo {
+            width: size.width,
+            height: size.height,
+            rotation: 90,
+            ..crate::apply::tests::display(1, "AAAA")
+        };
+        assert_eq!(info.size(), SizePx::new(1440, 2560));
+        assert!(is_native_landscape(&info));
+        assert_eq!(Orientation::Portrait.degree(is_native_landscape(&info)), 90);
+    }
+
+    #[test]
+    fn test_hold_mode() {
+        // Sticks on the second attempt
+        let mut modes = [3, 7].into_iter();
+        let mut sets = 0;
+        let set = || {
+            sets += 1;
+            Ok(())
+        };
+        let result = hold_mode(2, 7, set, || Ok(modes.next()));
+        assert!(result.is_ok());
+        assert_eq!(sets, 2);
+
+        let error = hold_mode(2, 7, || Ok(()), || Ok(Some(3))).unwrap_err();
+        assert_eq!(
+            error.kind,
+            error::ErrorKind::ModeRejected {
+                requested: 7,
+                actual: 3
+            }
+        );
+        assert!(error.to_string().contains("mode rejected by display"));
+
+        assert!(hold_mode(2, 7, || Ok(()), || Ok(None)).is_ok());
+    }
+
+    #[test]
+    fn test_display_services_available() {
+        // This should not panic
+        let _available = is_display_services_available();
+    }
+
+    #[test]
+    fn test_get_active_displays() {
+        let displays = get_active_displays();
+        // Should have at least one display
+        assert!(!displays.is_empty(), "No displays found");
+    }
+
+    #[test]
+    fn test_display_info() {
+        let displays = get_active_displays();
+        if let Some(&display_id) = displays.first() {
+            let info = get_display_info(display_id);
+            assert!(info.is_some(), "Could not get display info");
+        }
+    }
+
+    #[test]
+    fn test_get_modes() {
+        let displays = get_active_displays();
+        if let Some(&display_id) = displays.first() {
+            let modes = get_all_modes(display_id);
+            assert!(!modes.is_empty(), "No modes found for display");
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs::{File, OpenOptions};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// The open lock file and how many guards in this process share it.
+static HELD: Mutex<(Option<File>, usize)> = Mutex::new((None, 0));
+
+/// Turn the cross-process apply lock on or off (`--no-lock`).
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+/// Per-user lock file shared by every macdisp process.
+pub fn lock_path() -> PathBuf {
+    let uid = unsafe { libc::getuid() };
+    std::env::temp_dir().join(format!("macdisp-{}.lock", uid))
+}
+
+/// Held while this process changes the display arrangement.
+///
+/// The in-process configuration lock only serializes threads; this advisory
+/// `flock` keeps a login script and a hotplug hook running at the same time
+/// from interleaving their changes. Guards nest within a process.
+pub struct ApplyLock {
+    _private: (),
+}
+
+impl ApplyLock {
+    /// Wait until no other macdisp process is applying, then take the lock.
+    ///
+    /// Fails straight away without a window server session, where no change
+    /// could succeed.
+    pub fn acquire() -> Result<ApplyLock, String> {
+        crate::session::require_window_server()?;
+        let mut held = HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if held.1 > 0 || !ENABLED.load(Ordering::SeqCst) {
+            held.1 += 1;
+            return Ok(ApplyLock { _private: () });
+        }
+
+        let path = lock_path();
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .map_err(|e| format!("Failed to open lock file {}: {}", path.display(), e))?;
+
+        let fd = file.as_raw_fd();
+        if unsafe { libc::flock(fd, libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            crate::output::notice(
+                "Waiting for another macdisp process to finish changing displays...",
+            );
+            if unsafe { libc::flock(fd, libc::LOCK_EX) } != 0 {
+                return Err(format!(
+                    "Failed to lock {}: {}",
+                    path.display(),
+                    std::io::Error::last_os_error()
+                ));
+            }
+        }
+
+        *held = (Some(file), 1);
+        Ok(ApplyLock { _private: () })
+    }
+}
+
+impl Drop for ApplyLock {
+    fn drop(&mut self) {
+        let mut held = HELD.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        held.1 -= 1;
+        if held.1 == 0 {
+            // Closing the file releases the flock
+            held.0 = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_nests() {
+        let outer = ApplyLock::acquire().unwrap();
+        let inner = ApplyLock::acquire().unwrap();
+        drop(inner);
+        assert!(HELD.lock().unwrap().0.is_some());
+        drop(outer);
+        assert!(HELD.lock().unwrap().0.is_none());
+    }
+}
@@ -0,0 +1,373 @@
+//! Comparing the modes of two displays before mirroring them.
+//!
+//! Every display in a mirror set runs at the same resolution, so macOS drops
+//! them all to a size each of them offers, even when one could do far better.
+
+use crate::{DisplayInfo, DisplayMode};
+use serde::Serialize;
+use std::cmp::Ordering;
+
+/// A resolution both displays offer, with the mode each would use for it.
+#[derive(Debug, Clone, Serialize)]
+pub struct SharedMode {
+    pub width: u32,
+    pub height: u32,
+    pub first: DisplayMode,
+    pub second: DisplayMode,
+}
+
+impl SharedMode {
+    /// Whether both displays report their mode as safe for the hardware.
+    pub fn is_safe(&self) -> bool {
+        self.first.is_safe_for_hardware && self.second.is_safe_for_hardware
+    }
+}
+
+/// Which resolutions two displays share and which only one of them offers.
+#[derive(Debug, Clone, Serialize)]
+pub struct ModeComparison {
+    /// Largest first
+    pub shared: Vec<SharedMode>,
+    pub only_first: Vec<(u32, u32)>,
+    pub only_second: Vec<(u32, u32)>,
+}
+
+impl ModeComparison {
+    /// The resolution mirroring would most likely run at: the largest shared
+    /// resolution that is safe on both displays.
+    pub fn likely_mirror_mode(&self) -> Option<&SharedMode> {
+        self.shared.iter().find(|shared| shared.is_safe())
+    }
+}
+
+/// What mirroring one display onto another will do to their resolutions.
+#[derive(Debug, Clone)]
+pub struct MirrorPlan {
+    /// The resolution the mirror set will likely run at; `first` is the
+    /// mirroring display and `second` the one it mirrors
+    pub mode: SharedMode,
+    /// Displays that will run below their current resolution, with it
+    pub lowered: Vec<(u32, (u32, u32))>,
+}
+
+/// Check that `display` can mirror `target` before forming the mirror set.
+///
+/// Fails when the two share no mode that is safe on both, since macOS would
+/// then pick a mode one of them may not be able to show.
+pub fn plan_mirror(
+    display: &DisplayInfo,
+    display_modes: &[DisplayMode],
+    target: &DisplayInfo,
+    target_modes: &[DisplayMode],
+) -> Result<MirrorPlan, String> {
+    let comparison = compare_modes(display_modes, target_modes);
+    let mode = comparison.likely_mirror_mode().cloned().ok_or_else(|| {
+        format!(
+            "Display {} and display {} share no mode that is safe on both, so they cannot be mirrored reliably",
+            display.id, target.id
+        )
+    })?;
+    let lowered = [display, target]
+        .iter()
+        .filter(|info| info.width * info.height > mode.width * mode.height)
+        .map(|info| (info.id, (info.width, info.height)))
+        .collect();
+    Ok(MirrorPlan { mode, lowered })
+}
+
+/// A display in a mirror set and the largest resolution it offers.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MirrorMember {
+    pub display_id: u32,
+    /// Largest resolution among the display's safe modes
+    pub largest: Option<(u32, u32)>,
+}
+
+/// A mirror set as it runs: every display in it shows the master's resolution.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MirrorSet {
+    /// The display the others copy
+    pub master: u32,
+    /// The master first, then the displays mirroring it
+    pub members: Vec<MirrorMember>,
+    /// The resolution the whole set shows
+    pub width: u32,
+    pub height: u32,
+    /// The display holding the set at this resolution, when another display
+    /// in it could show more
+    pub constrained_by: Option<u32>,
+}
+
+impl MirrorSet {
+    /// One line on the set as seen from `display_id`, e.g. `mirrors display
+    /// 6 at 1920x1080; display 7 offers at most 1920x1080`.
+    pub fn describe(&self, display_id: u32) -> String {
+        let others: Vec<String> = self
+            .members
+            .iter()
+            .filter(|member| member.display_id != self.master)
+            .map(|member| member.display_id.to_string())
+            .collect();
+        let mut line = if display_id == self.master {
+            format!(
+                "mirrored by display {} at {}x{}",
+                others.join(", "),
+                self.width,
+                self.height
+            )
+        } else {
+            format!(
+                "mirrors display {} at {}x{}",
+                self.master, self.width, self.height
+            )
+        };
+        let limit = self.constrained_by.and_then(|id| {
+            let member = self.members.iter().find(|m| m.display_id == id)?;
+            Some((id, member.largest?))
+        });
+        if let Some((id, (width, height))) = limit {
+            line.push_str(&format!(
+                "; display {} offers at most {}x{}",
+                id, width, height
+            ));
+        }
+        line
+    }
+}
+
+/// The mirror set `display_id` belongs to, if any, with the modes of each
+/// display in it from `modes_of`.
+pub fn mirror_set(
+    display_id: u32,
+    displays: &[DisplayInfo],
+    modes_of: impl Fn(u32) -> Vec<DisplayMode>,
+) -> Option<MirrorSet> {
+    let info = displays.iter().find(|info| info.id == display_id)?;
+    let master_id = info.mirror_of.unwrap_or(display_id);
+    let master = displays.iter().find(|info| info.id == master_id)?;
+    let mut ids = vec![master_id];
+    ids.extend(
+        displays
+            .iter()
+            .filter(|info| info.mirror_of == Some(master_id))
+            .map(|info| info.id),
+    );
+    if ids.len() < 2 {
+        return None;
+    }
+
+    let members: Vec<MirrorMember> = ids
+        .into_iter()
+        .map(|id| {
+            let modes = modes_of(id);
+            let safe: Vec<&DisplayMode> = modes.iter().filter(|m| m.is_safe_for_hardware).collect();
+            let candidates = if safe.is_empty() {
+                modes.iter().collect()
+            } else {
+                safe
+            };
+            MirrorMember {
+                display_id: id,
+                largest: candidates
+                    .into_iter()
+                    .max_by_key(|m| (m.width * m.height, m.width))
+                    .map(|m| (m.width, m.height)),
+            }
+        })
+        .collect();
+
+    let area = |(width, height): (u32, u32)| width * height;
+    let shown = area((master.width, master.height));
+    let smallest = members
+        .iter()
+        .filter_map(|m| m.largest.map(|largest| (m.display_id, area(largest))))
+        .min_by_key(|(_, largest)| *largest);
+    let held_back = members
+        .iter()
+        .any(|m| m.largest.is_some_and(|largest| area(largest) > shown));
+    Some(MirrorSet {
+        master: master_id,
+        members,
+        width: master.width,
+        height: master.height,
+        constrained_by: smallest
+            .filter(|(_, largest)| held_back && *largest <= shown)
+            .map(|(id, _)| id),
+    })
+}
+
+/// Order modes of the same size from least to most preferable: safe,
+/// progressive, HiDPI, then the fastest and deepest.
+fn preference(a: &DisplayMode, b: &DisplayMode) -> Ordering {
+    a.is_safe_for_hardware
+        .cmp(&b.is_safe_for_hardware)
+        .then(b.is_interlaced.cmp(&a.is_interlaced))
+        .then((a.pixel_width * a.pixel_height).cmp(&(b.pixel_width * b.pixel_height)))
+        .then(a.refresh_rate.total_cmp(&b.refresh_rate))
+        .then(a.depth.cmp(&b.depth))
+}
+
+/// The preferable mode of each resolution, largest resolution first.
+fn best_per_size(modes: &[DisplayMode]) -> Vec<&DisplayMode> {
+    let mut best: Vec<&DisplayMode> = Vec::new();
+    for mode in modes {
+        match best
+            .iter_mut()
+            .find(|m| m.width == mode.width && m.height == mode.height)
+        {
+            Some(existing) => {
+                if preference(mode, existing) == Ordering::Greater {
+                    *existing = mode;
+                }
+            }
+            None => best.push(mode),
+        }
+    }
+    best.sort_by(|a, b| {
+        (b.width * b.height)
+            .cmp(&(a.width * a.height))
+            .then(b.width.cmp(&a.width))
+    });
+    best
+}
+
+/// Compare the modes of two displays by resolution in points, in the panels'
+/// native orientation.
+pub fn compare_modes(first: &[DisplayMode], second: &[DisplayMode]) -> ModeComparison {
+    let first = best_per_size(first);
+    let second = best_per_size(second);
+    let find = |modes: &[&DisplayMode], mode: &DisplayMode| {
+        modes
+            .iter()
+            .find(|m| m.width == mode.width && m.height == mode.height)
+            .map(|m| (*m).clone())
+    };
+
+    ModeComparison {
+        shared: first
+            .iter()
+            .filter_map(|mode| {
+                find(&second, mode).map(|other| SharedMode {
+                    width: mode.width,
+                    height: mode.height,
+                    first: (*mode).clone(),
+                    second: other,
+                })
+            })
+            .collect(),
+        only_first: first
+            .iter()
+            .filter(|mode| find(&second, mode).is_none())
+            .map(|mode| (mode.width, mode.height))
+            .collect(),
+        only_second: second
+            .iter()
+            .filter(|mode| find(&first, mode).is_none())
+            .map(|mode| (mode.width, mode.height))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::mode;
+
+    #[test]
+    fn test_mirror_set() {
+        let mut studio = crate::apply::tests::display(1, "AAAA");
+        studio.width = 1920;
+        studio.height = 1080;
+        let mut projector = crate::apply::tests::display(2, "BBBB");
+        projector.mirror_of = Some(1);
+        let alone = crate::apply::tests::display(3, "CCCC");
+        let displays = vec![studio, projector, alone];
+        let modes_of = |id| match id {
+            1 => vec![mode(1, 3840, 2160, false), mode(2, 1920, 1080, false)],
+            _ => vec![
+                DisplayMode {
+                    is_safe_for_hardware: false,
+                    ..mode(10, 2560, 1440, false)
+                },
+                mode(11, 1920, 1080, false),
+            ],
+        };
+
+        let set = mirror_set(2, &displays, modes_of).unwrap();
+        assert_eq!(set, mirror_set(1, &displays, modes_of).unwrap());
+        assert_eq!(set.members[0].largest, Some((3840, 2160)));
+        assert_eq!(set.members[1].largest, Some((1920, 1080)));
+        assert_eq!(set.constrained_by, Some(2));
+        assert_eq!(
+            set.describe(2),
+            "mirrors display 1 at 1920x1080; display 2 offers at most 1920x1080"
+        );
+        assert!(set
+            .describe(1)
+            .starts_with("mirrored by display 2 at 1920x1080"));
+        assert!(mirror_set(3, &displays, modes_of).is_none());
+    }
+
+    #[test]
+    fn test_compare_modes() {
+        let studio = vec![
+            mode(1, 5120, 2880, false),
+            mode(2, 2560, 1440, true),
+            mode(3, 2560, 1440, false),
+            mode(4, 1920, 1080, true),
+        ];
+        let projector = vec![
+            DisplayMode {
+                is_safe_for_hardware: false,
+                ..mode(10, 2560, 1440, false)
+            },
+            mode(11, 1920, 1080, false),
+            mode(12, 1280, 720, false),
+        ];
+
+        let comparison = compare_modes(&studio, &projector);
+        let shared: Vec<(u32, u32)> = comparison
+            .shared
+            .iter()
+            .map(|s| (s.width, s.height))
+            .collect();
+        assert_eq!(shared, vec![(2560, 1440), (1920, 1080)]);
+        assert_eq!(comparison.shared[0].first.mode_number, 2);
+        assert_eq!(comparison.only_first, vec![(5120, 2880)]);
+        assert_eq!(comparison.only_second, vec![(1280, 720)]);
+
+        // 2560x1440 is not safe on the projector
+        let likely = comparison.likely_mirror_mode().unwrap();
+        assert_eq!(
+            (likely.first.mode_number, likely.second.mode_number),
+            (4, 11)
+        );
+    }
+
+    #[test]
+    fn test_plan_mirror() {
+        let studio = crate::apply::tests::display(1, "STUDIO");
+        let projector = DisplayInfo {
+            width: 1920,
+            height: 1080,
+            ..crate::apply::tests::display(2, "PROJECTOR")
+        };
+        let studio_modes = vec![mode(1, 2560, 1440, true), mode(2, 1920, 1080, true)];
+
+        let plan = plan_mirror(
+            &projector,
+            &[mode(10, 1920, 1080, false)],
+            &studio,
+            &studio_modes,
+        )
+        .unwrap();
+        assert_eq!((plan.mode.width, plan.mode.height), (1920, 1080));
+        assert_eq!(plan.lowered, vec![(1, (2560, 1440))]);
+
+        let unsafe_only = [DisplayMode {
+            is_safe_for_hardware: false,
+            ..mode(10, 1920, 1080, false)
+        }];
+        assert!(plan_mirror(&projector, &unsafe_only, &studio, &studio_modes).is_err());
+    }
+}
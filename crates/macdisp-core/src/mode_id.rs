@@ -0,0 +1,247 @@
+use crate::DisplayMode;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Default for how far apart two refresh rates may be and still match, in
+/// hertz.
+///
+/// Wide enough that `hz:60` finds a 59.94Hz mode on displays that only offer
+/// that; when both are offered, the closer one is used.
+pub const DEFAULT_HZ_TOLERANCE: f64 = 0.1;
+
+static HZ_TOLERANCE_MILLIHZ: AtomicU32 = AtomicU32::new((DEFAULT_HZ_TOLERANCE * 1000.0) as u32);
+
+/// Set how far apart a requested and an offered refresh rate may be and
+/// still match. `0` only matches rates equal to the millihertz.
+pub fn set_hz_tolerance(tolerance: f64) {
+    HZ_TOLERANCE_MILLIHZ.store(millihz(tolerance.max(0.0)), Ordering::SeqCst);
+}
+
+pub fn hz_tolerance() -> f64 {
+    HZ_TOLERANCE_MILLIHZ.load(Ordering::SeqCst) as f64 / 1000.0
+}
+
+/// Whether two refresh rates are the same within [`hz_tolerance`].
+pub fn same_hz(a: f64, b: f64) -> bool {
+    millihz(a).abs_diff(millihz(b)) <= HZ_TOLERANCE_MILLIHZ.load(Ordering::SeqCst)
+}
+
+/// Format a refresh rate to the millihertz without trailing zeros: `60`,
+/// `59.94`, `23.976`.
+pub fn format_hz(hz: f64) -> String {
+    format_millihz(millihz(hz))
+}
+
+fn millihz(hz: f64) -> u32 {
+    (hz * 1000.0).round() as u32
+}
+
+fn format_millihz(millihz: u32) -> String {
+    let (whole, fraction) = (millihz / 1000, millihz % 1000);
+    if fraction == 0 {
+        whole.to_string()
+    } else {
+        format!("{}.{:03}", whole, fraction)
+            .trim_end_matches('0')
+            .to_string()
+    }
+}
+
+/// Identifies a display mode by its properties rather than its index.
+///
+/// `mode_number` values are positions in the list reported by the system and
+/// can shift between boots and OS updates. A `ModeId` stays the same as long as
+/// the display offers a mode with the same point size, pixel size, refresh
+/// rate, depth and flags.
+///
+/// The string form is `<points>/<pixels>@<hz>/d<depth>[/hidpi][/interlaced]`,
+/// for example `1512x982/3024x1964@120/d8/hidpi`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModeId {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+    /// Refresh rate in millihertz, so 59.94Hz and 60Hz stay distinct
+    pub refresh_millihz: u32,
+    pub depth: u32,
+    pub hidpi: bool,
+    pub interlaced: bool,
+}
+
+impl ModeId {
+    pub fn of(mode: &DisplayMode) -> ModeId {
+        ModeId {
+            width: mode.width,
+            height: mode.height,
+            pixel_width: mode.pixel_width,
+            pixel_height: mode.pixel_height,
+            refresh_millihz: millihz(mode.refresh_rate),
+            depth: mode.depth,
+            hidpi: mode.is_scaled,
+            interlaced: mode.is_interlaced,
+        }
+    }
+
+    pub fn refresh_rate(&self) -> f64 {
+        self.refresh_millihz as f64 / 1000.0
+    }
+
+    /// The refresh rate as written in ids, `60` or `59.94`.
+    fn hz(&self) -> String {
+        format_millihz(self.refresh_millihz)
+    }
+
+    /// The size and refresh rate alone, e.g. `1920x1080@60 HiDPI`.
+    pub fn short(&self) -> String {
+        format!(
+            "{}x{}@{}{}",
+            self.width,
+            self.height,
+            self.hz(),
+            if self.hidpi { " HiDPI" } else { "" }
+        )
+    }
+}
+
+impl DisplayMode {
+    pub fn mode_id(&self) -> ModeId {
+        ModeId::of(self)
+    }
+}
+
+/// Pick the mode matching `id`, using `hint` (a mode number) to choose between
+/// identical entries.
+pub fn find_mode<'a>(
+    modes: &'a [DisplayMode],
+    id: &ModeId,
+    hint: Option<u32>,
+) -> Option<&'a DisplayMode> {
+    let mut matches = modes.iter().filter(|mode| mode.mode_id() == *id);
+    let first = matches.next()?;
+    match hint {
+        Some(hint) if first.mode_number != hint => Some(
+            modes
+                .iter()
+                .find(|mode| mode.mode_number == hint && mode.mode_id() == *id)
+                .unwrap_or(first),
+        ),
+        _ => Some(first),
+    }
+}
+
+impl fmt::Display for ModeId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{}/{}x{}@{}/d{}",
+            self.width,
+            self.height,
+            self.pixel_width,
+            self.pixel_height,
+            self.hz(),
+            self.depth
+        )?;
+        if self.hidpi {
+            write!(f, "/hidpi")?;
+        }
+        if self.interlaced {
+            write!(f, "/interlaced")?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_size(value: &str) -> Option<(u32, u32)> {
+    let (w, h) = value.split_once('x')?;
+    Some((w.parse().ok()?, h.parse().ok()?))
+}
+
+impl FromStr for ModeId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid mode id: {}", s);
+
+        let mut parts = s.split('/');
+        let (width, height) = parts.next().and_then(parse_size).ok_or_else(invalid)?;
+        let (pixels, hz) = parts
+            .next()
+            .and_then(|p| p.split_once('@'))
+            .ok_or_else(invalid)?;
+        let (pixel_width, pixel_height) = parse_size(pixels).ok_or_else(invalid)?;
+        let refresh_rate: f64 = hz.parse().map_err(|_| invalid())?;
+        let depth = parts
+            .next()
+            .and_then(|d| d.strip_prefix('d'))
+            .and_then(|d| d.parse().ok())
+            .ok_or_else(invalid)?;
+
+        let mut id = ModeId {
+            width,
+            height,
+            pixel_width,
+            pixel_height,
+            refresh_millihz: millihz(refresh_rate),
+            depth,
+            hidpi: false,
+            interlaced: false,
+        };
+        for flag in parts {
+            match flag {
+                "hidpi" => id.hidpi = true,
+                "interlaced" => id.interlaced = true,
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(id)
+    }
+}
+
+impl Serialize for ModeId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ModeId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_id_round_trip() {
+        for s in [
+            "1512x982/3024x1964@120/d8/hidpi",
+            "1920x1080/1920x1080@59.94/d8",
+            "1920x1080/1920x1080@60/d8/interlaced",
+        ] {
+            let id: ModeId = s.parse().unwrap();
+            assert_eq!(id.to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_mode_id_rejects_garbage() {
+        assert!("1920x1080".parse::<ModeId>().is_err());
+        assert!("1920x1080/1920x1080@60/d8/bogus".parse::<ModeId>().is_err());
+    }
+
+    #[test]
+    fn test_format_hz() {
+        assert_eq!(format_hz(60.0), "60");
+        assert_eq!(format_hz(59.940_059_94), "59.94");
+        assert_eq!(format_hz(23.976_023_98), "23.976");
+        assert!(same_hz(59.94, 59.940_059_94));
+        assert!(same_hz(60.0, 59.94));
+        assert!(!same_hz(60.0, 50.0));
+    }
+}
@@ -0,0 +1,265 @@
+#ifndef DISPLAY_SERVICES_H
+#define DISPLAY_SERVICES_H
+
+#include <stdint.h>
+#include <stdbool.h>
+
+typedef struct {
+    uint32_t width;
+    uint32_t height;
+    double refresh_rate;
+    uint32_t depth;
+    uint32_t mode_number;
+    bool is_stretched;
+    bool is_interlaced;
+    bool is_tv_mode;
+    bool is_safe_for_hardware;
+    bool is_scaled;  // HiDPI/Retina scaling
+    uint32_t pixel_width;   // Backing resolution in pixels
+    uint32_t pixel_height;
+} DisplayMode;
+
+typedef struct {
+    DisplayMode *modes;
+    size_t count;
+} DisplayModeList;
+
+// Changes for ds_configure_display. Each value is only applied when its
+// has_* flag is set, so (0,0) origins and display id 0 are unambiguous.
+typedef struct {
+    bool has_origin;
+    int32_t x;
+    int32_t y;
+    bool has_rotation;
+    uint32_t rotation;
+    bool has_mirror;
+    uint32_t mirror_display_id;  // kCGNullDirectDisplay (0) stops mirroring
+    bool has_enabled;
+    bool enabled;
+} DisplayConfigureOptions;
+
+// Check if DisplayServices framework is available
+bool ds_is_available(void);
+
+// Get all available modes for a display (using DisplayServices if available)
+DisplayModeList *ds_get_all_modes(uint32_t display_id);
+
+// Get current mode for a display
+DisplayMode *ds_get_current_mode(uint32_t display_id);
+
+// Failures detected by this library. Calls that reach CoreGraphics return its
+// CGError instead (0 on success, 1000 and up on failure)
+#define DS_ERROR_INVALID_ARGUMENT -1
+#define DS_ERROR_DISPLAY_NOT_FOUND -2
+#define DS_ERROR_MODE_NOT_FOUND -3
+#define DS_ERROR_NOT_SUPPORTED -4
+
+#define DS_ERROR_MESSAGE_LEN 256
+
+// Why the last failing call on a thread failed
+typedef struct {
+    int32_t code;                         // what the call returned, 0 if nothing failed
+    char message[DS_ERROR_MESSAGE_LEN];   // the failing API and what it reported
+} DSError;
+
+// Copy the failure last recorded on the calling thread into *error, if not
+// NULL, and clear it. Calls record a failure along with returning its code,
+// with the NSError, IOReturn or CGError text the code alone would lose
+void ds_take_last_error(DSError *error);
+
+// Set display mode (returns 0 on success, a DS_ERROR_* value or a CGError)
+int ds_set_mode(uint32_t display_id, uint32_t mode_number);
+
+// Get the persistent UUID of a display (free with ds_free_string)
+char *ds_get_display_uuid(uint32_t display_id);
+
+// Get a description of the display type (free with ds_free_string)
+char *ds_get_display_type(uint32_t display_id);
+
+// Read the brightness (0.0-1.0) of a display (returns 0 on success)
+int ds_get_brightness(uint32_t display_id, float *brightness);
+
+// Set the brightness (0.0-1.0) of a display (returns 0 on success)
+int ds_set_brightness(uint32_t display_id, float brightness);
+
+// Get the IOPlatformUUID of this Mac (free with ds_free_string)
+char *ds_get_hardware_uuid(void);
+
+// Called for every USB device that appears or disappears
+typedef void (*UsbDeviceCallback)(uint16_t vendor_id, uint16_t product_id, bool added, void *context);
+
+// Report USB device arrival and removal on the current thread's run loop.
+// Devices already connected are reported as added before this returns.
+// The registration lasts for the rest of the process (returns 0 on success)
+int ds_watch_usb_devices(UsbDeviceCallback callback, void *context);
+
+// Called when the power source may have changed
+typedef void (*PowerSourceCallback)(bool on_battery, void *context);
+
+// Check whether this Mac is running on battery (1), AC power (0) or unknown (-1)
+int ds_on_battery_power(void);
+
+// Report power source changes on the current thread's run loop.
+// The registration lasts for the rest of the process (returns 0 on success)
+int ds_watch_power_source(PowerSourceCallback callback, void *context);
+
+// Wake the displays as user input would (returns 0 on success or an IOReturn)
+int ds_declare_user_activity(void);
+
+// Called when displays may have gone to sleep or woken up
+typedef void (*DisplayPowerCallback)(void *context);
+
+typedef struct DisplayPowerWatch DisplayPowerWatch;
+
+// Report display wrangler power changes on the current thread's run loop, once
+// CGDisplayIsAsleep reflects them, until passed to ds_unwatch_display_power
+// (returns 0 on success or DS_ERROR_NOT_SUPPORTED without a wrangler)
+int ds_watch_display_power(DisplayPowerCallback callback, void *context,
+                           DisplayPowerWatch **watch);
+
+// Stop a registration made on the current thread
+void ds_unwatch_display_power(DisplayPowerWatch *watch);
+
+// Run work(context) on a dedicated thread that keeps a CFRunLoop running,
+// waiting until it has finished. Runs inline when called on that thread
+void ds_run_on_executor(void (*work)(void *), void *context);
+
+// Apply origin, rotation, mirroring and enabled state in one transaction
+// (returns 0 on success, a DS_ERROR_* value or a CGError)
+int ds_configure_display(uint32_t display_id, const DisplayConfigureOptions *options);
+
+// Apply options[i] to display_ids[i] for every display in one transaction,
+// so the window server never sees a half-applied arrangement
+int ds_configure_displays(const uint32_t *display_ids, const DisplayConfigureOptions *options,
+                          size_t count);
+
+// One DDC/CI exchange with an external display over I2C (Apple silicon only):
+// write request (starting with the host address byte), wait delay_us, then
+// read reply_len bytes into reply unless reply_len is 0.
+// Returns 0 on success, DS_ERROR_NOT_SUPPORTED when the display has no I2C
+// service, or an IOReturn
+int ds_ddc_transact(uint32_t display_id, const uint8_t *request, size_t request_len,
+                    uint8_t *reply, size_t reply_len, uint32_t delay_us);
+
+// Copy up to capacity bytes of a display's EDID into buffer and store its full
+// length in *length. Returns 0 on success, DS_ERROR_NOT_SUPPORTED when the
+// EDID cannot be reached, or an IOReturn
+int ds_read_edid(uint32_t display_id, uint8_t *buffer, size_t capacity, size_t *length);
+
+// Copy the EDID of the index-th display IOKit knows about, without going
+// through the window server: every display with a framebuffer EDID (Intel),
+// then every external display answering over I2C (Apple silicon). Returns 0,
+// DS_ERROR_DISPLAY_NOT_FOUND past the last display, or an IOReturn
+int ds_read_iokit_edid(size_t index, uint8_t *buffer, size_t capacity, size_t *length);
+
+// Whether this process has a window server session
+#define DS_SESSION_NONE 0        // no session, e.g. over SSH or in a system launch daemon
+#define DS_SESSION_BACKGROUND 1  // a session behind another user's on the console
+#define DS_SESSION_CONSOLE 2     // the session on the console
+
+int ds_session_state(void);
+
+// Called when the console user may have changed
+typedef void (*ConsoleUserCallback)(void *context);
+
+// Report console user changes (fast user switching, login, logout) on the
+// current thread's run loop.
+// The registration lasts for the rest of the process (returns 0 on success)
+int ds_watch_console_user(ConsoleUserCallback callback, void *context);
+
+#define DS_PRESET_NAME_LEN 128
+
+// A reference mode of a display with fine color controls, such as
+// "Photography (P3-D65)"; each fixes a white point and SDR reference luminance
+typedef struct {
+    int32_t index;
+    bool active;
+    char name[DS_PRESET_NAME_LEN];  // NUL-terminated, truncated if longer
+} ReferencePreset;
+
+// Copy up to capacity reference modes of a display into presets and store how
+// many it has in *count. Returns 0 on success or DS_ERROR_NOT_SUPPORTED for
+// displays without reference modes
+int ds_get_reference_presets(uint32_t display_id, ReferencePreset *presets, size_t capacity,
+                             size_t *count);
+
+// Switch a display to the reference mode with this index (returns 0 on
+// success, DS_ERROR_MODE_NOT_FOUND or DS_ERROR_NOT_SUPPORTED)
+int ds_set_reference_preset(uint32_t display_id, int32_t index);
+
+#define DS_DETAIL_LEN 128
+
+// How a display is attached and driven; empty strings are unknown
+typedef struct {
+    char connection[DS_DETAIL_LEN];   // "built-in", "HDMI", "DisplayPort", "AirPlay", ...
+    char gpu[DS_DETAIL_LEN];          // name of the GPU driving the display
+    char color_space[DS_DETAIL_LEN];  // name of the display's color profile
+    double width_mm;                  // physical size, 0 when the EDID has none
+    double height_mm;
+} DisplayDetails;
+
+// Fill in the connection, GPU, color profile and size of a display (returns
+// 0 on success or DS_ERROR_INVALID_ARGUMENT)
+int ds_get_display_details(uint32_t display_id, DisplayDetails *details);
+
+// Copy the path of a display's desktop picture into buffer, NUL-terminated
+// (returns 0, DS_ERROR_DISPLAY_NOT_FOUND when no screen shows the display, or
+// DS_ERROR_NOT_SUPPORTED when the picture is not a file or does not fit)
+int ds_get_wallpaper(uint32_t display_id, char *buffer, size_t capacity);
+
+// Set a display's desktop picture to the image at path (returns 0,
+// DS_ERROR_DISPLAY_NOT_FOUND when no screen shows the display,
+// DS_ERROR_INVALID_ARGUMENT when path is missing, or DS_ERROR_NOT_SUPPORTED
+// when macOS refuses the image)
+int ds_set_wallpaper(uint32_t display_id, const char *path);
+
+// Called when a brightness key goes down, with the display under the pointer.
+// Returns whether the press was handled; handled presses and their key ups
+// never reach macOS
+typedef bool (*BrightnessKeyCallback)(bool up, uint32_t display_id, void *context);
+
+// Intercept the brightness keys on the current thread's run loop. Needs the
+// Accessibility permission (returns 0 on success or DS_ERROR_NOT_SUPPORTED).
+// The registration lasts for the rest of the process
+int ds_tap_brightness_keys(BrightnessKeyCallback callback, void *context);
+
+// Show the system brightness OSD on a display, filled to brightness (0.0-1.0)
+// (returns 0 on success or DS_ERROR_NOT_SUPPORTED)
+int ds_show_brightness_osd(uint32_t display_id, float brightness);
+
+#define DS_WINDOW_TITLE_LEN 256
+
+// A standard window of a running app, in global points from the top left of
+// the main display
+typedef struct {
+    int32_t pid;
+    uint32_t index;      // position among the app's windows, front to back
+    uint32_t window_id;  // CGWindowID, or 0 when it cannot be read
+    double x;
+    double y;
+    double width;
+    double height;
+    char title[DS_WINDOW_TITLE_LEN];  // NUL-terminated, truncated if longer
+} WindowFrame;
+
+// Copy up to capacity windows of the regular apps into windows and store how
+// many there are in *count. Needs the Accessibility permission (returns 0 on
+// success or DS_ERROR_NOT_SUPPORTED)
+int ds_list_windows(WindowFrame *windows, size_t capacity, size_t *count);
+
+// Move and resize the window of app pid with this window_id, or when it has
+// none, the one at index with this title. Returns 0 on success,
+// DS_ERROR_DISPLAY_NOT_FOUND when the window is gone, or
+// DS_ERROR_NOT_SUPPORTED when the app refuses
+int ds_set_window_frame(const WindowFrame *window);
+
+// Free mode list
+void ds_free_mode_list(DisplayModeList *list);
+
+// Free single mode
+void ds_free_mode(DisplayMode *mode);
+
+// Free a string returned by this library
+void ds_free_string(char *str);
+
+#endif // DISPLAY_SERVICES_H
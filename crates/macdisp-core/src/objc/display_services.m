@@ -0,0 +1,1777 @@
+#import <Foundation/Foundation.h>
+#import <AppKit/AppKit.h>
+#import <ApplicationServices/ApplicationServices.h>
+#import <CoreGraphics/CoreGraphics.h>
+#import <Metal/Metal.h>
+#import <IOKit/IOMessage.h>
+#import <IOKit/graphics/IOGraphicsLib.h>
+#import <IOKit/ps/IOPowerSources.h>
+#import <IOKit/pwr_mgt/IOPMLib.h>
+#import <IOKit/ps/IOPSKeys.h>
+#import <SystemConfiguration/SystemConfiguration.h>
+#import "display_services.h"
+#import <dlfcn.h>
+#import <objc/message.h>
+#import <mach/mach_error.h>
+#import <pthread.h>
+#import <stdarg.h>
+#import <string.h>
+
+// Mode description buffer size
+#define MODE_DESC_SIZE 256
+
+static __thread DSError last_error;
+
+void ds_take_last_error(DSError *error) {
+    if (error) {
+        *error = last_error;
+    }
+    memset(&last_error, 0, sizeof(last_error));
+}
+
+// Record why a call failed and return its code
+static int ds_fail(int code, const char *format, ...) __attribute__((format(printf, 2, 3)));
+static int ds_fail(int code, const char *format, ...) {
+    last_error.code = code;
+    va_list args;
+    va_start(args, format);
+    vsnprintf(last_error.message, sizeof(last_error.message), format, args);
+    va_end(args);
+    return code;
+}
+
+// Record a failed IOKit call with the system's description of its IOReturn
+static int ds_fail_io(IOReturn result, const char *call) {
+    return ds_fail(result, "%s returned 0x%08x (%s)", call, result, mach_error_string(result));
+}
+
+// CGS API function pointer types for Sequoia
+typedef int (*CGSGetNumberOfDisplayModes_t)(uint32_t displayID, int *outCount);
+typedef int (*CGSGetDisplayModeDescription_t)(uint32_t displayID, int idx, int *outData);
+typedef int (*CGSGetCurrentDisplayMode_t)(uint32_t displayID, int *outModeNum);
+typedef int (*CGSConfigureDisplayMode_t)(void *config, uint32_t displayID, int modeNum);
+
+// DisplayServices brightness (built-in and Apple displays)
+typedef int (*DisplayServicesGetBrightness_t)(uint32_t displayID, float *brightness);
+typedef int (*DisplayServicesSetBrightness_t)(uint32_t displayID, float brightness);
+
+// CGDisplayCreateUUIDFromDisplayID function pointer type
+typedef CFUUIDRef (*CGDisplayCreateUUIDFromDisplayID_t)(CGDirectDisplayID display);
+
+// Global function pointers
+static void *ds_handle = NULL;
+static CGSGetNumberOfDisplayModes_t cgs_get_num_modes = NULL;
+static CGSGetDisplayModeDescription_t cgs_get_mode_desc = NULL;
+static CGSGetCurrentDisplayMode_t cgs_get_current = NULL;
+static CGSConfigureDisplayMode_t cgs_configure = NULL;
+static DisplayServicesGetBrightness_t ds_get_brightness_fn = NULL;
+static DisplayServicesSetBrightness_t ds_set_brightness_fn = NULL;
+static CGDisplayCreateUUIDFromDisplayID_t cg_display_create_uuid = NULL;
+static dispatch_once_t ds_init_once;
+
+static DisplayMode mode_from_cgs(int *buffer) {
+    DisplayMode mode = {0};
+
+    // Based on test output:
+    // buffer[0] = mode_id
+    // buffer[1] = flags (various display mode properties)
+    // buffer[2] = width
+    // buffer[3] = height
+    // buffer[4] = depth
+    // buffer[9] = refresh_rate
+
+    mode.mode_number = buffer[0];
+    mode.width = buffer[2];
+    mode.height = buffer[3];
+    mode.depth = buffer[4];
+    mode.refresh_rate = buffer[9];
+    mode.is_safe_for_hardware = true;
+
+    // HiDPI/scaled detection:
+    // Modes with width <= 1800 are marked as "scaling:on" in displayplacer
+    // These represent true 2x HiDPI modes where 1 logical pixel = 2 physical pixels
+    // Larger resolutions (1920+) are "looks like" modes without the scaling flag
+    mode.is_scaled = (mode.width <= 1800 && (buffer[1] & 0x0F) != 0);
+
+    // The description buffer only reports point dimensions; HiDPI modes are
+    // backed by twice as many pixels in each direction
+    uint32_t scale = mode.is_scaled ? 2 : 1;
+    mode.pixel_width = mode.width * scale;
+    mode.pixel_height = mode.height * scale;
+
+    return mode;
+}
+
+static DisplayMode mode_from_cg(CGDisplayModeRef mode_ref) {
+    DisplayMode mode = {0};
+
+    mode.width = (uint32_t)CGDisplayModeGetWidth(mode_ref);
+    mode.height = (uint32_t)CGDisplayModeGetHeight(mode_ref);
+    mode.refresh_rate = CGDisplayModeGetRefreshRate(mode_ref);
+    mode.depth = 32;  // Default since CGDisplayModeCopyPixelEncoding is deprecated
+    mode.mode_number = (uint32_t)CGDisplayModeGetIODisplayModeID(mode_ref);
+    mode.is_safe_for_hardware = true;
+    mode.pixel_width = (uint32_t)CGDisplayModeGetPixelWidth(mode_ref);
+    mode.pixel_height = (uint32_t)CGDisplayModeGetPixelHeight(mode_ref);
+    mode.is_scaled = mode.pixel_width > mode.width;
+
+    return mode;
+}
+
+static void ds_load(void);
+
+// Safe to call from any thread; the lookups run exactly once. Only calls that
+// read or change modes or brightness need DisplayServices, so read-only
+// CoreGraphics paths such as `status` and `connected` never load it.
+static void ds_init(void) {
+    dispatch_once(&ds_init_once, ^{
+        ds_load();
+    });
+}
+
+static void ds_load(void) {
+    // Load DisplayServices framework (it's in the dyld shared cache on Sequoia)
+    ds_handle = dlopen("/System/Library/PrivateFrameworks/DisplayServices.framework/DisplayServices", RTLD_LAZY);
+
+    if (!ds_handle) {
+        return;
+    }
+
+    // Load CGS functions (Sequoia API)
+    cgs_get_num_modes = dlsym(ds_handle, "CGSGetNumberOfDisplayModes");
+    cgs_get_mode_desc = dlsym(ds_handle, "CGSGetDisplayModeDescription");
+    cgs_get_current = dlsym(ds_handle, "CGSGetCurrentDisplayMode");
+    cgs_configure = dlsym(ds_handle, "CGSConfigureDisplayMode");
+    ds_get_brightness_fn = dlsym(ds_handle, "DisplayServicesGetBrightness");
+    ds_set_brightness_fn = dlsym(ds_handle, "DisplayServicesSetBrightness");
+
+    // Without the mode functions, fall back to CoreGraphics for modes
+    if (!cgs_get_num_modes || !cgs_get_mode_desc || !cgs_get_current) {
+        cgs_get_num_modes = NULL;
+        // Keep the handle open while the brightness functions are still usable
+        if (!ds_get_brightness_fn && !ds_set_brightness_fn) {
+            dlclose(ds_handle);
+            ds_handle = NULL;
+        }
+    }
+
+}
+
+// CGDisplayCreateUUIDFromDisplayID is in CoreGraphics, which is already
+// linked, but not in its public headers
+static void cg_uuid_init(void) {
+    static dispatch_once_t once;
+    dispatch_once(&once, ^{
+        cg_display_create_uuid = dlsym(RTLD_DEFAULT, "CGDisplayCreateUUIDFromDisplayID");
+    });
+}
+
+bool ds_is_available(void) {
+    ds_init();
+    return ds_handle != NULL && cgs_get_num_modes != NULL;
+}
+
+DisplayModeList *ds_get_all_modes(uint32_t display_id) {
+    ds_init();
+
+    DisplayModeList *list = malloc(sizeof(DisplayModeList));
+    if (!list) return NULL;
+
+    if (ds_is_available()) {
+        // Use CGS API (Sequoia)
+        int count = 0;
+        if (cgs_get_num_modes(display_id, &count) != 0 || count <= 0) {
+            free(list);
+            return NULL;
+        }
+
+        list->count = (size_t)count;
+        list->modes = malloc(sizeof(DisplayMode) * count);
+        if (!list->modes) {
+            free(list);
+            return NULL;
+        }
+
+        for (int i = 0; i < count; i++) {
+            int buffer[MODE_DESC_SIZE] = {0};
+            if (cgs_get_mode_desc(display_id, i, buffer) == 0) {
+                list->modes[i] = mode_from_cgs(buffer);
+            }
+        }
+    } else {
+        // Fallback to CoreGraphics
+        CFArrayRef modes_array = CGDisplayCopyAllDisplayModes(display_id, NULL);
+        if (!modes_array) {
+            free(list);
+            return NULL;
+        }
+
+        CFIndex count = CFArrayGetCount(modes_array);
+        list->count = (size_t)count;
+        list->modes = malloc(sizeof(DisplayMode) * count);
+
+        for (CFIndex i = 0; i < count; i++) {
+            CGDisplayModeRef mode_ref = (CGDisplayModeRef)CFArrayGetValueAtIndex(modes_array, i);
+            list->modes[i] = mode_from_cg(mode_ref);
+        }
+
+        CFRelease(modes_array);
+    }
+
+    return list;
+}
+
+DisplayMode *ds_get_current_mode(uint32_t display_id) {
+    ds_init();
+
+    DisplayMode *mode = malloc(sizeof(DisplayMode));
+    if (!mode) return NULL;
+
+    if (ds_is_available()) {
+        // Use CGS API
+        int mode_num = 0;
+        if (cgs_get_current(display_id, &mode_num) != 0) {
+            free(mode);
+            return NULL;
+        }
+
+        // The mode_num IS the correct index - use it directly
+        int buffer[MODE_DESC_SIZE] = {0};
+        if (cgs_get_mode_desc(display_id, mode_num, buffer) == 0) {
+            *mode = mode_from_cgs(buffer);
+        } else {
+            // Fallback: just set the mode number
+            mode->mode_number = mode_num;
+        }
+    } else {
+        // Fallback to CoreGraphics
+        CGDisplayModeRef mode_ref = CGDisplayCopyDisplayMode(display_id);
+        if (!mode_ref) {
+            free(mode);
+            return NULL;
+        }
+
+        *mode = mode_from_cg(mode_ref);
+        CGDisplayModeRelease(mode_ref);
+    }
+
+    return mode;
+}
+
+int ds_set_mode(uint32_t display_id, uint32_t mode_number) {
+    ds_init();
+
+    // If DisplayServices is available, use CGSConfigureDisplayMode for HiDPI/scaled modes
+    if (ds_is_available() && cgs_configure) {
+        CGDisplayConfigRef config;
+        CGBeginDisplayConfiguration(&config);
+
+        int result = cgs_configure(config, display_id, (int)mode_number);
+
+        if (result == 0) {
+            CGError error = CGCompleteDisplayConfiguration(config, kCGConfigureForSession);
+            if (error != kCGErrorSuccess) {
+                return ds_fail(error, "CGCompleteDisplayConfiguration failed after "
+                                      "CGSConfigureDisplayMode set mode %u", mode_number);
+            }
+            return 0;
+        } else {
+            CGCancelDisplayConfiguration(config);
+            // Fall through to CoreGraphics method
+        }
+    }
+
+    // Fallback to CoreGraphics for native modes
+    CFArrayRef modes_array = CGDisplayCopyAllDisplayModes(display_id, NULL);
+    if (!modes_array) {
+        return ds_fail(DS_ERROR_DISPLAY_NOT_FOUND, "CGDisplayCopyAllDisplayModes returned no modes");
+    }
+
+    CGDisplayModeRef target_mode = NULL;
+    CFIndex count = CFArrayGetCount(modes_array);
+
+    // Try to find mode by resolution/refresh rate match
+    if (ds_is_available()) {
+        int buffer[MODE_DESC_SIZE] = {0};
+        if (cgs_get_mode_desc(display_id, mode_number, buffer) == 0) {
+            int target_width = buffer[2];
+            int target_height = buffer[3];
+            int target_hz = buffer[9];
+
+            for (CFIndex i = 0; i < count; i++) {
+                CGDisplayModeRef mode = (CGDisplayModeRef)CFArrayGetValueAtIndex(modes_array, i);
+                int mode_width = (int)CGDisplayModeGetWidth(mode);
+                int mode_height = (int)CGDisplayModeGetHeight(mode);
+                int mode_hz = (int)CGDisplayModeGetRefreshRate(mode);
+
+                if (mode_width == target_width &&
+                    mode_height == target_height &&
+                    mode_hz == target_hz) {
+                    target_mode = mode;
+                    break;
+                }
+            }
+        }
+    } else {
+        // Match by mode ID
+        for (CFIndex i = 0; i < count; i++) {
+            CGDisplayModeRef mode = (CGDisplayModeRef)CFArrayGetValueAtIndex(modes_array, i);
+            if ((uint32_t)CGDisplayModeGetIODisplayModeID(mode) == mode_number) {
+                target_mode = mode;
+                break;
+            }
+        }
+    }
+
+    int result;
+    if (target_mode) {
+        CGDisplayConfigRef config;
+        CGBeginDisplayConfiguration(&config);
+        CGError error = CGConfigureDisplayWithDisplayMode(config, display_id, target_mode, NULL);
+        if (error == kCGErrorSuccess) {
+            error = CGCompleteDisplayConfiguration(config, kCGConfigureForSession);
+            result = error == kCGErrorSuccess
+                         ? 0
+                         : ds_fail(error, "CGCompleteDisplayConfiguration failed for mode %u",
+                                   mode_number);
+        } else {
+            CGCancelDisplayConfiguration(config);
+            result = ds_fail(error, "CGConfigureDisplayWithDisplayMode rejected mode %u",
+                             mode_number);
+        }
+    } else {
+        result = ds_fail(DS_ERROR_MODE_NOT_FOUND,
+                         "mode %u is not among the %ld modes CoreGraphics lists", mode_number,
+                         (long)count);
+    }
+
+    CFRelease(modes_array);
+    return result;
+}
+
+void ds_free_mode_list(DisplayModeList *list) {
+    if (list) {
+        if (list->modes) {
+            free(list->modes);
+        }
+        free(list);
+    }
+}
+
+void ds_free_mode(DisplayMode *mode) {
+    if (mode) {
+        free(mode);
+    }
+}
+
+char *ds_get_display_uuid(uint32_t display_id) {
+    cg_uuid_init();
+
+    // Try to use CGDisplayCreateUUIDFromDisplayID if available
+    if (cg_display_create_uuid) {
+        CFUUIDRef uuid_ref = cg_display_create_uuid(display_id);
+        if (uuid_ref) {
+            // Convert CFUUIDRef to string
+            CFStringRef uuid_string = CFUUIDCreateString(kCFAllocatorDefault, uuid_ref);
+            CFRelease(uuid_ref);
+
+            if (uuid_string) {
+                // Convert CFString to C string
+                CFIndex length = CFStringGetLength(uuid_string);
+                CFIndex maxSize = CFStringGetMaximumSizeForEncoding(length, kCFStringEncodingUTF8) + 1;
+                char *buffer = malloc(maxSize);
+
+                if (buffer) {
+                    if (CFStringGetCString(uuid_string, buffer, maxSize, kCFStringEncodingUTF8)) {
+                        CFRelease(uuid_string);
+                        return buffer;
+                    }
+                    free(buffer);
+                }
+                CFRelease(uuid_string);
+            }
+        }
+    }
+
+    // Fallback: create a pseudo-UUID from display ID
+    char *buffer = malloc(64);
+    if (buffer) {
+        snprintf(buffer, 64, "%08X-0000-0000-0000-000000000000", display_id);
+    }
+    return buffer;
+}
+
+typedef CFDictionaryRef (*CoreDisplayCreateInfoDictionaryFn)(CGDirectDisplayID display);
+
+static bool info_string_contains(CFTypeRef value, CFStringRef needle) {
+    return value && CFGetTypeID(value) == CFStringGetTypeID() &&
+           CFStringFind(value, needle, kCFCompareCaseInsensitive).location != kCFNotFound;
+}
+
+static void product_name_contains(const void *key, const void *value, void *found) {
+    if (info_string_contains(value, CFSTR("Sidecar"))) {
+        *(bool *)found = true;
+    }
+}
+
+// Sidecar and AirPlay displays have no framebuffer; CoreDisplay flags AirPlay
+// ones and names Sidecar ones after the feature
+static const char *streamed_display_type(uint32_t display_id) {
+    static CoreDisplayCreateInfoDictionaryFn create_info;
+    static dispatch_once_t once;
+    dispatch_once(&once, ^{
+        void *handle =
+            dlopen("/System/Library/Frameworks/CoreDisplay.framework/CoreDisplay", RTLD_LAZY);
+        if (handle) {
+            create_info = dlsym(handle, "CoreDisplay_DisplayCreateInfoDictionary");
+        }
+    });
+    if (!create_info) {
+        return NULL;
+    }
+    CFDictionaryRef info = create_info(display_id);
+    if (!info) {
+        return NULL;
+    }
+
+    const char *type = NULL;
+    bool sidecar = false;
+    CFTypeRef names = CFDictionaryGetValue(info, CFSTR(kDisplayProductName));
+    if (names && CFGetTypeID(names) == CFDictionaryGetTypeID()) {
+        CFDictionaryApplyFunction(names, product_name_contains, &sidecar);
+    }
+    CFTypeRef airplay = CFDictionaryGetValue(info, CFSTR("kCGDisplayIsAirPlay"));
+    if (sidecar) {
+        type = "Sidecar display";
+    } else if (airplay && CFGetTypeID(airplay) == CFBooleanGetTypeID() &&
+               CFBooleanGetValue(airplay)) {
+        type = "AirPlay display";
+    }
+    CFRelease(info);
+    return type;
+}
+
+char *ds_get_display_type(uint32_t display_id) {
+    const char *type_str;
+
+    if (CGDisplayIsBuiltin(display_id)) {
+        type_str = "MacBook built in screen";
+    } else if ((type_str = streamed_display_type(display_id)) == NULL) {
+        type_str = "External display";
+    }
+
+    return strdup(type_str);
+}
+
+int ds_get_brightness(uint32_t display_id, float *brightness) {
+    ds_init();
+    if (!brightness) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+    if (!ds_get_brightness_fn) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED, "DisplayServicesGetBrightness is not available");
+    }
+
+    // Non-zero for displays DisplayServices cannot control (most external monitors)
+    int result = ds_get_brightness_fn(display_id, brightness);
+    if (result != 0) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED, "DisplayServicesGetBrightness returned %d", result);
+    }
+    return 0;
+}
+
+int ds_set_brightness(uint32_t display_id, float brightness) {
+    ds_init();
+    if (brightness < 0.0f || brightness > 1.0f) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+    if (!ds_set_brightness_fn) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED, "DisplayServicesSetBrightness is not available");
+    }
+
+    int result = ds_set_brightness_fn(display_id, brightness);
+    if (result != 0) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED, "DisplayServicesSetBrightness returned %d", result);
+    }
+    return 0;
+}
+
+// CGSConfigureDisplayEnabled is in CoreGraphics (SkyLight) but not in its
+// public headers
+typedef CGError (*CGSConfigureDisplayEnabled_t)(CGDisplayConfigRef config, uint32_t displayID,
+                                                bool enabled);
+static CGSConfigureDisplayEnabled_t cgs_configure_enabled = NULL;
+
+static void cgs_enabled_init(void) {
+    static dispatch_once_t once;
+    dispatch_once(&once, ^{
+        cgs_configure_enabled = dlsym(RTLD_DEFAULT, "CGSConfigureDisplayEnabled");
+    });
+}
+
+static CGError configure_one(CGDisplayConfigRef config, uint32_t display_id,
+                             const DisplayConfigureOptions *options) {
+    CGError error;
+
+    // macOS has no public call that rotates a display, so anything but the
+    // current rotation is refused instead of left undone
+    if (options->has_rotation &&
+        options->rotation != (uint32_t)CGDisplayRotation(display_id)) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED, "rotating display %u to %u degrees is not supported",
+                       display_id, options->rotation);
+    }
+
+    // Set origin (position)
+    if (options->has_origin) {
+        error = CGConfigureDisplayOrigin(config, display_id, options->x, options->y);
+        if (error != kCGErrorSuccess) {
+            return ds_fail(error, "CGConfigureDisplayOrigin rejected (%d,%d) for display %u",
+                           options->x, options->y, display_id);
+        }
+    }
+
+    // Set or clear mirroring (kCGNullDirectDisplay stops mirroring)
+    if (options->has_mirror) {
+        error = CGConfigureDisplayMirrorOfDisplay(config, display_id, options->mirror_display_id);
+        if (error != kCGErrorSuccess) {
+            return ds_fail(error, "CGConfigureDisplayMirrorOfDisplay rejected mirroring display "
+                                  "%u onto display %u", display_id, options->mirror_display_id);
+        }
+    }
+
+    // Enable/disable display through the private CGSConfigureDisplayEnabled,
+    // which takes the display out of the arrangement without a lid closed
+    if (options->has_enabled) {
+        cgs_enabled_init();
+        if (!cgs_configure_enabled) {
+            return ds_fail(DS_ERROR_NOT_SUPPORTED, "CGSConfigureDisplayEnabled is not available");
+        }
+        error = cgs_configure_enabled(config, display_id, options->enabled);
+        if (error != kCGErrorSuccess) {
+            return ds_fail(error, "CGSConfigureDisplayEnabled rejected %s display %u",
+                           options->enabled ? "enabling" : "disabling", display_id);
+        }
+    }
+
+    return kCGErrorSuccess;
+}
+
+int ds_configure_display(uint32_t display_id, const DisplayConfigureOptions *options) {
+    return ds_configure_displays(&display_id, options, 1);
+}
+
+int ds_configure_displays(const uint32_t *display_ids, const DisplayConfigureOptions *options,
+                          size_t count) {
+    if (!display_ids || !options || count == 0) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+
+    CGDisplayConfigRef config;
+    CGError error = CGBeginDisplayConfiguration(&config);
+
+    if (error != kCGErrorSuccess) {
+        return ds_fail(error, "CGBeginDisplayConfiguration failed");
+    }
+
+    for (size_t i = 0; i < count; i++) {
+        error = configure_one(config, display_ids[i], &options[i]);
+        if (error != kCGErrorSuccess) {
+            CGCancelDisplayConfiguration(config);
+            return error;
+        }
+    }
+
+    error = CGCompleteDisplayConfiguration(config, kCGConfigureForSession);
+    if (error != kCGErrorSuccess) {
+        return ds_fail(error, "CGCompleteDisplayConfiguration failed for %zu display(s)", count);
+    }
+    return 0;
+}
+
+void ds_free_string(char *str) {
+    if (str) {
+        free(str);
+    }
+}
+
+char *ds_get_hardware_uuid(void) {
+    io_service_t platform = IOServiceGetMatchingService(kIOMasterPortDefault,
+                                                        IOServiceMatching("IOPlatformExpertDevice"));
+    if (!platform) {
+        return NULL;
+    }
+
+    CFStringRef uuid_string = IORegistryEntryCreateCFProperty(platform, CFSTR(kIOPlatformUUIDKey),
+                                                              kCFAllocatorDefault, 0);
+    IOObjectRelease(platform);
+    if (!uuid_string) {
+        return NULL;
+    }
+
+    char *buffer = malloc(64);
+    if (buffer && !CFStringGetCString(uuid_string, buffer, 64, kCFStringEncodingUTF8)) {
+        free(buffer);
+        buffer = NULL;
+    }
+    CFRelease(uuid_string);
+    return buffer;
+}
+
+typedef struct {
+    UsbDeviceCallback callback;
+    void *context;
+    bool added;
+} UsbWatch;
+
+static uint16_t usb_property(io_service_t device, CFStringRef key) {
+    int value = 0;
+    CFTypeRef number = IORegistryEntryCreateCFProperty(device, key, kCFAllocatorDefault, 0);
+    if (number) {
+        if (CFGetTypeID(number) == CFNumberGetTypeID()) {
+            CFNumberGetValue(number, kCFNumberIntType, &value);
+        }
+        CFRelease(number);
+    }
+    return (uint16_t)value;
+}
+
+// Report every device in the iterator; draining also re-arms the notification
+static void usb_devices_changed(void *refcon, io_iterator_t iterator) {
+    UsbWatch *watch = refcon;
+    io_service_t device;
+    while ((device = IOIteratorNext(iterator))) {
+        uint16_t vendor_id = usb_property(device, CFSTR("idVendor"));
+        uint16_t product_id = usb_property(device, CFSTR("idProduct"));
+        watch->callback(vendor_id, product_id, watch->added, watch->context);
+        IOObjectRelease(device);
+    }
+}
+
+int ds_watch_usb_devices(UsbDeviceCallback callback, void *context) {
+    IONotificationPortRef port = IONotificationPortCreate(kIOMasterPortDefault);
+    if (!port) {
+        return -1;
+    }
+    CFRunLoopAddSource(CFRunLoopGetCurrent(), IONotificationPortGetRunLoopSource(port),
+                       kCFRunLoopDefaultMode);
+
+    const char *notifications[2] = {kIOFirstMatchNotification, kIOTerminatedNotification};
+    for (int i = 0; i < 2; i++) {
+        UsbWatch *watch = malloc(sizeof(UsbWatch));
+        if (!watch) {
+            return -1;
+        }
+        watch->callback = callback;
+        watch->context = context;
+        watch->added = (i == 0);
+
+        // The matching dictionary is consumed by IOServiceAddMatchingNotification
+        io_iterator_t iterator;
+        kern_return_t result = IOServiceAddMatchingNotification(
+            port, notifications[i], IOServiceMatching("IOUSBHostDevice"), usb_devices_changed,
+            watch, &iterator);
+        if (result != KERN_SUCCESS) {
+            free(watch);
+            return result;
+        }
+        usb_devices_changed(watch, iterator);
+    }
+
+    return 0;
+}
+
+int ds_on_battery_power(void) {
+    CFTypeRef info = IOPSCopyPowerSourcesInfo();
+    if (!info) {
+        return -1;
+    }
+
+    int result = -1;
+    CFStringRef type = IOPSGetProvidingPowerSourceType(info);
+    if (type) {
+        result = CFEqual(type, CFSTR(kIOPSBatteryPowerValue)) ? 1 : 0;
+    }
+    CFRelease(info);
+    return result;
+}
+
+typedef struct {
+    PowerSourceCallback callback;
+    void *context;
+} PowerWatch;
+
+// Also fires for battery level updates, so callers filter for real transitions
+static void power_source_changed(void *context) {
+    PowerWatch *watch = context;
+    int on_battery = ds_on_battery_power();
+    if (on_battery >= 0) {
+        watch->callback(on_battery == 1, watch->context);
+    }
+}
+
+int ds_watch_power_source(PowerSourceCallback callback, void *context) {
+    PowerWatch *watch = malloc(sizeof(PowerWatch));
+    if (!watch) {
+        return -1;
+    }
+    watch->callback = callback;
+    watch->context = context;
+
+    CFRunLoopSourceRef source = IOPSNotificationCreateRunLoopSource(power_source_changed, watch);
+    if (!source) {
+        free(watch);
+        return -1;
+    }
+    CFRunLoopAddSource(CFRunLoopGetCurrent(), source, kCFRunLoopDefaultMode);
+    CFRelease(source);
+    return 0;
+}
+
+int ds_declare_user_activity(void) {
+    IOPMAssertionID assertion;
+    IOReturn result = IOPMAssertionDeclareUserActivity(CFSTR("macdisp applying a configuration"),
+                                                       kIOPMUserActiveLocal, &assertion);
+    if (result != kIOReturnSuccess) {
+        return ds_fail_io(result, "IOPMAssertionDeclareUserActivity");
+    }
+    // The activity itself wakes the displays and restarts their idle timer
+    IOPMAssertionRelease(assertion);
+    return 0;
+}
+
+// CGDisplayIsAsleep lags the wrangler's notifications by a moment
+#define DISPLAY_POWER_SETTLE_SECONDS 1.0
+
+struct DisplayPowerWatch {
+    DisplayPowerCallback callback;
+    void *context;
+    IONotificationPortRef port;
+    io_object_t notification;
+    CFRunLoopTimerRef pending;
+};
+
+static void display_power_settled(CFRunLoopTimerRef timer, void *info) {
+    DisplayPowerWatch *watch = info;
+    watch->pending = NULL;
+    watch->callback(watch->context);
+}
+
+static void display_wrangler_message(void *refcon, io_service_t service, natural_t type,
+                                     void *argument) {
+    DisplayPowerWatch *watch = refcon;
+    if ((type != kIOMessageDeviceWillPowerOff && type != kIOMessageDeviceHasPoweredOn) ||
+        watch->pending) {
+        return;
+    }
+    CFRunLoopTimerContext context = {0, watch, NULL, NULL, NULL};
+    watch->pending =
+        CFRunLoopTimerCreate(kCFAllocatorDefault,
+                             CFAbsoluteTimeGetCurrent() + DISPLAY_POWER_SETTLE_SECONDS, 0, 0, 0,
+                             display_power_settled, &context);
+    CFRunLoopAddTimer(CFRunLoopGetCurrent(), watch->pending, kCFRunLoopDefaultMode);
+    CFRelease(watch->pending);
+}
+
+int ds_watch_display_power(DisplayPowerCallback callback, void *context,
+                           DisplayPowerWatch **watch_out) {
+    io_service_t wrangler =
+        IOServiceGetMatchingService(kIOMasterPortDefault, IOServiceMatching("IODisplayWrangler"));
+    if (!wrangler) {
+        return DS_ERROR_NOT_SUPPORTED;
+    }
+
+    DisplayPowerWatch *watch = calloc(1, sizeof(DisplayPowerWatch));
+    if (!watch) {
+        IOObjectRelease(wrangler);
+        return -1;
+    }
+    watch->callback = callback;
+    watch->context = context;
+    watch->port = IONotificationPortCreate(kIOMasterPortDefault);
+    if (!watch->port) {
+        free(watch);
+        IOObjectRelease(wrangler);
+        return -1;
+    }
+
+    kern_return_t result =
+        IOServiceAddInterestNotification(watch->port, wrangler, kIOGeneralInterest,
+                                         display_wrangler_message, watch, &watch->notification);
+    IOObjectRelease(wrangler);
+    if (result != KERN_SUCCESS) {
+        IONotificationPortDestroy(watch->port);
+        free(watch);
+        return result;
+    }
+    CFRunLoopAddSource(CFRunLoopGetCurrent(), IONotificationPortGetRunLoopSource(watch->port),
+                       kCFRunLoopDefaultMode);
+    *watch_out = watch;
+    return 0;
+}
+
+void ds_unwatch_display_power(DisplayPowerWatch *watch) {
+    if (watch->pending) {
+        CFRunLoopTimerInvalidate(watch->pending);
+    }
+    CFRunLoopRemoveSource(CFRunLoopGetCurrent(), IONotificationPortGetRunLoopSource(watch->port),
+                          kCFRunLoopDefaultMode);
+    IOObjectRelease(watch->notification);
+    IONotificationPortDestroy(watch->port);
+    free(watch);
+}
+
+// Executor thread: owns a CFRunLoop so notification sources registered on it
+// keep firing no matter which thread the caller uses
+static CFRunLoopRef executor_run_loop = NULL;
+static dispatch_once_t executor_once;
+
+static void executor_keepalive(CFRunLoopTimerRef timer, void *info) {}
+
+static void *executor_main(void *ready) {
+    executor_run_loop = (CFRunLoopRef)CFRetain(CFRunLoopGetCurrent());
+
+    // CFRunLoopRun returns immediately when the loop has no sources
+    CFRunLoopTimerRef keepalive = CFRunLoopTimerCreate(
+        kCFAllocatorDefault, CFAbsoluteTimeGetCurrent() + 1e10, 1e10, 0, 0, executor_keepalive, NULL);
+    CFRunLoopAddTimer(executor_run_loop, keepalive, kCFRunLoopDefaultMode);
+    CFRelease(keepalive);
+
+    dispatch_semaphore_signal((__bridge dispatch_semaphore_t)ready);
+    CFRunLoopRun();
+    return NULL;
+}
+
+void ds_run_on_executor(void (*work)(void *), void *context) {
+    dispatch_once(&executor_once, ^{
+        dispatch_semaphore_t ready = dispatch_semaphore_create(0);
+        pthread_t thread;
+        pthread_create(&thread, NULL, executor_main, (__bridge void *)ready);
+        pthread_detach(thread);
+        dispatch_semaphore_wait(ready, DISPATCH_TIME_FOREVER);
+    });
+
+    if (CFRunLoopGetCurrent() == executor_run_loop) {
+        work(context);
+        return;
+    }
+
+    dispatch_semaphore_t done = dispatch_semaphore_create(0);
+    CFRunLoopPerformBlock(executor_run_loop, kCFRunLoopDefaultMode, ^{
+        work(context);
+        dispatch_semaphore_signal(done);
+    });
+    CFRunLoopWakeUp(executor_run_loop);
+    dispatch_semaphore_wait(done, DISPATCH_TIME_FOREVER);
+}
+
+// DDC/CI over the DCP I2C service of external displays (Apple silicon).
+// IOAVService is private; the functions are looked up at runtime
+typedef CFTypeRef (*IOAVServiceCreateWithService_t)(CFAllocatorRef allocator, io_service_t service);
+typedef IOReturn (*IOAVServiceReadI2C_t)(CFTypeRef service, uint32_t chip_address, uint32_t offset,
+                                         void *buffer, uint32_t size);
+typedef IOReturn (*IOAVServiceWriteI2C_t)(CFTypeRef service, uint32_t chip_address,
+                                          uint32_t data_address, void *buffer, uint32_t size);
+
+#define DDC_CHIP_ADDRESS 0x37
+
+static IOAVServiceCreateWithService_t av_create = NULL;
+static IOAVServiceReadI2C_t av_read_i2c = NULL;
+static IOAVServiceWriteI2C_t av_write_i2c = NULL;
+static dispatch_once_t av_once;
+
+static bool number_matches(CFDictionaryRef dict, CFStringRef key, uint32_t expected) {
+    CFNumberRef number = dict ? CFDictionaryGetValue(dict, key) : NULL;
+    uint32_t value = 0;
+    if (!number || CFGetTypeID(number) != CFNumberGetTypeID() ||
+        !CFNumberGetValue(number, kCFNumberSInt32Type, &value)) {
+        // Missing attributes do not rule a framebuffer out
+        return true;
+    }
+    return value == expected;
+}
+
+// Whether a framebuffer entry drives the display with this vendor, model and serial
+static bool framebuffer_matches(io_registry_entry_t entry, uint32_t display_id) {
+    CFDictionaryRef attributes = IORegistryEntryCreateCFProperty(
+        entry, CFSTR("DisplayAttributes"), kCFAllocatorDefault, 0);
+    if (!attributes) {
+        return false;
+    }
+
+    CFDictionaryRef product = CFDictionaryGetValue(attributes, CFSTR("ProductAttributes"));
+    bool matches = product && CFGetTypeID(product) == CFDictionaryGetTypeID() &&
+                   number_matches(product, CFSTR("LegacyManufacturerID"),
+                                  CGDisplayVendorNumber(display_id)) &&
+                   number_matches(product, CFSTR("ProductID"), CGDisplayModelNumber(display_id));
+    uint32_t serial = CGDisplaySerialNumber(display_id);
+    if (matches && serial != 0) {
+        matches = number_matches(product, CFSTR("SerialNumber"), serial);
+    }
+
+    CFRelease(attributes);
+    return matches;
+}
+
+static bool is_external_av_service(io_service_t service) {
+    CFStringRef location =
+        IORegistryEntryCreateCFProperty(service, CFSTR("Location"), kCFAllocatorDefault, 0);
+    bool external = location && CFGetTypeID(location) == CFStringGetTypeID() &&
+                    CFStringCompare(location, CFSTR("External"), 0) == kCFCompareEqualTo;
+    if (location) {
+        CFRelease(location);
+    }
+    return external;
+}
+
+// The I2C service of an external display: the first external DCPAVServiceProxy
+// after its framebuffer in the service plane
+static io_service_t find_av_service(uint32_t display_id) {
+    io_registry_entry_t root = IORegistryGetRootEntry(kIOMasterPortDefault);
+    io_iterator_t iterator;
+    if (IORegistryEntryCreateIterator(root, kIOServicePlane, kIORegistryIterateRecursively,
+                                      &iterator) != KERN_SUCCESS) {
+        IOObjectRelease(root);
+        return 0;
+    }
+
+    io_service_t found = 0;
+    bool in_matching_framebuffer = false;
+    io_registry_entry_t entry;
+    while (!found && (entry = IOIteratorNext(iterator))) {
+        io_name_t name;
+        if (IORegistryEntryGetName(entry, name) == KERN_SUCCESS) {
+            if (strcmp(name, "AppleCLCD2") == 0 || strcmp(name, "IOMobileFramebufferShim") == 0) {
+                in_matching_framebuffer = framebuffer_matches(entry, display_id);
+            } else if (in_matching_framebuffer && strcmp(name, "DCPAVServiceProxy") == 0 &&
+                       is_external_av_service(entry)) {
+                found = entry;
+                IOObjectRetain(found);
+            }
+        }
+        IOObjectRelease(entry);
+    }
+
+    IOObjectRelease(iterator);
+    IOObjectRelease(root);
+    return found;
+}
+
+// Intel Macs and older macOS versions have no IOAVService
+static bool av_load(void) {
+    dispatch_once(&av_once, ^{
+        av_create = dlsym(RTLD_DEFAULT, "IOAVServiceCreateWithService");
+        av_read_i2c = dlsym(RTLD_DEFAULT, "IOAVServiceReadI2C");
+        av_write_i2c = dlsym(RTLD_DEFAULT, "IOAVServiceWriteI2C");
+    });
+    return av_create && av_read_i2c && av_write_i2c;
+}
+
+int ds_ddc_transact(uint32_t display_id, const uint8_t *request, size_t request_len,
+                    uint8_t *reply, size_t reply_len, uint32_t delay_us) {
+    if (!request || request_len < 2 || (reply_len > 0 && !reply)) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+    if (!av_load()) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED, "IOAVService is not available on this Mac");
+    }
+
+    io_service_t service = find_av_service(display_id);
+    if (!service) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED, "no DCPAVServiceProxy for display %u", display_id);
+    }
+    CFTypeRef av_service = av_create(kCFAllocatorDefault, service);
+    IOObjectRelease(service);
+    if (!av_service) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED, "IOAVServiceCreateWithService failed");
+    }
+
+    // The host address byte goes out as the I2C data address
+    IOReturn result = av_write_i2c(av_service, DDC_CHIP_ADDRESS, request[0],
+                                   (void *)(request + 1), (uint32_t)(request_len - 1));
+    if (result != kIOReturnSuccess) {
+        ds_fail_io(result, "IOAVServiceWriteI2C");
+    } else if (reply_len > 0) {
+        // Monitors need time to prepare a reply before it can be read
+        usleep(delay_us);
+        result = av_read_i2c(av_service, DDC_CHIP_ADDRESS, request[0], reply, (uint32_t)reply_len);
+        if (result != kIOReturnSuccess) {
+            ds_fail_io(result, "IOAVServiceReadI2C");
+        }
+    }
+
+    CFRelease(av_service);
+    return result;
+}
+
+// Reference modes live in the private MonitorPanel framework that System
+// Settings uses. Its classes are looked up at runtime and every property read
+// through KVC, so a macOS release that renames them reports NOT_SUPPORTED
+// instead of crashing.
+static Class mp_manager_class(void) {
+    static Class manager_class = Nil;
+    static dispatch_once_t once;
+    dispatch_once(&once, ^{
+        if (dlopen("/System/Library/PrivateFrameworks/MonitorPanel.framework/MonitorPanel",
+                   RTLD_LAZY)) {
+            manager_class = NSClassFromString(@"MPDisplayMgr");
+        }
+    });
+    return manager_class;
+}
+
+static id mp_display(uint32_t display_id) {
+    Class manager_class = mp_manager_class();
+    if (!manager_class) {
+        return nil;
+    }
+    id manager = [[manager_class alloc] init];
+    SEL display_with_id = NSSelectorFromString(@"displayWithID:");
+    if (![manager respondsToSelector:display_with_id]) {
+        return nil;
+    }
+    id (*send)(id, SEL, int) = (id (*)(id, SEL, int))objc_msgSend;
+    return send(manager, display_with_id, (int)display_id);
+}
+
+// The valid presets of a display, or nil without reference modes
+static NSArray *mp_presets(id display) {
+    if (!display || ![display respondsToSelector:NSSelectorFromString(@"presets")]) {
+        return nil;
+    }
+    NSMutableArray *valid = [NSMutableArray array];
+    for (id preset in [display valueForKey:@"presets"]) {
+        if ([[preset valueForKey:@"isValid"] boolValue]) {
+            [valid addObject:preset];
+        }
+    }
+    return valid.count > 0 ? valid : nil;
+}
+
+int ds_get_reference_presets(uint32_t display_id, ReferencePreset *presets, size_t capacity,
+                             size_t *count) {
+    if (!count || (capacity > 0 && !presets)) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+    @autoreleasepool {
+        @try {
+            id display = mp_display(display_id);
+            NSArray *all = mp_presets(display);
+            if (!all) {
+                return ds_fail(DS_ERROR_NOT_SUPPORTED, "MonitorPanel lists no presets for display %u",
+                               display_id);
+            }
+            id active = [display valueForKey:@"activePreset"];
+
+            *count = all.count;
+            for (size_t i = 0; i < all.count && i < capacity; i++) {
+                id preset = all[i];
+                ReferencePreset *out = &presets[i];
+                memset(out, 0, sizeof(*out));
+                out->index = [[preset valueForKey:@"presetIndex"] intValue];
+                out->active = active && [[active valueForKey:@"presetIndex"] intValue] == out->index;
+                NSString *name = [preset valueForKey:@"presetName"];
+                if ([name isKindOfClass:[NSString class]]) {
+                    strlcpy(out->name, name.UTF8String, sizeof(out->name));
+                }
+            }
+            return 0;
+        } @catch (NSException *exception) {
+            return ds_fail(DS_ERROR_NOT_SUPPORTED, "MonitorPanel raised %s: %s",
+                           exception.name.UTF8String, exception.reason.UTF8String ?: "");
+        }
+    }
+}
+
+int ds_set_reference_preset(uint32_t display_id, int32_t index) {
+    @autoreleasepool {
+        @try {
+            id display = mp_display(display_id);
+            NSArray *all = mp_presets(display);
+            SEL set_active = NSSelectorFromString(@"setActivePreset:");
+            if (!all || ![display respondsToSelector:set_active]) {
+                return ds_fail(DS_ERROR_NOT_SUPPORTED,
+                               "MonitorPanel cannot switch presets on display %u", display_id);
+            }
+            for (id preset in all) {
+                if ([[preset valueForKey:@"presetIndex"] intValue] == index) {
+                    void (*send)(id, SEL, id) = (void (*)(id, SEL, id))objc_msgSend;
+                    send(display, set_active, preset);
+                    return 0;
+                }
+            }
+            return ds_fail(DS_ERROR_MODE_NOT_FOUND, "no preset with index %d among %lu",
+                           index, (unsigned long)all.count);
+        } @catch (NSException *exception) {
+            return ds_fail(DS_ERROR_NOT_SUPPORTED, "MonitorPanel raised %s: %s",
+                           exception.name.UTF8String, exception.reason.UTF8String ?: "");
+        }
+    }
+}
+
+#define EDID_CHIP_ADDRESS 0x50
+#define EDID_BLOCK_SIZE 128
+
+// The EDID the framebuffer read from the display (Intel Macs)
+static CFDataRef copy_framebuffer_edid(uint32_t display_id) {
+#pragma clang diagnostic push
+#pragma clang diagnostic ignored "-Wdeprecated-declarations"
+    io_service_t port = CGDisplayIOServicePort(display_id);
+#pragma clang diagnostic pop
+    if (!port) {
+        return NULL;
+    }
+    CFDictionaryRef info = IODisplayCreateInfoDictionary(port, kIODisplayOnlyPreferredName);
+    if (!info) {
+        return NULL;
+    }
+    CFDataRef edid = CFDictionaryGetValue(info, CFSTR(kIODisplayEDIDKey));
+    if (edid && CFGetTypeID(edid) == CFDataGetTypeID()) {
+        CFRetain(edid);
+    } else {
+        edid = NULL;
+    }
+    CFRelease(info);
+    return edid;
+}
+
+// Read the EDID of a display over I2C (Apple silicon)
+static int read_av_edid(CFTypeRef av_service, uint8_t *buffer, size_t capacity, size_t *length) {
+    // The base block says how many extension blocks follow. Blocks past the
+    // first 256 bytes need E-DDC segment addressing and are left out
+    uint8_t block[EDID_BLOCK_SIZE] = {0};
+    IOReturn result = av_read_i2c(av_service, EDID_CHIP_ADDRESS, 0, block, EDID_BLOCK_SIZE);
+    size_t total = (block[126] > 0 ? 2 : 1) * EDID_BLOCK_SIZE;
+    for (size_t offset = 0; result == kIOReturnSuccess && offset < total;
+         offset += EDID_BLOCK_SIZE) {
+        if (offset > 0) {
+            result = av_read_i2c(av_service, EDID_CHIP_ADDRESS, (uint32_t)offset, block,
+                                 EDID_BLOCK_SIZE);
+        }
+        if (result == kIOReturnSuccess && offset < capacity) {
+            size_t n = capacity - offset < EDID_BLOCK_SIZE ? capacity - offset : EDID_BLOCK_SIZE;
+            memcpy(buffer + offset, block, n);
+        }
+    }
+
+    if (result != kIOReturnSuccess) {
+        return ds_fail_io(result, "IOAVServiceReadI2C");
+    }
+    *length = total;
+    return 0;
+}
+
+// Read the EDID straight from the display over I2C (Apple silicon)
+static int read_i2c_edid(uint32_t display_id, uint8_t *buffer, size_t capacity, size_t *length) {
+    if (!av_load()) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED,
+                       "the framebuffer has no EDID and IOAVService is not available");
+    }
+    io_service_t service = find_av_service(display_id);
+    if (!service) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED,
+                       "no framebuffer EDID and no DCPAVServiceProxy for display %u", display_id);
+    }
+    CFTypeRef av_service = av_create(kCFAllocatorDefault, service);
+    IOObjectRelease(service);
+    if (!av_service) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED, "IOAVServiceCreateWithService failed");
+    }
+
+    int result = read_av_edid(av_service, buffer, capacity, length);
+    CFRelease(av_service);
+    return result;
+}
+
+static void copy_edid_data(CFDataRef edid, uint8_t *buffer, size_t capacity, size_t *length) {
+    size_t edid_length = (size_t)CFDataGetLength(edid);
+    CFDataGetBytes(edid, CFRangeMake(0, edid_length < capacity ? edid_length : capacity), buffer);
+    *length = edid_length;
+}
+
+int ds_read_edid(uint32_t display_id, uint8_t *buffer, size_t capacity, size_t *length) {
+    if (!buffer || !length) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+
+    CFDataRef edid = copy_framebuffer_edid(display_id);
+    if (!edid) {
+        return read_i2c_edid(display_id, buffer, capacity, length);
+    }
+    copy_edid_data(edid, buffer, capacity, length);
+    CFRelease(edid);
+    return 0;
+}
+
+int ds_read_iokit_edid(size_t index, uint8_t *buffer, size_t capacity, size_t *length) {
+    if (!buffer || !length) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+
+    size_t seen = 0;
+    io_iterator_t iterator;
+    io_service_t service;
+    if (IOServiceGetMatchingServices(kIOMasterPortDefault, IOServiceMatching("IODisplayConnect"),
+                                     &iterator) == KERN_SUCCESS) {
+        bool found = false;
+        while (!found && (service = IOIteratorNext(iterator))) {
+            CFDictionaryRef info =
+                IODisplayCreateInfoDictionary(service, kIODisplayOnlyPreferredName);
+            IOObjectRelease(service);
+            if (!info) {
+                continue;
+            }
+            CFDataRef edid = CFDictionaryGetValue(info, CFSTR(kIODisplayEDIDKey));
+            if (edid && CFGetTypeID(edid) == CFDataGetTypeID() && seen++ == index) {
+                copy_edid_data(edid, buffer, capacity, length);
+                found = true;
+            }
+            CFRelease(info);
+        }
+        IOObjectRelease(iterator);
+        if (found) {
+            return 0;
+        }
+    }
+
+    if (!av_load() ||
+        IOServiceGetMatchingServices(kIOMasterPortDefault, IOServiceMatching("DCPAVServiceProxy"),
+                                     &iterator) != KERN_SUCCESS) {
+        return DS_ERROR_DISPLAY_NOT_FOUND;
+    }
+    int result = DS_ERROR_DISPLAY_NOT_FOUND;
+    while (result == DS_ERROR_DISPLAY_NOT_FOUND && (service = IOIteratorNext(iterator))) {
+        if (is_external_av_service(service) && seen++ == index) {
+            CFTypeRef av_service = av_create(kCFAllocatorDefault, service);
+            result = av_service ? read_av_edid(av_service, buffer, capacity, length)
+                                : ds_fail(DS_ERROR_NOT_SUPPORTED,
+                                          "IOAVServiceCreateWithService failed");
+            if (av_service) {
+                CFRelease(av_service);
+            }
+        }
+        IOObjectRelease(service);
+    }
+    IOObjectRelease(iterator);
+    return result;
+}
+
+int ds_session_state(void) {
+    CFDictionaryRef session = CGSessionCopyCurrentDictionary();
+    if (!session) {
+        return DS_SESSION_NONE;
+    }
+    CFBooleanRef on_console = CFDictionaryGetValue(session, kCGSessionOnConsoleKey);
+    int state = on_console && CFGetTypeID(on_console) == CFBooleanGetTypeID() &&
+                        CFBooleanGetValue(on_console)
+                    ? DS_SESSION_CONSOLE
+                    : DS_SESSION_BACKGROUND;
+    CFRelease(session);
+    return state;
+}
+
+typedef struct {
+    ConsoleUserCallback callback;
+    void *context;
+} ConsoleUserWatch;
+
+static void console_user_changed(SCDynamicStoreRef store, CFArrayRef changed_keys, void *info) {
+    ConsoleUserWatch *watch = info;
+    watch->callback(watch->context);
+}
+
+int ds_watch_console_user(ConsoleUserCallback callback, void *context) {
+    ConsoleUserWatch *watch = malloc(sizeof(ConsoleUserWatch));
+    if (!watch) {
+        return -1;
+    }
+    watch->callback = callback;
+    watch->context = context;
+
+    SCDynamicStoreContext store_context = {0, watch, NULL, NULL, NULL};
+    SCDynamicStoreRef store = SCDynamicStoreCreate(NULL, CFSTR("macdisp"), console_user_changed,
+                                                   &store_context);
+    if (!store) {
+        free(watch);
+        return ds_fail(-1, "SCDynamicStoreCreate failed: %s", SCErrorString(SCError()));
+    }
+
+    CFStringRef key = SCDynamicStoreKeyCreateConsoleUser(NULL);
+    CFArrayRef keys = CFArrayCreate(NULL, (const void **)&key, 1, &kCFTypeArrayCallBacks);
+    Boolean watching = SCDynamicStoreSetNotificationKeys(store, keys, NULL);
+    CFRelease(keys);
+    CFRelease(key);
+    if (!watching) {
+        CFRelease(store);
+        free(watch);
+        return ds_fail(-1, "SCDynamicStoreSetNotificationKeys failed: %s",
+                       SCErrorString(SCError()));
+    }
+
+    CFRunLoopSourceRef source = SCDynamicStoreCreateRunLoopSource(NULL, store, 0);
+    CFRelease(store);
+    if (!source) {
+        free(watch);
+        return ds_fail(-1, "SCDynamicStoreCreateRunLoopSource failed");
+    }
+    CFRunLoopAddSource(CFRunLoopGetCurrent(), source, kCFRunLoopDefaultMode);
+    CFRelease(source);
+    return 0;
+}
+
+// How an external display is cabled on Apple silicon, from the "Transport"
+// property of its framebuffer: the downstream end is the display's own input,
+// which differs from the upstream end behind an adapter. Intel framebuffers do
+// not report it
+static CFStringRef copy_framebuffer_transport(uint32_t display_id) {
+    const char *names[] = {"AppleCLCD2", "IOMobileFramebufferShim"};
+    CFStringRef transport = NULL;
+    for (size_t i = 0; i < sizeof(names) / sizeof(names[0]) && !transport; i++) {
+        io_iterator_t iterator;
+        if (IOServiceGetMatchingServices(kIOMasterPortDefault, IOServiceNameMatching(names[i]),
+                                         &iterator) != KERN_SUCCESS) {
+            continue;
+        }
+        io_registry_entry_t entry;
+        while (!transport && (entry = IOIteratorNext(iterator))) {
+            if (framebuffer_matches(entry, display_id)) {
+                CFDictionaryRef property = IORegistryEntryCreateCFProperty(
+                    entry, CFSTR("Transport"), kCFAllocatorDefault, 0);
+                if (property && CFGetTypeID(property) == CFDictionaryGetTypeID()) {
+                    CFStringRef downstream = CFDictionaryGetValue(property, CFSTR("Downstream"));
+                    if (downstream && CFGetTypeID(downstream) == CFStringGetTypeID()) {
+                        transport = CFRetain(downstream);
+                    }
+                }
+                if (property) {
+                    CFRelease(property);
+                }
+            }
+            IOObjectRelease(entry);
+        }
+        IOObjectRelease(iterator);
+    }
+    return transport;
+}
+
+static void copy_detail(char *buffer, NSString *value) {
+    if (value) {
+        strlcpy(buffer, value.UTF8String, DS_DETAIL_LEN);
+    }
+}
+
+int ds_get_display_details(uint32_t display_id, DisplayDetails *details) {
+    if (!details) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+    memset(details, 0, sizeof(*details));
+
+    @autoreleasepool {
+        const char *streamed = NULL;
+        if (CGDisplayIsBuiltin(display_id)) {
+            copy_detail(details->connection, @"built-in");
+        } else if ((streamed = streamed_display_type(display_id)) != NULL) {
+            copy_detail(details->connection,
+                        strncmp(streamed, "Sidecar", 7) == 0 ? @"Sidecar" : @"AirPlay");
+        } else {
+            CFStringRef transport = copy_framebuffer_transport(display_id);
+            if (transport) {
+                NSString *name = (__bridge NSString *)transport;
+                copy_detail(details->connection,
+                            [name isEqualToString:@"DP"] ? @"DisplayPort" : name);
+                CFRelease(transport);
+            }
+        }
+
+        id<MTLDevice> device = CGDirectDisplayCopyCurrentMetalDevice(display_id);
+        copy_detail(details->gpu, device.name);
+
+        CGColorSpaceRef space = CGDisplayCopyColorSpace(display_id);
+        if (space) {
+            NSColorSpace *color_space = [[NSColorSpace alloc] initWithCGColorSpace:space];
+            copy_detail(details->color_space, color_space.localizedName);
+            CGColorSpaceRelease(space);
+        }
+    }
+
+    CGSize size = CGDisplayScreenSize(display_id);
+    details->width_mm = size.width;
+    details->height_mm = size.height;
+    return 0;
+}
+
+// AppKit caches the screen list and rebuilds it only when the app hears that
+// the screen parameters changed, which a command-line process that has just
+// rearranged the displays never does. Posting the notification itself makes
+// [NSScreen screens] describe the current arrangement.
+static void refresh_screens(void) {
+    [NSApplication sharedApplication];
+    [[NSNotificationCenter defaultCenter]
+        postNotificationName:NSApplicationDidChangeScreenParametersNotification
+                      object:NSApp];
+}
+
+// The screen showing a display; mirrored and disabled displays have none
+static NSScreen *screen_for_display(uint32_t display_id) {
+    refresh_screens();
+    for (NSScreen *screen in [NSScreen screens]) {
+        NSNumber *number = screen.deviceDescription[@"NSScreenNumber"];
+        if (number && number.unsignedIntValue == display_id) {
+            return screen;
+        }
+    }
+    return nil;
+}
+
+int ds_get_wallpaper(uint32_t display_id, char *buffer, size_t capacity) {
+    if (!buffer || capacity == 0) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+    @autoreleasepool {
+        NSScreen *screen = screen_for_display(display_id);
+        if (!screen) {
+            return ds_fail(DS_ERROR_DISPLAY_NOT_FOUND, "no NSScreen shows display %u", display_id);
+        }
+        NSURL *url = [[NSWorkspace sharedWorkspace] desktopImageURLForScreen:screen];
+        if (!url || !url.isFileURL) {
+            return ds_fail(DS_ERROR_NOT_SUPPORTED, "the desktop picture is not a file (%s)",
+                           url ? url.absoluteString.UTF8String : "none");
+        }
+        if (strlcpy(buffer, url.path.UTF8String, capacity) >= capacity) {
+            return ds_fail(DS_ERROR_NOT_SUPPORTED, "the desktop picture path is longer than %zu",
+                           capacity);
+        }
+    }
+    return 0;
+}
+
+int ds_set_wallpaper(uint32_t display_id, const char *path) {
+    if (!path) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+    @autoreleasepool {
+        NSScreen *screen = screen_for_display(display_id);
+        if (!screen) {
+            return ds_fail(DS_ERROR_DISPLAY_NOT_FOUND, "no NSScreen shows display %u", display_id);
+        }
+        NSURL *url = [NSURL fileURLWithPath:[NSString stringWithUTF8String:path]];
+        NSWorkspace *workspace = [NSWorkspace sharedWorkspace];
+        // Keep the scaling and fill color the user chose for this screen
+        NSDictionary *options = [workspace desktopImageOptionsForScreen:screen];
+        NSError *error = nil;
+        if (![workspace setDesktopImageURL:url forScreen:screen options:options ?: @{}
+                                     error:&error]) {
+            return ds_fail(DS_ERROR_NOT_SUPPORTED, "setDesktopImageURL failed: %s (%s %ld)",
+                           error.localizedDescription.UTF8String ?: "no description",
+                           error.domain.UTF8String ?: "", (long)error.code);
+        }
+    }
+    return 0;
+}
+
+// NX_SYSDEFINED events carry the media and brightness keys of Apple keyboards
+#define SYSDEFINED_EVENT 14
+#define AUX_CONTROL_BUTTONS_SUBTYPE 8
+#define KEYTYPE_BRIGHTNESS_UP 2
+#define KEYTYPE_BRIGHTNESS_DOWN 3
+#define KEY_STATE_DOWN 0x0A
+
+typedef struct {
+    BrightnessKeyCallback callback;
+    void *context;
+    CFMachPortRef tap;
+    // Key ups of presses handled here are swallowed too
+    bool held[2];
+} BrightnessKeyTap;
+
+static CGEventRef brightness_key_event(CGEventTapProxy proxy, CGEventType type, CGEventRef event,
+                                       void *info) {
+    BrightnessKeyTap *tap = info;
+    // macOS turns off taps that are slow or while secure input is on
+    if (type == kCGEventTapDisabledByTimeout || type == kCGEventTapDisabledByUserInput) {
+        CGEventTapEnable(tap->tap, true);
+        return event;
+    }
+    if (type != SYSDEFINED_EVENT) {
+        return event;
+    }
+
+    @autoreleasepool {
+        NSEvent *ns_event = [NSEvent eventWithCGEvent:event];
+        if (!ns_event || ns_event.subtype != AUX_CONTROL_BUTTONS_SUBTYPE) {
+            return event;
+        }
+        long data = ns_event.data1;
+        int key = (int)((data & 0xFFFF0000) >> 16);
+        if (key != KEYTYPE_BRIGHTNESS_UP && key != KEYTYPE_BRIGHTNESS_DOWN) {
+            return event;
+        }
+        bool up = key == KEYTYPE_BRIGHTNESS_UP;
+        bool *held = &tap->held[up ? 0 : 1];
+        if (((data & 0xFF00) >> 8) != KEY_STATE_DOWN) {
+            bool swallow = *held;
+            *held = false;
+            return swallow ? NULL : event;
+        }
+
+        CGDirectDisplayID display_id = 0;
+        uint32_t count = 0;
+        if (CGGetDisplaysWithPoint(CGEventGetLocation(event), 1, &display_id, &count) !=
+                kCGErrorSuccess ||
+            count == 0) {
+            return event;
+        }
+        *held = tap->callback(up, display_id, tap->context);
+        return *held ? NULL : event;
+    }
+}
+
+int ds_tap_brightness_keys(BrightnessKeyCallback callback, void *context) {
+    BrightnessKeyTap *tap = calloc(1, sizeof(BrightnessKeyTap));
+    if (!tap) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+    tap->callback = callback;
+    tap->context = context;
+
+    tap->tap = CGEventTapCreate(kCGSessionEventTap, kCGHeadInsertEventTap,
+                                kCGEventTapOptionDefault, CGEventMaskBit(SYSDEFINED_EVENT),
+                                brightness_key_event, tap);
+    if (!tap->tap) {
+        free(tap);
+        return ds_fail(DS_ERROR_NOT_SUPPORTED,
+                       "CGEventTapCreate failed; allow macdisp under Accessibility in "
+                       "Privacy & Security");
+    }
+    CFRunLoopSourceRef source = CFMachPortCreateRunLoopSource(kCFAllocatorDefault, tap->tap, 0);
+    CFRunLoopAddSource(CFRunLoopGetCurrent(), source, kCFRunLoopCommonModes);
+    CFRelease(source);
+    CGEventTapEnable(tap->tap, true);
+    return 0;
+}
+
+// The brightness OSD belongs to the private OSDUIHelper framework, the same
+// one the built-in keys use. It is looked up at runtime like MonitorPanel.
+@protocol OSDUIHelperManager
++ (id)sharedManager;
+- (void)showImage:(long long)image
+       onDisplayID:(CGDirectDisplayID)display_id
+          priority:(unsigned int)priority
+     msecUntilFade:(unsigned int)msec
+    filledChiclets:(unsigned int)filled
+     totalChiclets:(unsigned int)total
+            locked:(BOOL)locked;
+@end
+
+#define OSD_IMAGE_BRIGHTNESS 1
+#define OSD_PRIORITY 0x1F4
+#define OSD_FADE_MSEC 1000
+#define OSD_CHICLETS 64
+
+int ds_show_brightness_osd(uint32_t display_id, float brightness) {
+    static Class manager_class = Nil;
+    static dispatch_once_t once;
+    dispatch_once(&once, ^{
+        if (dlopen("/System/Library/PrivateFrameworks/OSDUIHelper.framework/OSDUIHelper",
+                   RTLD_LAZY)) {
+            manager_class = NSClassFromString(@"OSDManager");
+        }
+    });
+
+    @autoreleasepool {
+        @try {
+            id<OSDUIHelperManager> manager =
+                [manager_class respondsToSelector:@selector(sharedManager)]
+                    ? [(Class<OSDUIHelperManager>)manager_class sharedManager]
+                    : nil;
+            if (![manager respondsToSelector:@selector(showImage:onDisplayID:priority:msecUntilFade:
+                                                       filledChiclets:totalChiclets:locked:)]) {
+                return ds_fail(DS_ERROR_NOT_SUPPORTED, "OSDUIHelper has no OSDManager");
+            }
+            float level = brightness < 0 ? 0 : brightness > 1 ? 1 : brightness;
+            [manager showImage:OSD_IMAGE_BRIGHTNESS
+                   onDisplayID:display_id
+                      priority:OSD_PRIORITY
+                 msecUntilFade:OSD_FADE_MSEC
+                filledChiclets:(unsigned int)lroundf(level * OSD_CHICLETS)
+                 totalChiclets:OSD_CHICLETS
+                        locked:NO];
+            return 0;
+        } @catch (NSException *exception) {
+            return ds_fail(DS_ERROR_NOT_SUPPORTED, "OSDUIHelper raised %s: %s",
+                           exception.name.UTF8String, exception.reason.UTF8String ?: "");
+        }
+    }
+}
+
+// Windows are reached through the Accessibility API, the only public way to
+// move another app's windows. Apps that do not implement it are skipped.
+static NSArray *ax_windows(pid_t pid) {
+    // An app that is not answering would otherwise hold up every call for
+    // the system default of six seconds
+    static dispatch_once_t once;
+    dispatch_once(&once, ^{
+        AXUIElementRef system_wide = AXUIElementCreateSystemWide();
+        AXUIElementSetMessagingTimeout(system_wide, 1.0f);
+        CFRelease(system_wide);
+    });
+
+    AXUIElementRef app = AXUIElementCreateApplication(pid);
+    if (!app) {
+        return nil;
+    }
+    CFTypeRef windows = NULL;
+    AXError error = AXUIElementCopyAttributeValue(app, kAXWindowsAttribute, &windows);
+    CFRelease(app);
+    if (error != kAXErrorSuccess || !windows) {
+        return nil;
+    }
+    return CFBridgingRelease(windows);
+}
+
+static bool ax_window_frame(AXUIElementRef window, CGPoint *origin, CGSize *size) {
+    CFTypeRef position = NULL;
+    CFTypeRef extent = NULL;
+    bool ok = AXUIElementCopyAttributeValue(window, kAXPositionAttribute, &position) ==
+                  kAXErrorSuccess &&
+              AXUIElementCopyAttributeValue(window, kAXSizeAttribute, &extent) ==
+                  kAXErrorSuccess &&
+              AXValueGetValue(position, kAXValueCGPointType, origin) &&
+              AXValueGetValue(extent, kAXValueCGSizeType, size);
+    if (position) {
+        CFRelease(position);
+    }
+    if (extent) {
+        CFRelease(extent);
+    }
+    return ok;
+}
+
+static NSString *ax_window_title(AXUIElementRef window) {
+    CFTypeRef title = NULL;
+    if (AXUIElementCopyAttributeValue(window, kAXTitleAttribute, &title) != kAXErrorSuccess ||
+        !title) {
+        return @"";
+    }
+    id value = CFBridgingRelease(title);
+    return [value isKindOfClass:[NSString class]] ? value : @"";
+}
+
+// _AXUIElementGetWindow is in HIServices but not in its public headers
+typedef AXError (*AXUIElementGetWindow_t)(AXUIElementRef element, CGWindowID *window_id);
+
+static CGWindowID ax_window_id(AXUIElementRef window) {
+    static AXUIElementGetWindow_t get_window = NULL;
+    static dispatch_once_t once;
+    dispatch_once(&once, ^{
+        get_window = dlsym(RTLD_DEFAULT, "_AXUIElementGetWindow");
+    });
+    CGWindowID window_id = 0;
+    if (!get_window || get_window(window, &window_id) != kAXErrorSuccess) {
+        return 0;
+    }
+    return window_id;
+}
+
+static bool ax_is_standard_window(AXUIElementRef window) {
+    CFTypeRef subrole = NULL;
+    if (AXUIElementCopyAttributeValue(window, kAXSubroleAttribute, &subrole) != kAXErrorSuccess ||
+        !subrole) {
+        return false;
+    }
+    bool standard = CFEqual(subrole, kAXStandardWindowSubrole);
+    CFRelease(subrole);
+    return standard;
+}
+
+int ds_list_windows(WindowFrame *windows, size_t capacity, size_t *count) {
+    if (!count || (capacity > 0 && !windows)) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+    if (!AXIsProcessTrusted()) {
+        return ds_fail(DS_ERROR_NOT_SUPPORTED,
+                       "allow macdisp under Accessibility in Privacy & Security");
+    }
+
+    @autoreleasepool {
+        size_t found = 0;
+        for (NSRunningApplication *app in [[NSWorkspace sharedWorkspace] runningApplications]) {
+            if (app.activationPolicy != NSApplicationActivationPolicyRegular) {
+                continue;
+            }
+            NSArray *app_windows = ax_windows(app.processIdentifier);
+            for (NSUInteger i = 0; i < app_windows.count; i++) {
+                AXUIElementRef window = (__bridge AXUIElementRef)app_windows[i];
+                CGPoint origin;
+                CGSize size;
+                if (!ax_is_standard_window(window) || !ax_window_frame(window, &origin, &size)) {
+                    continue;
+                }
+                if (found < capacity) {
+                    WindowFrame *out = &windows[found];
+                    memset(out, 0, sizeof(*out));
+                    out->pid = app.processIdentifier;
+                    out->index = (uint32_t)i;
+                    out->window_id = ax_window_id(window);
+                    out->x = origin.x;
+                    out->y = origin.y;
+                    out->width = size.width;
+                    out->height = size.height;
+                    strlcpy(out->title, ax_window_title(window).UTF8String ?: "",
+                            sizeof(out->title));
+                }
+                found++;
+            }
+        }
+        *count = found;
+    }
+    return 0;
+}
+
+int ds_set_window_frame(const WindowFrame *frame) {
+    if (!frame) {
+        return DS_ERROR_INVALID_ARGUMENT;
+    }
+
+    @autoreleasepool {
+        NSArray *app_windows = ax_windows(frame->pid);
+        AXUIElementRef window = NULL;
+        if (frame->window_id != 0) {
+            for (id candidate in app_windows) {
+                AXUIElementRef element = (__bridge AXUIElementRef)candidate;
+                if (ax_window_id(element) == frame->window_id) {
+                    window = element;
+                    break;
+                }
+            }
+        } else if (frame->index < app_windows.count) {
+            // Without an id, two windows of an app may share a title, so
+            // the position has to match as well
+            AXUIElementRef element = (__bridge AXUIElementRef)app_windows[frame->index];
+            if (strcmp(ax_window_title(element).UTF8String ?: "", frame->title) == 0) {
+                window = element;
+            }
+        }
+        if (!window) {
+            return ds_fail(DS_ERROR_DISPLAY_NOT_FOUND, "window %u of process %d is gone",
+                           frame->index, frame->pid);
+        }
+
+        // Size first, so a window growing into place is not held back by the
+        // display edge at its old position, then move, then size again for
+        // apps that limit the size to the display the window is on
+        CGPoint origin = CGPointMake(frame->x, frame->y);
+        CGSize size = CGSizeMake(frame->width, frame->height);
+        AXValueRef position = AXValueCreate(kAXValueCGPointType, &origin);
+        AXValueRef extent = AXValueCreate(kAXValueCGSizeType, &size);
+        AXUIElementSetAttributeValue(window, kAXSizeAttribute, extent);
+        AXError error = AXUIElementSetAttributeValue(window, kAXPositionAttribute, position);
+        AXUIElementSetAttributeValue(window, kAXSizeAttribute, extent);
+        CFRelease(position);
+        CFRelease(extent);
+        if (error != kAXErrorSuccess) {
+            return ds_fail(DS_ERROR_NOT_SUPPORTED, "process %d refused to move its window (AXError %d)",
+                           frame->pid, (int)error);
+        }
+    }
+    return 0;
+}
@@ -0,0 +1,45 @@
+//! Where the messages of the library go.
+//!
+//! The library never writes to stdout or stderr itself. Progress, daemon log
+//! lines and warnings are handed to the function set with [`set_handler`]:
+//! the `macdisp` command prints them, an app can show them in its own UI.
+//! Without a handler they are dropped.
+
+use std::sync::OnceLock;
+
+/// What a message is, which decides where `macdisp` prints it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// A step of a command, such as a change made by an apply, or a daemon
+    /// log line
+    Info,
+    /// Something happening on the side, such as waiting for a lock
+    Notice,
+    /// Something that did not stop the work but may not be what was asked for
+    Warning,
+}
+
+static HANDLER: OnceLock<fn(Level, &str)> = OnceLock::new();
+
+/// Send every message to `handler`. Only the first call takes effect.
+pub fn set_handler(handler: fn(Level, &str)) {
+    let _ = HANDLER.set(handler);
+}
+
+fn emit(level: Level, message: &str) {
+    if let Some(handler) = HANDLER.get() {
+        handler(level, message);
+    }
+}
+
+pub(crate) fn info(message: &str) {
+    emit(Level::Info, message);
+}
+
+pub(crate) fn notice(message: &str) {
+    emit(Level::Notice, message);
+}
+
+pub(crate) fn warning(message: &str) {
+    emit(Level::Warning, message);
+}
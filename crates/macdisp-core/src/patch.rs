@@ -0,0 +1,203 @@
+//! Apply a JSON description of the displays, as printed by `list --json`.
+//!
+//! Scripts read the state, edit it with jq and hand it back; only the fields
+//! that differ from the live state become configuration keys.
+
+use crate::geometry::SizePx;
+use crate::mode_id::{same_hz, ModeId};
+use crate::{DisplayConfig, DisplayInfo};
+use serde::Deserialize;
+use serde_json::Value;
+
+/// One display of the document, with the fields of [`DisplayInfo`] that can
+/// be changed. Every field but an identifier may be left out; fields such as
+/// `display_type` or `modes` are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct DisplayPatch {
+    pub id: Option<u32>,
+    pub persistent_id: Option<String>,
+    pub serial: Option<u32>,
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub rotation: Option<u32>,
+    pub hz: Option<f64>,
+    pub depth: Option<u32>,
+    pub scaling: Option<bool>,
+    pub mode_id: Option<ModeId>,
+    /// `null` stops mirroring; a missing key leaves it as it is
+    #[serde(default, deserialize_with = "present")]
+    pub mirror_of: Option<Option<u32>>,
+    pub enabled: Option<bool>,
+}
+
+/// Tell an explicit `null` from a missing key.
+fn present<'de, D>(deserializer: D) -> Result<Option<Option<u32>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<u32>::deserialize(deserializer).map(Some)
+}
+
+impl DisplayPatch {
+    /// The connected display this entry describes: by persistent id, then
+    /// display id, then serial number.
+    fn find<'a>(&self, displays: &'a [DisplayInfo]) -> Option<&'a DisplayInfo> {
+        if let Some(uuid) = &self.persistent_id {
+            return displays
+                .iter()
+                .find(|info| info.persistent_id.eq_ignore_ascii_case(uuid));
+        }
+        if let Some(id) = self.id {
+            return displays.iter().find(|info| info.id == id);
+        }
+        let serial = self.serial.filter(|serial| *serial != 0)?;
+        displays.iter().find(|info| info.serial == serial)
+    }
+
+    fn label(&self) -> String {
+        match (&self.persistent_id, self.id, self.serial) {
+            (Some(uuid), _, _) => uuid.clone(),
+            (None, Some(id), _) => id.to_string(),
+            (None, None, Some(serial)) => format!("s{}", serial),
+            (None, None, None) => "(no id)".to_string(),
+        }
+    }
+
+    /// The configuration that takes `info` to this entry, or `None` when it
+    /// already matches.
+    pub fn config_for(&self, info: &DisplayInfo) -> Option<DisplayConfig> {
+        let mut config = DisplayConfig {
+            id: info.persistent_id.clone(),
+            ..Default::default()
+        };
+
+        // Sizes are rotated, as in `list`; the native size matches too
+        let size = info.size();
+        let want = SizePx::new(
+            self.width.unwrap_or(size.width),
+            self.height.unwrap_or(size.height),
+        );
+        let size_changed = want != size && want != size.rotated(info.rotation);
+        let mode_changed = size_changed
+            || self.hz.is_some_and(|hz| !same_hz(hz, info.hz))
+            || self.depth.is_some_and(|depth| depth != info.depth)
+            || self.scaling.is_some_and(|scaling| scaling != info.scaling);
+        match self.mode_id {
+            Some(mode_id) if mode_id != info.mode_id => config.mode_id = Some(mode_id),
+            _ if mode_changed => {
+                config.resolution = Some((want.width, want.height));
+                config.hz = Some(self.hz.unwrap_or(info.hz));
+                config.color_depth = Some(self.depth.unwrap_or(info.depth));
+                config.scaling = Some(self.scaling.unwrap_or(info.scaling));
+            }
+            _ => {}
+        }
+
+        let origin = (self.x.unwrap_or(info.x), self.y.unwrap_or(info.y));
+        if origin != (info.x, info.y) {
+            config.origin = Some(origin);
+        }
+        config.degree = self.rotation.filter(|rotation| *rotation != info.rotation);
+        config.mirror = match self.mirror_of {
+            Some(Some(id)) if info.mirror_of != Some(id) => Some(id.to_string()),
+            Some(None) if info.mirror_of.is_some() => Some("off".to_string()),
+            _ => None,
+        };
+        config.enabled = self.enabled.filter(|enabled| *enabled != info.enabled);
+
+        let unchanged = config.mode_id.is_none()
+            && config.resolution.is_none()
+            && config.origin.is_none()
+            && config.degree.is_none()
+            && config.mirror.is_none()
+            && config.enabled.is_none();
+        (!unchanged).then_some(config)
+    }
+}
+
+/// Read the displays from a document: the whole `list --json` output, its
+/// `displays` array, or a single display.
+pub fn parse_document(json: &str) -> Result<Vec<DisplayPatch>, String> {
+    let value: Value =
+        serde_json::from_str(json).map_err(|e| format!("Invalid JSON document: {}", e))?;
+    let displays = match value {
+        Value::Object(mut object) if object.contains_key("displays") => {
+            object.remove("displays").unwrap_or_default()
+        }
+        Value::Object(_) => Value::Array(vec![value]),
+        other => other,
+    };
+    let patches: Vec<DisplayPatch> = serde_json::from_value(displays)
+        .map_err(|e| format!("Invalid display in JSON document: {}", e))?;
+    if let Some(index) = patches.iter().position(|patch| {
+        patch.id.is_none() && patch.persistent_id.is_none() && patch.serial.is_none()
+    }) {
+        return Err(format!(
+            "Display {} in the JSON document has no id, persistent_id or serial",
+            index + 1
+        ));
+    }
+    Ok(patches)
+}
+
+/// The configurations that turn `displays` into what `patches` describe,
+/// one for each display that differs.
+pub fn patch_configs(
+    patches: &[DisplayPatch],
+    displays: &[DisplayInfo],
+) -> Result<Vec<DisplayConfig>, String> {
+    let mut configs = Vec::new();
+    for patch in patches {
+        let info = patch
+            .find(displays)
+            .ok_or_else(|| format!("Display {} is not connected", patch.label()))?;
+        configs.extend(patch.config_for(info));
+    }
+    Ok(configs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    #[test]
+    fn test_patch_configs() {
+        let mut mirror = display(2, "BBBB");
+        mirror.mirror_of = Some(1);
+        let displays = vec![display(1, "AAAA"), mirror];
+
+        // Round trip of an unedited listing changes nothing
+        let listing = serde_json::json!({ "schema_version": 1, "displays": displays });
+        let patches = parse_document(&listing.to_string()).unwrap();
+        assert!(patch_configs(&patches, &displays).unwrap().is_empty());
+
+        let patches = parse_document(
+            r#"[
+                { "persistent_id": "aaaa", "x": -2560, "hz": 60.0, "width": 1920, "height": 1080 },
+                { "id": 2, "mirror_of": null, "rotation": 90 }
+            ]"#,
+        )
+        .unwrap();
+        let configs = patch_configs(&patches, &displays).unwrap();
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0].id, "AAAA");
+        assert_eq!(configs[0].origin, Some((-2560, 0)));
+        assert_eq!(configs[0].resolution, Some((1920, 1080)));
+        assert_eq!(configs[0].color_depth, Some(8));
+        assert_eq!(configs[1].mirror.as_deref(), Some("off"));
+        assert_eq!(configs[1].degree, Some(90));
+        assert_eq!(configs[1].resolution, None);
+
+        let single = parse_document(r#"{ "serial": 0, "id": 9 }"#).unwrap();
+        assert!(patch_configs(&single, &displays)
+            .unwrap_err()
+            .contains("not connected"));
+        assert!(parse_document(r#"[{ "x": 0 }]"#)
+            .unwrap_err()
+            .contains("no id"));
+        assert!(parse_document("[").is_err());
+    }
+}
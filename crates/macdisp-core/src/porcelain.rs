@@ -0,0 +1,116 @@
+//! `--porcelain` output: the state as records for shell scripts.
+//!
+//! The text output of `list` and `modes` is written for people and may be
+//! reworded in any release. Porcelain v1 does not change: each line is one
+//! record, its fields are separated by tabs, the first field names the
+//! record type and the rest always come in the order documented on each
+//! function. Flags are `1` or `0` and a missing value is `-`. Anything that
+//! needs another field or another notation becomes a new version.
+
+use crate::mode_id::format_hz;
+use crate::{DisplayInfo, DisplayMode};
+
+fn flag(value: bool) -> &'static str {
+    if value {
+        "1"
+    } else {
+        "0"
+    }
+}
+
+fn record(fields: &[String]) -> String {
+    fields.join("\t") + "\n"
+}
+
+/// One `display` record for each of `displays`:
+///
+/// `display id persistent_id serial x y width height hz depth scaling
+/// rotation main enabled mirror_of mode_id`
+///
+/// Width and height are as laid out, after rotation; `hz` is written to the
+/// millihertz without trailing zeros (`60`, `59.94`).
+pub fn display_records(displays: &[DisplayInfo]) -> String {
+    displays
+        .iter()
+        .map(|info| {
+            record(&[
+                "display".to_string(),
+                info.id.to_string(),
+                info.persistent_id.clone(),
+                info.serial.to_string(),
+                info.x.to_string(),
+                info.y.to_string(),
+                info.width.to_string(),
+                info.height.to_string(),
+                format_hz(info.hz),
+                info.depth.to_string(),
+                flag(info.scaling).to_string(),
+                info.rotation.to_string(),
+                flag(info.is_main).to_string(),
+                flag(info.enabled).to_string(),
+                info.mirror_of
+                    .map(|id| id.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                info.mode_id.to_string(),
+            ])
+        })
+        .collect()
+}
+
+/// One `mode` record for each of a display's `modes`:
+///
+/// `mode mode_number width height hz depth hidpi safe current mode_id`
+///
+/// Width and height are in points at the display's `rotation`, as `res:`
+/// takes them; `current` marks the mode numbered `current`.
+pub fn mode_records(modes: &[DisplayMode], current: Option<u32>, rotation: u32) -> String {
+    modes
+        .iter()
+        .map(|mode| {
+            let size = mode.rotated_size(rotation);
+            record(&[
+                "mode".to_string(),
+                mode.mode_number.to_string(),
+                size.width.to_string(),
+                size.height.to_string(),
+                format_hz(mode.refresh_rate),
+                mode.depth.to_string(),
+                flag(mode.is_scaled).to_string(),
+                flag(mode.is_safe_for_hardware).to_string(),
+                flag(current == Some(mode.mode_number)).to_string(),
+                mode.mode_id().to_string(),
+            ])
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    #[test]
+    fn test_records() {
+        let mut mirror = display(2, "BBBB");
+        mirror.mirror_of = Some(1);
+        mirror.hz = 59.940_059_94;
+        let records = display_records(&[display(1, "AAAA"), mirror]);
+        let lines: Vec<&str> = records.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let fields: Vec<&str> = lines[1].split('\t').collect();
+        assert_eq!(fields.len(), 16);
+        assert_eq!(&fields[..3], ["display", "2", "BBBB"]);
+        assert_eq!(fields[8], "59.94");
+        assert_eq!(fields[14], "1");
+        assert_eq!(lines[0].split('\t').nth(14), Some("-"));
+
+        let mode = DisplayMode {
+            refresh_rate: 120.0,
+            ..crate::apply::tests::mode(7, 1512, 982, true)
+        };
+        assert_eq!(
+            mode_records(&[mode], Some(7), 90),
+            "mode\t7\t982\t1512\t120\t8\t1\t1\t1\t1512x982/3024x1964@120/d8/hidpi\n"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::executor::{guard_callback, on_run_loop_thread};
+use crate::{get_online_displays, is_asleep, DisplayMode};
+use serde::{Deserialize, Serialize};
+use std::os::raw::c_void;
+use std::thread;
+use std::time::{Duration, Instant};
+
+type PowerSourceCallback = extern "C" fn(on_battery: bool, context: *mut c_void);
+
+extern "C" {
+    fn ds_on_battery_power() -> i32;
+    fn ds_watch_power_source(callback: PowerSourceCallback, context: *mut c_void) -> i32;
+    fn ds_declare_user_activity() -> i32;
+}
+
+/// How long [`wake_displays`] waits for sleeping displays to come back.
+const WAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const WAKE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where this Mac is drawing power from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PowerSource {
+    Ac,
+    Battery,
+}
+
+impl PowerSource {
+    fn from_battery_flag(on_battery: bool) -> Self {
+        if on_battery {
+            PowerSource::Battery
+        } else {
+            PowerSource::Ac
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PowerSource::Ac => "AC power",
+            PowerSource::Battery => "battery",
+        }
+    }
+}
+
+/// The current power source, or `None` if it cannot be determined.
+pub fn current_power_source() -> Option<PowerSource> {
+    match unsafe { ds_on_battery_power() } {
+        1 => Some(PowerSource::Battery),
+        0 => Some(PowerSource::Ac),
+        _ => None,
+    }
+}
+
+struct PowerWatch {
+    last: Option<PowerSource>,
+    handler: Box<dyn FnMut(PowerSource) + Send>,
+}
+
+extern "C" fn power_source_callback(on_battery: bool, context: *mut c_void) {
+    let watch = unsafe { &mut *(context as *mut PowerWatch) };
+    let source = PowerSource::from_battery_flag(on_battery);
+    // Battery level updates arrive through the same notification.
+    if watch.last != Some(source) {
+        watch.last = Some(source);
+        guard_callback("power source", || (watch.handler)(source));
+    }
+}
+
+/// Call `handler` whenever the Mac switches between AC power and battery.
+///
+/// Like [`crate::usb::watch_usb`], this can be called from any thread and the
+/// registration lasts until the process exits.
+pub fn watch_power_source<F>(handler: F) -> Result<(), String>
+where
+    F: FnMut(PowerSource) + Send + 'static,
+{
+    let watch = Box::new(PowerWatch {
+        last: current_power_source(),
+        handler: Box::new(handler),
+    });
+    let context = Box::into_raw(watch) as usize;
+
+    let result = on_run_loop_thread(move || unsafe {
+        ds_watch_power_source(power_source_callback, context as *mut c_void)
+    });
+    if result != 0 {
+        return Err(format!(
+            "Failed to register for power source notifications: error code {}",
+            result
+        ));
+    }
+    Ok(())
+}
+
+/// Wake sleeping displays the way a key press would, and wait until they are
+/// awake. Returns the displays that were asleep.
+pub fn wake_displays() -> Result<Vec<u32>, String> {
+    let asleep: Vec<u32> = get_online_displays()
+        .into_iter()
+        .filter(|&id| is_asleep(id))
+        .collect();
+    if asleep.is_empty() {
+        return Ok(asleep);
+    }
+
+    let status = crate::error::status(|| unsafe { ds_declare_user_activity() });
+    if !status.is_ok() {
+        return Err(format!("Failed to wake the displays: {}", status));
+    }
+
+    let deadline = Instant::now() + WAKE_TIMEOUT;
+    loop {
+        let still_asleep: Vec<String> = asleep
+            .iter()
+            .filter(|&&id| is_asleep(id))
+            .map(|id| id.to_string())
+            .collect();
+        if still_asleep.is_empty() {
+            return Ok(asleep);
+        }
+        if Instant::now() >= deadline {
+            return Err(format!(
+                "Display {} did not wake within {}s",
+                still_asleep.join(", "),
+                WAKE_TIMEOUT.as_secs()
+            ));
+        }
+        thread::sleep(WAKE_POLL_INTERVAL);
+    }
+}
+
+/// The mode to use on battery: the fastest mode at or below `max_hz` with the
+/// same size, depth and scaling as `current`.
+///
+/// Returns `None` when `current` already runs at or below `max_hz`.
+pub fn battery_mode<'a>(
+    modes: &'a [DisplayMode],
+    current: &DisplayMode,
+    max_hz: f64,
+) -> Option<&'a DisplayMode> {
+    if current.refresh_rate <= max_hz + 0.1 {
+        return None;
+    }
+
+    modes
+        .iter()
+        .filter(|mode| {
+            mode.width == current.width
+                && mode.height == current.height
+                && mode.pixel_width == current.pixel_width
+                && mode.pixel_height == current.pixel_height
+                && mode.depth == current.depth
+                && mode.is_scaled == current.is_scaled
+                && mode.refresh_rate > 0.0
+                && mode.refresh_rate <= max_hz + 0.1
+        })
+        .max_by(|a, b| a.refresh_rate.total_cmp(&b.refresh_rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_battery_mode() {
+        let mode = |mode_number, width, refresh_rate| DisplayMode {
+            refresh_rate,
+            ..crate::apply::tests::mode(mode_number, width, 982, true)
+        };
+        let modes = vec![
+            mode(1, 1512, 120.0),
+            mode(2, 1512, 60.0),
+            mode(3, 1512, 48.0),
+            mode(4, 1800, 60.0),
+        ];
+
+        assert_eq!(
+            battery_mode(&modes, &modes[0], 60.0).map(|m| m.mode_number),
+            Some(2)
+        );
+        assert!(battery_mode(&modes, &modes[1], 60.0).is_none());
+        assert!(battery_mode(&modes, &modes[0], 30.0).is_none());
+    }
+}
@@ -0,0 +1,275 @@
+//! Workarounds for monitors with known DDC/CI oddities, keyed by the EDID
+//! vendor and product ids.
+//!
+//! A small table is built in; `quirks.json` in the config directory adds
+//! entries or overrides built-in ones.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// A VCP code or value, written as two hex digits (`10`, `0x60`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HexByte(pub u8);
+
+impl fmt::Display for HexByte {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}", self.0)
+    }
+}
+
+impl FromStr for HexByte {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+        u8::from_str_radix(digits, 16)
+            .map(HexByte)
+            .map_err(|_| format!("Invalid hex byte: {}", s))
+    }
+}
+
+impl Serialize for HexByte {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for HexByte {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Workarounds applied to DDC/CI exchanges with one monitor model.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Quirks {
+    /// VCP codes that can be set but return garbage when read
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub write_only: Vec<HexByte>,
+    /// Input source values that differ from MCCS, with their port names
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub input_sources: BTreeMap<HexByte, String>,
+    /// Wait this long for a reply instead of the standard 50ms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reply_delay_ms: Option<u64>,
+}
+
+impl Quirks {
+    pub fn is_empty(&self) -> bool {
+        *self == Quirks::default()
+    }
+
+    /// Layer `other` on top: its codes and input names are added and its
+    /// delay wins when set.
+    pub fn merge(mut self, other: &Quirks) -> Quirks {
+        for code in &other.write_only {
+            if !self.write_only.contains(code) {
+                self.write_only.push(*code);
+            }
+        }
+        self.input_sources.extend(
+            other
+                .input_sources
+                .iter()
+                .map(|(value, name)| (*value, name.clone())),
+        );
+        self.reply_delay_ms = other.reply_delay_ms.or(self.reply_delay_ms);
+        self
+    }
+
+    pub fn is_write_only(&self, code: u8) -> bool {
+        self.write_only.contains(&HexByte(code))
+    }
+
+    /// The port name of an input source value, preferring this model's codes.
+    pub fn input_source_name(&self, value: u8) -> Option<&str> {
+        self.input_sources
+            .get(&HexByte(value))
+            .map(String::as_str)
+            .or_else(|| crate::ddc::input_source_name(value))
+    }
+}
+
+/// Quirks for a vendor, or for one of its models.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuirkEntry {
+    /// EDID manufacturer id in hex, e.g. `10ac` for Dell
+    #[serde(deserialize_with = "hex_u16", serialize_with = "to_hex")]
+    pub vendor: u32,
+    /// EDID product id in hex; every model of the vendor when absent
+    #[serde(
+        default,
+        deserialize_with = "optional_hex_u16",
+        serialize_with = "optional_to_hex",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub model: Option<u32>,
+    /// Why the entry exists
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(flatten)]
+    pub quirks: Quirks,
+}
+
+impl QuirkEntry {
+    fn matches(&self, vendor: u32, model: u32) -> bool {
+        self.vendor == vendor && self.model.unwrap_or(model) == model
+    }
+}
+
+fn parse_hex_u16(s: &str) -> Result<u32, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16)
+        .map(u32::from)
+        .map_err(|_| format!("Invalid hex id: {}", s))
+}
+
+fn hex_u16<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    parse_hex_u16(&s).map_err(serde::de::Error::custom)
+}
+
+fn optional_hex_u16<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|s| parse_hex_u16(&s).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+fn to_hex<S: Serializer>(value: &u32, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_args!("{:04x}", value))
+}
+
+fn optional_to_hex<S: Serializer>(value: &Option<u32>, serializer: S) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => to_hex(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+const LG: u32 = 0x1E6D;
+const SAMSUNG: u32 = 0x4C2D;
+
+/// Built-in entries. Keep this to widely reported problems; anything model
+/// specific that is not well established belongs in the user's quirks.json.
+fn builtin_entries() -> Vec<QuirkEntry> {
+    vec![
+        QuirkEntry {
+            vendor: LG,
+            model: None,
+            note: Some("LG monitors use their own input source codes".to_string()),
+            quirks: Quirks {
+                input_sources: BTreeMap::from([
+                    (HexByte(0x90), "HDMI-1".to_string()),
+                    (HexByte(0x91), "HDMI-2".to_string()),
+                    (HexByte(0xD0), "DisplayPort-1".to_string()),
+                    (HexByte(0xD1), "USB-C".to_string()),
+                ]),
+                ..Default::default()
+            },
+        },
+        QuirkEntry {
+            vendor: SAMSUNG,
+            model: None,
+            note: Some("Samsung monitors answer DDC/CI slowly".to_string()),
+            quirks: Quirks {
+                reply_delay_ms: Some(150),
+                ..Default::default()
+            },
+        },
+    ]
+}
+
+/// Path of the user's quirks file.
+pub fn quirks_path() -> std::path::PathBuf {
+    crate::settings::config_dir().join("quirks.json")
+}
+
+/// Read the user's quirks file; a missing file means no entries.
+pub fn load_user_quirks() -> Result<Vec<QuirkEntry>, String> {
+    let path = quirks_path();
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    serde_json::from_str(&contents).map_err(|e| format!("Invalid {}: {}", path.display(), e))
+}
+
+/// Combine the matching entries: built-in ones first, vendor-wide before
+/// model-specific, then the user's in file order.
+pub fn lookup(entries: &[QuirkEntry], vendor: u32, model: u32) -> Quirks {
+    let mut matching: Vec<&QuirkEntry> = entries
+        .iter()
+        .filter(|entry| entry.matches(vendor, model))
+        .collect();
+    // Stable, so entries of the same specificity keep their order
+    matching.sort_by_key(|entry| entry.model.is_some());
+    matching
+        .into_iter()
+        .fold(Quirks::default(), |quirks, entry| {
+            quirks.merge(&entry.quirks)
+        })
+}
+
+/// EDID vendor and product ids of a display.
+pub fn display_vendor_model(display_id: u32) -> (u32, u32) {
+    unsafe {
+        (
+            core_graphics::display::CGDisplayVendorNumber(display_id),
+            core_graphics::display::CGDisplayModelNumber(display_id),
+        )
+    }
+}
+
+/// The quirks for a display, including the user's overrides.
+///
+/// An unreadable quirks file is reported and the built-in table used alone.
+pub fn for_display(display_id: u32) -> Quirks {
+    let (vendor, model) = display_vendor_model(display_id);
+    let builtin = lookup(&builtin_entries(), vendor, model);
+    match load_user_quirks() {
+        Ok(user) => builtin.merge(&lookup(&user, vendor, model)),
+        Err(e) => {
+            crate::output::warning(&e);
+            builtin
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let user: Vec<QuirkEntry> = serde_json::from_str(
+            r#"[
+                {"vendor": "1e6d", "model": "5b8e", "write_only": ["10"], "reply_delay_ms": 200},
+                {"vendor": "0x1e6d", "input_sources": {"d1": "Thunderbolt"}}
+            ]"#,
+        )
+        .unwrap();
+
+        let builtin = lookup(&builtin_entries(), LG, 0x5B8E);
+        assert_eq!(builtin.input_source_name(0xD0), Some("DisplayPort-1"));
+
+        let quirks = builtin.merge(&lookup(&user, LG, 0x5B8E));
+        assert!(quirks.is_write_only(0x10));
+        assert_eq!(quirks.reply_delay_ms, Some(200));
+        assert_eq!(quirks.input_source_name(0xD1), Some("Thunderbolt"));
+        assert_eq!(quirks.input_source_name(0x11), Some("HDMI-1"));
+
+        assert!(lookup(&user, LG, 0x1234).write_only.is_empty());
+        assert!(lookup(&builtin_entries(), 0x10AC, 0).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_entry() {
+        assert!(serde_json::from_str::<Vec<QuirkEntry>>(r#"[{"vendor": "dell"}]"#).is_err());
+        assert!(serde_json::from_str::<Vec<QuirkEntry>>(r#"[{"write_only": ["10"]}]"#).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+//! `macdisp recover`: get a picture back on every display after an
+//! experiment left screens black or unusable.
+//!
+//! Every step is tried even when an earlier one fails, since a half-working
+//! setup is the usual starting point.
+
+use crate::layout::{extended_layout, is_current_layout};
+use crate::mode_id::ModeId;
+use crate::power::wake_displays;
+use crate::{
+    configure_displays, edid, gamma, get_active_displays, get_all_display_info, get_all_modes,
+    get_online_displays, is_asleep, mirrors_display, output, set_display_mode, snapshot,
+    ConfigureOptions, DisplayMode, MirrorSetting,
+};
+
+/// The mode a display is reset to: its native resolution, unscaled, at the
+/// refresh rate closest to 60Hz. When the native resolution is unknown or not
+/// offered, the largest unscaled mode is used instead.
+pub fn recovery_mode(modes: &[DisplayMode], native: Option<(u32, u32)>) -> Option<&DisplayMode> {
+    let safe: Vec<&DisplayMode> = modes
+        .iter()
+        .filter(|mode| {
+            mode.is_safe_for_hardware
+                && !mode.is_scaled
+                && !mode.is_interlaced
+                && !mode.is_stretched
+                && !mode.is_tv_mode
+        })
+        .collect();
+    let pixels = |mode: &DisplayMode| (mode.pixel_width, mode.pixel_height);
+    let size = native
+        .filter(|native| safe.iter().any(|mode| pixels(mode) == *native))
+        .or_else(|| {
+            safe.iter()
+                .map(|mode| pixels(mode))
+                .max_by_key(|&(width, height)| width as u64 * height as u64)
+        })?;
+    safe.into_iter()
+        .filter(|mode| pixels(mode) == size)
+        .min_by(|a, b| {
+            (a.refresh_rate - 60.0)
+                .abs()
+                .total_cmp(&(b.refresh_rate - 60.0).abs())
+                .then(b.depth.cmp(&a.depth))
+        })
+}
+
+/// What recovery changes on an online display: enable it when it is missing
+/// from the active list for no other reason, and stop it mirroring `mirrors`.
+///
+/// Asleep displays and hardware mirror secondaries are left out of the active
+/// list too, but are not disabled.
+fn recovery_change(active: bool, asleep: bool, mirrors: Option<u32>) -> Option<ConfigureOptions> {
+    let disabled = !active && !asleep && mirrors.is_none();
+    let mirrored = mirrors.is_some();
+    (disabled || mirrored).then(|| ConfigureOptions {
+        enabled: disabled.then_some(true),
+        mirror: mirrored.then_some(MirrorSetting::Off),
+        ..Default::default()
+    })
+}
+
+/// Wake, re-enable and unmirror every connected display, put it on its
+/// recovery mode and ColorSync profile, and lay the displays out side by side
+/// with the main display at (0,0).
+///
+/// Does not wait for the apply lock, so a hung macdisp cannot block it. The
+/// state found is saved for `macdisp restore` first. Returns the steps that
+/// failed.
+pub fn recover() -> Vec<String> {
+    let mut warnings = Vec::new();
+    let mut warn = |message: String| {
+        output::warning(&message);
+        warnings.push(message);
+    };
+
+    if let Err(e) = wake_displays() {
+        warn(e);
+    }
+    gamma::restore();
+    output::info("Put every display back on its ColorSync profile");
+
+    // Disabled displays are online but missing from the active list
+    let active = get_active_displays();
+    if let Err(e) = snapshot::save_automatic(&get_all_display_info()) {
+        warn(e);
+    }
+    let changes: Vec<(u32, ConfigureOptions)> = get_online_displays()
+        .into_iter()
+        .filter_map(|id| {
+            let change = recovery_change(active.contains(&id), is_asleep(id), mirrors_display(id));
+            change.map(|options| (id, options))
+        })
+        .collect();
+    if !changes.is_empty() {
+        match configure_displays(&changes) {
+            Ok(()) => {
+                for (id, options) in &changes {
+                    if options.enabled.is_some() {
+                        output::info(&format!("Enabled display {}", id));
+                    }
+                    if options.mirror.is_some() {
+                        output::info(&format!("Stopped mirroring on display {}", id));
+                    }
+                }
+            }
+            Err(e) => warn(format!("Failed to enable and unmirror the displays: {}", e)),
+        }
+    }
+
+    for info in get_all_display_info() {
+        let modes = get_all_modes(info.id);
+        let native = edid::for_display(info.id).ok().and_then(|edid| edid.native);
+        let Some(mode) = recovery_mode(&modes, native) else {
+            warn(format!("Display {}: no safe mode to switch to", info.id));
+            continue;
+        };
+        if mode.mode_number == info.mode_number {
+            continue;
+        }
+        match set_display_mode(info.id, mode.mode_number) {
+            Ok(()) => output::info(&format!(
+                "Switched display {} to {}",
+                info.id,
+                ModeId::of(mode).short()
+            )),
+            Err(e) => warn(format!("Display {}: {}", info.id, e)),
+        }
+    }
+
+    let displays = get_all_display_info();
+    let layout = extended_layout(&displays);
+    if !is_current_layout(&layout, &displays) {
+        match configure_displays(&layout) {
+            Ok(()) => output::info("Laid the displays out side by side from (0,0)"),
+            Err(e) => warn(format!("Failed to arrange the displays: {}", e)),
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::mode;
+
+    #[test]
+    fn test_recovery_mode() {
+        let mut modes = vec![
+            DisplayMode {
+                refresh_rate: 144.0,
+                ..mode(1, 3840, 2160, false)
+            },
+            mode(2, 3840, 2160, false),
+            mode(3, 2560, 1440, false),
+            // 5120x2880 pixels, but scaled
+            mode(4, 2560, 1440, true),
+        ];
+        let native = recovery_mode(&modes, Some((3840, 2160))).unwrap();
+        assert_eq!((native.width, native.refresh_rate), (3840, 60.0));
+        // A native size the display does not offer unscaled
+        let largest = recovery_mode(&modes, Some((5120, 2880))).unwrap();
+        assert_eq!((largest.width, largest.refresh_rate), (3840, 60.0));
+
+        modes[1].is_safe_for_hardware = false;
+        let fallback = recovery_mode(&modes, None).unwrap();
+        assert_eq!(fallback.refresh_rate, 144.0);
+        assert!(recovery_mode(&modes[3..], None).is_none());
+    }
+
+    #[test]
+    fn test_recovery_change() {
+        assert!(recovery_change(true, false, None).is_none());
+        let disabled = recovery_change(false, false, None).unwrap();
+        assert_eq!((disabled.enabled, disabled.mirror), (Some(true), None));
+        // Still asleep after the wake request
+        assert!(recovery_change(false, true, None).is_none());
+        // A hardware mirror secondary is not in the active list either
+        let secondary = recovery_change(false, false, Some(1)).unwrap();
+        assert_eq!(
+            (secondary.enabled, secondary.mirror),
+            (None, Some(MirrorSetting::Off))
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! Reference modes of displays with fine color controls: the Pro Display XDR,
+//! Studio Display and the XDR panels of recent MacBook Pros.
+//!
+//! Each reference mode fixes a white point and SDR reference luminance, for
+//! example "Photography (P3-D65)" at D65 and 160 nits. They are read and set
+//! through the private MonitorPanel framework behind System Settings, so the
+//! list is the display's own, including custom modes made there.
+//!
+//! White point and luminance are changed only by switching modes. Setting
+//! them to arbitrary values is not supported: System Settings does that by
+//! saving a custom mode, which can then be switched to like any other.
+
+use crate::error::DisplayError;
+use crate::executor;
+use serde::Serialize;
+use std::os::raw::c_char;
+
+/// Mirrors `DS_PRESET_NAME_LEN` in display_services.h.
+const PRESET_NAME_LEN: usize = 128;
+/// More reference modes than any display offers.
+const MAX_PRESETS: usize = 64;
+
+/// Layout of `ReferencePreset` in display_services.h.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawPreset {
+    index: i32,
+    active: u8,
+    name: [c_char; PRESET_NAME_LEN],
+}
+
+extern "C" {
+    fn ds_get_reference_presets(
+        display_id: u32,
+        presets: *mut RawPreset,
+        capacity: usize,
+        count: *mut usize,
+    ) -> i32;
+    fn ds_set_reference_preset(display_id: u32, index: i32) -> i32;
+}
+
+/// A reference mode of a display.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ReferencePreset {
+    /// Identifies the mode to [`set_preset`]
+    pub index: i32,
+    pub name: String,
+    pub active: bool,
+}
+
+impl From<&RawPreset> for ReferencePreset {
+    fn from(raw: &RawPreset) -> Self {
+        let bytes: Vec<u8> = raw
+            .name
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        ReferencePreset {
+            index: raw.index,
+            name: String::from_utf8_lossy(&bytes).into_owned(),
+            active: raw.active != 0,
+        }
+    }
+}
+
+/// The reference modes a display offers.
+///
+/// Displays without fine color controls fail with
+/// [`ErrorKind::NotSupported`](crate::error::ErrorKind::NotSupported).
+pub fn presets(display_id: u32) -> Result<Vec<ReferencePreset>, DisplayError> {
+    let result = executor::with_timeout(move || {
+        let empty = RawPreset {
+            index: 0,
+            active: 0,
+            name: [0; PRESET_NAME_LEN],
+        };
+        let mut raw = vec![empty; MAX_PRESETS];
+        let mut count = 0usize;
+        let status = crate::error::status(|| unsafe {
+            ds_get_reference_presets(display_id, raw.as_mut_ptr(), raw.len(), &mut count)
+        });
+        raw.truncate(count.min(MAX_PRESETS));
+        (status, raw)
+    });
+    crate::error::check(
+        "read reference modes",
+        display_id,
+        result.as_ref().map(|(status, _)| status.clone()),
+    )?;
+    let (_, raw) = result.unwrap_or_default();
+    Ok(raw.iter().map(ReferencePreset::from).collect())
+}
+
+/// Switch a display to a reference mode.
+pub fn set_preset(display_id: u32, index: i32) -> Result<(), DisplayError> {
+    let result = executor::with_timeout(move || {
+        crate::error::status(|| unsafe { ds_set_reference_preset(display_id, index) })
+    });
+    crate::error::check("set reference mode", display_id, result)
+}
+
+/// Find a reference mode by index, by name ignoring case, or by the start of
+/// a name when only one mode starts that way (`photo` for "Photography
+/// (P3-D65)").
+pub fn find_preset<'a>(
+    presets: &'a [ReferencePreset],
+    query: &str,
+) -> Result<&'a ReferencePreset, String> {
+    if let Ok(index) = query.parse::<i32>() {
+        if let Some(preset) = presets.iter().find(|p| p.index == index) {
+            return Ok(preset);
+        }
+    }
+    let query_lower = query.to_lowercase();
+    if let Some(preset) = presets
+        .iter()
+        .find(|p| p.name.to_lowercase() == query_lower)
+    {
+        return Ok(preset);
+    }
+
+    let matching: Vec<&ReferencePreset> = presets
+        .iter()
+        .filter(|p| p.name.to_lowercase().starts_with(&query_lower))
+        .collect();
+    match matching.as_slice() {
+        [preset] => Ok(preset),
+        [] => Err(format!("No reference mode matches {}", query)),
+        _ => Err(format!(
+            "{} matches several reference modes: {}",
+            query,
+            matching
+                .iter()
+                .map(|p| p.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_preset() {
+        let preset = |index, name: &str| ReferencePreset {
+            index,
+            name: name.to_string(),
+            active: false,
+        };
+        let presets = vec![
+            preset(1, "Pro Display XDR (P3-1600 nits)"),
+            preset(5, "Photography (P3-D65)"),
+            preset(6, "Internet & Web (sRGB)"),
+            preset(9, "Design & Print (P3-D50)"),
+            preset(10, "Digital Cinema (P3-DCI)"),
+            preset(11, "Digital Cinema (P3-D65)"),
+        ];
+
+        assert_eq!(find_preset(&presets, "photo").unwrap().index, 5);
+        assert_eq!(
+            find_preset(&presets, "internet & web (SRGB)")
+                .unwrap()
+                .index,
+            6
+        );
+        assert_eq!(find_preset(&presets, "9").unwrap().index, 9);
+        assert!(find_preset(&presets, "digital")
+            .unwrap_err()
+            .contains("several"));
+        assert!(find_preset(&presets, "hdr").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::apply::find_display_by;
+use crate::config::{Config, IdStrategy};
+use crate::{DisplayIdentity, DisplayInfo};
+
+/// Prefix that makes a selector name a group from the config file.
+pub const GROUP_PREFIX: char = '@';
+
+/// Whether a display is the Mac's own panel rather than an external monitor.
+pub fn is_builtin(info: &DisplayInfo) -> bool {
+    info.display_type.contains("MacBook") || info.display_type.contains("built")
+}
+
+/// What selectors match a display by, so the full [`DisplayInfo`] and the
+/// cheaper [`DisplayIdentity`] are selected the same way.
+pub trait Selectable {
+    /// `None` when the display has no id to match, as without a window server
+    fn display_id(&self) -> Option<u32>;
+    fn persistent_id(&self) -> &str;
+    fn serial(&self) -> u32;
+    fn is_main(&self) -> bool;
+    fn is_builtin(&self) -> bool;
+}
+
+impl Selectable for DisplayInfo {
+    fn display_id(&self) -> Option<u32> {
+        Some(self.id)
+    }
+
+    fn persistent_id(&self) -> &str {
+        &self.persistent_id
+    }
+
+    fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    fn is_main(&self) -> bool {
+        self.is_main
+    }
+
+    fn is_builtin(&self) -> bool {
+        is_builtin(self)
+    }
+}
+
+impl Selectable for DisplayIdentity {
+    fn display_id(&self) -> Option<u32> {
+        self.id
+    }
+
+    fn persistent_id(&self) -> &str {
+        &self.persistent_id
+    }
+
+    fn serial(&self) -> u32 {
+        self.serial
+    }
+
+    fn is_main(&self) -> bool {
+        self.is_main
+    }
+
+    fn is_builtin(&self) -> bool {
+        self.is_builtin == Some(true)
+    }
+}
+
+/// Find the display a command line selector refers to.
+///
+/// A selector is a display id (decimal, or hex such as `0x04280A80`), a
+/// persistent id (case-insensitive), `s` followed by a serial number, an
+/// alias from the config file, `main` for the display with the menu bar, or
+/// `builtin` for the Mac's own panel.
+pub fn select_display<'a, D: Selectable>(
+    selector: &str,
+    displays: &'a [D],
+    config: &Config,
+) -> Result<&'a D, String> {
+    let found = match selector {
+        "main" => displays.iter().find(|d| d.is_main()),
+        "builtin" => displays.iter().find(|d| d.is_builtin()),
+        _ => {
+            let id = config.resolve_alias(selector);
+            find_display_by(id, displays, IdStrategy::Persistent).or_else(|| {
+                displays
+                    .iter()
+                    .find(|d| d.persistent_id().eq_ignore_ascii_case(id))
+            })
+        }
+    };
+    found.ok_or_else(|| format!("No display matches {}", selector))
+}
+
+/// Find the connected display a selector refers to, with [`select_display`]
+/// over identities alone.
+pub fn connected_display<'a>(
+    selector: &str,
+    identities: &'a [DisplayIdentity],
+    config: &Config,
+) -> Option<&'a DisplayIdentity> {
+    select_display(selector, identities, config).ok()
+}
+
+/// Find the displays a selector refers to: the members of a group for
+/// `@name`, otherwise the single display [`select_display`] finds.
+///
+/// Every member of a group must be connected, so a command never quietly
+/// skips part of the group.
+pub fn select_displays<'a>(
+    selector: &str,
+    displays: &'a [DisplayInfo],
+    config: &Config,
+) -> Result<Vec<&'a DisplayInfo>, String> {
+    let Some(name) = selector.strip_prefix(GROUP_PREFIX) else {
+        return select_display(selector, displays, config).map(|info| vec![info]);
+    };
+    let members = config
+        .groups
+        .get(name)
+        .ok_or_else(|| format!("No group named {}", name))?;
+    if members.is_empty() {
+        return Err(format!("Group {} is empty", name));
+    }
+
+    let mut selected: Vec<&DisplayInfo> = Vec::new();
+    for member in members {
+        let info = select_display(member, displays, config)
+            .map_err(|e| format!("Group {}: {}", name, e))?;
+        if !selected.iter().any(|d| d.id == info.id) {
+            selected.push(info);
+        }
+    }
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    #[test]
+    fn test_select_display() {
+        let mut displays = vec![display(1, "AAAA"), display(2, "BBBB")];
+        displays[0].display_type = "MacBook built in screen".to_string();
+        let mut config = Config::default();
+        config
+            .aliases
+            .insert("desk".to_string(), "BBBB".to_string());
+
+        assert_eq!(select_display("2", &displays, &config).unwrap().id, 2);
+        assert_eq!(
+            select_display("0x00000002", &displays, &config).unwrap().id,
+            2
+        );
+        assert_eq!(select_display("bbbb", &displays, &config).unwrap().id, 2);
+        assert_eq!(select_display("desk", &displays, &config).unwrap().id, 2);
+        displays[1].serial = 4242;
+        assert_eq!(select_display("s4242", &displays, &config).unwrap().id, 2);
+        assert_eq!(select_display("main", &displays, &config).unwrap().id, 1);
+        assert_eq!(select_display("builtin", &displays, &config).unwrap().id, 1);
+        assert!(select_display("CCCC", &displays, &config).is_err());
+    }
+
+    #[test]
+    fn test_connected_display() {
+        let identity = |id, persistent_id: &str, serial| DisplayIdentity {
+            id: Some(id),
+            persistent_id: persistent_id.to_string(),
+            serial,
+            is_main: id == 1,
+            is_builtin: Some(id == 1),
+        };
+        // Read through IOKit, without a window server session
+        let headless = DisplayIdentity {
+            id: None,
+            persistent_id: String::new(),
+            serial: 99,
+            is_main: false,
+            is_builtin: None,
+        };
+        let identities = vec![identity(1, "AAAA", 0), identity(2, "BBBB", 4242), headless];
+        let mut config = Config::default();
+        config
+            .aliases
+            .insert("desk".to_string(), "BBBB".to_string());
+
+        let id = |selector| connected_display(selector, &identities, &config).and_then(|d| d.id);
+        assert_eq!(id("desk"), Some(2));
+        assert_eq!(id("bbbb"), Some(2));
+        assert_eq!(id("s4242"), Some(2));
+        assert_eq!(id("2"), Some(2));
+        assert_eq!(id("0x2"), Some(2));
+        assert_eq!(id("builtin"), Some(1));
+        assert_eq!(id("main"), Some(1));
+        assert_eq!(id("s0"), None);
+        assert_eq!(id("CCCC"), None);
+        let serial = |selector| connected_display(selector, &identities, &config).map(|d| d.serial);
+        assert_eq!(serial("s99"), Some(99));
+        assert_eq!(serial("0"), None);
+    }
+
+    #[test]
+    fn test_select_group() {
+        let displays = vec![display(1, "AAAA"), display(2, "BBBB"), display(3, "CCCC")];
+        let mut config = Config::default();
+        config
+            .aliases
+            .insert("desk".to_string(), "BBBB".to_string());
+        config.groups.insert(
+            "editing".to_string(),
+            vec!["desk".to_string(), "3".to_string(), "bbbb".to_string()],
+        );
+        config
+            .groups
+            .insert("away".to_string(), vec!["DDDD".to_string()]);
+
+        let ids = |selector| {
+            select_displays(selector, &displays, &config)
+                .map(|found| found.iter().map(|d| d.id).collect::<Vec<_>>())
+        };
+        assert_eq!(ids("@editing"), Ok(vec![2, 3]));
+        assert_eq!(ids("main"), Ok(vec![1]));
+        assert!(ids("@away").unwrap_err().contains("DDDD"));
+        assert!(ids("@missing").is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use crate::encoding::{base64, sha1};
+use crate::events::{watch, DisplayEvent};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Appended to the client key before hashing, per RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest request head accepted during the handshake.
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// Largest frame accepted from a client, which has nothing to send but
+/// control frames.
+const MAX_FRAME_SIZE: u64 = 8192;
+
+/// Frame opcodes, per RFC 6455 section 5.2.
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+type Clients = Arc<Mutex<Vec<TcpStream>>>;
+
+/// Serve the `/events` WebSocket on `addr`, pushing every display event as a
+/// JSON text message in the same shape as `watch --json`.
+///
+/// Runs the current thread's CFRunLoop and never returns unless the listener
+/// or the event subscription fails.
+pub fn serve(addr: SocketAddr) -> Result<(), String> {
+    let listener =
+        TcpListener::bind(addr).map_err(|e| format!("Failed to listen on {}: {}", addr, e))?;
+    let clients: Clients = Arc::new(Mutex::new(Vec::new()));
+
+    let accepted = Arc::clone(&clients);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let clients = Arc::clone(&accepted);
+            // A slow client must not hold up the others' handshakes
+            thread::spawn(move || {
+                let Ok(stream) = accept(stream) else {
+                    return;
+                };
+                let Ok(reader) = stream.try_clone() else {
+                    return;
+                };
+                clients
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .push(stream);
+                answer_control_frames(reader, &clients);
+            });
+        }
+    });
+
+    watch(move |event| broadcast(&clients, &event))
+}
+
+fn broadcast(clients: &Clients, event: &DisplayEvent) {
+    let json = match serde_json::to_string(event) {
+        Ok(json) => json,
+        Err(e) => {
+            crate::output::notice(&format!("Failed to serialize event: {}", e));
+            return;
+        }
+    };
+    let frame = text_frame(&json);
+
+    let mut clients = clients
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    // Clients that went away fail the write and are dropped
+    clients.retain_mut(|stream| stream.write_all(&frame).is_ok());
+}
+
+/// Complete the WebSocket handshake, answering anything but `GET /events`
+/// with a plain HTTP error.
+fn accept(mut stream: TcpStream) -> Result<TcpStream, String> {
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .map_err(|e| e.to_string())?;
+    let request = read_request_head(&mut stream)?;
+    let key = handshake_key(&request);
+
+    let response = match key {
+        Ok(key) => format!(
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Accept: {}\r\n\r\n",
+            accept_key(key)
+        ),
+        Err(status) => format!(
+            "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            status
+        ),
+    };
+    stream
+        .write_all(response.as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    key.map_err(|status| status.to_string())?;
+    // Events are written from the watch thread; a stalled client must not block it
+    stream
+        .set_write_timeout(Some(Duration::from_secs(5)))
+        .map_err(|e| e.to_string())?;
+    // An open connection can stay quiet for as long as it likes
+    stream.set_read_timeout(None).map_err(|e| e.to_string())?;
+    Ok(stream)
+}
+
+/// Read the client's frames until the connection ends, answering pings with
+/// a pong and a close with a close. Anything else the client sends is
+/// ignored.
+///
+/// The socket is shut down on the way out, so the next broadcast fails to
+/// write to it and drops the client.
+fn answer_control_frames(mut stream: TcpStream, clients: &Clients) {
+    while let Ok(received) = read_frame(&mut stream) {
+        let reply = match received.opcode {
+            OPCODE_PING => frame(OPCODE_PONG, &received.payload),
+            // Echo the status code, leaving out the reason
+            OPCODE_CLOSE => frame(
+                OPCODE_CLOSE,
+                &received.payload[..received.payload.len().min(2)],
+            ),
+            _ => continue,
+        };
+        let sent = {
+            // Held so the reply cannot land in the middle of an event
+            let _clients = clients
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            stream.write_all(&reply).is_ok()
+        };
+        if !sent || received.opcode == OPCODE_CLOSE {
+            break;
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+/// A frame sent by a client, unmasked.
+#[derive(Debug, PartialEq, Eq)]
+struct Frame {
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+fn read_frame(stream: &mut impl Read) -> io::Result<Frame> {
+    let mut head = [0u8; 2];
+    stream.read_exact(&mut head)?;
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0u8; 8];
+            stream.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Frame too large",
+        ));
+    }
+
+    let mut mask = [0u8; 4];
+    if head[1] & 0x80 != 0 {
+        stream.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0u8; len as usize];
+    stream.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok(Frame {
+        opcode: head[0] & 0x0f,
+        payload,
+    })
+}
+
+fn read_request_head(stream: &mut TcpStream) -> Result<String, String> {
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_SIZE {
+            return Err("Request too large".to_string());
+        }
+        let read = stream.read(&mut buffer).map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Err("Connection closed during handshake".to_string());
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    String::from_utf8(head).map_err(|_| "Request is not valid UTF-8".to_string())
+}
+
+/// The `Sec-WebSocket-Key` of a valid upgrade request for `/events`, or the
+/// HTTP status to answer with.
+fn handshake_key(request: &str) -> Result<&str, &'static str> {
+    let mut lines = request.lines();
+    let mut request_line = lines.next().unwrap_or("").split_whitespace();
+    let (method, target) = (request_line.next(), request_line.next().unwrap_or(""));
+    let path = target.split('?').next().unwrap_or("");
+
+    if path != "/events" {
+        return Err("404 Not Found");
+    }
+    if method != Some("GET") {
+        return Err("405 Method Not Allowed");
+    }
+
+    lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("sec-websocket-key"))
+        .map(|(_, value)| value.trim())
+        .filter(|key| !key.is_empty())
+        .ok_or("426 Upgrade Required")
+}
+
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+/// An unmasked, unfragmented text frame, as servers send them.
+fn text_frame(payload: &str) -> Vec<u8> {
+    frame(OPCODE_TEXT, payload.as_bytes())
+}
+
+/// An unmasked, unfragmented frame of any kind.
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_key() {
+        // Example from RFC 6455, section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn test_handshake_key() {
+        let request = "GET /events HTTP/1.1\r\nHost: localhost\r\n\
+                       sec-websocket-key: abc==\r\n\r\n";
+        assert_eq!(handshake_key(request), Ok("abc=="));
+        assert!(handshake_key("GET /other HTTP/1.1\r\n\r\n").is_err());
+        assert!(handshake_key("GET /events HTTP/1.1\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_text_frame() {
+        assert_eq!(text_frame("hi"), vec![0x81, 2, b'h', b'i']);
+        let long = text_frame(&"x".repeat(300));
+        assert_eq!(&long[..4], &[0x81, 126, 1, 44]);
+        assert_eq!(frame(OPCODE_PONG, b""), vec![0x8A, 0]);
+    }
+
+    #[test]
+    fn test_read_frame() {
+        // Examples from RFC 6455, section 5.7
+        let masked: &[u8] = &[
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert_eq!(
+            read_frame(&mut &masked[..]).unwrap(),
+            Frame {
+                opcode: OPCODE_TEXT,
+                payload: b"Hello".to_vec(),
+            }
+        );
+        let ping: &[u8] = &[0x89, 0x05, b'H', b'e', b'l', b'l', b'o'];
+        assert_eq!(read_frame(&mut &ping[..]).unwrap().opcode, OPCODE_PING);
+
+        // Cut short, or too large to be a control frame
+        assert!(read_frame(&mut &masked[..6]).is_err());
+        let huge: &[u8] = &[0x82, 127, 0, 0, 0, 1, 0, 0, 0, 0];
+        assert!(read_frame(&mut &huge[..]).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+//! Whether this process can reach the window server.
+//!
+//! Over SSH, in a system launch daemon or before anyone logs in there is no
+//! window server session: CoreGraphics lists no displays, reports garbage
+//! bounds and fails every change with `kCGErrorInvalidConnection`. IOKit still
+//! answers, so the EDIDs of connected displays can be read.
+
+use crate::executor::{guard_callback, on_run_loop_thread};
+use serde::Serialize;
+use std::os::raw::c_void;
+
+type ConsoleUserCallback = extern "C" fn(context: *mut c_void);
+
+extern "C" {
+    fn ds_session_state() -> i32;
+    fn ds_watch_console_user(callback: ConsoleUserCallback, context: *mut c_void) -> i32;
+}
+
+/// The window server session this process runs in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionState {
+    /// The session on the console
+    Console,
+    /// A session behind another user's on the console, after fast user switching.
+    /// Displays can be read but changes may not stick.
+    Background,
+    /// No window server session at all
+    Headless,
+}
+
+impl SessionState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionState::Console => "console",
+            SessionState::Background => "background",
+            SessionState::Headless => "headless",
+        }
+    }
+}
+
+/// Where this process runs, as far as it limits what macdisp can do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Session {
+    pub state: SessionState,
+    /// Whether the process was started from an SSH login
+    pub ssh: bool,
+}
+
+impl Session {
+    pub fn has_window_server(&self) -> bool {
+        self.state != SessionState::Headless
+    }
+
+    /// Why display commands cannot work here, when they cannot.
+    pub fn problem(&self) -> Option<String> {
+        if self.has_window_server() {
+            return None;
+        }
+        Some(format!(
+            "No window server session{}: displays cannot be listed or changed from here. \
+             Run macdisp in a logged-in GUI session, or as a LaunchAgent of that user",
+            if self.ssh { " (running over SSH)" } else { "" }
+        ))
+    }
+}
+
+/// The session of this process.
+pub fn current() -> Session {
+    let state = match unsafe { ds_session_state() } {
+        2 => SessionState::Console,
+        1 => SessionState::Background,
+        _ => SessionState::Headless,
+    };
+    let ssh = ["SSH_CONNECTION", "SSH_TTY"]
+        .iter()
+        .any(|key| std::env::var_os(key).is_some());
+    Session { state, ssh }
+}
+
+struct SessionWatch {
+    last: SessionState,
+    handler: Box<dyn FnMut(Session) + Send>,
+}
+
+extern "C" fn console_user_callback(context: *mut c_void) {
+    let watch = unsafe { &mut *(context as *mut SessionWatch) };
+    let session = current();
+    // Logins and logouts of other users change the console user too.
+    if watch.last != session.state {
+        watch.last = session.state;
+        guard_callback("console user", || (watch.handler)(session));
+    }
+}
+
+/// Call `handler` whenever this session moves to or from the console, as it
+/// does when fast user switching brings another user's session to the front.
+///
+/// Like [`crate::power::watch_power_source`], this can be called from any
+/// thread and the registration lasts until the process exits.
+pub fn watch_session<F>(handler: F) -> Result<(), String>
+where
+    F: FnMut(Session) + Send + 'static,
+{
+    let watch = Box::new(SessionWatch {
+        last: current().state,
+        handler: Box::new(handler),
+    });
+    let context = Box::into_raw(watch) as usize;
+
+    let status = on_run_loop_thread(move || {
+        crate::error::status(|| unsafe {
+            ds_watch_console_user(console_user_callback, context as *mut c_void)
+        })
+    });
+    if !status.is_ok() {
+        drop(unsafe { Box::from_raw(context as *mut SessionWatch) });
+        return Err(format!(
+            "Failed to register for console user notifications: {}",
+            status
+        ));
+    }
+    Ok(())
+}
+
+/// Fail with an explanation when there is no window server to talk to.
+pub fn require_window_server() -> Result<(), String> {
+    match current().problem() {
+        Some(problem) => Err(problem),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_problem() {
+        let headless = Session {
+            state: SessionState::Headless,
+            ssh: true,
+        };
+        assert!(headless
+            .problem()
+            .unwrap()
+            .starts_with("No window server session (running over SSH): "));
+
+        let console = Session {
+            state: SessionState::Console,
+            ssh: true,
+        };
+        assert!(console.problem().is_none());
+        assert!(Session {
+            state: SessionState::Background,
+            ssh: false
+        }
+        .has_window_server());
+    }
+}
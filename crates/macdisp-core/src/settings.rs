@@ -0,0 +1,302 @@
+use crate::config::{Config, IdStrategy, OutputFormat};
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+/// Environment variables overriding config file values.
+pub const ENV_CONFIG_DIR: &str = "MACDISP_CONFIG_DIR";
+pub const ENV_PROFILE: &str = "MACDISP_PROFILE";
+pub const ENV_FORMAT: &str = "MACDISP_FORMAT";
+pub const ENV_DEFAULT_HZ: &str = "MACDISP_DEFAULT_HZ";
+pub const ENV_HZ_TOLERANCE: &str = "MACDISP_HZ_TOLERANCE";
+pub const ENV_DEBOUNCE_MS: &str = "MACDISP_DEBOUNCE_MS";
+pub const ENV_ENFORCE: &str = "MACDISP_ENFORCE";
+pub const ENV_CALL_TIMEOUT_MS: &str = "MACDISP_CALL_TIMEOUT_MS";
+pub const ENV_MODE_SET_INTERVAL_MS: &str = "MACDISP_MODE_SET_INTERVAL_MS";
+pub const ENV_STAGGER_MS: &str = "MACDISP_STAGGER_MS";
+pub const ENV_ID_STRATEGY: &str = "MACDISP_ID_STRATEGY";
+pub const ENV_DEFER_GAMMA: &str = "MACDISP_DEFER_GAMMA";
+pub const ENV_BRIGHTNESS_KEYS: &str = "MACDISP_BRIGHTNESS_KEYS";
+
+static CONFIG_DIR_FLAG: OnceLock<PathBuf> = OnceLock::new();
+
+/// Values that take precedence over the config file, from flags or the
+/// environment.
+///
+/// Kept separately so they survive a config reload.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigOverrides {
+    pub debounce_ms: Option<u64>,
+    pub enforce: Option<String>,
+    pub profile: Option<String>,
+    pub format: Option<OutputFormat>,
+    pub default_hz: Option<f64>,
+    pub hz_tolerance: Option<f64>,
+    pub call_timeout_ms: Option<u64>,
+    pub mode_set_interval_ms: Option<u64>,
+    pub stagger_ms: Option<u64>,
+    pub id_strategy: Option<IdStrategy>,
+    pub defer_gamma: Option<bool>,
+    pub brightness_keys: Option<bool>,
+}
+
+impl ConfigOverrides {
+    /// Read overrides from `MACDISP_*` environment variables.
+    pub fn from_env() -> Result<ConfigOverrides, String> {
+        ConfigOverrides::from_lookup(|key| std::env::var(key).ok())
+    }
+
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<ConfigOverrides, String> {
+        let get = |key: &str| lookup(key).filter(|value| !value.is_empty());
+        let invalid = |key: &str, value: &str| format!("Invalid {}: {}", key, value);
+
+        Ok(ConfigOverrides {
+            debounce_ms: get(ENV_DEBOUNCE_MS)
+                .map(|v| v.parse().map_err(|_| invalid(ENV_DEBOUNCE_MS, &v)))
+                .transpose()?,
+            enforce: get(ENV_ENFORCE),
+            profile: get(ENV_PROFILE),
+            format: get(ENV_FORMAT).map(|v| v.parse()).transpose()?,
+            default_hz: get(ENV_DEFAULT_HZ)
+                .map(|v| {
+                    v.parse::<f64>()
+                        .ok()
+                        .filter(|hz| *hz > 0.0)
+                        .ok_or_else(|| invalid(ENV_DEFAULT_HZ, &v))
+                })
+                .transpose()?,
+            hz_tolerance: get(ENV_HZ_TOLERANCE)
+                .map(|v| parse_hz_tolerance(&v).map_err(|_| invalid(ENV_HZ_TOLERANCE, &v)))
+                .transpose()?,
+            call_timeout_ms: get(ENV_CALL_TIMEOUT_MS)
+                .map(|v| v.parse().map_err(|_| invalid(ENV_CALL_TIMEOUT_MS, &v)))
+                .transpose()?,
+            mode_set_interval_ms: get(ENV_MODE_SET_INTERVAL_MS)
+                .map(|v| v.parse().map_err(|_| invalid(ENV_MODE_SET_INTERVAL_MS, &v)))
+                .transpose()?,
+            stagger_ms: get(ENV_STAGGER_MS)
+                .map(|v| parse_millis(&v).map_err(|_| invalid(ENV_STAGGER_MS, &v)))
+                .transpose()?,
+            id_strategy: get(ENV_ID_STRATEGY).map(|v| v.parse()).transpose()?,
+            defer_gamma: get(ENV_DEFER_GAMMA)
+                .map(|v| parse_flag(&v).ok_or_else(|| invalid(ENV_DEFER_GAMMA, &v)))
+                .transpose()?,
+            brightness_keys: get(ENV_BRIGHTNESS_KEYS)
+                .map(|v| parse_flag(&v).ok_or_else(|| invalid(ENV_BRIGHTNESS_KEYS, &v)))
+                .transpose()?,
+        })
+    }
+
+    /// Fill the values not set here from `other`.
+    pub fn or(self, other: ConfigOverrides) -> ConfigOverrides {
+        ConfigOverrides {
+            debounce_ms: self.debounce_ms.or(other.debounce_ms),
+            enforce: self.enforce.or(other.enforce),
+            profile: self.profile.or(other.profile),
+            format: self.format.or(other.format),
+            default_hz: self.default_hz.or(other.default_hz),
+            hz_tolerance: self.hz_tolerance.or(other.hz_tolerance),
+            call_timeout_ms: self.call_timeout_ms.or(other.call_timeout_ms),
+            mode_set_interval_ms: self.mode_set_interval_ms.or(other.mode_set_interval_ms),
+            stagger_ms: self.stagger_ms.or(other.stagger_ms),
+            id_strategy: self.id_strategy.or(other.id_strategy),
+            defer_gamma: self.defer_gamma.or(other.defer_gamma),
+            brightness_keys: self.brightness_keys.or(other.brightness_keys),
+        }
+    }
+
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(ms) = self.debounce_ms {
+            config.debounce_ms = ms;
+        }
+        if let Some(profile) = &self.enforce {
+            config.enforce = Some(profile.clone());
+        }
+        if let Some(profile) = &self.profile {
+            config.profile = Some(profile.clone());
+        }
+        if let Some(format) = self.format {
+            config.format = format;
+        }
+        if let Some(hz) = self.default_hz {
+            config.default_hz = Some(hz);
+        }
+        if let Some(tolerance) = self.hz_tolerance {
+            config.hz_tolerance = tolerance;
+        }
+        if let Some(ms) = self.call_timeout_ms {
+            config.call_timeout_ms = ms;
+        }
+        if let Some(ms) = self.mode_set_interval_ms {
+            config.mode_set_interval_ms = ms;
+        }
+        if let Some(ms) = self.stagger_ms {
+            config.stagger_ms = ms;
+        }
+        if let Some(strategy) = self.id_strategy {
+            config.id_strategy = strategy;
+        }
+        if let Some(defer) = self.defer_gamma {
+            config.defer_gamma = defer;
+        }
+        if let Some(keys) = self.brightness_keys {
+            config.brightness_keys = keys;
+        }
+    }
+}
+
+/// Parse a duration such as `500ms`, `2s` or `0.5s` into milliseconds. A bare
+/// number is taken as milliseconds.
+pub fn parse_millis(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let (number, scale) = if let Some(ms) = value.strip_suffix("ms") {
+        (ms, 1.0)
+    } else if let Some(s) = value.strip_suffix('s') {
+        (s, 1000.0)
+    } else {
+        (value, 1.0)
+    };
+    number
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite() && *n >= 0.0)
+        .map(|n| (n * scale).round() as u64)
+        .ok_or_else(|| format!("Invalid duration: {} (expected e.g. 500ms or 2s)", value))
+}
+
+/// A boolean environment variable: `1`, `true` or `yes`, or `0`, `false` or
+/// `no`, in any case.
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" => Some(true),
+        "0" | "false" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse a refresh rate tolerance in hertz, such as `0.01`.
+pub fn parse_hz_tolerance(value: &str) -> Result<f64, String> {
+    value
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|hz| hz.is_finite() && *hz >= 0.0)
+        .ok_or_else(|| {
+            format!(
+                "Invalid refresh rate tolerance: {} (expected e.g. 0.01)",
+                value
+            )
+        })
+}
+
+/// Load the settings shared by the CLI and the daemon. Each value comes from
+/// the first source that sets it:
+///
+/// 1. command line flags (`flags`)
+/// 2. `MACDISP_*` environment variables
+/// 3. `config.json`
+/// 4. built-in defaults
+///
+/// The environment is read on every call, so a daemon reload picks up the
+/// values it was started with.
+pub fn load(flags: &ConfigOverrides) -> Result<Config, String> {
+    let mut config = Config::load()?;
+    flags
+        .clone()
+        .or(ConfigOverrides::from_env()?)
+        .apply(&mut config);
+    Ok(config)
+}
+
+/// Use `dir` instead of the environment for the rest of the process.
+pub fn set_config_dir(dir: PathBuf) {
+    let _ = CONFIG_DIR_FLAG.set(dir);
+}
+
+/// Directory holding macdisp's configuration: `--config-dir`,
+/// `$MACDISP_CONFIG_DIR`, `$XDG_CONFIG_HOME/macdisp` or `~/.config/macdisp`.
+pub fn config_dir() -> PathBuf {
+    if let Some(dir) = CONFIG_DIR_FLAG.get() {
+        return dir.clone();
+    }
+    if let Some(dir) = std::env::var_os(ENV_CONFIG_DIR).filter(|d| !d.is_empty()) {
+        return PathBuf::from(dir);
+    }
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        return PathBuf::from(dir).join("macdisp");
+    }
+    let home = std::env::var_os("HOME").unwrap_or_default();
+    PathBuf::from(home).join(".config").join("macdisp")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |key| vars.get(key).cloned()
+    }
+
+    #[test]
+    fn test_env_overrides() {
+        let env = ConfigOverrides::from_lookup(lookup(&[
+            (ENV_PROFILE, "desk"),
+            (ENV_FORMAT, "json"),
+            (ENV_DEFAULT_HZ, "120"),
+            (ENV_DEBOUNCE_MS, ""),
+            (ENV_ID_STRATEGY, "Serial"),
+            (ENV_MODE_SET_INTERVAL_MS, "0"),
+            (ENV_STAGGER_MS, "0.5s"),
+            (ENV_DEFER_GAMMA, "True"),
+            (ENV_HZ_TOLERANCE, "0.01"),
+            (ENV_BRIGHTNESS_KEYS, "no"),
+        ]))
+        .unwrap();
+        assert_eq!(env.profile.as_deref(), Some("desk"));
+        assert_eq!(env.format, Some(OutputFormat::Json));
+        assert_eq!(env.default_hz, Some(120.0));
+        assert_eq!(env.debounce_ms, None);
+        assert_eq!(env.id_strategy, Some(IdStrategy::Serial));
+        assert_eq!(env.mode_set_interval_ms, Some(0));
+        assert_eq!(env.stagger_ms, Some(500));
+        assert_eq!(env.defer_gamma, Some(true));
+        assert_eq!(env.hz_tolerance, Some(0.01));
+        assert_eq!(env.brightness_keys, Some(false));
+
+        assert!(ConfigOverrides::from_lookup(lookup(&[(ENV_DEFAULT_HZ, "fast")])).is_err());
+        assert!(ConfigOverrides::from_lookup(lookup(&[(ENV_FORMAT, "xml")])).is_err());
+        assert!(ConfigOverrides::from_lookup(lookup(&[(ENV_STAGGER_MS, "-1")])).is_err());
+        assert!(ConfigOverrides::from_lookup(lookup(&[(ENV_HZ_TOLERANCE, "-0.1")])).is_err());
+        assert!(ConfigOverrides::from_lookup(lookup(&[(ENV_DEFER_GAMMA, "maybe")])).is_err());
+
+        assert_eq!(parse_millis("250"), Ok(250));
+        assert_eq!(parse_millis("250ms"), Ok(250));
+        assert_eq!(parse_millis("2s"), Ok(2000));
+        assert!(parse_millis("soon").is_err());
+    }
+
+    #[test]
+    fn test_precedence() {
+        let mut config: Config =
+            serde_json::from_str(r#"{ "profile": "file", "default_hz": 60, "debounce_ms": 100 }"#)
+                .unwrap();
+        let flags = ConfigOverrides {
+            profile: Some("flag".to_string()),
+            ..Default::default()
+        };
+        let env =
+            ConfigOverrides::from_lookup(lookup(&[(ENV_PROFILE, "env"), (ENV_DEFAULT_HZ, "144")]))
+                .unwrap();
+
+        flags.or(env).apply(&mut config);
+        assert_eq!(config.profile.as_deref(), Some("flag"));
+        assert_eq!(config.default_hz, Some(144.0));
+        assert_eq!(config.debounce_ms, 100);
+        assert_eq!(config.format, OutputFormat::Text);
+    }
+}
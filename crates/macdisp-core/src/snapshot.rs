@@ -0,0 +1,96 @@
+use crate::apply::parse_config;
+use crate::config::IdStrategy;
+use crate::export::format_utc_timestamp;
+use crate::{format_display_command, DisplayConfig, DisplayInfo};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// The display arrangement as it was before a change, saved so the change
+/// can be undone.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// When the snapshot was taken, `YYYY-MM-DD HH:MM:SS UTC`
+    pub taken: String,
+    /// Configuration strings that recreate the arrangement; they also work as
+    /// a profile in the config file
+    pub configs: Vec<String>,
+    /// Everything known about each display at the time
+    pub displays: Vec<DisplayInfo>,
+}
+
+impl Snapshot {
+    pub fn of(displays: &[DisplayInfo]) -> Snapshot {
+        Snapshot {
+            taken: format_utc_timestamp(SystemTime::now()),
+            configs: displays
+                .iter()
+                .map(|info| format_display_command(info, IdStrategy::Persistent))
+                .collect(),
+            displays: displays.to_vec(),
+        }
+    }
+
+    /// Write the snapshot as JSON. The file is replaced in one step, so a
+    /// crash never leaves half a snapshot behind.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize snapshot: {}", e))?;
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, json + "\n")
+            .and_then(|_| std::fs::rename(&partial, path))
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<Snapshot, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid snapshot {}: {}", path.display(), e))
+    }
+
+    /// The configurations that put the displays back the way they were.
+    pub fn display_configs(&self) -> Result<Vec<DisplayConfig>, String> {
+        self.configs.iter().map(|s| parse_config(s)).collect()
+    }
+}
+
+/// Where the arrangement from before the most recent change is kept for
+/// `macdisp restore`.
+pub fn last_snapshot_path() -> PathBuf {
+    crate::settings::config_dir().join("last-snapshot.json")
+}
+
+/// Save `displays` as the arrangement `restore` goes back to.
+///
+/// Called right before displays are changed. A failure only costs the undo,
+/// so callers report it as a warning and go ahead with the change.
+pub fn save_automatic(displays: &[DisplayInfo]) -> Result<(), String> {
+    let path = last_snapshot_path();
+    match path.parent() {
+        Some(dir) => std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create {}: {}", dir.display(), e)),
+        None => Ok(()),
+    }
+    .and_then(|_| Snapshot::of(displays).save(&path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let snapshot = Snapshot::of(&[display(1, "AAAA"), display(2, "BBBB")]);
+        let path = std::env::temp_dir().join(format!("macdisp-test-{}.json", std::process::id()));
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let configs = loaded.unwrap().display_configs().unwrap();
+        assert_eq!(configs[1].id, "BBBB");
+        assert_eq!(configs[1].resolution, Some((2560, 1440)));
+        assert_eq!(configs[1].origin, Some((0, 0)));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::mode_id::format_hz;
+use crate::{get_active_displays, get_current_mode, is_notch_hidden};
+use core_graphics::display::CGDisplay;
+use std::path::PathBuf;
+
+/// Output styles for the one-line status summary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusFormat {
+    /// `3 displays · main 2560x1440@119.88 · notch hidden`
+    Plain,
+    /// `3 · 2560x1440@120 · notch hidden`, short enough for a SketchyBar label
+    Sketchybar,
+    /// `3 displays | main 2560x1440@120 | notch hidden`, ASCII only
+    PolybarStyle,
+}
+
+/// Build the one-line summary for the currently connected displays.
+///
+/// Status bars run this every few seconds, so it reads CoreGraphics alone
+/// where it can and does not load DisplayServices. The built-in panel's modes
+/// are enumerated only the first time it shows a mode, to tell the notch
+/// state.
+pub fn status_line(format: StatusFormat) -> String {
+    // CoreGraphics lists no displays there, which is not the same as none
+    if !crate::session::current().has_window_server() {
+        return match format {
+            StatusFormat::Sketchybar => "headless".to_string(),
+            _ => "no window server session".to_string(),
+        };
+    }
+    let displays = get_active_displays();
+    let main = CGDisplay::main().id;
+    let main_mode = displays
+        .contains(&main)
+        .then(|| current_mode(main))
+        .flatten();
+    let notch_hidden = displays
+        .iter()
+        .find(|id| CGDisplay::new(**id).is_builtin())
+        .and_then(|id| notch_hidden(*id));
+
+    format_status(displays.len(), main_mode, notch_hidden, format)
+}
+
+/// Width and height in points and refresh rate of the current mode.
+///
+/// CoreGraphics reports 0Hz for some built-in panels; only those are asked
+/// through DisplayServices.
+fn current_mode(display_id: u32) -> Option<(u32, u32, f64)> {
+    let mode = CGDisplay::new(display_id).display_mode()?;
+    if mode.refresh_rate() > 0.0 {
+        return Some((
+            mode.width() as u32,
+            mode.height() as u32,
+            mode.refresh_rate(),
+        ));
+    }
+    get_current_mode(display_id).map(|mode| (mode.width, mode.height, mode.refresh_rate))
+}
+
+/// Whether the notch is hidden on the built-in panel.
+///
+/// Telling takes the panel's whole mode list from DisplayServices, which is
+/// most of what `status` would otherwise cost. The answer depends only on the
+/// mode, so it is kept in a per-user file under the current CoreGraphics mode
+/// and worked out again only when the panel shows another one.
+fn notch_hidden(display_id: u32) -> Option<bool> {
+    let mode = CGDisplay::new(display_id).display_mode()?;
+    let key = format!(
+        "{}x{} {}x{}@{}",
+        mode.width(),
+        mode.height(),
+        mode.pixel_width(),
+        mode.pixel_height(),
+        format_hz(mode.refresh_rate())
+    );
+    let path = notch_cache(display_id);
+    if let Some(hidden) = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|contents| cached_notch_state(&contents, &key))
+    {
+        return hidden;
+    }
+    let hidden = is_notch_hidden(display_id);
+    // Missing the cache only costs the next call the mode list
+    let _ = std::fs::write(&path, format_notch_state(&key, hidden));
+    hidden
+}
+
+fn notch_cache(display_id: u32) -> PathBuf {
+    let uid = unsafe { libc::getuid() };
+    std::env::temp_dir().join(format!("macdisp-{}-notch-{}", uid, display_id))
+}
+
+fn format_notch_state(key: &str, hidden: Option<bool>) -> String {
+    let state = match hidden {
+        Some(true) => "hidden",
+        Some(false) => "visible",
+        None => "none",
+    };
+    format!("{}={}\n", key, state)
+}
+
+/// The notch state cached for the mode `key`, if the cache is for that mode.
+fn cached_notch_state(contents: &str, key: &str) -> Option<Option<bool>> {
+    let (cached_key, state) = contents.trim_end().split_once('=')?;
+    if cached_key != key {
+        return None;
+    }
+    match state {
+        "hidden" => Some(Some(true)),
+        "visible" => Some(Some(false)),
+        "none" => Some(None),
+        _ => None,
+    }
+}
+
+fn format_status(
+    count: usize,
+    main_mode: Option<(u32, u32, f64)>,
+    notch_hidden: Option<bool>,
+    format: StatusFormat,
+) -> String {
+    let mut parts = Vec::new();
+
+    parts.push(match format {
+        StatusFormat::Sketchybar => count.to_string(),
+        _ if count == 1 => "1 display".to_string(),
+        _ => format!("{} displays", count),
+    });
+
+    if let Some((width, height, hz)) = main_mode {
+        let mode = format!("{}x{}@{}", width, height, format_hz(hz));
+        parts.push(match format {
+            StatusFormat::Sketchybar => mode,
+            _ => format!("main {}", mode),
+        });
+    }
+
+    if let Some(hidden) = notch_hidden {
+        parts.push(format!(
+            "notch {}",
+            if hidden { "hidden" } else { "visible" }
+        ));
+    }
+
+    let separator = match format {
+        StatusFormat::PolybarStyle => " | ",
+        _ => " · ",
+    };
+    parts.join(separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_status_without_displays() {
+        assert_eq!(
+            format_status(0, None, None, StatusFormat::Plain),
+            "0 displays"
+        );
+        assert_eq!(
+            format_status(0, None, Some(true), StatusFormat::PolybarStyle),
+            "0 displays | notch hidden"
+        );
+        assert_eq!(format_status(0, None, None, StatusFormat::Sketchybar), "0");
+    }
+
+    #[test]
+    fn test_cached_notch_state() {
+        let key = "1512x945 3024x1890@120";
+        for hidden in [Some(true), Some(false), None] {
+            let contents = format_notch_state(key, hidden);
+            assert_eq!(cached_notch_state(&contents, key), Some(hidden));
+        }
+        // Another mode, or a damaged file, is worked out again
+        let contents = format_notch_state(key, Some(true));
+        assert_eq!(
+            cached_notch_state(&contents, "1512x982 3024x1964@120"),
+            None
+        );
+        assert_eq!(cached_notch_state("garbage", key), None);
+    }
+
+    #[test]
+    fn test_format_status() {
+        let main = Some((2560, 1440, 119.88));
+        assert_eq!(
+            format_status(2, main, Some(false), StatusFormat::Plain),
+            "2 displays · main 2560x1440@119.88 · notch visible"
+        );
+        assert_eq!(
+            format_status(1, main, None, StatusFormat::Sketchybar),
+            "1 · 2560x1440@119.88"
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! The live arrangement written in terms of the monitors themselves, so the
+//! same monitors plugged into another Mac get the same layout.
+//!
+//! Persistent display ids are made up by each Mac. A sync file names each
+//! display by a key such as `display1` and records the vendor, model and
+//! serial number behind it; on import the keys are mapped to the connected
+//! displays with the same fingerprint. The built-in panel differs from Mac
+//! to Mac and matches whichever built-in panel is connected.
+
+use crate::bundle::{referenced_ids, rewrite_ids, Fingerprint};
+use crate::export::format_utc_timestamp;
+use crate::selector::is_builtin;
+use crate::{format_display_command_with_id, DisplayInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Version of the sync file layout.
+pub const SYNC_FORMAT: u32 = 1;
+
+/// Key of the built-in panel in sync files.
+const BUILTIN_KEY: &str = "builtin";
+
+/// A display of the exported arrangement.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SyncDisplay {
+    /// The id the configurations name the display by
+    pub key: String,
+    pub fingerprint: Fingerprint,
+    /// Monitor name from the EDID, for whoever reads the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+/// An arrangement that can be applied on another Mac.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncArrangement {
+    pub format: u32,
+    /// When the arrangement was exported, `YYYY-MM-DD HH:MM:SS UTC`
+    pub exported: String,
+    /// Configuration strings naming displays by their key
+    pub configs: Vec<String>,
+    pub displays: Vec<SyncDisplay>,
+}
+
+/// A sync file's configurations rewritten for the displays of this Mac.
+#[derive(Debug, Default)]
+pub struct LocalArrangement {
+    pub configs: Vec<String>,
+    /// Displays that could not be matched or were matched by model only
+    pub warnings: Vec<String>,
+    /// The displays of the file that are not connected
+    pub missing: Vec<String>,
+}
+
+impl SyncArrangement {
+    /// Describe the `connected` displays; `name` gives the EDID name of each.
+    pub fn export(
+        connected: &[(DisplayInfo, Fingerprint)],
+        name: impl Fn(&DisplayInfo) -> Option<String>,
+    ) -> SyncArrangement {
+        let mut keys = BTreeMap::new();
+        let mut displays: Vec<SyncDisplay> = Vec::new();
+        for (info, fingerprint) in connected {
+            let key = if is_builtin(info) {
+                BUILTIN_KEY.to_string()
+            } else {
+                let external = displays.iter().filter(|d| d.key != BUILTIN_KEY).count();
+                format!("display{}", external + 1)
+            };
+            keys.insert(info.id.to_string(), key.clone());
+            displays.push(SyncDisplay {
+                key,
+                fingerprint: *fingerprint,
+                name: name(info),
+            });
+        }
+        // Only `mirror:` still holds a display id to rewrite
+        let configs = connected
+            .iter()
+            .zip(&displays)
+            .map(|((info, _), display)| {
+                let config_str = if display.key == BUILTIN_KEY {
+                    builtin_command(info)
+                } else {
+                    format_display_command_with_id(info, &display.key)
+                };
+                rewrite_ids(&config_str, &keys)
+            })
+            .collect();
+
+        SyncArrangement {
+            format: SYNC_FORMAT,
+            exported: format_utc_timestamp(SystemTime::now()),
+            configs,
+            displays,
+        }
+    }
+
+    /// Map the file's displays onto `connected` and rewrite the
+    /// configurations to use their local persistent ids.
+    ///
+    /// A display is matched by vendor, model and serial number, then by
+    /// vendor and model when only one connected display left is that model.
+    /// Configurations naming a display that is not connected are dropped.
+    pub fn localize(&self, connected: &[(DisplayInfo, Fingerprint)]) -> LocalArrangement {
+        let mut local = LocalArrangement::default();
+        let mut ids = BTreeMap::new();
+        let mut used: Vec<u32> = Vec::new();
+        // Exact matches first, so a model-only match cannot take their display
+        let mut unmatched = Vec::new();
+        for display in &self.displays {
+            let free = |info: &DisplayInfo| !used.contains(&info.id);
+            let found = if display.key == BUILTIN_KEY {
+                connected
+                    .iter()
+                    .find(|(info, _)| is_builtin(info) && free(info))
+            } else {
+                connected
+                    .iter()
+                    .find(|(info, f)| *f == display.fingerprint && free(info))
+            };
+            match found {
+                Some((info, _)) => {
+                    used.push(info.id);
+                    ids.insert(display.key.clone(), info.persistent_id.clone());
+                }
+                None => unmatched.push(display),
+            }
+        }
+        for display in unmatched {
+            let same_model: Vec<_> = connected
+                .iter()
+                .filter(|(info, f)| {
+                    !is_builtin(info)
+                        && !used.contains(&info.id)
+                        && f.same_model(&display.fingerprint)
+                })
+                .collect();
+            match same_model.as_slice() {
+                [(info, _)] if display.key != BUILTIN_KEY => {
+                    local.warnings.push(format!(
+                        "{} matched display {} by model only; its serial number differs",
+                        display.label(),
+                        info.id
+                    ));
+                    used.push(info.id);
+                    ids.insert(display.key.clone(), info.persistent_id.clone());
+                }
+                _ => {
+                    local
+                        .warnings
+                        .push(format!("{} is not connected, skipping", display.label()));
+                    local.missing.push(display.label());
+                }
+            }
+        }
+
+        local.configs = self
+            .configs
+            .iter()
+            .filter(|config_str| {
+                referenced_ids(config_str)
+                    .iter()
+                    .all(|key| ids.contains_key(key))
+            })
+            .map(|config_str| rewrite_ids(config_str, &ids))
+            .collect();
+        local
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize arrangement: {}", e))?;
+        std::fs::write(path, json + "\n")
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn load(path: &Path) -> Result<SyncArrangement, String> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let arrangement: SyncArrangement = serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid sync file {}: {}", path.display(), e))?;
+        if arrangement.format > SYNC_FORMAT {
+            return Err(format!(
+                "{} is a newer sync format ({}); upgrade macdisp to import it",
+                path.display(),
+                arrangement.format
+            ));
+        }
+        Ok(arrangement)
+    }
+}
+
+/// The configuration of the built-in panel, without its mode: the panel on
+/// the importing Mac may be another size and refresh rate, so only where it
+/// sits and how it is turned carry over.
+fn builtin_command(info: &DisplayInfo) -> String {
+    let mut command = format!(
+        "id:{} origin:({},{}) degree:{}",
+        BUILTIN_KEY, info.x, info.y, info.rotation
+    );
+    if let Some(mirror_id) = info.mirror_of {
+        command.push_str(&format!(" mirror:{}", mirror_id));
+    }
+    command.push_str(&format!(" enabled:{}", info.enabled));
+    command
+}
+
+impl SyncDisplay {
+    fn label(&self) -> String {
+        match &self.name {
+            Some(name) => format!("Display {} ({})", self.key, name),
+            None => format!("Display {}", self.key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    fn fingerprint(model: u32, serial: u32) -> Fingerprint {
+        Fingerprint {
+            vendor: 0x10ac,
+            model,
+            serial,
+        }
+    }
+
+    #[test]
+    fn test_export_and_localize() {
+        let mut builtin = display(1, "AAAA");
+        builtin.display_type = "MacBook built-in display".to_string();
+        let mut left = display(2, "BBBB");
+        left.x = -2560;
+        let mut mirror = display(3, "CCCC");
+        mirror.mirror_of = Some(2);
+        mirror.x = -2560;
+        let desk = vec![
+            (builtin, fingerprint(0xa0c3, 0)),
+            (left, fingerprint(0xa0c4, 111)),
+            (mirror, fingerprint(0xa0c5, 222)),
+        ];
+        let exported = SyncArrangement::export(&desk, |info| {
+            (info.id == 2).then(|| "DELL U2720Q".to_string())
+        });
+        assert_eq!(exported.displays[0].key, "builtin");
+        assert_eq!(exported.displays[1].key, "display1");
+        // Another Mac's panel may not offer this one's mode
+        assert_eq!(
+            exported.configs[0],
+            "id:builtin origin:(0,0) degree:0 enabled:true"
+        );
+        assert!(exported.configs[1].starts_with("id:display1 res:2560x1440"));
+        assert!(exported.configs[2].contains("mirror:display1"));
+
+        // Another MacBook: a different panel, the same Dell and a second
+        // unit of the mirrored model; the mirror is matched by model
+        let mut other_builtin = display(7, "DDDD");
+        other_builtin.display_type = "MacBook built-in display".to_string();
+        let other = vec![
+            (other_builtin, fingerprint(0xa0b0, 0)),
+            (display(9, "FFFF"), fingerprint(0xa0c5, 999)),
+            (display(8, "EEEE"), fingerprint(0xa0c4, 111)),
+        ];
+        let local = exported.localize(&other);
+        assert!(local.configs[0].starts_with("id:DDDD "));
+        assert!(local.configs[1].starts_with("id:EEEE "));
+        assert!(local.configs[2].contains("mirror:EEEE"));
+        assert_eq!(local.warnings.len(), 1);
+        assert!(local.warnings[0].contains("model only"));
+
+        // Without the Dell, the mirror of it is dropped too
+        let local = exported.localize(&other[..2]);
+        assert_eq!(local.configs.len(), 1);
+        assert!(local.warnings[0].contains("Display display1 (DELL U2720Q) is not connected"));
+        assert_eq!(local.missing, ["Display display1 (DELL U2720Q)"]);
+    }
+}
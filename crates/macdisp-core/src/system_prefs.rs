@@ -0,0 +1,340 @@
+use crate::mode_id::format_hz;
+use crate::DisplayInfo;
+use core_foundation::boolean::CFBoolean;
+use core_foundation::data::CFData;
+use core_foundation::dictionary::CFDictionary;
+use core_foundation::number::CFNumber;
+use core_foundation::propertylist::{create_with_data, CFPropertyList};
+use core_foundation::string::CFString;
+use core_foundation::{array::CFArray, date::CFDate};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// System-wide arrangement persisted by the WindowServer.
+pub const SYSTEM_PREFS_PATH: &str = "/Library/Preferences/com.apple.windowserver.displays.plist";
+
+/// One display entry of a persisted arrangement.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PersistedDisplay {
+    pub uuid: String,
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub hz: Option<f64>,
+    pub depth: Option<u32>,
+    pub scale: Option<f64>,
+    pub rotation: Option<u32>,
+}
+
+/// An arrangement macOS remembers for a particular set of connected displays.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PersistedArrangement {
+    pub displays: Vec<PersistedDisplay>,
+}
+
+/// The persisted preferences together with the file they were read from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SystemPrefs {
+    pub path: PathBuf,
+    pub arrangements: Vec<PersistedArrangement>,
+}
+
+/// A single field that differs between the persisted and the live state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrefsDifference {
+    pub uuid: String,
+    pub field: String,
+    pub persisted: String,
+    pub live: String,
+}
+
+/// Locate the preferences file the WindowServer consults for this user.
+///
+/// Per-user `ByHost` preferences take precedence over the system-wide file.
+pub fn find_system_prefs_path() -> Option<PathBuf> {
+    if let Some(home) = std::env::var_os("HOME") {
+        let by_host = Path::new(&home).join("Library/Preferences/ByHost");
+        if let Ok(entries) = std::fs::read_dir(&by_host) {
+            let mut candidates: Vec<PathBuf> = entries
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| {
+                    p.file_name()
+                        .and_then(|n| n.to_str())
+                        .map(|n| {
+                            n.starts_with("com.apple.windowserver.displays.")
+                                && n.ends_with(".plist")
+                        })
+                        .unwrap_or(false)
+                })
+                .collect();
+            candidates.sort();
+            if let Some(path) = candidates.pop() {
+                return Some(path);
+            }
+        }
+    }
+
+    let system = PathBuf::from(SYSTEM_PREFS_PATH);
+    if system.exists() {
+        Some(system)
+    } else {
+        None
+    }
+}
+
+/// Read a property list file and convert it to JSON.
+///
+/// Binary data blobs are replaced by their length since they carry no
+/// arrangement information.
+pub fn read_plist_as_json(path: &Path) -> Result<Value, String> {
+    let bytes =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let data = CFData::from_buffer(&bytes);
+    let (plist_ref, _format) = create_with_data(data, 0)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e.description()))?;
+    let plist = unsafe { CFPropertyList::wrap_under_create_rule(plist_ref) };
+    Ok(plist_to_json(&plist))
+}
+
+fn plist_to_json(value: &CFPropertyList) -> Value {
+    if let Some(dict) = value.downcast::<CFDictionary>() {
+        let (keys, values) = dict.get_keys_and_values();
+        let mut map = serde_json::Map::new();
+        for (key, value) in keys.into_iter().zip(values) {
+            let key = unsafe { CFPropertyList::wrap_under_get_rule(key) };
+            let value = unsafe { CFPropertyList::wrap_under_get_rule(value) };
+            if let Some(key) = key.downcast::<CFString>() {
+                map.insert(key.to_string(), plist_to_json(&value));
+            }
+        }
+        Value::Object(map)
+    } else if let Some(array) = value.downcast::<CFArray>() {
+        array
+            .get_all_values()
+            .into_iter()
+            .map(|item| plist_to_json(&unsafe { CFPropertyList::wrap_under_get_rule(item) }))
+            .collect()
+    } else if let Some(string) = value.downcast::<CFString>() {
+        Value::String(string.to_string())
+    } else if let Some(boolean) = value.downcast::<CFBoolean>() {
+        Value::Bool(boolean.into())
+    } else if let Some(number) = value.downcast::<CFNumber>() {
+        if let Some(int) = number.to_i64() {
+            Value::from(int)
+        } else {
+            number.to_f64().map(Value::from).unwrap_or(Value::Null)
+        }
+    } else if let Some(date) = value.downcast::<CFDate>() {
+        Value::from(date.abs_time())
+    } else if let Some(data) = value.downcast::<CFData>() {
+        Value::String(format!("<{} bytes>", data.len()))
+    } else {
+        Value::Null
+    }
+}
+
+/// Read the arrangements macOS has persisted for this user.
+pub fn read_system_prefs() -> Result<SystemPrefs, String> {
+    let path = find_system_prefs_path()
+        .ok_or_else(|| "No persisted WindowServer display preferences found".to_string())?;
+    let raw = read_plist_as_json(&path)?;
+    Ok(SystemPrefs {
+        path,
+        arrangements: parse_arrangements(&raw),
+    })
+}
+
+/// Extract arrangements from the JSON form of com.apple.windowserver.displays.
+///
+/// Both the current layout (`DisplayAnyUserSets.Configs[].DisplayConfig[]` with a
+/// `CurrentInfo` dictionary) and the older flat layout (`DisplayAnyUserSets` as an
+/// array of arrays) are understood.
+pub fn parse_arrangements(raw: &Value) -> Vec<PersistedArrangement> {
+    let sets = match raw.get("DisplayAnyUserSets") {
+        Some(sets) => sets,
+        None => return Vec::new(),
+    };
+
+    let mut arrangements = Vec::new();
+    if let Some(configs) = sets.get("Configs").and_then(Value::as_array) {
+        for config in configs {
+            if let Some(entries) = config.get("DisplayConfig").and_then(Value::as_array) {
+                arrangements.push(PersistedArrangement {
+                    displays: entries.iter().filter_map(parse_display).collect(),
+                });
+            }
+        }
+    } else if let Some(sets) = sets.as_array() {
+        for set in sets {
+            if let Some(entries) = set.as_array() {
+                arrangements.push(PersistedArrangement {
+                    displays: entries.iter().filter_map(parse_display).collect(),
+                });
+            }
+        }
+    }
+
+    arrangements.retain(|a| !a.displays.is_empty());
+    arrangements
+}
+
+fn parse_display(entry: &Value) -> Option<PersistedDisplay> {
+    let uuid = entry.get("UUID")?.as_str()?.to_string();
+    let info = entry.get("CurrentInfo").unwrap_or(entry);
+    let mode = entry.get("Mode");
+
+    let number = |keys: &[&str]| -> Option<f64> {
+        keys.iter()
+            .find_map(|k| info.get(*k).and_then(Value::as_f64))
+            .or_else(|| {
+                mode.and_then(|m| keys.iter().find_map(|k| m.get(*k).and_then(Value::as_f64)))
+            })
+    };
+
+    Some(PersistedDisplay {
+        uuid,
+        x: number(&["OriginX"]).map(|v| v as i32),
+        y: number(&["OriginY"]).map(|v| v as i32),
+        width: number(&["Wide", "Width"]).map(|v| v as u32),
+        height: number(&["High", "Height"]).map(|v| v as u32),
+        hz: number(&["Hz", "RefreshRate"]),
+        depth: number(&["Depth", "BitsPerPixel"]).map(|v| v as u32),
+        scale: number(&["Scale", "kCGDisplayResolution"]),
+        rotation: number(&["Rotation"]).map(|v| v as u32),
+    })
+}
+
+/// Pick the persisted arrangement that describes exactly the connected displays.
+pub fn find_matching_arrangement<'a>(
+    prefs: &'a SystemPrefs,
+    live: &[DisplayInfo],
+) -> Option<&'a PersistedArrangement> {
+    let mut live_ids: Vec<&str> = live.iter().map(|d| d.persistent_id.as_str()).collect();
+    live_ids.sort_unstable();
+
+    prefs.arrangements.iter().find(|arrangement| {
+        let mut ids: Vec<&str> = arrangement
+            .displays
+            .iter()
+            .map(|d| d.uuid.as_str())
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids == live_ids
+    })
+}
+
+/// Compare a persisted arrangement with the live display state.
+pub fn diff_arrangement(
+    arrangement: &PersistedArrangement,
+    live: &[DisplayInfo],
+) -> Vec<PrefsDifference> {
+    let mut differences = Vec::new();
+
+    for persisted in &arrangement.displays {
+        let info = match live.iter().find(|d| d.persistent_id == persisted.uuid) {
+            Some(info) => info,
+            None => {
+                differences.push(PrefsDifference {
+                    uuid: persisted.uuid.clone(),
+                    field: "connected".to_string(),
+                    persisted: "true".to_string(),
+                    live: "false".to_string(),
+                });
+                continue;
+            }
+        };
+
+        let mut compare = |field: &str, persisted: Option<String>, live: String| {
+            if let Some(persisted) = persisted {
+                if persisted != live {
+                    differences.push(PrefsDifference {
+                        uuid: info.persistent_id.clone(),
+                        field: field.to_string(),
+                        persisted,
+                        live,
+                    });
+                }
+            }
+        };
+
+        compare(
+            "origin",
+            persisted
+                .x
+                .zip(persisted.y)
+                .map(|(x, y)| format!("({},{})", x, y)),
+            format!("({},{})", info.x, info.y),
+        );
+        compare(
+            "resolution",
+            persisted
+                .width
+                .zip(persisted.height)
+                .map(|(w, h)| format!("{}x{}", w, h)),
+            format!("{}x{}", info.width, info.height),
+        );
+        compare(
+            "hz",
+            persisted.hz.filter(|hz| *hz > 0.0).map(format_hz),
+            format_hz(info.hz),
+        );
+        compare(
+            "rotation",
+            persisted.rotation.map(|r| r.to_string()),
+            info.rotation.to_string(),
+        );
+    }
+
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_current_layout() {
+        let raw = json!({
+            "DisplayAnyUserSets": {
+                "Configs": [{
+                    "DisplayConfig": [{
+                        "UUID": "37D8832A-2D66-02CA-B9F7-8F30A301B230",
+                        "CurrentInfo": {
+                            "OriginX": 0, "OriginY": 0,
+                            "Wide": 1512, "High": 982,
+                            "Hz": 120, "Depth": 8, "Scale": 2.0, "Rotation": 0
+                        }
+                    }]
+                }]
+            }
+        });
+
+        let arrangements = parse_arrangements(&raw);
+        assert_eq!(arrangements.len(), 1);
+        let display = &arrangements[0].displays[0];
+        assert_eq!(display.width, Some(1512));
+        assert_eq!(display.height, Some(982));
+        assert_eq!(display.hz, Some(120.0));
+    }
+
+    #[test]
+    fn test_parse_legacy_layout() {
+        let raw = json!({
+            "DisplayAnyUserSets": [[{
+                "UUID": "ABC",
+                "OriginX": -1920, "OriginY": 0,
+                "Mode": { "Width": 1920, "Height": 1080, "RefreshRate": 60.0 }
+            }]]
+        });
+
+        let arrangements = parse_arrangements(&raw);
+        let display = &arrangements[0].displays[0];
+        assert_eq!(display.x, Some(-1920));
+        assert_eq!(display.width, Some(1920));
+        assert_eq!(display.hz, Some(60.0));
+    }
+}
@@ -0,0 +1,136 @@
+//! Variables in profile configuration strings, so one config file can serve
+//! Macs with different monitors.
+//!
+//! `${NAME}` is replaced with the persistent id of a connected display when
+//! the profile is applied: `MAIN` for the display with the menu bar, `BUILTIN`
+//! for the Mac's own panel and `EXTERNAL_1`, `EXTERNAL_2`, ... for the other
+//! displays from left to right. Variables from the `variables` config key may
+//! use these.
+
+use crate::selector::is_builtin;
+use crate::DisplayInfo;
+use std::collections::BTreeMap;
+
+/// Variables naming the connected displays.
+pub fn display_variables(displays: &[DisplayInfo]) -> BTreeMap<String, String> {
+    let mut variables = BTreeMap::new();
+    if let Some(main) = displays.iter().find(|info| info.is_main) {
+        variables.insert("MAIN".to_string(), main.persistent_id.clone());
+    }
+    if let Some(builtin) = displays.iter().find(|info| is_builtin(info)) {
+        variables.insert("BUILTIN".to_string(), builtin.persistent_id.clone());
+    }
+
+    let mut external: Vec<&DisplayInfo> =
+        displays.iter().filter(|info| !is_builtin(info)).collect();
+    external.sort_by_key(|info| (info.x, info.y));
+    for (index, info) in external.iter().enumerate() {
+        variables.insert(
+            format!("EXTERNAL_{}", index + 1),
+            info.persistent_id.clone(),
+        );
+    }
+    variables
+}
+
+/// The display variables plus the user's own, expanded against them.
+pub fn resolve_variables(
+    user: &BTreeMap<String, String>,
+    displays: &[DisplayInfo],
+) -> Result<BTreeMap<String, String>, String> {
+    let mut variables = display_variables(displays);
+    let mut expanded = BTreeMap::new();
+    for (name, value) in user {
+        let value =
+            expand(value, &variables).map_err(|e| format!("In variable {}: {}", name, e))?;
+        expanded.insert(name.clone(), value);
+    }
+    variables.extend(expanded);
+    Ok(variables)
+}
+
+/// Whether `name` is one of the built-in variables naming a display.
+pub fn is_display_variable(name: &str) -> bool {
+    matches!(name, "MAIN" | "BUILTIN")
+        || name
+            .strip_prefix("EXTERNAL_")
+            .is_some_and(|n| n.parse::<u32>().is_ok())
+}
+
+/// Names of the variables used in `template`, in order.
+pub fn variable_names(template: &str) -> Vec<&str> {
+    template
+        .split("${")
+        .skip(1)
+        .filter_map(|part| part.find('}').map(|end| &part[..end]))
+        .collect()
+}
+
+/// Replace every `${NAME}` in `template`. A `$` not followed by `{` is kept.
+pub fn expand(template: &str, variables: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("Unterminated ${{ in {}", template))?;
+        let name = &after[..end];
+        match variables.get(name) {
+            Some(value) => output.push_str(value),
+            None if is_display_variable(name) => {
+                return Err(format!(
+                    "${{{}}} names a display that is not connected",
+                    name
+                ))
+            }
+            None => return Err(format!("Undefined variable ${{{}}}", name)),
+        }
+        rest = &after[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    #[test]
+    fn test_expand_display_variables() {
+        let mut displays = vec![
+            display(1, "LAPTOP"),
+            display(2, "RIGHT"),
+            display(3, "LEFT"),
+        ];
+        displays[0].display_type = "MacBook built in screen".to_string();
+        displays[1].x = 2560;
+        displays[2].x = -2560;
+
+        let user = BTreeMap::from([
+            ("DESK".to_string(), "${EXTERNAL_1}".to_string()),
+            ("HZ".to_string(), "60".to_string()),
+        ]);
+        let variables = resolve_variables(&user, &displays).unwrap();
+        assert_eq!(variables["MAIN"], "LAPTOP");
+        assert_eq!(variables["BUILTIN"], "LAPTOP");
+        assert_eq!(variables["EXTERNAL_1"], "LEFT");
+        assert_eq!(variables["EXTERNAL_2"], "RIGHT");
+
+        assert_eq!(
+            expand("id:${DESK} hz:${HZ} mirror:${MAIN}", &variables).unwrap(),
+            "id:LEFT hz:60 mirror:LAPTOP"
+        );
+        assert_eq!(expand("id:$1", &variables).unwrap(), "id:$1");
+        assert!(expand("id:${EXTERNAL_3}", &variables)
+            .unwrap_err()
+            .contains("not connected"));
+        assert!(expand("id:${OTHER}", &variables)
+            .unwrap_err()
+            .contains("Undefined"));
+        assert!(expand("id:${MAIN", &variables).is_err());
+        assert_eq!(variable_names("id:${DESK} hz:${HZ} $x"), vec!["DESK", "HZ"]);
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Default minimum time between two mode switches of one display.
+///
+/// Some monitors that are switched again while still negotiating the last
+/// mode get stuck retrying the handshake.
+pub const DEFAULT_MODE_SET_INTERVAL: Duration = Duration::from_secs(1);
+
+static MODE_SET_INTERVAL_MS: AtomicU64 =
+    AtomicU64::new(DEFAULT_MODE_SET_INTERVAL.as_millis() as u64);
+
+/// How often a request waiting on a switch in progress checks again.
+const BUSY_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Set the minimum time between two mode switches of the same display.
+/// `Duration::ZERO` switches modes as fast as they are requested.
+pub fn set_mode_set_interval(interval: Duration) {
+    MODE_SET_INTERVAL_MS.store(interval.as_millis() as u64, Ordering::SeqCst);
+}
+
+pub fn mode_set_interval() -> Duration {
+    Duration::from_millis(MODE_SET_INTERVAL_MS.load(Ordering::SeqCst))
+}
+
+#[derive(Debug, Default)]
+struct Slot {
+    /// When the last switch finished
+    last_set: Option<Instant>,
+    /// The newest request; older ones still waiting give way to it
+    latest: u64,
+    running: bool,
+}
+
+/// Spaces out calls per display, letting only the newest waiting call run.
+pub(crate) struct Throttle {
+    slots: Mutex<BTreeMap<u32, Slot>>,
+}
+
+impl Throttle {
+    pub const fn new() -> Self {
+        Throttle {
+            slots: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn slots(&self) -> MutexGuard<'_, BTreeMap<u32, Slot>> {
+        // Slots are only updated whole, so a panic elsewhere leaves them consistent.
+        self.slots
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Run `f` once `interval` has passed since the last call for
+    /// `display_id` finished, here or at `elsewhere`.
+    ///
+    /// Returns `None` without running `f` when a newer call for the display
+    /// arrived while this one was waiting; the newer one runs instead.
+    pub fn run<T>(
+        &self,
+        display_id: u32,
+        interval: Duration,
+        elsewhere: Option<Instant>,
+        f: impl FnOnce() -> T,
+    ) -> Option<T> {
+        if interval.is_zero() {
+            return Some(f());
+        }
+        let ticket = self.ticket(display_id);
+        self.run_ticket(display_id, ticket, interval, elsewhere, f)
+    }
+
+    /// Queue a call for `display_id`; only the newest ticket gets to run.
+    fn ticket(&self, display_id: u32) -> u64 {
+        let mut slots = self.slots();
+        let slot = slots.entry(display_id).or_default();
+        slot.latest += 1;
+        slot.latest
+    }
+
+    fn run_ticket<T>(
+        &self,
+        display_id: u32,
+        ticket: u64,
+        interval: Duration,
+        elsewhere: Option<Instant>,
+        f: impl FnOnce() -> T,
+    ) -> Option<T> {
+        loop {
+            let wait = {
+                let mut slots = self.slots();
+                let slot = slots.entry(display_id).or_default();
+                if slot.latest != ticket {
+                    return None;
+                }
+                let last = slot.last_set.max(elsewhere);
+                let wait = remaining(last, interval, Instant::now());
+                if slot.running {
+                    BUSY_POLL_INTERVAL
+                } else if wait.is_zero() {
+                    slot.running = true;
+                    break;
+                } else {
+                    wait
+                }
+            };
+            thread::sleep(wait);
+        }
+
+        let _running = Running {
+            throttle: self,
+            display_id,
+        };
+        Some(f())
+    }
+}
+
+/// Marks the call for a display finished, even if it panicked.
+struct Running<'a> {
+    throttle: &'a Throttle,
+    display_id: u32,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let mut slots = self.throttle.slots();
+        let slot = slots.entry(self.display_id).or_default();
+        slot.running = false;
+        slot.last_set = Some(Instant::now());
+    }
+}
+
+/// How long is left of `interval` after `last`.
+fn remaining(last: Option<Instant>, interval: Duration, now: Instant) -> Duration {
+    last.map_or(Duration::ZERO, |at| {
+        (at + interval).saturating_duration_since(now)
+    })
+}
+
+static MODE_SETS: Throttle = Throttle::new();
+
+/// A per-user file for each display whose modification time is when any
+/// macdisp process last switched its mode, so separate invocations from
+/// scripts and hotkeys are spaced out too.
+fn mode_set_stamp(display_id: u32) -> PathBuf {
+    let uid = unsafe { libc::getuid() };
+    std::env::temp_dir().join(format!("macdisp-{}-mode-set-{}", uid, display_id))
+}
+
+/// When any process last switched the mode of `display_id`.
+fn last_mode_set(display_id: u32) -> Option<Instant> {
+    let modified = std::fs::metadata(mode_set_stamp(display_id))
+        .and_then(|metadata| metadata.modified())
+        .ok()?;
+    let ago = SystemTime::now().duration_since(modified).ok()?;
+    Instant::now().checked_sub(ago)
+}
+
+/// Run a mode switch of `display_id` under the [`mode_set_interval`] limit;
+/// `None` means a newer switch of the display superseded it.
+pub(crate) fn mode_set<T>(display_id: u32, f: impl FnOnce() -> T) -> Option<T> {
+    let interval = mode_set_interval();
+    let result = MODE_SETS.run(display_id, interval, last_mode_set(display_id), f);
+    if result.is_some() && !interval.is_zero() {
+        // Missing the stamp only costs the spacing for other processes
+        let _ = std::fs::write(mode_set_stamp(display_id), b"");
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttle() {
+        let throttle = Throttle::new();
+        let interval = Duration::from_secs(1);
+
+        // Two requests queue up; only the newest runs
+        let old = throttle.ticket(1);
+        let new = throttle.ticket(1);
+        assert_eq!(throttle.run_ticket(1, old, interval, None, || "old"), None);
+        assert_eq!(
+            throttle.run_ticket(1, new, interval, None, || "new"),
+            Some("new")
+        );
+        // Other displays are not held up
+        let other = throttle.ticket(2);
+        assert_eq!(throttle.run_ticket(2, other, interval, None, || 2), Some(2));
+        assert_eq!(throttle.run(1, Duration::ZERO, None, || 3), Some(3));
+
+        let now = Instant::now();
+        assert_eq!(remaining(None, interval, now), Duration::ZERO);
+        assert_eq!(
+            remaining(Some(now), interval, now + Duration::from_millis(300)),
+            Duration::from_millis(700)
+        );
+        assert_eq!(
+            remaining(Some(now), interval, now + Duration::from_secs(2)),
+            Duration::ZERO
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use crate::executor::{guard_callback, on_run_loop_thread};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::os::raw::c_void;
+use std::str::FromStr;
+
+type UsbDeviceCallback =
+    extern "C" fn(vendor_id: u16, product_id: u16, added: bool, context: *mut c_void);
+
+extern "C" {
+    fn ds_watch_usb_devices(callback: UsbDeviceCallback, context: *mut c_void) -> i32;
+}
+
+/// A USB device model, written `VID:PID` in hex (e.g. `0bda:8153`) as shown by
+/// `system_profiler SPUSBDataType`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UsbId {
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+impl fmt::Display for UsbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vendor_id, self.product_id)
+    }
+}
+
+impl FromStr for UsbId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid USB id: {} (expected VID:PID in hex)", s);
+        let parse = |part: &str| {
+            let part = part.trim_start_matches("0x").trim_start_matches("0X");
+            u16::from_str_radix(part, 16).map_err(|_| invalid())
+        };
+
+        let (vendor, product) = s.split_once(':').ok_or_else(invalid)?;
+        Ok(UsbId {
+            vendor_id: parse(vendor)?,
+            product_id: parse(product)?,
+        })
+    }
+}
+
+impl Serialize for UsbId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for UsbId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A USB device appearing or disappearing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UsbEvent {
+    pub id: UsbId,
+    pub added: bool,
+}
+
+extern "C" fn usb_device_callback(
+    vendor_id: u16,
+    product_id: u16,
+    added: bool,
+    context: *mut c_void,
+) {
+    let handler = unsafe { &mut *(context as *mut Box<dyn FnMut(UsbEvent) + Send>) };
+    guard_callback("USB device", || {
+        handler(UsbEvent {
+            id: UsbId {
+                vendor_id,
+                product_id,
+            },
+            added,
+        })
+    });
+}
+
+/// Call `handler` whenever a USB device appears or disappears.
+///
+/// Can be called from any thread: the notifications are delivered on the
+/// library's executor thread, which keeps a CFRunLoop running. Devices already
+/// connected are reported as added before this returns. The registration lasts
+/// until the process exits.
+pub fn watch_usb<F>(handler: F) -> Result<(), String>
+where
+    F: FnMut(UsbEvent) + Send + 'static,
+{
+    let handler: Box<Box<dyn FnMut(UsbEvent) + Send>> = Box::new(Box::new(handler));
+    // Passed as an address since raw pointers are not Send.
+    let context = Box::into_raw(handler) as usize;
+
+    let result = on_run_loop_thread(move || unsafe {
+        ds_watch_usb_devices(usb_device_callback, context as *mut c_void)
+    });
+    if result != 0 {
+        return Err(format!(
+            "Failed to register for USB device notifications: error code {}",
+            result
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usb_id_parse() {
+        let id: UsbId = "0BDA:8153".parse().unwrap();
+        assert_eq!(id.vendor_id, 0x0bda);
+        assert_eq!(id.product_id, 0x8153);
+        assert_eq!(id.to_string(), "0bda:8153");
+        assert_eq!(
+            "0x5ac:0x1460".parse::<UsbId>().unwrap().to_string(),
+            "05ac:1460"
+        );
+        assert!("0bda".parse::<UsbId>().is_err());
+        assert!("0bda:zzzz".parse::<UsbId>().is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Desktop pictures per display, so a profile can also put a clean background
+//! on a projector.
+//!
+//! macOS keeps one desktop picture per screen, set through `NSWorkspace`.
+//! Mirrored and disabled displays have no screen of their own and no picture.
+
+use crate::error::DisplayError;
+use crate::executor;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::path::{Path, PathBuf};
+
+/// Longer than any path macOS accepts (`PATH_MAX`).
+const MAX_PATH_LEN: usize = 1024;
+
+extern "C" {
+    fn ds_get_wallpaper(display_id: u32, buffer: *mut c_char, capacity: usize) -> i32;
+    fn ds_set_wallpaper(display_id: u32, path: *const c_char) -> i32;
+}
+
+/// The image file a display shows as its desktop picture.
+pub fn wallpaper(display_id: u32) -> Result<PathBuf, DisplayError> {
+    let result = executor::with_timeout(move || {
+        let mut buffer = vec![0 as c_char; MAX_PATH_LEN];
+        let status = crate::error::status(|| unsafe {
+            ds_get_wallpaper(display_id, buffer.as_mut_ptr(), buffer.len())
+        });
+        let bytes: Vec<u8> = buffer
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        (status, bytes)
+    });
+    crate::error::check(
+        "read desktop picture",
+        display_id,
+        result.as_ref().map(|(status, _)| status.clone()),
+    )?;
+    let (_, bytes) = result.unwrap_or_default();
+    Ok(PathBuf::from(String::from_utf8_lossy(&bytes).into_owned()))
+}
+
+/// Set the desktop picture of a display, keeping its scaling and fill color.
+pub fn set_wallpaper(display_id: u32, path: &Path) -> Result<(), DisplayError> {
+    let path = CString::new(path.to_string_lossy().into_owned()).map_err(|_| DisplayError {
+        operation: "set desktop picture",
+        display_id,
+        kind: crate::error::ErrorKind::InvalidArgument,
+        detail: None,
+    })?;
+    let result = executor::with_timeout(move || {
+        crate::error::status(|| unsafe { ds_set_wallpaper(display_id, path.as_ptr()) })
+    });
+    crate::error::check("set desktop picture", display_id, result)
+}
+
+/// The image a `wallpaper:` value names: `~/` is the home directory and
+/// relative paths are taken from the current directory. Fails when there is
+/// no such file.
+pub fn resolve_path(value: &str) -> Result<PathBuf, String> {
+    let path = match (value.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(value),
+    };
+    std::fs::canonicalize(&path)
+        .ok()
+        .filter(|path| path.is_file())
+        .ok_or_else(|| format!("Wallpaper {} not found", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_path() {
+        let path = std::env::temp_dir().join(format!("macdisp-test-{}.png", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        let resolved = resolve_path(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            resolved.unwrap(),
+            std::fs::canonicalize(path.parent().unwrap())
+                .unwrap()
+                .join(path.file_name().unwrap())
+        );
+        assert!(resolve_path("/nonexistent/clean.png")
+            .unwrap_err()
+            .contains("not found"));
+        assert!(resolve_path(std::env::temp_dir().to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,349 @@
+//! `--preserve-windows`: put windows back in place after the arrangement
+//! changes.
+//!
+//! When a display changes resolution or moves, macOS pushes the windows on
+//! it wherever they still fit, often all onto one corner or another display.
+//! Their frames are recorded before applying, and afterwards each window
+//! that moved is put at the same place relative to its display, scaled to
+//! the display's new size. Windows are moved through the Accessibility API,
+//! so only apps that implement it take part.
+
+use crate::error;
+use crate::geometry::{PointPx, Rect, SizePx};
+use crate::DisplayInfo;
+use std::os::raw::c_char;
+use std::time::Duration;
+
+/// Mirrors `DS_WINDOW_TITLE_LEN` in display_services.h.
+const TITLE_LEN: usize = 256;
+
+/// Room for this many windows on the first try; more takes a second call.
+const INITIAL_CAPACITY: usize = 256;
+
+/// How long macOS is given to finish moving windows off changed displays
+/// before they are put back, so it does not move them again afterwards.
+pub const SETTLE_DELAY: Duration = Duration::from_millis(500);
+
+/// Layout of `WindowFrame` in display_services.h.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawWindow {
+    pid: i32,
+    index: u32,
+    window_id: u32,
+    x: f64,
+    y: f64,
+    width: f64,
+    height: f64,
+    title: [c_char; TITLE_LEN],
+}
+
+extern "C" {
+    fn ds_list_windows(windows: *mut RawWindow, capacity: usize, count: *mut usize) -> i32;
+    fn ds_set_window_frame(window: *const RawWindow) -> i32;
+}
+
+impl RawWindow {
+    const EMPTY: RawWindow = RawWindow {
+        pid: 0,
+        index: 0,
+        window_id: 0,
+        x: 0.0,
+        y: 0.0,
+        width: 0.0,
+        height: 0.0,
+        title: [0; TITLE_LEN],
+    };
+}
+
+/// A standard window of a running app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Window {
+    pub pid: i32,
+    /// Position among the app's windows, front to back
+    pub index: u32,
+    /// The window server's id of the window, 0 when it cannot be read
+    pub window_id: u32,
+    pub title: String,
+    pub frame: Rect,
+}
+
+impl Window {
+    fn from_raw(raw: &RawWindow) -> Window {
+        let bytes: Vec<u8> = raw
+            .title
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as u8)
+            .collect();
+        Window {
+            pid: raw.pid,
+            index: raw.index,
+            window_id: raw.window_id,
+            title: String::from_utf8_lossy(&bytes).into_owned(),
+            frame: Rect::new(
+                PointPx::new(raw.x.round() as i32, raw.y.round() as i32),
+                SizePx::new(raw.width.round() as u32, raw.height.round() as u32),
+            ),
+        }
+    }
+
+    fn to_raw(&self) -> RawWindow {
+        let mut raw = RawWindow {
+            pid: self.pid,
+            index: self.index,
+            window_id: self.window_id,
+            x: self.frame.left() as f64,
+            y: self.frame.top() as f64,
+            width: self.frame.size.width as f64,
+            height: self.frame.size.height as f64,
+            ..RawWindow::EMPTY
+        };
+        // Same truncation as the shim, so the titles still compare equal
+        for (slot, byte) in raw
+            .title
+            .iter_mut()
+            .zip(self.title.bytes().take(TITLE_LEN - 1))
+        {
+            *slot = byte as c_char;
+        }
+        raw
+    }
+
+    /// Whether `other` is this window, as listed at another time.
+    ///
+    /// Windows are told apart by their window server id. Without one, two
+    /// windows of an app can share a title, so the position among the app's
+    /// windows has to match too.
+    fn is(&self, other: &Window) -> bool {
+        if self.pid != other.pid {
+            return false;
+        }
+        if self.window_id != 0 && other.window_id != 0 {
+            return self.window_id == other.window_id;
+        }
+        self.index == other.index && self.title == other.title
+    }
+
+    fn label(&self) -> String {
+        if self.title.is_empty() {
+            format!("window {} of process {}", self.index, self.pid)
+        } else {
+            format!("\"{}\"", self.title)
+        }
+    }
+}
+
+/// The windows of every regular app, front to back within each app.
+///
+/// Needs the Accessibility permission.
+pub fn record() -> Result<Vec<Window>, String> {
+    let mut raw = vec![RawWindow::EMPTY; INITIAL_CAPACITY];
+    loop {
+        let mut count = 0;
+        let status =
+            error::status(|| unsafe { ds_list_windows(raw.as_mut_ptr(), raw.len(), &mut count) });
+        if !status.is_ok() {
+            return Err(format!("Failed to list the windows: {}", status));
+        }
+        // Windows opened between the two calls are left out
+        if count <= raw.len() {
+            raw.truncate(count);
+            return Ok(raw.iter().map(Window::from_raw).collect());
+        }
+        raw = vec![RawWindow::EMPTY; count];
+    }
+}
+
+/// Move and resize a window, found by its id or else its index and title.
+pub fn set_frame(window: &Window) -> Result<(), String> {
+    let raw = window.to_raw();
+    let status = error::status(|| unsafe { ds_set_window_frame(&raw) });
+    if !status.is_ok() {
+        return Err(format!("Failed to move {}: {}", window.label(), status));
+    }
+    Ok(())
+}
+
+/// Where a window at `frame` on a display at `from` goes when the display is
+/// now at `to`: the same place relative to the display, scaled by how much
+/// the display grew or shrank, and kept on it.
+pub fn moved_frame(frame: Rect, from: Rect, to: Rect) -> Rect {
+    let scale_x = to.size.width as f64 / from.size.width.max(1) as f64;
+    let scale_y = to.size.height as f64 / from.size.height.max(1) as f64;
+    let width = ((frame.size.width as f64 * scale_x).round() as u32).min(to.size.width);
+    let height = ((frame.size.height as f64 * scale_y).round() as u32).min(to.size.height);
+    let left = to.left() + ((frame.left() - from.left()) as f64 * scale_x).round() as i32;
+    let top = to.top() + ((frame.top() - from.top()) as f64 * scale_y).round() as i32;
+    Rect::new(
+        PointPx::new(
+            left.clamp(to.left(), to.right() - width as i32),
+            top.clamp(to.top(), to.bottom() - height as i32),
+        ),
+        SizePx::new(width, height),
+    )
+}
+
+/// The display `frame` mostly lies on.
+fn display_of(frame: &Rect, displays: &[DisplayInfo]) -> Option<u32> {
+    displays
+        .iter()
+        .filter(|info| info.enabled && info.mirror_of.is_none())
+        .filter_map(|info| {
+            let shared = info.bounds().intersection(frame)?;
+            Some((
+                shared.size.width as u64 * shared.size.height as u64,
+                info.id,
+            ))
+        })
+        .max()
+        .map(|(_, id)| id)
+}
+
+/// Where each of `windows`, recorded on the `before` arrangement, belongs on
+/// the `after` one. Windows off every display, or on a display that is gone,
+/// disabled or now mirroring, are left to macOS.
+pub fn plan_restore(
+    windows: &[Window],
+    before: &[DisplayInfo],
+    after: &[DisplayInfo],
+) -> Vec<Window> {
+    windows
+        .iter()
+        .filter_map(|window| {
+            let id = display_of(&window.frame, before)?;
+            let from = before.iter().find(|info| info.id == id)?;
+            let to = after
+                .iter()
+                .find(|info| info.id == id && info.enabled && info.mirror_of.is_none())?;
+            Some(Window {
+                frame: moved_frame(window.frame, from.bounds(), to.bounds()),
+                ..window.clone()
+            })
+        })
+        .collect()
+}
+
+/// Windows put back by [`restore`], and those that could not be.
+#[derive(Debug, Default)]
+pub struct Restored {
+    pub moved: usize,
+    pub failed: Vec<String>,
+}
+
+/// Put `windows`, recorded on the `before` arrangement, back in place on the
+/// `after` one. Only windows no longer where they belong are touched.
+pub fn restore(windows: &[Window], before: &[DisplayInfo], after: &[DisplayInfo]) -> Restored {
+    let mut restored = Restored::default();
+    let current = match record() {
+        Ok(current) => current,
+        Err(e) => {
+            restored.failed.push(e);
+            return restored;
+        }
+    };
+    for target in plan_restore(windows, before, after) {
+        // A window closed while applying is not missed
+        let Some(now) = current.iter().find(|window| window.is(&target)) else {
+            continue;
+        };
+        if now.frame == target.frame {
+            continue;
+        }
+        match set_frame(&target) {
+            Ok(()) => restored.moved += 1,
+            Err(e) => restored.failed.push(e),
+        }
+    }
+    restored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apply::tests::display;
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> Rect {
+        Rect::new(PointPx::new(x, y), SizePx::new(width, height))
+    }
+
+    #[test]
+    fn test_moved_frame() {
+        let from = rect(0, 0, 2560, 1440);
+        let to = rect(0, 0, 1920, 1080);
+        // The right half stays the right half
+        assert_eq!(
+            moved_frame(rect(1280, 0, 1280, 1440), from, to),
+            rect(960, 0, 960, 1080)
+        );
+        // A display moved without resizing carries its windows along
+        assert_eq!(
+            moved_frame(rect(100, 50, 800, 600), from, rect(-2560, 0, 2560, 1440)),
+            rect(-2460, 50, 800, 600)
+        );
+        // Hanging off the edge is pulled back on
+        assert_eq!(
+            moved_frame(rect(2400, 1300, 400, 300), from, from),
+            rect(2160, 1140, 400, 300)
+        );
+
+        let mut left = display(2, "BBBB");
+        left.x = -2560;
+        let before = vec![display(1, "AAAA"), left.clone()];
+        let mut smaller = left;
+        smaller.width = 1920;
+        smaller.height = 1080;
+        smaller.x = -1920;
+        let window = Window {
+            pid: 42,
+            index: 0,
+            window_id: 0,
+            title: "Notes".to_string(),
+            frame: rect(-1280, 0, 1280, 720),
+        };
+        let offscreen = Window {
+            frame: rect(-9000, 0, 100, 100),
+            ..window.clone()
+        };
+        let planned = plan_restore(
+            &[window, offscreen],
+            &before,
+            &[display(1, "AAAA"), smaller],
+        );
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].frame, rect(-960, 0, 960, 540));
+    }
+
+    #[test]
+    fn test_window_is() {
+        let window = Window {
+            pid: 42,
+            index: 0,
+            window_id: 0,
+            title: "Untitled".to_string(),
+            frame: rect(0, 0, 800, 600),
+        };
+        // Two untitled documents of one app are different windows
+        let other = Window {
+            index: 1,
+            ..window.clone()
+        };
+        assert!(!window.is(&other));
+        assert!(window.is(&window.clone()));
+
+        // The window server id wins over the position, which changes as
+        // windows come to the front
+        let with_id = Window {
+            window_id: 7,
+            ..window.clone()
+        };
+        assert!(with_id.is(&Window {
+            window_id: 7,
+            ..other.clone()
+        }));
+        assert!(!with_id.is(&Window {
+            window_id: 8,
+            ..window.clone()
+        }));
+    }
+}
@@ -0,0 +1,187 @@
+//! Golden tests: the stages of macdisp that work on display state rather than
+//! hardware, run against fixture display sets so regressions show up without
+//! a Mac on the desk.
+//!
+//! Each fixture is an `ipc::DisplayState` as the daemon caches it. After an
+//! intended change, review the new output with `cargo insta review`.
+
+use macdisp_core::apply::{config_drift, find_display_by, parse_config, ConfigKey, KeyFilter};
+use macdisp_core::config::{CommandIds, IdStrategy};
+use macdisp_core::ipc::DisplayState;
+use macdisp_core::mirror::{compare_modes, plan_mirror};
+use macdisp_core::{display_listing, format_display_list, notch_mode_family, DisplayMode};
+
+/// A built-in notched panel with a 4K monitor above-right of it.
+const MACBOOK_4K: &str = include_str!("fixtures/macbook_4k.json");
+/// Three 1440p monitors, the left one in portrait and the right one without
+/// a serial number.
+const TRIPLE_MONITOR: &str = include_str!("fixtures/triple_monitor.json");
+/// A projector mirroring a MacBook Air.
+const MIRRORED_PAIR: &str = include_str!("fixtures/mirrored_pair.json");
+
+const FIXTURES: [(&str, &str); 3] = [
+    ("macbook_4k", MACBOOK_4K),
+    ("triple_monitor", TRIPLE_MONITOR),
+    ("mirrored_pair", MIRRORED_PAIR),
+];
+
+fn fixture(json: &str) -> DisplayState {
+    serde_json::from_str(json).expect("invalid fixture")
+}
+
+fn modes(state: &DisplayState, display_id: u32) -> &[DisplayMode] {
+    &state.modes[&display_id]
+}
+
+/// What applying `configs` would change, one line per configuration, as the
+/// planner in `apply_configuration` decides it.
+fn plan(state: &DisplayState, configs: &[&str], keys: &KeyFilter) -> String {
+    let mut output = String::new();
+    for config_str in configs {
+        let mut config = parse_config(config_str).unwrap();
+        let left_out = keys.filter(&mut config);
+        let line = match find_display_by(&config.id, &state.displays, IdStrategy::Persistent) {
+            None => format!("{}: not found", config.id),
+            Some(info) => {
+                let drift = config_drift(&config, info, &state.displays);
+                if drift.is_empty() {
+                    format!("display {}: no change", info.id)
+                } else {
+                    format!("display {}: {}", info.id, drift.join(", "))
+                }
+            }
+        };
+        output.push_str(&line);
+        if !left_out.is_empty() {
+            output.push_str(&format!(" [left out: {}]", left_out.join(", ")));
+        }
+        output.push('\n');
+    }
+    output
+}
+
+#[test]
+fn list_text() {
+    for (name, json) in FIXTURES {
+        let state = fixture(json);
+        insta::assert_snapshot!(
+            format!("list_{}", name),
+            format_display_list(&state, &CommandIds::Strategy(IdStrategy::Persistent))
+        );
+    }
+}
+
+#[test]
+fn list_json() {
+    for (name, json) in FIXTURES {
+        let state = fixture(json);
+        insta::assert_json_snapshot!(
+            format!("list_json_{}", name),
+            display_listing(&state, &CommandIds::Strategy(IdStrategy::Serial))
+        );
+    }
+}
+
+#[test]
+fn modes_diff() {
+    let state = fixture(MACBOOK_4K);
+    let comparison = compare_modes(modes(&state, 1), modes(&state, 2));
+    let shared: Vec<String> = comparison
+        .shared
+        .iter()
+        .map(|s| {
+            format!(
+                "{}x{} (modes {} and {}{})",
+                s.width,
+                s.height,
+                s.first.mode_number,
+                s.second.mode_number,
+                if s.is_safe() { "" } else { ", unsafe" }
+            )
+        })
+        .collect();
+    let likely = comparison
+        .likely_mirror_mode()
+        .map(|s| format!("{}x{}", s.width, s.height));
+    insta::assert_snapshot!(format!(
+        "shared: {:?}\nonly first: {:?}\nonly second: {:?}\nlikely mirror mode: {:?}\n",
+        shared, comparison.only_first, comparison.only_second, likely
+    ));
+}
+
+#[test]
+fn apply_plan() {
+    let macbook = fixture(MACBOOK_4K);
+    let docked = [
+        "id:F466F621-B5FA-04A0-0800-CFA6C258DECD res:3840x2160 hz:60 origin:(1512,0)",
+        "id:37D8832A-2D66-02CA-B9F7-8F30A301B230 res:1512x982 hz:120 scaling:on origin:(0,0)",
+    ];
+    let triple = fixture(TRIPLE_MONITOR);
+    let desk = [
+        "id:0A1B2C3D-0000-4000-8000-000000000001 degree:0 origin:(-2560,0)",
+        "id:0A1B2C3D-0000-4000-8000-000000000002 res:2560x1440 hz:144",
+        "id:0A1B2C3D-0000-4000-8000-000000000003 hz:144 origin:(2560,0)",
+        "id:0A1B2C3D-0000-4000-8000-00000000000F origin:(5120,0)",
+    ];
+    let pair = fixture(MIRRORED_PAIR);
+    let unmirror = [
+        "id:5D6E7F80-91A2-4B3C-8D4E-5F6071829304 mirror:off origin:(1920,0)",
+        "id:9C1F0A2E-5B34-4C71-8E1D-6A2B3C4D5E6F res:1920x1080 scaling:off",
+    ];
+
+    insta::assert_snapshot!(format!(
+        "macbook_4k:\n{}\nmacbook_4k --only origin:\n{}\ntriple_monitor:\n{}\ntriple_monitor --skip mode:\n{}\nmirrored_pair:\n{}",
+        plan(&macbook, &docked, &KeyFilter::All),
+        plan(&macbook, &docked, &KeyFilter::Only(vec![ConfigKey::Origin])),
+        plan(&triple, &desk, &KeyFilter::All),
+        plan(&triple, &desk, &KeyFilter::Skip(vec![ConfigKey::Mode])),
+        plan(&pair, &unmirror, &KeyFilter::All),
+    ));
+}
+
+#[test]
+fn mirror_plan() {
+    let state = fixture(MACBOOK_4K);
+    let plan = plan_mirror(
+        &state.displays[1],
+        modes(&state, 2),
+        &state.displays[0],
+        modes(&state, 1),
+    );
+    let summary = match plan {
+        Ok(plan) => format!(
+            "{}x{} (modes {} and {}), lowered: {:?}",
+            plan.mode.width,
+            plan.mode.height,
+            plan.mode.first.mode_number,
+            plan.mode.second.mode_number,
+            plan.lowered
+        ),
+        Err(e) => e,
+    };
+    insta::assert_snapshot!(summary);
+}
+
+#[test]
+fn notch_family() {
+    let mut output = String::new();
+    for (name, json) in FIXTURES {
+        let state = fixture(json);
+        for info in &state.displays {
+            let modes = modes(&state, info.id);
+            let current = modes
+                .iter()
+                .find(|m| m.mode_number == info.mode_number)
+                .unwrap();
+            let heights: Vec<u32> = notch_mode_family(modes, current)
+                .iter()
+                .map(|m| m.height)
+                .collect();
+            output.push_str(&format!(
+                "{} display {}: heights {:?}\n",
+                name, info.id, heights
+            ));
+        }
+    }
+    insta::assert_snapshot!(output);
+}